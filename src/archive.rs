@@ -0,0 +1,755 @@
+//! Archives of the database as JSON lines, plain or gzipped, or as
+//! Parquet. Each file is one line, or row, with every column of `files`;
+//! DuckDB (`read_json`, `read_parquet`), pandas and jq read them as they
+//! are. The rows of the other tables worth keeping, see
+//! [`LockDB::for_each_archived_row`], follow the files as lines naming
+//! their `table`, or in the `deduper.rows` metadata of a Parquet file, and
+//! [`read`] reads all of it back for [`restore`].
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use rusqlite::types::Value;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    database::{serialize_option_path, File, LibraryAsset, LockDB, TableRow},
+    error::{DeduperError, Result},
+    platform,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Jsonl,
+    JsonlGz,
+    /// Only in builds with the `parquet` feature
+    Parquet,
+}
+
+impl ArchiveFormat {
+    /// The format the name of `path` ends in, `.jsonl`, `.jsonl.gz` or
+    /// `.parquet`.
+    pub fn of(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        if name.ends_with(".jsonl.gz") {
+            Some(Self::JsonlGz)
+        } else if name.ends_with(".jsonl") {
+            Some(Self::Jsonl)
+        } else if name.ends_with(".parquet") {
+            Some(Self::Parquet)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ArchiveError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("line {line}: {source}")]
+    InvalidLine {
+        line: usize,
+        source: serde_json::Error,
+    },
+    #[error("line {line}: invalid path bytes {bytes:?}")]
+    InvalidPath { line: usize, bytes: String },
+}
+
+/// What an archive holds.
+#[derive(Debug, Default)]
+pub struct Archive {
+    pub files: Vec<Archived>,
+    pub rows: Vec<TableRow>,
+}
+
+/// A file of an archive: its row, where `organize` placed it and what its
+/// Apple Photos library said about it.
+#[derive(Debug)]
+pub struct Archived {
+    pub file: File,
+    pub dest_path: Option<PathBuf>,
+    pub library: Option<LibraryAsset>,
+}
+
+/// A line of an archive: an [`Archived`] file.
+/// A path that is not valid UTF-8 is written with replacement characters,
+/// for reading, and its bytes go in the matching `_bytes` field as
+/// [`platform::encode_path`] encodes them.
+#[derive(Serialize, Deserialize)]
+struct Line {
+    #[serde(flatten)]
+    file: File,
+    #[serde(serialize_with = "serialize_option_path")]
+    dest_path: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    library: Option<LibraryAsset>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    path_bytes: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    volume_path_bytes: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dest_path_bytes: Option<String>,
+}
+
+/// The bytes of `path` when they are not UTF-8.
+fn path_bytes(path: Option<&PathBuf>) -> Option<String> {
+    path.filter(|path| path.to_str().is_none())
+        .map(|path| platform::encode_path(path))
+}
+
+/// The path `bytes` encodes, or `path` when there are none.
+fn decode(
+    bytes: Option<String>,
+    path: Option<PathBuf>,
+    line: usize,
+) -> Result<Option<PathBuf>, ArchiveError> {
+    let Some(bytes) = bytes else {
+        return Ok(path);
+    };
+    platform::decode_path(&bytes)
+        .map(Some)
+        .ok_or(ArchiveError::InvalidPath { line, bytes })
+}
+
+/// `row` as a JSON object with its `table`. Paths the database holds as
+/// BLOBs, not being UTF-8, become `{"bytes": ...}` as
+/// [`platform::encode_path`] encodes them.
+fn row_json(row: TableRow) -> serde_json::Value {
+    let mut object = serde_json::Map::new();
+    object.insert("table".to_owned(), row.table.into());
+    for (name, value) in row.columns {
+        let value = match value {
+            Value::Null => serde_json::Value::Null,
+            Value::Integer(value) => value.into(),
+            Value::Real(value) => value.into(),
+            Value::Text(value) => value.into(),
+            Value::Blob(bytes) => serde_json::json!({
+                "bytes": platform::encode_path(&platform::path_from_bytes(&bytes))
+            }),
+        };
+        object.insert(name, value);
+    }
+    object.into()
+}
+
+/// The row [`row_json`] wrote as `object`.
+fn json_row(
+    table: String,
+    object: serde_json::Map<String, serde_json::Value>,
+    line: usize,
+) -> Result<TableRow, ArchiveError> {
+    let invalid = |message: &str| ArchiveError::InvalidLine {
+        line,
+        source: serde::de::Error::custom(message),
+    };
+    let mut columns = Vec::new();
+    for (name, value) in object {
+        let value = match value {
+            serde_json::Value::Null => Value::Null,
+            serde_json::Value::Bool(value) => Value::Integer(value.into()),
+            serde_json::Value::Number(number) => match number.as_i64() {
+                Some(value) => Value::Integer(value),
+                None => Value::Real(number.as_f64().unwrap_or_default()),
+            },
+            serde_json::Value::String(value) => Value::Text(value),
+            serde_json::Value::Object(object) => {
+                let bytes = object
+                    .get("bytes")
+                    .and_then(serde_json::Value::as_str)
+                    .ok_or_else(|| invalid("expected {\"bytes\": ...}"))?;
+                let path = platform::decode_path(bytes).ok_or(ArchiveError::InvalidPath {
+                    line,
+                    bytes: bytes.to_owned(),
+                })?;
+                Value::Blob(platform::os_bytes(path.as_os_str()).into_owned())
+            }
+            serde_json::Value::Array(_) => return Err(invalid("unexpected array")),
+        };
+        columns.push((name, value));
+    }
+    Ok(TableRow { table, columns })
+}
+
+/// Adds what `value`, line `line` of an archive, holds to `archive`.
+fn push(archive: &mut Archive, value: serde_json::Value, line: usize) -> Result<(), ArchiveError> {
+    let invalid = |source| ArchiveError::InvalidLine { line, source };
+    if let serde_json::Value::Object(mut object) = value {
+        if let Some(table) = object.remove("table") {
+            let table = serde_json::from_value(table).map_err(invalid)?;
+            archive.rows.push(json_row(table, object, line)?);
+            return Ok(());
+        }
+        return push_file(archive, object.into(), line);
+    }
+    push_file(archive, value, line)
+}
+
+fn push_file(
+    archive: &mut Archive,
+    value: serde_json::Value,
+    line: usize,
+) -> Result<(), ArchiveError> {
+    let invalid = |source| ArchiveError::InvalidLine { line, source };
+    let parsed: Line = serde_json::from_value(value).map_err(invalid)?;
+    let mut file = parsed.file;
+    if let Some(path) = decode(parsed.path_bytes, None, line)? {
+        file.path = path;
+    }
+    file.volume_path = decode(parsed.volume_path_bytes, file.volume_path, line)?;
+    let dest_path = decode(parsed.dest_path_bytes, parsed.dest_path, line)?;
+    archive.files.push(Archived {
+        file,
+        dest_path,
+        library: parsed.library,
+    });
+    Ok(())
+}
+
+/// Calls `visit` with a line for every file of `db` in path order.
+fn for_each_line(db: &LockDB, mut visit: impl FnMut(Line) -> Result<()>) -> Result<()> {
+    let mut dest_paths = db
+        .find_placements()?
+        .into_iter()
+        .map(|placement| (placement.path, placement.dest_path))
+        .collect::<HashMap<_, _>>();
+    let mut library = db.find_library_assets()?;
+    db.for_each_file(|file| {
+        let dest_path = dest_paths.remove(&file.path);
+        visit(Line {
+            path_bytes: path_bytes(Some(&file.path)),
+            volume_path_bytes: path_bytes(file.volume_path.as_ref()),
+            dest_path_bytes: path_bytes(dest_path.as_ref()),
+            dest_path,
+            library: library.remove(&file.path),
+            file,
+        })
+    })
+}
+
+/// Writes every file of `db` to `writer` in path order, and the rows of
+/// the other archived tables after them, and returns how many files there
+/// were.
+pub fn write(db: &LockDB, format: ArchiveFormat, writer: impl Write + Send) -> Result<usize> {
+    let mut writer: Box<dyn Write + Send> = match format {
+        ArchiveFormat::Jsonl => Box::new(writer),
+        ArchiveFormat::JsonlGz => Box::new(GzEncoder::new(writer, Compression::default())),
+        ArchiveFormat::Parquet => return parquet_file::write(db, writer),
+    };
+    let mut count = 0;
+    for_each_line(db, |line| {
+        serde_json::to_writer(&mut writer, &line).map_err(io::Error::from)?;
+        writer.write_all(b"\n")?;
+        count += 1;
+        Ok(())
+    })?;
+    db.for_each_archived_row(|row| {
+        serde_json::to_writer(&mut writer, &row_json(row)).map_err(io::Error::from)?;
+        writer.write_all(b"\n")?;
+        Ok::<_, DeduperError>(())
+    })?;
+    writer.flush()?;
+    // finishes the gzip stream
+    drop(writer);
+    Ok(count)
+}
+
+/// Reads an archive written by [`write`]. Blank lines are skipped.
+pub fn read(format: ArchiveFormat, reader: impl Read) -> Result<Archive, ArchiveError> {
+    let reader: Box<dyn BufRead> = match format {
+        ArchiveFormat::Jsonl => Box::new(BufReader::new(reader)),
+        ArchiveFormat::JsonlGz => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        ArchiveFormat::Parquet => return parquet_file::read(reader),
+    };
+    let mut archive = Archive::default();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let value = serde_json::from_str(&line).map_err(|source| ArchiveError::InvalidLine {
+            line: index + 1,
+            source,
+        })?;
+        push(&mut archive, value, index + 1)?;
+    }
+    Ok(archive)
+}
+
+/// Records `archive` in `db`, replacing the files at the same paths and
+/// the rows with the same keys.
+pub fn restore(db: &LockDB, archive: &Archive) -> rusqlite::Result<()> {
+    db.restore_files(
+        archive
+            .files
+            .iter()
+            .map(|archived| (&archived.file, archived.dest_path.as_deref())),
+    )?;
+    for archived in &archive.files {
+        if let Some(library) = &archived.library {
+            db.record_library_asset(&archived.file.path, library)?;
+        }
+    }
+    db.restore_rows(archive.rows.iter())
+}
+
+#[cfg(not(feature = "parquet"))]
+mod parquet_file {
+    use super::*;
+
+    fn unsupported() -> io::Error {
+        io::Error::other("Parquet archives need a build with the parquet feature")
+    }
+
+    pub fn write(_db: &LockDB, _writer: impl Write + Send) -> Result<usize> {
+        Err(unsupported().into())
+    }
+
+    pub fn read(_reader: impl Read) -> Result<Archive, ArchiveError> {
+        Err(unsupported().into())
+    }
+}
+
+/// Files as rows of a Parquet file, with a column per field of a [`Line`],
+/// and the rows of the other tables as JSON lines in its metadata.
+#[cfg(feature = "parquet")]
+mod parquet_file {
+    use std::sync::Arc;
+
+    use parquet::{
+        basic::{Compression, GzipLevel},
+        data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int32Type, Int64Type},
+        file::{
+            properties::WriterProperties,
+            reader::{FileReader, SerializedFileReader},
+            writer::SerializedFileWriter,
+        },
+        format::KeyValue,
+        schema::parser::parse_message_type,
+    };
+
+    use super::*;
+
+    /// The key of the metadata holding the rows of the other tables.
+    const ROWS_KEY: &str = "deduper.rows";
+    const ROW_GROUP_SIZE: usize = 64 * 1024;
+
+    #[derive(Clone, Copy)]
+    enum Column {
+        Text,
+        /// A nested value as JSON text
+        Json,
+        Bool,
+        Int32,
+        UInt32,
+        Int64,
+        UInt64,
+        Double,
+    }
+
+    const COLUMNS: [(&str, Column); 32] = [
+        ("path", Column::Text),
+        ("hash", Column::Text),
+        ("hash_algorithm", Column::Text),
+        ("size", Column::UInt64),
+        ("media_type", Column::Text),
+        ("created_at", Column::Int64),
+        ("modified_at", Column::Int64),
+        ("original", Column::Bool),
+        ("optimized", Column::Text),
+        ("phash", Column::UInt64),
+        ("utc_offset", Column::Int32),
+        ("latitude", Column::Double),
+        ("longitude", Column::Double),
+        ("camera_make", Column::Text),
+        ("camera_model", Column::Text),
+        ("lens_model", Column::Text),
+        ("host", Column::Text),
+        ("volume", Column::Text),
+        ("volume_path", Column::Text),
+        ("dev", Column::UInt64),
+        ("inode", Column::UInt64),
+        ("source", Column::Text),
+        ("width", Column::UInt32),
+        ("height", Column::UInt32),
+        ("duration", Column::Double),
+        ("codec", Column::Text),
+        ("label", Column::Text),
+        ("dest_path", Column::Text),
+        ("library", Column::Json),
+        ("path_bytes", Column::Text),
+        ("volume_path_bytes", Column::Text),
+        ("dest_path_bytes", Column::Text),
+    ];
+
+    fn schema() -> String {
+        let fields = COLUMNS
+            .iter()
+            .map(|(name, column)| match column {
+                Column::Text | Column::Json => format!("OPTIONAL BYTE_ARRAY {name} (UTF8);"),
+                Column::Bool => format!("OPTIONAL BOOLEAN {name};"),
+                Column::Int32 => format!("OPTIONAL INT32 {name};"),
+                Column::UInt32 => format!("OPTIONAL INT32 {name} (UINT_32);"),
+                Column::Int64 => format!("OPTIONAL INT64 {name};"),
+                Column::UInt64 => format!("OPTIONAL INT64 {name} (UINT_64);"),
+                Column::Double => format!("OPTIONAL DOUBLE {name};"),
+            })
+            .collect::<Vec<_>>();
+        format!("message file {{ {} }}", fields.join(" "))
+    }
+
+    fn other(err: parquet::errors::ParquetError) -> io::Error {
+        io::Error::other(err)
+    }
+
+    pub fn write(db: &LockDB, writer: impl Write + Send) -> Result<usize> {
+        let schema = Arc::new(parse_message_type(&schema()).map_err(other)?);
+        let mut rows = String::new();
+        db.for_each_archived_row(|row| {
+            rows.push_str(&row_json(row).to_string());
+            rows.push('\n');
+            Ok::<_, DeduperError>(())
+        })?;
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::GZIP(GzipLevel::default()))
+            .set_key_value_metadata(Some(vec![KeyValue::new(ROWS_KEY.to_owned(), rows)]))
+            .build();
+        let mut writer =
+            SerializedFileWriter::new(writer, schema, Arc::new(properties)).map_err(other)?;
+        let mut group = Vec::with_capacity(ROW_GROUP_SIZE);
+        let mut count = 0;
+        for_each_line(db, |line| {
+            let serde_json::Value::Object(object) =
+                serde_json::to_value(&line).map_err(io::Error::from)?
+            else {
+                unreachable!("a line is an object");
+            };
+            group.push(object);
+            count += 1;
+            if group.len() == ROW_GROUP_SIZE {
+                write_group(&mut writer, &group)?;
+                group.clear();
+            }
+            Ok(())
+        })?;
+        if !group.is_empty() {
+            write_group(&mut writer, &group)?;
+        }
+        writer.close().map_err(other)?;
+        Ok(count)
+    }
+
+    fn write_group<W: Write + Send>(
+        writer: &mut SerializedFileWriter<W>,
+        lines: &[serde_json::Map<String, serde_json::Value>],
+    ) -> io::Result<()> {
+        let mut group = writer.next_row_group().map_err(other)?;
+        for (name, column) in COLUMNS {
+            let mut writer = group
+                .next_column()
+                .map_err(other)?
+                .expect("the schema has a column per entry");
+            let values = lines
+                .iter()
+                .map(|line| line.get(name).filter(|value| !value.is_null()));
+            let levels = values
+                .clone()
+                .map(|value| i16::from(value.is_some()))
+                .collect::<Vec<_>>();
+            let values = values.flatten();
+            let written = match column {
+                Column::Text => {
+                    let values = values
+                        .map(|value| ByteArray::from(value.as_str().unwrap_or_default()))
+                        .collect::<Vec<_>>();
+                    writer
+                        .typed::<ByteArrayType>()
+                        .write_batch(&values, Some(&levels), None)
+                }
+                Column::Json => {
+                    let values = values
+                        .map(|value| ByteArray::from(value.to_string().into_bytes()))
+                        .collect::<Vec<_>>();
+                    writer
+                        .typed::<ByteArrayType>()
+                        .write_batch(&values, Some(&levels), None)
+                }
+                Column::Bool => {
+                    let values = values
+                        .map(|value| value.as_bool().unwrap_or_default())
+                        .collect::<Vec<_>>();
+                    writer
+                        .typed::<BoolType>()
+                        .write_batch(&values, Some(&levels), None)
+                }
+                Column::Int32 | Column::UInt32 => {
+                    // unsigned values keep their bits
+                    let values = values
+                        .map(|value| value.as_i64().unwrap_or_default() as i32)
+                        .collect::<Vec<_>>();
+                    writer
+                        .typed::<Int32Type>()
+                        .write_batch(&values, Some(&levels), None)
+                }
+                Column::Int64 => {
+                    let values = values
+                        .map(|value| value.as_i64().unwrap_or_default())
+                        .collect::<Vec<_>>();
+                    writer
+                        .typed::<Int64Type>()
+                        .write_batch(&values, Some(&levels), None)
+                }
+                Column::UInt64 => {
+                    let values = values
+                        .map(|value| value.as_u64().unwrap_or_default() as i64)
+                        .collect::<Vec<_>>();
+                    writer
+                        .typed::<Int64Type>()
+                        .write_batch(&values, Some(&levels), None)
+                }
+                Column::Double => {
+                    let values = values
+                        .map(|value| value.as_f64().unwrap_or_default())
+                        .collect::<Vec<_>>();
+                    writer
+                        .typed::<DoubleType>()
+                        .write_batch(&values, Some(&levels), None)
+                }
+            };
+            written.map_err(other)?;
+            writer.close().map_err(other)?;
+        }
+        group.close().map_err(other)?;
+        Ok(())
+    }
+
+    /// Reads the whole file, as Parquet keeps its layout at the end. Rows
+    /// count as lines in errors.
+    pub fn read(mut reader: impl Read) -> Result<Archive, ArchiveError> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        let reader = SerializedFileReader::new(::bytes::Bytes::from(bytes)).map_err(other)?;
+        let mut archive = Archive::default();
+        for (index, row) in reader.get_row_iter(None).map_err(other)?.enumerate() {
+            let serde_json::Value::Object(mut object) = row.map_err(other)?.to_json_value() else {
+                unreachable!("a row is an object");
+            };
+            for (name, column) in COLUMNS {
+                if let (Column::Json, Some(serde_json::Value::String(json))) =
+                    (column, object.get(name))
+                {
+                    let value =
+                        serde_json::from_str(json).map_err(|source| ArchiveError::InvalidLine {
+                            line: index + 1,
+                            source,
+                        })?;
+                    object.insert(name.to_owned(), value);
+                }
+            }
+            push_file(&mut archive, object.into(), index + 1)?;
+        }
+        let rows = reader
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()
+            .into_iter()
+            .flatten()
+            .filter(|entry| entry.key == ROWS_KEY)
+            .filter_map(|entry| entry.value.as_deref());
+        for (index, line) in rows.flat_map(str::lines).enumerate() {
+            let value = serde_json::from_str(line).map_err(|source| ArchiveError::InvalidLine {
+                line: index + 1,
+                source,
+            })?;
+            push(&mut archive, value, index + 1)?;
+        }
+        Ok(archive)
+    }
+}
+
+#[test]
+fn test_round_trip() {
+    use crate::database::{Decision, FileError, Optimized, Resolution, UndoAction, DB};
+
+    let dir = std::env::temp_dir();
+    let path = dir.join(format!("deduper-archive-{}.db", std::process::id()));
+    let restored_path = dir.join(format!(
+        "deduper-archive-restored-{}.db",
+        std::process::id()
+    ));
+    let file = File {
+        size: 10,
+        created_at: 1000,
+        modified_at: 2000,
+        original: true,
+        // above i64::MAX, as perceptual hashes often are
+        phash: Some(u64::MAX - 1),
+        utc_offset: 3600,
+        latitude: Some(52.5),
+        longitude: Some(13.4),
+        camera_make: Some("Canon".to_owned()),
+        volume: Some("UUID=1234".to_owned()),
+        volume_path: Some(PathBuf::from("/a.jpg")),
+        dev: Some(1),
+        inode: Some(2),
+        width: Some(4000),
+        height: Some(3000),
+        label: Some("backup2019".to_owned()),
+        ..File::test("/photos/a.jpg", "abc")
+    };
+    let db = DB::new(&path).unwrap();
+    db.lock().upsert_file(&file).unwrap();
+    db.lock().mark_original_files().unwrap();
+    db.lock().mark_skipped(&file.path).unwrap();
+    let library = LibraryAsset {
+        original_name: Some("IMG_0001.JPG".to_owned()),
+        favorite: true,
+        albums: vec!["Holidays".to_owned(), "Paris".to_owned()],
+    };
+    db.lock()
+        .record_library_asset(&file.path, &library)
+        .unwrap();
+    db.lock()
+        .set_dest_path(&file.path, Path::new("/library/2023/a.jpg"))
+        .unwrap();
+    let recorded = db.lock().find_file(&file.path).unwrap().unwrap();
+    assert_eq!(Optimized::Skipped, recorded.optimized);
+    let decision = Decision {
+        hash: "abc".to_owned(),
+        keep_path: file.path.clone(),
+        resolution: Resolution::Keep,
+        decided_at: 3000,
+    };
+    db.lock().decide(&decision).unwrap();
+    let error = FileError {
+        path: PathBuf::from("/photos/broken.jpg"),
+        stage: "metadata".to_owned(),
+        kind: "exif".to_owned(),
+        message: "truncated".to_owned(),
+        size: Some(5),
+        modified_at: None,
+        attempts: 1,
+        at: 4000,
+        label: None,
+    };
+    db.lock().record_error(&error).unwrap();
+    db.lock()
+        .set_source_priority(&[PathBuf::from("/photos")])
+        .unwrap();
+    let run_id = db.lock().start_run("organize", "", 5000).unwrap();
+    db.lock()
+        .log_undo(run_id, UndoAction::Moved, &file.path, None, "abc", "blake3")
+        .unwrap();
+
+    let mut formats = vec![ArchiveFormat::Jsonl, ArchiveFormat::JsonlGz];
+    if cfg!(feature = "parquet") {
+        formats.push(ArchiveFormat::Parquet);
+    }
+    for format in formats {
+        let mut archive = Vec::new();
+        assert_eq!(1, write(&db.lock(), format, &mut archive).unwrap());
+        let archive = read(format, archive.as_slice()).unwrap();
+        assert_eq!(1, archive.files.len());
+
+        let restored = DB::new(&restored_path).unwrap();
+        // the archived run comes after this one
+        restored.lock().start_run("scan", "", 0).unwrap();
+        restore(&restored.lock(), &archive).unwrap();
+        let row = restored.lock().find_file(&file.path).unwrap().unwrap();
+        assert_eq!(format!("{:?}", recorded), format!("{:?}", row));
+        assert_eq!(
+            Some(PathBuf::from("/library/2023/a.jpg")),
+            restored.lock().find_dest_path(&file.path).unwrap()
+        );
+        assert_eq!(
+            Some(&library),
+            restored
+                .lock()
+                .find_library_assets()
+                .unwrap()
+                .get(&file.path)
+        );
+        assert_eq!(vec![decision.clone()], restored.lock().decisions().unwrap());
+        assert_eq!(vec![error.clone()], restored.lock().errors().unwrap());
+        assert_eq!(
+            vec![PathBuf::from("/photos/")],
+            restored.lock().source_priority().unwrap()
+        );
+        let runs = restored.lock().undo_runs().unwrap();
+        assert_eq!(1, runs.len());
+        assert_eq!(
+            ("organize", 5000),
+            (runs[0].command.as_str(), runs[0].started_at)
+        );
+        let entries = restored.lock().undo_entries(runs[0].run_id).unwrap();
+        assert_eq!(file.path, entries[0].path);
+        drop(restored);
+        std::fs::remove_file(&restored_path).unwrap();
+    }
+    assert!(matches!(
+        read(ArchiveFormat::Jsonl, &b"{}\n"[..]),
+        Err(ArchiveError::InvalidLine { line: 1, .. })
+    ));
+    drop(db);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_utf8_paths() {
+    use crate::database::{FileError, DB};
+
+    let path =
+        std::env::temp_dir().join(format!("deduper-archive-latin1-{}.db", std::process::id()));
+    // "café.jpg" in Latin-1
+    let name = platform::path_from_bytes(b"/photos/caf\xe9.jpg");
+    let dest = platform::path_from_bytes(b"/library/caf\xe9.jpg");
+    let file = File {
+        size: 10,
+        created_at: 1000,
+        modified_at: 2000,
+        original: true,
+        volume_path: Some(name.clone()),
+        ..File::test(&name, "abc")
+    };
+    let db = DB::new(&path).unwrap();
+    db.lock().upsert_file(&file).unwrap();
+    db.lock().set_dest_path(&name, &dest).unwrap();
+    let mut archive = Vec::new();
+    db.lock()
+        .record_error(&FileError {
+            path: name.clone(),
+            stage: "hash".to_owned(),
+            kind: "io".to_owned(),
+            message: "unreadable".to_owned(),
+            size: None,
+            modified_at: None,
+            attempts: 1,
+            at: 0,
+            label: None,
+        })
+        .unwrap();
+    write(&db.lock(), ArchiveFormat::Jsonl, &mut archive).unwrap();
+    assert!(String::from_utf8_lossy(&archive).contains("\"/photos/caf\u{fffd}.jpg\""));
+    let archive = read(ArchiveFormat::Jsonl, archive.as_slice()).unwrap();
+    let restored = std::env::temp_dir().join(format!(
+        "deduper-archive-latin1-restored-{}.db",
+        std::process::id()
+    ));
+    let restored_db = DB::new(&restored).unwrap();
+    restore(&restored_db.lock(), &archive).unwrap();
+    assert_eq!(name, restored_db.lock().errors().unwrap()[0].path);
+    drop(restored_db);
+    std::fs::remove_file(&restored).unwrap();
+    let files = archive.files;
+    assert_eq!(name, files[0].file.path);
+    assert_eq!(Some(name), files[0].file.volume_path);
+    assert_eq!(Some(dest), files[0].dest_path);
+    drop(db);
+    std::fs::remove_file(&path).unwrap();
+}
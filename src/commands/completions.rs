@@ -0,0 +1,79 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::{Args, Command};
+use clap_complete::Shell;
+use clap_mangen::Man;
+use tracing::{error, info};
+
+use super::progress::Summary;
+
+#[derive(Args)]
+pub struct CompletionsArgs {
+    /// Shell to complete in
+    #[arg(value_enum)]
+    pub shell: Shell,
+}
+
+#[derive(Args)]
+pub struct ManArgs {
+    /// Directory to write a page per command to, `deduper.1`,
+    /// `deduper-scan.1`, `deduper-db-merge.1` and so on; the page of
+    /// `deduper` alone goes to standard output without it
+    #[arg(value_hint = clap::ValueHint::DirPath)]
+    pub dir: Option<PathBuf>,
+}
+
+/// Prints the completion script of `cli` for the shell, e.g. for
+/// `deduper completions bash > /etc/bash_completion.d/deduper`.
+pub fn completions(args: &CompletionsArgs, mut cli: Command) -> Summary {
+    let mut out = io::stdout().lock();
+    clap_complete::generate(args.shell, &mut cli, "deduper", &mut out);
+    if let Err(err) = out.flush() {
+        error!("failed to write completions: {}", err);
+        return Summary::aborted();
+    }
+    Summary::listed(1)
+}
+
+/// Writes the man pages of `cli` and its subcommands.
+pub fn man(args: &ManArgs, cli: Command) -> Summary {
+    let written = match &args.dir {
+        Some(dir) => fs::create_dir_all(dir).and_then(|()| write_pages(&cli, "deduper", dir)),
+        None => Man::new(cli).render(&mut io::stdout().lock()).map(|()| 1),
+    };
+    match written {
+        Ok(pages) => {
+            if let Some(dir) = &args.dir {
+                info!("wrote {} pages to {}", pages, dir.to_string_lossy());
+            }
+            Summary::listed(pages)
+        }
+        Err(err) => {
+            error!("failed to write man pages: {}", err);
+            Summary::aborted()
+        }
+    }
+}
+
+/// Writes the page of `command` as `name.1` into `dir`, then those of its
+/// visible subcommands named after the path to them, as git names its own,
+/// all with the version of deduper. Returns how many pages were written.
+fn write_pages(command: &Command, name: &str, dir: &Path) -> io::Result<usize> {
+    let command = command
+        .clone()
+        .name(name.to_owned())
+        .version(env!("CARGO_PKG_VERSION"));
+    let mut out = BufWriter::new(File::create(dir.join(format!("{}.1", name)))?);
+    Man::new(command.clone()).render(&mut out)?;
+    out.flush()?;
+    let mut pages = 1;
+    for subcommand in command.get_subcommands().filter(|sub| !sub.is_hide_set()) {
+        let name = format!("{}-{}", name, subcommand.get_name());
+        pages += write_pages(subcommand, &name, dir)?;
+    }
+    Ok(pages)
+}
@@ -0,0 +1,136 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+};
+
+use clap::{Args, Subcommand};
+use deduper::{csv, database::Conflict};
+use tracing::{error, info, warn};
+
+use super::{errors::ListFormat, history::format_time, open_database, progress::Summary};
+
+#[derive(Args)]
+pub struct ConflictsArgs {
+    #[command(subcommand)]
+    pub command: ConflictsCommand,
+}
+
+#[derive(Subcommand)]
+pub enum ConflictsCommand {
+    /// List the files placed under a numbered name because the name the
+    /// layout gave them held other contents
+    List(ListArgs),
+    /// Forget the conflicts of files placed at these paths once reviewed
+    Forget(ForgetArgs),
+}
+
+#[derive(Args)]
+pub struct ListArgs {
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    #[arg(long, value_enum, default_value_t)]
+    pub format: ListFormat,
+    /// File to write the report to; standard output without it
+    #[arg(value_hint = clap::ValueHint::FilePath)]
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct ForgetArgs {
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    /// Where the conflicting files were placed, as listed
+    #[arg(required = true, value_hint = clap::ValueHint::FilePath)]
+    pub paths: Vec<PathBuf>,
+}
+
+pub fn run(args: &ConflictsArgs) -> Summary {
+    match &args.command {
+        ConflictsCommand::List(args) => list(args),
+        ConflictsCommand::Forget(args) => forget(args),
+    }
+}
+
+fn list(args: &ListArgs) -> Summary {
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    let conflicts = match db.lock().conflicts() {
+        Ok(conflicts) => conflicts,
+        Err(err) => {
+            error!("failed to read recorded conflicts: {}", err);
+            return Summary::aborted();
+        }
+    };
+    let written = match &args.file {
+        Some(path) => File::create(path)
+            .and_then(|file| write_conflicts(&conflicts, args.format, BufWriter::new(file))),
+        None => write_conflicts(&conflicts, args.format, io::stdout().lock()),
+    };
+    if let Err(err) = written {
+        error!("failed to write conflicts: {}", err);
+        return Summary::aborted();
+    }
+    Summary::listed(conflicts.len())
+}
+
+fn write_conflicts(
+    conflicts: &[Conflict],
+    format: ListFormat,
+    mut out: impl Write,
+) -> io::Result<()> {
+    match format {
+        ListFormat::Table => {
+            for conflict in conflicts {
+                writeln!(
+                    out,
+                    "{}  {} is taken, {} went to {}",
+                    format_time(conflict.at),
+                    conflict.taken.to_string_lossy(),
+                    conflict.source.to_string_lossy(),
+                    conflict.path.to_string_lossy()
+                )?;
+            }
+        }
+        ListFormat::Csv => {
+            csv::write_row(&mut out, &["path", "source", "taken", "hash", "at"])?;
+            for conflict in conflicts {
+                csv::write_row(
+                    &mut out,
+                    &[
+                        &conflict.path.to_string_lossy(),
+                        &conflict.source.to_string_lossy(),
+                        &conflict.taken.to_string_lossy(),
+                        &conflict.hash,
+                        &conflict.at.to_string(),
+                    ],
+                )?;
+            }
+        }
+        ListFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, conflicts)?;
+            writeln!(out)?;
+        }
+    }
+    out.flush()
+}
+
+fn forget(args: &ForgetArgs) -> Summary {
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    let mut forgotten = 0;
+    for path in &args.paths {
+        match db.lock().forget_conflict(path) {
+            Ok(true) => forgotten += 1,
+            Ok(false) => warn!("no conflict recorded for {}", path.to_string_lossy()),
+            Err(err) => {
+                error!("failed to forget conflicts: {}", err);
+                return Summary::aborted();
+            }
+        }
+    }
+    info!("forgot {} conflicts", forgotten);
+    Summary::listed(forgotten)
+}
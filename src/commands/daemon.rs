@@ -0,0 +1,216 @@
+use std::{
+    fs,
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use clap::Args;
+use deduper::{
+    control::{self, Request, Response, Status},
+    media::walk_files,
+    watcher::SourceWatcher,
+};
+use rayon::prelude::*;
+use tracing::{error, info};
+
+use super::{
+    progress::{Progress, Summary},
+    watch::{Ingester, WatchArgs},
+};
+
+#[derive(Args)]
+pub struct DaemonArgs {
+    #[command(flatten)]
+    pub watch: WatchArgs,
+    /// Unix socket `deduper ctl` talks to
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.sock")]
+    pub socket: PathBuf,
+    /// Walk all sources once at startup, as `deduper ctl scan` does
+    #[arg(long)]
+    pub initial_scan: bool,
+}
+
+#[derive(Args)]
+pub struct CtlArgs {
+    #[arg(value_enum)]
+    pub request: Request,
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.sock")]
+    pub socket: PathBuf,
+}
+
+/// State shared between the worker and the control socket.
+#[derive(Default)]
+struct State {
+    paused: AtomicBool,
+    scan_requested: AtomicBool,
+    scanning: AtomicBool,
+    queued: AtomicUsize,
+    ingested: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl State {
+    fn handle(&self, request: Request) -> Response {
+        match request {
+            Request::Pause => self.paused.store(true, Ordering::Relaxed),
+            Request::Resume => self.paused.store(false, Ordering::Relaxed),
+            Request::Scan => self.scan_requested.store(true, Ordering::Relaxed),
+            Request::Status => {
+                return Response::Status(Status {
+                    paused: self.paused.load(Ordering::Relaxed),
+                    scanning: self.scanning.load(Ordering::Relaxed),
+                    queued: self.queued.load(Ordering::Relaxed),
+                    ingested: self.ingested.load(Ordering::Relaxed),
+                    failed: self.failed.load(Ordering::Relaxed),
+                })
+            }
+        }
+        Response::Ok
+    }
+
+    fn count(&self, ingested: bool) {
+        if ingested {
+            self.ingested.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn wait_while_paused(&self) {
+        while self.paused.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_secs(1));
+        }
+    }
+}
+
+/// Only returns when it cannot start.
+pub fn run(args: &DaemonArgs) -> Summary {
+    let progress = Arc::new(Progress::hidden());
+    let Some(ingester) = Ingester::new(&args.watch, "daemon", progress.clone()) else {
+        return Summary::aborted();
+    };
+    let Some(listener) = bind(&args.socket) else {
+        return Summary::aborted();
+    };
+    let mut watcher =
+        match SourceWatcher::new(&args.watch.sources, Duration::from_secs(args.watch.settle)) {
+            Ok(watcher) => watcher,
+            Err(err) => {
+                error!("failed to watch sources: {}", err);
+                return Summary::aborted();
+            }
+        };
+    let state = State {
+        scan_requested: AtomicBool::new(args.initial_scan),
+        ..State::default()
+    };
+    info!("listening on {}", args.socket.to_string_lossy());
+    thread::scope(|scope| {
+        scope.spawn(|| {
+            for stream in listener.incoming() {
+                let served = stream
+                    .and_then(|stream| control::serve(stream, |request| state.handle(request)));
+                if let Err(err) = served {
+                    progress.warn(format!("control connection failed: {}", err));
+                }
+            }
+        });
+
+        // files that settled while paused wait here
+        let mut queued = Vec::new();
+        loop {
+            for path in watcher.poll(Duration::from_secs(1)) {
+                match path {
+                    Ok(path) => queued.push(path),
+                    Err(err) => progress.warn(err),
+                }
+            }
+            state.queued.store(queued.len(), Ordering::Relaxed);
+            if state.paused.load(Ordering::Relaxed) {
+                continue;
+            }
+            for path in queued.drain(..) {
+                state.count(ingester.ingest(&path, &progress));
+            }
+            state.queued.store(0, Ordering::Relaxed);
+            if state.scan_requested.swap(false, Ordering::Relaxed) {
+                state.scanning.store(true, Ordering::Relaxed);
+                progress.info("scanning sources");
+                walk_files(&args.watch.sources, &ingester.walk_options())
+                    .par_bridge()
+                    .for_each(|entry| {
+                        state.wait_while_paused();
+                        match entry {
+                            Ok(path) => state.count(ingester.ingest(&path, &progress)),
+                            Err(err) => {
+                                state.count(false);
+                                progress.walk_failed(err);
+                            }
+                        }
+                    });
+                state.scanning.store(false, Ordering::Relaxed);
+            }
+        }
+    });
+    Summary::default()
+}
+
+/// Listens on `socket`, replacing a stale socket file left by a daemon that
+/// did not shut down cleanly.
+fn bind(socket: &Path) -> Option<UnixListener> {
+    if socket.exists() {
+        if UnixStream::connect(socket).is_ok() {
+            error!(
+                "another daemon is listening on {}",
+                socket.to_string_lossy()
+            );
+            return None;
+        }
+        let _ = fs::remove_file(socket);
+    }
+    match UnixListener::bind(socket) {
+        Ok(listener) => Some(listener),
+        Err(err) => {
+            error!("failed to listen on {}: {}", socket.to_string_lossy(), err);
+            None
+        }
+    }
+}
+
+pub fn ctl(args: &CtlArgs) -> Summary {
+    match control::send(&args.socket, args.request) {
+        Ok(Response::Ok) => println!("ok"),
+        Ok(Response::Status(status)) => {
+            println!(
+                "{}",
+                match (status.paused, status.scanning) {
+                    (true, _) => "paused",
+                    (false, true) => "scanning",
+                    (false, false) => "watching",
+                }
+            );
+            println!("queued: {}", status.queued);
+            println!("ingested: {}", status.ingested);
+            println!("failed: {}", status.failed);
+        }
+        Ok(Response::Error(err)) => {
+            error!("daemon error: {}", err);
+            return Summary::aborted();
+        }
+        Err(err) => {
+            error!(
+                "failed to reach daemon on {}: {}",
+                args.socket.to_string_lossy(),
+                err
+            );
+            return Summary::aborted();
+        }
+    }
+    Summary::default()
+}
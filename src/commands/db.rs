@@ -0,0 +1,257 @@
+use std::{
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use clap::{Args, Subcommand, ValueEnum};
+use deduper::{
+    archive::{self, ArchiveError, ArchiveFormat},
+    platform,
+};
+use tracing::{error, warn};
+
+use super::{open_database, progress::Summary};
+
+#[derive(Args)]
+pub struct DbArgs {
+    #[command(subcommand)]
+    pub command: DbCommand,
+}
+
+#[derive(Subcommand)]
+pub enum DbCommand {
+    /// Copy in the files another machine scanned, to find the duplicates
+    /// between the two
+    Merge(MergeArgs),
+    /// Write every recorded file, with the decisions, conflicts, failures,
+    /// undo log and source order, to an archive for safekeeping or to
+    /// analyze in DuckDB or pandas
+    Export(ExportArgs),
+    /// Record what an archive written by `db export` holds, replacing the
+    /// files at the same paths
+    Import(ImportArgs),
+}
+
+#[derive(Args)]
+pub struct MergeArgs {
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    /// Database of the other machine
+    #[arg(value_hint = clap::ValueHint::FilePath)]
+    pub other: PathBuf,
+    /// Name the files of the other machine are recorded under, their paths
+    /// prefixed with `NAME:` [default: the file name of OTHER without its
+    /// extension]
+    #[arg(long, value_name = "NAME")]
+    pub host: Option<String>,
+}
+
+#[derive(Args)]
+pub struct ExportArgs {
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    /// Format of the archive [default: by the extension of FILE, else
+    /// jsonl.gz]
+    #[arg(long, value_enum)]
+    pub format: Option<ArchiveFormatArg>,
+    /// Archive to write
+    #[arg(value_hint = clap::ValueHint::FilePath)]
+    pub file: PathBuf,
+}
+
+#[derive(Args)]
+pub struct ImportArgs {
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    /// Format of the archive [default: by the extension of FILE, else
+    /// jsonl.gz]
+    #[arg(long, value_enum)]
+    pub format: Option<ArchiveFormatArg>,
+    /// Archive written by `db export`
+    #[arg(value_hint = clap::ValueHint::FilePath)]
+    pub file: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ArchiveFormatArg {
+    /// One JSON object per file and line
+    Jsonl,
+    /// The same, gzipped
+    #[value(name = "jsonl.gz")]
+    JsonlGz,
+    /// One row per file; needs a build with the parquet feature
+    Parquet,
+}
+
+impl ArchiveFormatArg {
+    fn resolve(format: Option<Self>, path: &Path) -> ArchiveFormat {
+        match format {
+            Some(Self::Jsonl) => ArchiveFormat::Jsonl,
+            Some(Self::JsonlGz) => ArchiveFormat::JsonlGz,
+            Some(Self::Parquet) => ArchiveFormat::Parquet,
+            None => ArchiveFormat::of(path).unwrap_or(ArchiveFormat::JsonlGz),
+        }
+    }
+}
+
+pub fn run(args: &DbArgs) -> Summary {
+    match &args.command {
+        DbCommand::Merge(args) => merge(args),
+        DbCommand::Export(args) => export(args),
+        DbCommand::Import(args) => import(args),
+    }
+}
+
+fn export(args: &ExportArgs) -> Summary {
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    let format = ArchiveFormatArg::resolve(args.format, &args.file);
+    let exported = File::create(&args.file)
+        .map_err(Into::into)
+        .and_then(|file| archive::write(&db.read(), format, BufWriter::new(file)));
+    match exported {
+        Ok(count) => {
+            println!(
+                "exported {} files to {}",
+                count,
+                args.file.to_string_lossy()
+            );
+            Summary {
+                processed: count as u64,
+                ..Summary::default()
+            }
+        }
+        Err(err) => {
+            error!("failed to export files: {}", err);
+            Summary::aborted()
+        }
+    }
+}
+
+/// Reads the whole archive before touching the database, so a malformed
+/// one imports nothing.
+fn import(args: &ImportArgs) -> Summary {
+    let format = ArchiveFormatArg::resolve(args.format, &args.file);
+    let archive = match File::open(&args.file)
+        .map_err(ArchiveError::from)
+        .and_then(|file| archive::read(format, file))
+    {
+        Ok(archive) => archive,
+        Err(err) => {
+            error!("failed to read {}: {}", args.file.to_string_lossy(), err);
+            return Summary::aborted();
+        }
+    };
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    if let Err(err) = archive::restore(&db.lock(), &archive) {
+        error!("failed to import files: {}", err);
+        return Summary::aborted();
+    }
+    println!(
+        "imported {} files and {} other rows",
+        archive.files.len(),
+        archive.rows.len()
+    );
+    Summary {
+        processed: archive.files.len() as u64,
+        ..Summary::default()
+    }
+}
+
+/// Merging the same machine again replaces what it brought in before, so
+/// files deleted there disappear here too. Duplicate groups that span
+/// machines are listed; exits with 3 when there are any.
+fn merge(args: &MergeArgs) -> Summary {
+    let host = args.host.clone().unwrap_or_else(|| {
+        args.other
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned()
+    });
+    if host.is_empty()
+        || !host
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        error!(
+            "'{}' is not a usable host name; pass --host with letters, digits, '-', '_' or '.'",
+            host
+        );
+        return Summary::aborted();
+    }
+    if !args.other.is_file() {
+        error!("no database at {}", args.other.to_string_lossy());
+        return Summary::aborted();
+    }
+    if platform::canonicalize(&args.other).ok() == platform::canonicalize(&args.database).ok() {
+        error!("cannot merge a database into itself");
+        return Summary::aborted();
+    }
+    // brings the other database to the current schema
+    if open_database(&args.other).is_none() {
+        return Summary::aborted();
+    }
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+
+    let merged = match db.lock().merge(&args.other, &host) {
+        Ok(merged) => merged,
+        Err(err) => {
+            error!("failed to merge {}: {}", args.other.to_string_lossy(), err);
+            return Summary::aborted();
+        }
+    };
+    let db = db.lock();
+    if let (Ok(local), Ok(merged)) = (db.hash_algorithms(None), db.hash_algorithms(Some(&host))) {
+        if !local.is_empty() && local != merged {
+            warn!(
+                "files here are hashed with {} and on {} with {}; only files hashed alike are matched",
+                local.join(", "),
+                host,
+                merged.join(", ")
+            );
+        }
+    }
+    let hashes = match db.find_cross_host_signs() {
+        Ok(hashes) => hashes,
+        Err(err) => {
+            error!("failed to find duplicates across machines: {}", err);
+            return Summary::aborted();
+        }
+    };
+    let mut summary = Summary {
+        processed: merged,
+        ..Summary::default()
+    };
+    for hash in &hashes {
+        let files = match db.find_files_by_hash(hash) {
+            Ok(files) => files,
+            Err(err) => {
+                error!("failed to read files of {}: {}", hash, err);
+                return Summary::aborted();
+            }
+        };
+        println!("{}", hash);
+        for file in &files {
+            println!(
+                "\t{}\t{}",
+                file.host.as_deref().unwrap_or("local"),
+                file.path.to_string_lossy()
+            );
+        }
+        summary.duplicates += files.len() as u64 - 1;
+    }
+    println!(
+        "merged {} files of {}; {} duplicate groups span machines",
+        merged,
+        host,
+        hashes.len()
+    );
+    summary.settled()
+}
@@ -0,0 +1,401 @@
+use std::{path::PathBuf, sync::Arc};
+
+use clap::{ArgGroup, Args, ValueEnum};
+use deduper::{
+    database::{DupGroup, File, Optimized},
+    dedupe::{KeepPolicy, Removal},
+    linker::LinkStrategy,
+    source, trash,
+    undo::UndoLog,
+    Deduper,
+};
+use regex::Regex;
+use tracing::{error, info, warn};
+
+use super::{
+    confirm, open_database,
+    progress::{Progress, Summary},
+};
+#[cfg(feature = "tui")]
+use super::{review, ThumbnailArgs};
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("removal").args(["delete", "interactive"])))]
+#[command(group(ArgGroup::new("linking").args(["link", "interactive"])))]
+pub struct DedupeArgs {
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    /// Group visually similar images by perceptual hash instead of exact hash
+    #[arg(long)]
+    pub fuzzy: bool,
+    /// Maximum number of differing perceptual hash bits within a fuzzy group
+    #[arg(long, default_value_t = 10, requires = "fuzzy")]
+    pub distance: u32,
+    /// Which file of each group is kept as the original; in fuzzy groups,
+    /// which one is listed to keep among those of at least half the
+    /// quality of the best
+    #[arg(long, value_enum, default_value_t)]
+    pub keep: Keep,
+    /// Regular expressions matched against paths, in order of preference,
+    /// for `--keep path-regex`
+    #[arg(
+        long = "keep-pattern",
+        value_name = "REGEX",
+        required_if_eq("keep", "path-regex")
+    )]
+    pub keep_patterns: Vec<Regex>,
+    /// Keep files below these roots as originals over their copies
+    /// elsewhere, the earliest root first, before `--keep` picks among
+    /// them; the roots are stored in the database for later runs
+    #[arg(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+    pub prefer_source: Vec<PathBuf>,
+    /// Forget the roots an earlier `--prefer-source` stored
+    #[arg(long, conflicts_with = "prefer_source")]
+    pub no_prefer_source: bool,
+    /// Remove every file that is not the original of its duplicate group
+    #[arg(long, conflicts_with = "fuzzy")]
+    pub delete: bool,
+    /// Review the groups one by one in the terminal, choosing which file to
+    /// keep and whether to delete or link the others
+    #[arg(short, long, conflicts_with = "fuzzy")]
+    pub interactive: bool,
+    #[cfg(feature = "tui")]
+    #[command(flatten)]
+    pub thumbnail: ThumbnailArgs,
+    /// Move removed duplicates to the trash instead of unlinking them
+    #[arg(long, requires = "removal")]
+    pub trash: bool,
+    /// Directory to use as the trash instead of the XDG trash
+    #[arg(long, value_hint = clap::ValueHint::DirPath, requires = "trash")]
+    pub trash_dir: Option<PathBuf>,
+    /// Move removed duplicates into a folder of the day below this
+    /// directory, at their full path, to purge after a review period with
+    /// `quarantine purge`
+    #[arg(
+        long,
+        value_name = "DIR",
+        value_hint = clap::ValueHint::DirPath,
+        requires = "removal",
+        conflicts_with = "trash"
+    )]
+    pub quarantine: Option<PathBuf>,
+    /// Replace every file that is not the original of its group with a link to the original
+    #[arg(long, conflicts_with_all = ["fuzzy", "delete"])]
+    pub link: bool,
+    /// Kind of link that replaces duplicates
+    #[arg(long, value_enum, default_value_t, requires = "linking")]
+    pub link_type: LinkType,
+    /// Compare every duplicate with its original byte for byte before
+    /// removing or replacing it
+    #[arg(long)]
+    pub paranoid: bool,
+    /// Do not ask for confirmation before removing or replacing files
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Keep {
+    /// The earliest capture
+    #[default]
+    Oldest,
+    /// The latest capture
+    Newest,
+    /// The shortest path
+    ShortestPath,
+    /// The image or video with the most pixels
+    LargestResolution,
+    /// The most pixels, discounted for heavy compression
+    BestQuality,
+    /// The file matching the earliest `--keep-pattern`
+    PathRegex,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LinkType {
+    #[default]
+    Hardlink,
+    Symlink,
+}
+
+/// Exits with 3 when duplicates are listed but left in place.
+pub fn run(args: &DedupeArgs) -> Summary {
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    let keep = match args.keep {
+        Keep::Oldest => KeepPolicy::Oldest,
+        Keep::Newest => KeepPolicy::Newest,
+        Keep::ShortestPath => KeepPolicy::ShortestPath,
+        Keep::LargestResolution => KeepPolicy::LargestResolution,
+        Keep::BestQuality => KeepPolicy::BestQuality,
+        Keep::PathRegex => KeepPolicy::PathPriority(args.keep_patterns.clone()),
+    };
+    if !args.prefer_source.is_empty() || args.no_prefer_source {
+        if let Err(err) = db.lock().set_source_priority(&args.prefer_source) {
+            error!("failed to store the preferred sources: {}", err);
+            return Summary::aborted();
+        }
+    }
+    // duplicates found, removed and linked are logged at debug level
+    let mut deduper = Deduper::new(db.lock())
+        .compare_bytes(args.paranoid)
+        .keep(keep)
+        .events(Arc::new(Progress::hidden()));
+    if args.delete || args.link || args.interactive {
+        match UndoLog::start(deduper.db(), "dedupe") {
+            Ok(undo) => deduper = deduper.undo_log(undo),
+            Err(err) => {
+                error!("failed to start the undo log: {}", err);
+                return Summary::aborted();
+            }
+        }
+    }
+    if args.fuzzy {
+        return fuzzy(&deduper, args.distance);
+    }
+    let groups = match deduper.mark_originals() {
+        Ok(groups) => groups,
+        Err(err) => {
+            error!("failed to mark original files: {}", err);
+            return Summary::aborted();
+        }
+    };
+    if args.interactive {
+        #[cfg(feature = "tui")]
+        return review::run(&deduper, groups, args);
+        #[cfg(not(feature = "tui"))]
+        {
+            error!("dedupe --interactive needs a build with the tui feature");
+            return Summary::aborted();
+        }
+    }
+    let mut summary = Summary {
+        processed: groups.iter().map(|group| group.files.len() as u64).sum(),
+        ..Summary::default()
+    };
+    for group in &groups {
+        println!("{}", group.hash);
+        for file in &group.files {
+            let marker = match &file.label {
+                Some(label) => label,
+                None if file.original => "original",
+                None => "duplicate",
+            };
+            let transcoded = if file.optimized == Optimized::Yes {
+                " (transcoded)"
+            } else {
+                ""
+            };
+            println!(
+                "\t{}\t{}{}",
+                marker,
+                file.path.to_string_lossy(),
+                transcoded
+            );
+        }
+    }
+    match deduper.db().count_redundant_files() {
+        Ok((count, bytes)) => {
+            println!(
+                "{} duplicate groups, {} redundant files, {} bytes reclaimable",
+                groups.len(),
+                count,
+                bytes
+            );
+            summary.duplicates = count;
+        }
+        Err(err) => error!("failed to count redundant files: {}", err),
+    }
+    if args.delete {
+        delete_duplicates(&deduper, &groups, args)
+    } else if args.link {
+        link_duplicates(&deduper, &groups, args)
+    } else {
+        summary.settled()
+    }
+}
+
+/// Duplicates with their originals. Files merged from other machines are
+/// out of reach and those of read-only indexes are not to be touched, so
+/// pairs with one of them are left out.
+fn duplicates(groups: &[DupGroup]) -> Vec<(&File, &File)> {
+    groups
+        .iter()
+        .filter_map(|group| Some((group.original()?, group)))
+        .flat_map(|(original, group)| group.duplicates().map(move |file| (original, file)))
+        .filter(|(original, file)| original.host.is_none() && file.host.is_none())
+        .filter(|(original, file)| original.label.is_none() && file.label.is_none())
+        .collect()
+}
+
+/// Counts removed duplicates as processed and those whose original is
+/// missing as skipped.
+pub(super) fn delete_duplicates(
+    deduper: &Deduper,
+    groups: &[DupGroup],
+    args: &DedupeArgs,
+) -> Summary {
+    let duplicates = duplicates(groups);
+    if duplicates.is_empty() {
+        info!("no duplicates to remove");
+        return Summary::default().settled();
+    }
+    let removal = match (&args.trash_dir, args.trash) {
+        (Some(dir), _) => Removal::Trash(dir.clone()),
+        (None, true) => match trash::xdg_trash_dir() {
+            Some(dir) => Removal::Trash(dir),
+            None => {
+                error!("could not locate the XDG trash; pass --trash-dir");
+                return Summary::aborted();
+            }
+        },
+        (None, false) => match &args.quarantine {
+            Some(dir) => Removal::Quarantine(dir.clone()),
+            None => Removal::Delete,
+        },
+    };
+    let bytes = duplicates.iter().map(|(_, file)| file.size).sum::<u64>();
+    let action = match &removal {
+        Removal::Trash(dir) => format!("move to {}", dir.to_string_lossy()),
+        Removal::Quarantine(dir) => format!("quarantine in {}", dir.to_string_lossy()),
+        Removal::Delete => "permanently delete".to_owned(),
+    };
+    if !args.yes
+        && !confirm(&format!(
+            "{} {} duplicates ({} bytes)?",
+            action,
+            duplicates.len(),
+            bytes
+        ))
+    {
+        info!("aborted");
+        return Summary::aborted();
+    }
+
+    let mut summary = Summary::default();
+    for (original, file) in duplicates {
+        match deduper.remove(original, file, &removal) {
+            Ok(true) => summary.processed += 1,
+            Ok(false) if source::is_remote(&file.path) => {
+                info!("keeping remote copy {}", file.path.to_string_lossy());
+                summary.skipped += 1;
+            }
+            Ok(false) if original.path.exists() => {
+                info!(
+                    "{} is the same file as its original {}, keeping it",
+                    file.path.to_string_lossy(),
+                    original.path.to_string_lossy()
+                );
+                summary.skipped += 1;
+            }
+            Ok(false) => {
+                warn!(
+                    "original {} is missing, keeping {}",
+                    original.path.to_string_lossy(),
+                    file.path.to_string_lossy()
+                );
+                summary.skipped += 1;
+            }
+            Err(err) => {
+                error!("failed to remove {}: {}", file.path.to_string_lossy(), err);
+                summary.fail(&err);
+            }
+        }
+    }
+    println!("removed {} duplicates", summary.processed);
+    summary.settled()
+}
+
+pub(super) fn link_duplicates(
+    deduper: &Deduper,
+    groups: &[DupGroup],
+    args: &DedupeArgs,
+) -> Summary {
+    let duplicates = duplicates(groups);
+    if duplicates.is_empty() {
+        info!("no duplicates to link");
+        return Summary::default().settled();
+    }
+    let strategy = match args.link_type {
+        LinkType::Hardlink => LinkStrategy::Hardlink,
+        LinkType::Symlink => LinkStrategy::Symlink,
+    };
+    if !args.yes
+        && !confirm(&format!(
+            "replace {} duplicates with {}s?",
+            duplicates.len(),
+            strategy
+        ))
+    {
+        info!("aborted");
+        return Summary::aborted();
+    }
+
+    let mut summary = Summary::default();
+    for (original, file) in duplicates {
+        match deduper.link(original, file, strategy) {
+            Ok(true) => summary.processed += 1,
+            Ok(false) => summary.skipped += 1,
+            Err(err) => {
+                error!("failed to link {}: {}", file.path.to_string_lossy(), err);
+                summary.fail(&err);
+            }
+        }
+    }
+    println!(
+        "replaced {} duplicates with {}s",
+        summary.processed, strategy
+    );
+    summary.settled()
+}
+
+/// Lists near-duplicate images and which of each group the keep policy
+/// prefers; nothing is marked since the copies differ.
+fn fuzzy(deduper: &Deduper, distance: u32) -> Summary {
+    let groups = match deduper.similar_images(distance) {
+        Ok(groups) => groups,
+        Err(err) => {
+            error!("failed to read perceptual hashes: {}", err);
+            return Summary::aborted();
+        }
+    };
+    let preferred = match deduper.db().source_priority() {
+        Ok(preferred) => preferred,
+        Err(err) => {
+            error!("failed to read the preferred sources: {}", err);
+            return Summary::aborted();
+        }
+    };
+    for (index, group) in groups.iter().enumerate() {
+        println!("similar group {}", index + 1);
+        let kept = deduper
+            .keep_policy()
+            .choose_similar(&preferred, group)
+            .map(|file| &file.path);
+        for file in group {
+            let marker = if Some(&file.path) == kept {
+                "keep"
+            } else {
+                "similar"
+            };
+            println!(
+                "\t{}\t{}\t{}",
+                marker,
+                file.size,
+                file.path.to_string_lossy()
+            );
+        }
+    }
+    println!(
+        "{} groups of similar images within {} bits",
+        groups.len(),
+        distance
+    );
+    Summary {
+        processed: groups.iter().map(|group| group.len() as u64).sum(),
+        duplicates: groups.iter().map(|group| group.len() as u64 - 1).sum(),
+        ..Summary::default()
+    }
+    .settled()
+}
@@ -0,0 +1,134 @@
+use std::{fs, path::PathBuf};
+
+use clap::Args;
+use deduper::{
+    duplicates::find_duplicates,
+    media::{walk_files, WalkOptions},
+    volume::Volumes,
+    Scanner,
+};
+
+use super::{
+    import::record_sets,
+    open_database,
+    progress::{OutputArgs, Progress, Summary},
+    thread_pool, IgnoreArgs, InspectArgs, ThrottleArgs,
+};
+
+#[derive(Args)]
+pub struct DuplicatesArgs {
+    #[arg(short, long, value_hint = clap::ValueHint::DirPath, num_args = 1.., required = true)]
+    pub sources: Vec<PathBuf>,
+    /// Number of worker threads used for hashing (0 = one per CPU)
+    #[arg(short, long, default_value_t = 0)]
+    pub jobs: usize,
+    #[command(flatten)]
+    pub throttle: ThrottleArgs,
+    #[command(flatten)]
+    pub inspect: InspectArgs,
+    /// Ignore files smaller than this many bytes; empty files are all alike
+    #[arg(long, default_value_t = 1)]
+    pub min_size: u64,
+    /// Follow symlinks to files and directories; symlink loops are reported
+    #[arg(long)]
+    pub follow_symlinks: bool,
+    #[command(flatten)]
+    pub ignore: IgnoreArgs,
+    /// Also record the duplicates in this database, as `import` does
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    pub database: Option<PathBuf>,
+}
+
+/// Lists identical files of the sources like fdupes, one path per line and
+/// a blank line after each set, so `import --from fdupes` reads it back.
+/// Files of a unique size are never hashed. Exits with 3 when there are
+/// duplicates.
+pub fn run(args: &DuplicatesArgs, output: &OutputArgs) -> Summary {
+    args.throttle.apply();
+    let walk = WalkOptions {
+        follow_symlinks: args.follow_symlinks,
+        ignore: args.ignore.rules(),
+        ..WalkOptions::default()
+    };
+    // the number of files to hash is only known stage by stage, so no bar
+    let progress = Progress::hidden();
+    let mut files = Vec::new();
+    for entry in walk_files(&args.sources, &walk) {
+        let path = match entry {
+            Ok(path) => path,
+            Err(err) => {
+                progress.walk_failed(err);
+                continue;
+            }
+        };
+        match fs::metadata(&path) {
+            Ok(metadata) if metadata.len() >= args.min_size => files.push((path, metadata.len())),
+            Ok(_) => {}
+            Err(err) => progress.fail(&path, &err.into()),
+        }
+    }
+    let listed = files.len();
+    let pool = thread_pool(args.jobs);
+    let found = pool.install(|| {
+        find_duplicates(
+            files,
+            args.inspect.hash_algo,
+            args.inspect.read_backend,
+            |path, err| progress.fail(path, &err.into()),
+        )
+    });
+    progress.clear();
+    for set in &found.sets {
+        for path in &set.paths {
+            println!("{}", path.to_string_lossy());
+        }
+        println!();
+    }
+    println!(
+        "{} duplicate sets among {} files; {} partially and {} fully hashed",
+        found.sets.len(),
+        listed,
+        found.partially_hashed,
+        found.fully_hashed
+    );
+    let mut summary = progress.summary();
+    summary.processed = listed as u64;
+    summary.duplicates = found
+        .sets
+        .iter()
+        .map(|set| set.paths.len() as u64 - 1)
+        .sum();
+
+    let Some(database) = &args.database else {
+        return summary.settled();
+    };
+    let Some(db) = open_database(database) else {
+        return Summary::aborted();
+    };
+    let scanner = Scanner::new(args.inspect.inspector()).volumes(Volumes::detect());
+    let progress = Progress::new(output, || {
+        found.sets.iter().map(|set| set.paths.len()).sum()
+    });
+    let (recorded, _) = pool.install(|| {
+        record_sets(
+            &found.sets,
+            &scanner,
+            args.inspect.hash_algo,
+            &db,
+            &progress,
+        )
+    });
+    progress.finish();
+    println!(
+        "recorded {} files in {}",
+        recorded,
+        database.to_string_lossy()
+    );
+    let recording = progress.summary();
+    summary.add(Summary {
+        failed: recording.failed,
+        failures: recording.failures,
+        ..Summary::default()
+    });
+    summary.settled()
+}
@@ -0,0 +1,287 @@
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+use clap::{Args, Subcommand, ValueEnum};
+use deduper::{
+    csv,
+    database::{FileError, DB},
+    media::Inspector,
+    scanner::{self, Scanner},
+    source,
+    volume::Volumes,
+    DeduperError,
+};
+use tracing::{error, info};
+
+use super::{
+    history::format_time,
+    open_database,
+    progress::{OutputArgs, Progress, Summary},
+    InspectArgs,
+};
+
+#[derive(Args)]
+pub struct ErrorsArgs {
+    #[command(subcommand)]
+    pub command: ErrorsCommand,
+}
+
+#[derive(Subcommand)]
+pub enum ErrorsCommand {
+    /// List the files scans, organize and transcode failed on and why
+    List(ListArgs),
+    /// Scan the files scans failed on again, e.g. after fixing their
+    /// permissions, and forget those that are recorded now
+    Retry(RetryArgs),
+}
+
+#[derive(Args)]
+pub struct ListArgs {
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    #[arg(long, value_enum, default_value_t)]
+    pub format: ListFormat,
+    /// File to write the report to; standard output without it
+    #[arg(value_hint = clap::ValueHint::FilePath)]
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ListFormat {
+    /// One line per file with when and how often it failed
+    #[default]
+    Table,
+    /// A header row and one row per file
+    Csv,
+    /// One JSON array of all failures
+    Json,
+}
+
+#[derive(Args)]
+pub struct RetryArgs {
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    /// Only retry failures of these kinds, e.g. io or exif
+    #[arg(long, value_delimiter = ',')]
+    pub kind: Vec<String>,
+    #[command(flatten)]
+    pub inspect: InspectArgs,
+}
+
+pub fn run(args: &ErrorsArgs, output: &OutputArgs) -> Summary {
+    match &args.command {
+        ErrorsCommand::List(args) => list(args),
+        ErrorsCommand::Retry(args) => retry(args, output),
+    }
+}
+
+/// Records that `stage` failed on `path` with `err`, scanning it into the
+/// read-only index `label` if any, unless the file is no media to begin
+/// with.
+pub(super) fn record(
+    db: &DB,
+    stage: &str,
+    path: &Path,
+    label: Option<&str>,
+    err: &DeduperError,
+    stat: Option<(u64, i64)>,
+    progress: &Progress,
+) {
+    if matches!(err, DeduperError::UnsupportedMedia(_)) {
+        return;
+    }
+    let error = FileError {
+        label: label.map(str::to_owned),
+        ..failure(stage, path, err.kind(), err.to_string(), stat)
+    };
+    store(db, &error, progress);
+}
+
+/// Records that re-encoding `path` failed with `message`.
+pub(super) fn record_transcode(db: &DB, path: &Path, message: &str, progress: &Progress) {
+    let error = failure(
+        FileError::TRANSCODE,
+        path,
+        FileError::TRANSCODE,
+        message.to_owned(),
+        scanner::stat(path),
+    );
+    store(db, &error, progress);
+}
+
+fn failure(
+    stage: &str,
+    path: &Path,
+    kind: &str,
+    message: String,
+    stat: Option<(u64, i64)>,
+) -> FileError {
+    FileError {
+        path: path.to_owned(),
+        stage: stage.to_owned(),
+        kind: kind.to_owned(),
+        message,
+        size: stat.map(|(size, _)| size),
+        modified_at: stat.map(|(_, modified_at)| modified_at),
+        attempts: 1,
+        at: Utc::now().timestamp(),
+        label: None,
+    }
+}
+
+fn store(db: &DB, error: &FileError, progress: &Progress) {
+    if let Err(err) = db.lock().record_error(error) {
+        progress.warn(format!(
+            "failed to record the failure of {}: {}",
+            error.path.to_string_lossy(),
+            err
+        ));
+    }
+}
+
+/// Forgets that `path` failed before.
+pub(super) fn forget(db: &DB, path: &Path, progress: &Progress) {
+    if let Err(err) = db.lock().forget_error(path) {
+        progress.warn(format!(
+            "failed to forget the failure of {}: {}",
+            path.to_string_lossy(),
+            err
+        ));
+    }
+}
+
+fn list(args: &ListArgs) -> Summary {
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    let errors = match db.lock().errors() {
+        Ok(errors) => errors,
+        Err(err) => {
+            error!("failed to read recorded errors: {}", err);
+            return Summary::aborted();
+        }
+    };
+    let written = match &args.file {
+        Some(path) => File::create(path)
+            .and_then(|file| write_errors(&errors, args.format, BufWriter::new(file))),
+        None => write_errors(&errors, args.format, io::stdout().lock()),
+    };
+    if let Err(err) = written {
+        error!("failed to write errors: {}", err);
+        return Summary::aborted();
+    }
+    Summary::listed(errors.len())
+}
+
+fn write_errors(errors: &[FileError], format: ListFormat, mut out: impl Write) -> io::Result<()> {
+    match format {
+        ListFormat::Table => {
+            for error in errors {
+                writeln!(
+                    out,
+                    "{}  {} x{}  {}: {}",
+                    format_time(error.at),
+                    error.stage,
+                    error.attempts,
+                    error.path.to_string_lossy(),
+                    error.message
+                )?;
+            }
+        }
+        ListFormat::Csv => {
+            csv::write_row(
+                &mut out,
+                &["path", "stage", "kind", "message", "attempts", "at"],
+            )?;
+            for error in errors {
+                csv::write_row(
+                    &mut out,
+                    &[
+                        &error.path.to_string_lossy(),
+                        &error.stage,
+                        &error.kind,
+                        &error.message,
+                        &error.attempts.to_string(),
+                        &error.at.to_string(),
+                    ],
+                )?;
+            }
+        }
+        ListFormat::Json => {
+            serde_json::to_writer_pretty(&mut out, errors)?;
+            writeln!(out)?;
+        }
+    }
+    out.flush()
+}
+
+/// Files recorded now count as processed and those that fail again as
+/// failed. Failures of files that are gone are forgotten, as are those of
+/// remote files, which the next scan of their source tries again. Files
+/// `organize` or `transcode` failed on are left to those commands.
+fn retry(args: &RetryArgs, output: &OutputArgs) -> Summary {
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    let errors = match db.lock().errors() {
+        Ok(errors) => errors,
+        Err(err) => {
+            error!("failed to read recorded errors: {}", err);
+            return Summary::aborted();
+        }
+    };
+    let errors = errors
+        .into_iter()
+        .filter(FileError::is_scan_failure)
+        .filter(|error| args.kind.is_empty() || args.kind.contains(&error.kind))
+        .collect::<Vec<_>>();
+    let scanner = Scanner::new(args.inspect.inspector()).volumes(Volumes::detect());
+    let progress = Progress::new(output, || errors.len());
+    for error in &errors {
+        let path = error.path.as_path();
+        if source::is_remote(path) || fs::symlink_metadata(path).is_err() {
+            forget(&db, path, &progress);
+            progress.info(format!(
+                "forgot {}, the next scan of its source tries it again",
+                path.to_string_lossy()
+            ));
+            progress.advance();
+            continue;
+        }
+        match scanner
+            .clone()
+            .label(error.label.clone())
+            .scan_file(path, &db)
+        {
+            Ok(_) => {
+                forget(&db, path, &progress);
+                progress.info(format!("recorded {}", path.to_string_lossy()));
+                progress.done();
+            }
+            Err(err) => {
+                record(
+                    &db,
+                    Inspector::stage_of(&err),
+                    path,
+                    error.label.as_deref(),
+                    &err,
+                    scanner::stat(path),
+                    &progress,
+                );
+                progress.fail(path, &err);
+            }
+        }
+    }
+    progress.finish();
+    if let Err(err) = db.flush() {
+        error!("failed to record the last files: {}", err);
+        return Summary::aborted();
+    }
+    let summary = progress.summary();
+    info!("{} failures left", summary.failed);
+    summary
+}
@@ -0,0 +1,273 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::{Args, ValueEnum};
+use deduper::{
+    csv,
+    database::DB,
+    error::Result,
+    hasher,
+    manifest::{self, ManifestAlgorithm},
+    media::{walk_files, WalkOptions},
+    platform, DeduperError,
+};
+use rayon::prelude::*;
+use tracing::error;
+
+use super::{open_database, progress::Summary};
+
+#[derive(Args)]
+pub struct ExportArgs {
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    #[arg(long, value_enum, default_value_t)]
+    pub format: ExportFormat,
+    /// Write a SHA256SUMS or BLAKE3SUMS manifest of the files below
+    /// --destination instead of the files table
+    #[arg(
+        long,
+        value_enum,
+        value_name = "ALGORITHM",
+        requires = "destination",
+        conflicts_with = "format"
+    )]
+    pub manifest: Option<ManifestAlgorithm>,
+    /// Destination tree the manifest lists, with paths relative to it
+    #[arg(long, value_hint = clap::ValueHint::DirPath, requires = "manifest")]
+    pub destination: Option<PathBuf>,
+    /// File to write; standard output without it
+    #[arg(value_hint = clap::ValueHint::FilePath)]
+    pub file: Option<PathBuf>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    /// A header row and one row per file, as import-csv reads it
+    #[default]
+    Csv,
+    /// One JSON array of all files
+    Json,
+    /// One JSON object per line, for jq and log pipelines
+    Ndjson,
+}
+
+#[derive(Args)]
+pub struct ImportCsvArgs {
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    /// CSV file written by export
+    #[arg(value_hint = clap::ValueHint::FilePath)]
+    pub file: PathBuf,
+}
+
+pub fn export(args: &ExportArgs) -> Summary {
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    if let (Some(algorithm), Some(destination)) = (args.manifest, &args.destination) {
+        return export_manifest(args, &db, algorithm, destination);
+    }
+    let exported = match &args.file {
+        Some(path) => File::create(path)
+            .map_err(DeduperError::from)
+            .and_then(|file| write_files(&db, args.format, BufWriter::new(file))),
+        None => write_files(&db, args.format, io::stdout().lock()),
+    };
+    match exported {
+        Ok(count) => {
+            if args.file.is_some() {
+                println!("exported {} files", count);
+            }
+            Summary {
+                processed: count as u64,
+                ..Summary::default()
+            }
+        }
+        Err(err) => {
+            error!("failed to export files: {}", err);
+            Summary::aborted()
+        }
+    }
+}
+
+/// Streams the files table to `writer` and returns the number of files.
+fn write_files(db: &DB, format: ExportFormat, mut writer: impl Write) -> Result<usize> {
+    match format {
+        ExportFormat::Csv => csv::write_header(&mut writer)?,
+        ExportFormat::Json => writer.write_all(b"[")?,
+        ExportFormat::Ndjson => {}
+    }
+    let mut count = 0;
+    db.lock().for_each_file(|file| {
+        match format {
+            ExportFormat::Csv => csv::write_file(&mut writer, &file)?,
+            ExportFormat::Json => {
+                if count > 0 {
+                    writer.write_all(b",")?;
+                }
+                writer.write_all(b"\n")?;
+                serde_json::to_writer(&mut writer, &file).map_err(io::Error::from)?;
+            }
+            ExportFormat::Ndjson => {
+                serde_json::to_writer(&mut writer, &file).map_err(io::Error::from)?;
+                writer.write_all(b"\n")?;
+            }
+        }
+        count += 1;
+        Ok::<_, DeduperError>(())
+    })?;
+    if format == ExportFormat::Json {
+        writer.write_all(b"\n]\n")?;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Lists every file below `destination` with its digest, in path order.
+/// The recorded digest of a placed file is used when it has the algorithm
+/// and the size still matches; other files are hashed.
+fn export_manifest(
+    args: &ExportArgs,
+    db: &DB,
+    algorithm: ManifestAlgorithm,
+    destination: &Path,
+) -> Summary {
+    let sources = match db.read().find_placements() {
+        Ok(placements) => placements
+            .into_iter()
+            .map(|placement| (placement.dest_path, placement.path))
+            .collect::<HashMap<_, _>>(),
+        Err(err) => {
+            error!("failed to read placements: {}", err);
+            return Summary::aborted();
+        }
+    };
+    // an earlier manifest, or the one the shell is redirecting into
+    let previous = destination.join(algorithm.file_name());
+    let output = args
+        .file
+        .as_ref()
+        .and_then(|file| platform::canonicalize(file).ok());
+    let mut summary = Summary::default();
+    // the tree is made of symlinks unless files were copied or moved
+    let walk = WalkOptions {
+        follow_symlinks: true,
+        ..WalkOptions::default()
+    };
+    let mut paths = Vec::new();
+    for entry in walk_files(&[destination.to_owned()], &walk) {
+        match entry {
+            Ok(path) if path == previous => {}
+            Ok(path) if output.is_some() && platform::canonicalize(&path).ok() == output => {}
+            Ok(path) => paths.push(path),
+            Err(err) => {
+                error!("failed to walk {}: {}", destination.to_string_lossy(), err);
+                summary.fail(&io::Error::from(err).into());
+            }
+        }
+    }
+    paths.sort();
+    let digests = paths
+        .par_iter()
+        .map(|path| manifest_digest(db, &sources, algorithm, path))
+        .collect::<Vec<_>>();
+
+    let written = match &args.file {
+        Some(path) => File::create(path)
+            .and_then(|file| write_manifest(destination, &paths, &digests, BufWriter::new(file))),
+        None => write_manifest(destination, &paths, &digests, io::stdout().lock()),
+    };
+    if let Err(err) = written {
+        error!("failed to write the manifest: {}", err);
+        return Summary::aborted();
+    }
+    for (path, digest) in paths.iter().zip(&digests) {
+        match digest {
+            Ok(_) => summary.processed += 1,
+            Err(err) => {
+                error!("failed to hash {}: {}", path.to_string_lossy(), err);
+                summary.fail(err);
+            }
+        }
+    }
+    if let Some(file) = &args.file {
+        println!(
+            "listed {} files in {}",
+            summary.processed,
+            file.to_string_lossy()
+        );
+    }
+    summary.settled()
+}
+
+fn manifest_digest(
+    db: &DB,
+    sources: &HashMap<PathBuf, PathBuf>,
+    algorithm: ManifestAlgorithm,
+    path: &Path,
+) -> Result<String> {
+    let algorithm = algorithm.hash_algorithm();
+    let recorded = sources
+        .get(path)
+        .and_then(|source| db.read().find_file(source).ok().flatten())
+        .filter(|file| file.hash_algorithm == algorithm.name())
+        .filter(|file| fs::metadata(path).is_ok_and(|metadata| metadata.len() == file.size))
+        .and_then(|file| manifest::hex_digest(&file.hash));
+    if let Some(hex) = recorded {
+        return Ok(hex);
+    }
+    let hash = hasher::file_hash(path, algorithm)?;
+    Ok(manifest::hex_digest(&hash.digest).expect("full digest"))
+}
+
+/// Writes a line per hashed file, leaving out those that failed.
+fn write_manifest(
+    destination: &Path,
+    paths: &[PathBuf],
+    digests: &[Result<String>],
+    mut writer: impl Write,
+) -> io::Result<()> {
+    for (path, digest) in paths.iter().zip(digests) {
+        if let Ok(hex) = digest {
+            let relative = path.strip_prefix(destination).unwrap_or(path);
+            writer.write_all(&manifest::line(hex, relative))?;
+        }
+    }
+    writer.flush()
+}
+
+/// Loads every row before touching the database, so a malformed file
+/// imports nothing.
+pub fn import(args: &ImportCsvArgs) -> Summary {
+    let read = match File::open(&args.file)
+        .map_err(csv::CsvError::from)
+        .and_then(|file| csv::read_files(BufReader::new(file)))
+    {
+        Ok(read) => read,
+        Err(err) => {
+            error!("failed to read {}: {}", args.file.to_string_lossy(), err);
+            return Summary::aborted();
+        }
+    };
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    let imported = db.lock().import_files(&read.files, &read.columns);
+    match imported {
+        Ok(()) => {
+            println!("imported {} files", read.files.len());
+            Summary {
+                processed: read.files.len() as u64,
+                ..Summary::default()
+            }
+        }
+        Err(err) => {
+            error!("failed to import files: {}", err);
+            Summary::aborted()
+        }
+    }
+}
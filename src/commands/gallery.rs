@@ -0,0 +1,302 @@
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, Datelike, FixedOffset, TimeZone};
+use clap::Args;
+use deduper::{
+    database::File,
+    html::{self, escape},
+    platform, source,
+    thumbnail::ThumbnailCache,
+    transcoder::FfmpegTools,
+};
+use rayon::prelude::*;
+use tracing::{error, info, warn};
+
+use super::{
+    open_database,
+    progress::{OutputArgs, Progress, Summary},
+    thread_pool, ThumbnailArgs,
+};
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em}\
+    nav{margin-bottom:1em}\
+    .months a{display:inline-block;margin:0 1em .5em 0}\
+    .grid{display:flex;flex-wrap:wrap;gap:.5em}\
+    figure{margin:0;width:12em}\
+    figure img,.missing{width:12em;height:12em;object-fit:cover;background:#eee}\
+    .missing{display:flex;align-items:center;justify-content:center;color:#666}\
+    figcaption{font-size:.8em;color:#666;overflow:hidden;text-overflow:ellipsis;\
+    white-space:nowrap}";
+
+/// Thumbnails are copied below the output directory, so the gallery does
+/// not depend on the cache.
+const THUMBNAIL_DIR: &str = "thumbnails";
+
+#[derive(Args)]
+pub struct GalleryArgs {
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    /// Directory the pages and their thumbnails are written to
+    #[arg(long, value_hint = clap::ValueHint::DirPath)]
+    pub out: PathBuf,
+    /// Destination tree to show; every placed file without it
+    #[arg(short, long, value_hint = clap::ValueHint::DirPath)]
+    pub destination: Option<PathBuf>,
+    #[command(flatten)]
+    pub thumbnail: ThumbnailArgs,
+    /// The ffmpeg executable, or the directory holding it, for thumbnails
+    /// of videos the cache lacks; looked up on the PATH without it
+    #[arg(long, value_hint = clap::ValueHint::AnyPath)]
+    pub ffmpeg_path: Option<PathBuf>,
+    /// Number of worker threads generating thumbnails (0 = one per CPU)
+    #[arg(short, long, default_value_t = 0)]
+    pub jobs: usize,
+}
+
+/// A placed file as the gallery shows it.
+struct Item {
+    file: File,
+    placed_at: PathBuf,
+    captured: DateTime<FixedOffset>,
+    thumbnail: bool,
+}
+
+/// Writes `index.html`, listing the months of the library by year, and a
+/// page of thumbnails for every month to the output directory.
+pub fn run(args: &GalleryArgs, output: &OutputArgs) -> Summary {
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    let placed = match db.lock().find_placed_files(args.destination.as_deref()) {
+        Ok(placed) => placed,
+        Err(err) => {
+            error!("failed to read placed files: {}", err);
+            return Summary::aborted();
+        }
+    };
+    if placed.is_empty() {
+        warn!("no placed files are recorded; run organize first");
+    }
+    let Some(mut cache) = args.thumbnail.cache() else {
+        error!("could not locate the cache directory; pass --thumbnail-dir");
+        return Summary::aborted();
+    };
+    let videos = placed
+        .iter()
+        .any(|(file, _)| file.media_type.starts_with("video/") && cache.get(&file.hash).is_none());
+    if videos {
+        match FfmpegTools::locate(args.ffmpeg_path.as_deref()) {
+            Ok(tools) => cache = cache.videos(tools),
+            Err(err) => warn!("{}; videos lacking a thumbnail get none", err),
+        }
+    }
+    let thumbnail_dir = args.out.join(THUMBNAIL_DIR);
+    for dir in [cache.dir(), thumbnail_dir.as_path()] {
+        if let Err(err) = fs::create_dir_all(dir) {
+            error!("failed to create {}: {}", dir.to_string_lossy(), err);
+            return Summary::aborted();
+        }
+    }
+
+    let progress = Progress::new(output, || placed.len());
+    let items = thread_pool(args.jobs).install(|| {
+        placed
+            .into_par_iter()
+            .map(|(file, placed_at)| {
+                let thumbnail = match copy_thumbnail(&cache, &file, &placed_at, &thumbnail_dir) {
+                    Ok(thumbnail) => {
+                        progress.done();
+                        thumbnail
+                    }
+                    Err(err) => {
+                        progress.fail(&placed_at, &err.into());
+                        false
+                    }
+                };
+                let captured = FixedOffset::east_opt(file.utc_offset)
+                    .and_then(|offset| offset.timestamp_opt(file.created_at, 0).single())
+                    .unwrap_or_default();
+                Item {
+                    file,
+                    placed_at,
+                    captured,
+                    thumbnail,
+                }
+            })
+            .collect::<Vec<_>>()
+    });
+    progress.finish();
+
+    let mut months = BTreeMap::<(i32, u32), Vec<Item>>::new();
+    for item in items {
+        let month = (item.captured.year(), item.captured.month());
+        months.entry(month).or_default().push(item);
+    }
+    if let Err(err) = write_pages(&args.out, &months) {
+        error!(
+            "failed to write gallery to {}: {}",
+            args.out.to_string_lossy(),
+            err
+        );
+        return Summary::aborted();
+    }
+    info!(
+        "wrote {} months to {}",
+        months.len(),
+        args.out.join("index.html").to_string_lossy()
+    );
+    progress.summary()
+}
+
+/// Copies the thumbnail of `file`, generated from `placed_at` unless the
+/// cache holds it, next to the pages. Returns whether there is one.
+fn copy_thumbnail(
+    cache: &ThumbnailCache,
+    file: &File,
+    placed_at: &Path,
+    thumbnail_dir: &Path,
+) -> io::Result<bool> {
+    let thumbnail = match cache.get(&file.hash) {
+        Some(thumbnail) => thumbnail,
+        // the placed file of a remote cannot be read in place
+        None if source::is_remote(placed_at) => return Ok(false),
+        None => match cache.generate(placed_at, &file.hash, &file.media_type)? {
+            Some(thumbnail) => thumbnail,
+            None => return Ok(false),
+        },
+    };
+    fs::copy(thumbnail, thumbnail_dir.join(thumbnail_name(&file.hash)))?;
+    Ok(true)
+}
+
+fn thumbnail_name(hash: &str) -> String {
+    format!("{}.jpg", hash)
+}
+
+fn month_page(year: i32, month: u32) -> String {
+    format!("{:04}-{:02}.html", year, month)
+}
+
+fn month_name(year: i32, month: u32) -> String {
+    chrono::NaiveDate::from_ymd_opt(year, month, 1).map_or_else(
+        || format!("{}-{:02}", year, month),
+        |date| date.format("%B %Y").to_string(),
+    )
+}
+
+fn write_pages(out: &Path, months: &BTreeMap<(i32, u32), Vec<Item>>) -> io::Result<()> {
+    let mut index = header("Library");
+    index.push_str("<h1>Library</h1>");
+    let mut year = None;
+    // newest first, as photo managers show a timeline
+    for (&(y, m), items) in months.iter().rev() {
+        if year != Some(y) {
+            if year.is_some() {
+                index.push_str("</div>");
+            }
+            index.push_str(&format!("<h2>{}</h2><div class=\"months\">", y));
+            year = Some(y);
+        }
+        index.push_str(&format!(
+            "<a href=\"{}\">{} ({})</a>",
+            month_page(y, m),
+            escape(&month_name(y, m)),
+            items.len()
+        ));
+    }
+    if year.is_some() {
+        index.push_str("</div>");
+    }
+    index.push_str("</body></html>");
+    fs::write(out.join("index.html"), index)?;
+
+    let keys = months.keys().copied().collect::<Vec<_>>();
+    for (position, &(y, m)) in keys.iter().enumerate() {
+        let mut page = header(&month_name(y, m));
+        page.push_str("<nav><a href=\"index.html\">all months</a>");
+        if let Some(&(py, pm)) = position.checked_sub(1).and_then(|p| keys.get(p)) {
+            page.push_str(&format!(
+                " · <a href=\"{}\">{}</a>",
+                month_page(py, pm),
+                escape(&month_name(py, pm))
+            ));
+        }
+        if let Some(&(ny, nm)) = keys.get(position + 1) {
+            page.push_str(&format!(
+                " · <a href=\"{}\">{}</a>",
+                month_page(ny, nm),
+                escape(&month_name(ny, nm))
+            ));
+        }
+        page.push_str(&format!(
+            "</nav><h1>{}</h1><div class=\"grid\">",
+            escape(&month_name(y, m))
+        ));
+        for item in &months[&(y, m)] {
+            render_item(&mut page, item);
+        }
+        page.push_str("</div></body></html>");
+        fs::write(out.join(month_page(y, m)), page)?;
+    }
+    Ok(())
+}
+
+fn header(title: &str) -> String {
+    format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>{}</title>\
+            <style>{}</style></head><body>",
+        escape(title),
+        STYLE
+    )
+}
+
+/// A thumbnail linking to the placed file, with its capture time, camera
+/// and name below.
+fn render_item(out: &mut String, item: &Item) {
+    let name = item
+        .placed_at
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let title = escape(&item.placed_at.to_string_lossy());
+    let picture = if item.thumbnail {
+        format!(
+            "<img src=\"{}/{}\" loading=\"lazy\" alt=\"{}\">",
+            THUMBNAIL_DIR,
+            html::encode_component(&thumbnail_name(&item.file.hash)),
+            escape(&name)
+        )
+    } else {
+        format!(
+            "<div class=\"missing\">{}</div>",
+            escape(&item.file.media_type)
+        )
+    };
+    // remote files have no URL a browser opens
+    let link = platform::canonicalize(&item.placed_at)
+        .ok()
+        .filter(|path| path.is_absolute())
+        .map(|path| format!("file://{}", platform::encode_path(&path)));
+    out.push_str(&format!("<figure title=\"{}\">", title));
+    match link {
+        Some(link) => out.push_str(&format!("<a href=\"{}\">{}</a>", escape(&link), picture)),
+        None => out.push_str(&picture),
+    }
+    let mut caption = item.captured.format("%F %H:%M").to_string();
+    if let Some(duration) = item.file.duration {
+        let seconds = duration.round() as u64;
+        caption.push_str(&format!(" · {}:{:02}", seconds / 60, seconds % 60));
+    }
+    if let Some(model) = &item.file.camera_model {
+        caption.push_str(&format!(" · {}", model));
+    }
+    out.push_str(&format!(
+        "<figcaption>{}<br>{}</figcaption></figure>",
+        escape(&caption),
+        escape(&name)
+    ));
+}
@@ -0,0 +1,134 @@
+use std::{io, path::PathBuf};
+
+use chrono::{DateTime, Local};
+use clap::{Args, ValueEnum};
+use deduper::database::{LockDB, Scan};
+use tracing::{error, info};
+
+use super::{open_database, progress::Summary};
+
+#[derive(Args)]
+pub struct HistoryArgs {
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    /// List what happened to files from the start of this scan on
+    #[arg(long, value_name = "SCAN", conflicts_with = "changes")]
+    pub since: Option<i64>,
+    /// List what changed in the latest scan and since
+    #[arg(long)]
+    pub changes: bool,
+    #[arg(long, value_enum, default_value_t)]
+    pub format: HistoryFormat,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HistoryFormat {
+    #[default]
+    Table,
+    Json,
+}
+
+/// Lists the scans, or with `--since`/`--changes` the file events since one.
+pub fn run(args: &HistoryArgs) -> Summary {
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    let db = db.lock();
+    let scans = match db.scans() {
+        Ok(scans) => scans,
+        Err(err) => {
+            error!("failed to read scans: {}", err);
+            return Summary::aborted();
+        }
+    };
+    let since = match (args.since, args.changes) {
+        (Some(scan_id), _) => Some(scan_id),
+        (None, true) => match scans.first() {
+            Some(scan) => Some(scan.id),
+            None => {
+                info!("no scans recorded");
+                return Summary::default().settled();
+            }
+        },
+        (None, false) => None,
+    };
+    let result = match since {
+        Some(scan_id) => print_events(&db, scan_id, args.format),
+        None => print_scans(&scans, args.format),
+    };
+    match result {
+        Ok(rows) => Summary::listed(rows),
+        Err(err) => {
+            error!("failed to write history: {}", err);
+            Summary::aborted()
+        }
+    }
+}
+
+/// Prints `scans` and returns how many there are.
+fn print_scans(scans: &[Scan], format: HistoryFormat) -> io::Result<usize> {
+    if format == HistoryFormat::Json {
+        serde_json::to_writer_pretty(io::stdout(), scans)?;
+        println!();
+        return Ok(scans.len());
+    }
+    println!(
+        "{:>6} {:<19} {:<19} {:>9} {:>9} {:>7} {:>7}  sources",
+        "scan", "started", "finished", "recorded", "unchanged", "failed", "deleted"
+    );
+    for scan in scans {
+        println!(
+            "{:>6} {:<19} {:<19} {:>9} {:>9} {:>7} {:>7}  {}",
+            scan.id,
+            format_time(scan.started_at),
+            scan.finished_at
+                .map(format_time)
+                .unwrap_or_else(|| "-".to_owned()),
+            scan.counts.recorded,
+            scan.counts.unchanged,
+            scan.counts.failed,
+            scan.counts.deleted,
+            scan.sources.join(", ")
+        );
+    }
+    Ok(scans.len())
+}
+
+/// Prints the file events since `scan_id` and returns how many there are.
+fn print_events(db: &LockDB, scan_id: i64, format: HistoryFormat) -> io::Result<usize> {
+    let events = db.events_since(scan_id).map_err(io::Error::other)?;
+    if format == HistoryFormat::Json {
+        serde_json::to_writer_pretty(io::stdout(), &events)?;
+        println!();
+        return Ok(events.len());
+    }
+    for event in &events {
+        match &event.detail {
+            Some(detail) => println!(
+                "{} {:<10} {} ({})",
+                format_time(event.at),
+                event.event.as_str(),
+                event.path.to_string_lossy(),
+                detail
+            ),
+            None => println!(
+                "{} {:<10} {}",
+                format_time(event.at),
+                event.event.as_str(),
+                event.path.to_string_lossy()
+            ),
+        }
+    }
+    println!("{} changes since scan {}", events.len(), scan_id);
+    Ok(events.len())
+}
+
+pub(super) fn format_time(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .map(|time| {
+            time.with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_default()
+}
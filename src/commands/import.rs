@@ -0,0 +1,116 @@
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use clap::Args;
+use deduper::{
+    database::DB,
+    hasher::{self, FileHash},
+    import::{DuplicateSet, ImportFormat},
+    scanner::{ScanOutcome, Scanner},
+    volume::Volumes,
+};
+use rayon::prelude::*;
+use tracing::error;
+
+use super::{
+    open_database, print_timestamp_source,
+    progress::{OutputArgs, Progress, Summary},
+    thread_pool, InspectArgs,
+};
+
+#[derive(Args)]
+pub struct ImportArgs {
+    /// Output of rmlint -o json, or of fdupes/jdupes
+    #[arg(value_hint = clap::ValueHint::FilePath)]
+    pub file: PathBuf,
+    /// Tool that wrote the file
+    #[arg(long, value_enum)]
+    pub from: ImportFormat,
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    /// Number of worker threads (0 = one per CPU)
+    #[arg(short, long, default_value_t = 0)]
+    pub jobs: usize,
+    #[command(flatten)]
+    pub inspect: InspectArgs,
+}
+
+/// Records the duplicates another tool found. Each set is hashed once, or
+/// not at all when the tool's digest is one deduper uses, and every member
+/// is recorded with that hash; only the metadata is read from each file.
+pub fn run(args: &ImportArgs, output: &OutputArgs) -> Summary {
+    let sets = match File::open(&args.file).and_then(|file| args.from.parse(BufReader::new(file))) {
+        Ok(sets) => sets,
+        Err(err) => {
+            error!("failed to read {}: {}", args.file.to_string_lossy(), err);
+            return Summary::aborted();
+        }
+    };
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    let scanner = Scanner::new(args.inspect.inspector()).volumes(Volumes::detect());
+    let progress = Progress::new(output, || sets.iter().map(|set| set.paths.len()).sum());
+    let (imported, hashed) = thread_pool(args.jobs)
+        .install(|| record_sets(&sets, &scanner, args.inspect.hash_algo, &db, &progress));
+    progress.finish();
+    println!(
+        "imported {} files in {} duplicate sets, hashing {} of the sets",
+        imported,
+        sets.len(),
+        hashed
+    );
+    progress.summary()
+}
+
+/// Records every member of `sets` with the hash of its set, and returns how
+/// many files were recorded and how many sets had to be hashed.
+pub(super) fn record_sets(
+    sets: &[DuplicateSet],
+    scanner: &Scanner,
+    algorithm: hasher::HashAlgorithm,
+    db: &DB,
+    progress: &Progress,
+) -> (usize, usize) {
+    let imported = AtomicUsize::new(0);
+    let hashed = AtomicUsize::new(0);
+    sets.par_iter().for_each(|set| {
+        let Some(hash) = set_hash(set, algorithm) else {
+            for path in &set.paths {
+                let err = io::Error::other("no file of its set can be hashed");
+                progress.fail(path, &err.into());
+            }
+            return;
+        };
+        if set.hash.as_ref() != Some(&hash) {
+            hashed.fetch_add(1, Ordering::Relaxed);
+        }
+        for path in &set.paths {
+            match scanner.import_file(path, hash.clone(), db) {
+                Ok(ScanOutcome::Recorded(media)) => {
+                    print_timestamp_source(progress, &media);
+                    progress.record(&media.hash.digest, 0);
+                    imported.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(ScanOutcome::Unchanged(file)) => progress.unchanged(&file.hash),
+                Err(err) => progress.fail(path, &err),
+            }
+        }
+    });
+    (imported.into_inner(), hashed.into_inner())
+}
+
+/// The tool's digest when it matches `algorithm`, or else the hash of the
+/// first file of the set that can be read.
+fn set_hash(set: &DuplicateSet, algorithm: hasher::HashAlgorithm) -> Option<FileHash> {
+    if let Some(hash) = set.hash.as_ref().filter(|hash| hash.algorithm == algorithm) {
+        return Some(hash.clone());
+    }
+    set.paths
+        .iter()
+        .find_map(|path| hasher::file_hash(path, algorithm).ok())
+}
@@ -0,0 +1,68 @@
+use std::{fs::OpenOptions, io, sync::Mutex};
+
+use clap::ValueEnum;
+use tracing::error;
+use tracing_subscriber::filter::LevelFilter;
+
+use super::progress::OutputArgs;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    /// Also every file that was skipped or needed attention
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => LevelFilter::ERROR,
+            LogLevel::Warn => LevelFilter::WARN,
+            LogLevel::Info => LevelFilter::INFO,
+            LogLevel::Debug => LevelFilter::DEBUG,
+            LogLevel::Trace => LevelFilter::TRACE,
+        }
+    }
+}
+
+/// `--log-level`, or else info raised by each `-v` and lowered to warnings
+/// by `--quiet`.
+fn max_level(output: &OutputArgs) -> LevelFilter {
+    if let Some(level) = output.log_level {
+        return level.into();
+    }
+    match output.verbose {
+        0 if output.quiet => LevelFilter::WARN,
+        0 => LevelFilter::INFO,
+        1 => LevelFilter::DEBUG,
+        _ => LevelFilter::TRACE,
+    }
+}
+
+/// Logs to standard error, or with timestamps to the end of `--log-file`.
+/// The results of a command, such as a report, stay on standard output.
+pub fn init(output: &OutputArgs) {
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(max_level(output))
+        .with_target(false);
+    let Some(path) = &output.log_file else {
+        return subscriber.without_time().with_writer(io::stderr).init();
+    };
+    match OpenOptions::new().create(true).append(true).open(path) {
+        Ok(file) => subscriber
+            .with_ansi(false)
+            .with_writer(Mutex::new(file))
+            .init(),
+        Err(err) => {
+            subscriber.without_time().with_writer(io::stderr).init();
+            error!(
+                "failed to open log file {}: {}",
+                path.to_string_lossy(),
+                err
+            );
+        }
+    }
+}
@@ -0,0 +1,541 @@
+pub mod completions;
+pub mod conflicts;
+#[cfg(unix)]
+pub mod daemon;
+pub mod db;
+pub mod dedupe;
+pub mod duplicates;
+pub mod errors;
+pub mod export;
+pub mod gallery;
+pub mod history;
+pub mod import;
+pub mod logging;
+pub mod organize;
+pub mod progress;
+pub mod quarantine;
+pub mod relayout;
+pub mod repair;
+pub mod report;
+#[cfg(feature = "tui")]
+pub mod review;
+pub mod scan;
+#[cfg(feature = "web")]
+pub mod serve;
+pub mod transcode;
+pub mod undo;
+pub mod verify;
+pub mod watch;
+
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use clap::{Args, ValueEnum};
+use deduper::{
+    database,
+    geo::Geocoder,
+    hasher,
+    ignore::{self, Rules},
+    journal::Journal,
+    layout::{self, Token},
+    linker,
+    media::{Category, Inspector, Media, TimestampSource},
+    organizer,
+    photoslibrary::PhotosLibrary,
+    rclone,
+    sidecar::Sidecars,
+    space::{self, Budget},
+    throttle,
+    thumbnail::{self, ThumbnailCache},
+    Organizer,
+};
+use indicatif::HumanBytes;
+use tracing::{error, info, warn};
+
+use progress::Progress;
+
+/// Options controlling how media files are hashed and dated.
+#[derive(Args)]
+pub struct InspectArgs {
+    /// Hash algorithm used to fingerprint files
+    #[arg(long, value_enum, default_value_t)]
+    pub hash_algo: hasher::HashAlgorithm,
+    /// How files are read for hashing; `mmap` and `io-uring` can keep fast
+    /// NVMe drives busier, and fall back to `buffered` where unavailable
+    #[arg(long, value_enum, default_value_t)]
+    pub read_backend: hasher::ReadBackend,
+    /// chrono format of a date embedded in file names, e.g. IMG_%Y%m%d_%H%M%S;
+    /// tried when a file has no metadata timestamp and replaces the built-in patterns
+    #[arg(long = "filename-pattern", value_name = "FORMAT")]
+    pub filename_patterns: Vec<String>,
+    /// Kinds of files to record and place; others are skipped as unsupported
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_values_t = Category::DEFAULT
+    )]
+    pub include_types: Vec<Category>,
+    /// Sources are Google Takeout exports: date and locate media by the
+    /// JSON file next to it, e.g. IMG_0001.JPG.json, before its EXIF
+    #[arg(long)]
+    pub takeout: bool,
+}
+
+impl InspectArgs {
+    pub fn inspector(&self) -> Inspector {
+        let inspector = Inspector::new(self.hash_algo)
+            .read_backend(self.read_backend)
+            .categories(self.include_types.clone())
+            .takeout(self.takeout);
+        if self.filename_patterns.is_empty() {
+            return inspector;
+        }
+        inspector.filename_patterns(self.filename_patterns.clone())
+    }
+}
+
+pub fn open_database(path: &Path) -> Option<database::DB> {
+    open_database_with(path, database::DbOptions::default())
+}
+
+pub fn open_database_with(path: &Path, options: database::DbOptions) -> Option<database::DB> {
+    match database::DB::new_with_options(path, options) {
+        Ok(db) => Some(db),
+        Err(err) => {
+            error!(
+                "failed to open database {}: {}",
+                path.to_string_lossy(),
+                err
+            );
+            None
+        }
+    }
+}
+
+/// Options controlling where and how media is placed into the destination.
+#[derive(Args)]
+pub struct PlacementArgs {
+    #[arg(short, long, value_hint = clap::ValueHint::DirPath, required = true)]
+    pub destination: PathBuf,
+    /// Place photos below this directory instead of the destination, e.g.
+    /// the photo share of a NAS
+    #[arg(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+    pub dest_photos: Option<PathBuf>,
+    /// Place camera RAW files below this directory instead of the
+    /// destination
+    #[arg(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+    pub dest_raw: Option<PathBuf>,
+    /// Place videos below this directory instead of the destination
+    #[arg(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+    pub dest_videos: Option<PathBuf>,
+    /// Place audio below this directory instead of the destination
+    #[arg(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+    pub dest_audio: Option<PathBuf>,
+    /// Place documents below this directory instead of the destination
+    #[arg(long, value_name = "DIR", value_hint = clap::ValueHint::DirPath)]
+    pub dest_documents: Option<PathBuf>,
+    /// How files are placed into the destination tree
+    #[arg(long, value_enum, default_value_t)]
+    pub strategy: linker::LinkStrategy,
+    /// Timezone used to pick the year folder and file name: capture (the
+    /// offset the file was recorded at), local, utc or an offset like +05:30
+    #[arg(long, default_value = "capture")]
+    pub timezone: organizer::Timezone,
+    /// Directories below the destination, built from {category}, {year},
+    /// {month}, {day}, {country}, {city}, {make}, {model}, {lens},
+    /// {group}, the live photo or burst of a file, {source}, the app it
+    /// was saved from, and {resolution}, such as 4K or 1080p
+    #[arg(long, default_value = layout::DEFAULT_LAYOUT)]
+    pub layout: layout::Layout,
+    /// Shorthand for the default layout bucketed by year (`2023/`), month
+    /// (`2023/09/`) or day (`2023/09/01/`)
+    #[arg(long, value_enum, conflicts_with = "layout")]
+    pub granularity: Option<layout::Granularity>,
+    /// geonames cities dump (e.g. cities15000.txt) resolving {country} and {city}
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    pub geonames: Option<PathBuf>,
+    /// Directory for media that no source could date [default: <DESTINATION>/Unknown]
+    #[arg(long, value_hint = clap::ValueHint::DirPath)]
+    pub unknown_dir: Option<PathBuf>,
+    /// Whether .thm, .aae, .srt and .xmp files next to media are placed
+    /// with it under its new name, or left in the sources
+    #[arg(long, value_enum, default_value_t)]
+    pub sidecars: Sidecars,
+    /// Set the modification and access times of copied, moved and
+    /// reflinked files to their capture time, for gallery apps that sort
+    /// by file date
+    #[arg(long)]
+    pub set_mtime: bool,
+    /// Give copied and moved files the permissions of the process instead
+    /// of the mode, owner and extended attributes of their source
+    #[arg(long)]
+    pub no_preserve: bool,
+}
+
+impl PlacementArgs {
+    /// Builds the organizer, loading the geonames dump when one is given.
+    /// Problems are printed and give `None`.
+    pub fn organizer(&self) -> Option<Organizer> {
+        if let Some(remote) = self
+            .destinations()
+            .find(|destination| rclone::remote_path(destination).is_some())
+            .filter(|_| {
+                !matches!(
+                    self.strategy,
+                    linker::LinkStrategy::Copy | linker::LinkStrategy::Move
+                )
+            })
+        {
+            error!(
+                "files can only be copied or moved to {}, pass --strategy copy or move",
+                remote.to_string_lossy()
+            );
+            return None;
+        }
+        let mut organizer = Organizer::new(&self.destination, self.strategy)
+            .timezone(self.timezone)
+            .layout(self.layout())
+            .sidecars(self.sidecars)
+            .set_mtime(self.set_mtime)
+            .preserve(!self.no_preserve);
+        if let Some(unknown_dir) = &self.unknown_dir {
+            organizer = organizer.unknown_dir(unknown_dir);
+        }
+        for (category, destination) in self.category_destinations() {
+            organizer = organizer.category_destination(category, destination);
+        }
+        match &self.geonames {
+            Some(geonames) => match Geocoder::load(geonames) {
+                Ok(geocoder) => organizer = organizer.geocoder(Arc::new(geocoder)),
+                Err(err) => {
+                    error!(
+                        "failed to load geonames {}: {}",
+                        geonames.to_string_lossy(),
+                        err
+                    );
+                    return None;
+                }
+            },
+            None if self.layout().uses(Token::is_geographic) => {
+                error!("layout {} needs --geonames", self.layout());
+                return None;
+            }
+            None => {}
+        }
+        Some(organizer)
+    }
+
+    /// `--layout`, or the one `--granularity` stands for.
+    pub fn layout(&self) -> layout::Layout {
+        match self.granularity {
+            Some(granularity) => granularity.layout(),
+            None => self.layout.clone(),
+        }
+    }
+
+    /// The directories media is placed in, which a walk of the sources
+    /// should not pick up again.
+    pub fn excluded(&self) -> Vec<PathBuf> {
+        self.destinations()
+            .chain(&self.unknown_dir)
+            .cloned()
+            .collect()
+    }
+
+    /// The categories given a `--dest-*` directory of their own.
+    pub fn category_destinations(&self) -> impl Iterator<Item = (Category, &PathBuf)> {
+        [
+            (Category::Photos, &self.dest_photos),
+            (Category::Raw, &self.dest_raw),
+            (Category::Videos, &self.dest_videos),
+            (Category::Audio, &self.dest_audio),
+            (Category::Documents, &self.dest_documents),
+        ]
+        .into_iter()
+        .filter_map(|(category, destination)| Some((category, destination.as_ref()?)))
+    }
+
+    /// The destination and the `--dest-*` directories.
+    pub fn destinations(&self) -> impl Iterator<Item = &PathBuf> {
+        [&self.destination].into_iter().chain(
+            self.category_destinations()
+                .map(|(_, destination)| destination),
+        )
+    }
+
+    /// Logs where media goes.
+    pub fn log_destinations(&self) {
+        info!("destination: {}", self.destination.to_string_lossy());
+        for (category, destination) in self.category_destinations() {
+            info!(
+                "destination of {}: {}",
+                category.name().to_lowercase(),
+                destination.to_string_lossy()
+            );
+        }
+    }
+}
+
+/// Where `scan --thumbnails` stores thumbnails and reviews look for them.
+#[derive(Args)]
+pub struct ThumbnailArgs {
+    /// Directory thumbnails are cached in
+    /// [default: $XDG_CACHE_HOME/deduper/thumbnails]
+    #[arg(long, value_hint = clap::ValueHint::DirPath)]
+    pub thumbnail_dir: Option<PathBuf>,
+}
+
+impl ThumbnailArgs {
+    /// `None` when no directory is given and there is no home to find the
+    /// default one in.
+    pub fn cache(&self) -> Option<ThumbnailCache> {
+        self.thumbnail_dir
+            .clone()
+            .or_else(thumbnail::default_cache_dir)
+            .map(ThumbnailCache::new)
+    }
+}
+
+/// Which ignore files walks of the sources honor.
+#[derive(Args)]
+pub struct IgnoreArgs {
+    /// Walk everything, disregarding `.deduperignore` files and the global
+    /// ignore file
+    #[arg(long)]
+    pub no_ignore: bool,
+    /// Ignore file whose patterns apply below every source
+    /// [default: $XDG_CONFIG_HOME/deduper/ignore]
+    #[arg(long, value_name = "FILE", value_hint = clap::ValueHint::FilePath, conflicts_with = "no_ignore")]
+    pub ignore_file: Option<PathBuf>,
+}
+
+impl IgnoreArgs {
+    /// The patterns of the global ignore file, or `None` with
+    /// `--no-ignore`. Without the default file there are none; a file that
+    /// cannot be read is warned about and disregarded.
+    pub fn rules(&self) -> Option<Rules> {
+        if self.no_ignore {
+            return None;
+        }
+        let path = match &self.ignore_file {
+            Some(path) => path.clone(),
+            None => match ignore::default_global_file() {
+                Some(path) if path.is_file() => path,
+                _ => return Some(Rules::default()),
+            },
+        };
+        match Rules::load(&path) {
+            Ok(rules) => Some(rules),
+            Err(err) => {
+                warn!(
+                    "failed to read ignore file {}: {}",
+                    path.to_string_lossy(),
+                    err
+                );
+                Some(Rules::default())
+            }
+        }
+    }
+}
+
+/// How hard reading the sources may load the disks, for runs in the
+/// background.
+#[derive(Args)]
+pub struct ThrottleArgs {
+    /// Read files for hashing at no more than this many megabytes per
+    /// second, all threads together
+    #[arg(long, value_name = "MB/s", value_parser = parse_rate)]
+    pub throttle: Option<f64>,
+    /// Only touch the disks when nothing else does, like ionice -c 3 (Linux)
+    #[arg(long)]
+    pub idle_io: bool,
+}
+
+impl ThrottleArgs {
+    /// Applies the limits to this process. Threads and processes started
+    /// before keep their I/O priority, so call it first.
+    pub fn apply(&self) {
+        if let Some(rate) = self.throttle {
+            throttle::limit_read_rate((rate * 1_000_000.0) as u64);
+        }
+        if self.idle_io {
+            if let Err(err) = throttle::idle_io() {
+                warn!("failed to lower the I/O priority: {}", err);
+            }
+        }
+    }
+}
+
+/// What a batch that writes does when the disk lacks the space it needs.
+#[derive(Args)]
+pub struct SpaceArgs {
+    /// Whether a batch that does not fit in the free space refuses to
+    /// start, writes the files that fit, or runs regardless
+    #[arg(long, value_enum, default_value_t)]
+    pub when_full: WhenFull,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum WhenFull {
+    #[default]
+    Refuse,
+    Trim,
+    Ignore,
+}
+
+impl SpaceArgs {
+    /// Checks that `required` bytes fit on the filesystem of `path`,
+    /// keeping [`space::RESERVE`] free. Gives `None` when the batch must not
+    /// start, printing why, and a budget to trim it to when it does not fit
+    /// but may run. Where the free space cannot be read the batch runs.
+    pub fn check(&self, path: &Path, required: u64) -> Option<Option<Arc<Budget>>> {
+        if self.when_full == WhenFull::Ignore || required == 0 {
+            return Some(None);
+        }
+        let available = match space::available(path) {
+            Ok(available) => available,
+            // Windows
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => return Some(None),
+            Err(err) => {
+                warn!(
+                    "failed to check the free space of {}: {}",
+                    path.to_string_lossy(),
+                    err
+                );
+                return Some(None);
+            }
+        };
+        let usable = available.saturating_sub(space::RESERVE);
+        if required <= usable {
+            return Some(None);
+        }
+        match self.when_full {
+            WhenFull::Refuse => {
+                error!(
+                    "{} are needed, and {} kept free, but {} has {} free; free up \
+                        space, or pass --when-full trim to write what fits",
+                    HumanBytes(required),
+                    HumanBytes(space::RESERVE),
+                    path.to_string_lossy(),
+                    HumanBytes(available)
+                );
+                None
+            }
+            _ => {
+                warn!(
+                    "{} are needed, and {} kept free, but {} has {} free; only \
+                        what fits is written",
+                    HumanBytes(required),
+                    HumanBytes(space::RESERVE),
+                    path.to_string_lossy(),
+                    HumanBytes(available)
+                );
+                Some(Some(Arc::new(Budget::new(usable))))
+            }
+        }
+    }
+}
+
+fn parse_rate(s: &str) -> Result<f64, String> {
+    let rate = s
+        .parse::<f64>()
+        .map_err(|err| format!("invalid rate {}: {}", s, err))?;
+    if rate > 0.0 && rate.is_finite() {
+        Ok(rate)
+    } else {
+        Err(format!("rate {} is not above 0", s))
+    }
+}
+
+/// Starts a journaled run, or with `resume` continues the last one that
+/// was interrupted.
+pub fn open_journal(
+    db: &database::DB,
+    command: &str,
+    parameters: &str,
+    resume: bool,
+) -> Option<Journal> {
+    match Journal::open(db, command, parameters, resume) {
+        Ok(journal) => {
+            if journal.resumed() > 0 {
+                info!("resuming, {} files already done", journal.resumed());
+            }
+            Some(journal)
+        }
+        Err(err) => {
+            error!("failed to open journal: {}", err);
+            None
+        }
+    }
+}
+
+pub fn record_journal(journal: &Journal, db: &database::DB, path: &Path, progress: &Progress) {
+    if let Err(err) = journal.record(db, path) {
+        progress.warn(format!(
+            "failed to journal {}: {}",
+            path.to_string_lossy(),
+            err
+        ));
+    }
+}
+
+pub fn finish_journal(journal: Journal, db: &database::DB) {
+    if let Err(err) = journal.finish(db) {
+        error!("failed to finish journal: {}", err);
+    }
+}
+
+/// Asks a yes/no question on stdin; anything but "y" or "yes" declines.
+pub fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+pub fn log_sources(sources: &[PathBuf]) {
+    info!(
+        "sources: {}",
+        sources
+            .iter()
+            .map(|s| s.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+}
+
+/// Warns of the library bundles among the sources that have no database.
+pub fn warn_unread(library: &PhotosLibrary) {
+    for bundle in &library.unread {
+        warn!(
+            "{} has no Photos database, dating its files like any other",
+            bundle.to_string_lossy()
+        );
+    }
+}
+
+pub fn thread_pool(jobs: usize) -> rayon::ThreadPool {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .expect("could not build thread pool")
+}
+
+pub fn print_timestamp_source(progress: &Progress, media: &Media) {
+    // documents are only ever dated by their mtime
+    if media.timestamp_source == TimestampSource::Filesystem
+        && media.category != Category::Documents.name()
+    {
+        progress.debug(format!(
+            "using filesystem timestamp for {}",
+            media.path.to_string_lossy()
+        ));
+    }
+}
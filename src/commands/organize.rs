@@ -0,0 +1,982 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::Utc;
+use clap::Args;
+use deduper::{
+    database::{Conflict, FileError, UndoAction, DB},
+    error::Result,
+    extractor, group,
+    hasher::{self, FileHash, HashAlgorithm},
+    journal,
+    layout::Token,
+    linker::LinkStrategy,
+    media::{walk_files, Category, Inspector, Media, WalkOptions},
+    photoslibrary::{self, PhotosLibrary},
+    plan, platform, rclone,
+    scanner::{self, ScanOutcome, Scanner},
+    space,
+    undo::UndoLog,
+    volume::Volumes,
+    DeduperError, Organizer,
+};
+use mime_guess::Mime;
+use rayon::prelude::*;
+use tracing::{error, info, warn};
+
+use super::{
+    errors, finish_journal, log_sources, open_database, open_journal, print_timestamp_source,
+    progress::{OutputArgs, Progress, Summary},
+    record_journal, thread_pool, warn_unread, IgnoreArgs, InspectArgs, PlacementArgs, SpaceArgs,
+    ThrottleArgs, WhenFull,
+};
+
+#[derive(Args)]
+pub struct OrganizeArgs {
+    #[arg(
+        short,
+        long,
+        value_hint = clap::ValueHint::DirPath,
+        num_args = 1..,
+        required_unless_present_any = ["repair", "from_database"]
+    )]
+    pub sources: Vec<PathBuf>,
+    #[command(flatten)]
+    pub placement: PlacementArgs,
+    #[command(flatten)]
+    pub space: SpaceArgs,
+    /// Database the run is journaled to, so it can be resumed, and where
+    /// placed files are recorded; a run without it records nothing. The
+    /// database read by --repair and --from-database, `deduper.db` by
+    /// default
+    #[arg(long, value_hint = clap::ValueHint::FilePath)]
+    pub database: Option<PathBuf>,
+    /// Number of worker threads used for hashing and extraction (0 = one per CPU)
+    #[arg(short, long, default_value_t = 0)]
+    pub jobs: usize,
+    #[command(flatten)]
+    pub throttle: ThrottleArgs,
+    #[command(flatten)]
+    pub inspect: InspectArgs,
+    /// Follow symlinks to files and directories; symlink loops are reported
+    #[arg(long)]
+    pub follow_symlinks: bool,
+    #[command(flatten)]
+    pub ignore: IgnoreArgs,
+    /// Do not walk into the destination or unknown directory when a source contains them
+    #[arg(long)]
+    pub skip_destination: bool,
+    /// Walk and hash the sources, but only report what would be linked
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Write the dry run plan as JSON to this file instead of printing it
+    #[arg(long, value_hint = clap::ValueHint::FilePath, requires = "dry_run")]
+    pub dry_run_json: Option<PathBuf>,
+    /// Continue the last interrupted run over the same sources and
+    /// destination, skipping the files it already placed
+    #[arg(long, conflicts_with = "dry_run", requires = "database")]
+    pub resume: bool,
+    /// Instead of walking sources, recreate the links recorded in the
+    /// database that are missing from the destination or dangle
+    #[arg(long, conflicts_with_all = ["sources", "dry_run", "resume"])]
+    pub repair: bool,
+    /// Instead of walking sources, place one file of every hash recorded in
+    /// the database from its recorded hash, capture time and metadata,
+    /// without reading the files
+    #[arg(long, conflicts_with_all = ["sources", "resume", "repair", "skip_destination"])]
+    pub from_database: bool,
+    /// Take the destination as recorded in the database as the index:
+    /// record the sources in the database and skip media whose contents
+    /// the destination holds already, so only new media is added
+    #[arg(
+        long,
+        conflicts_with_all = ["dry_run", "repair", "from_database"],
+        requires = "database"
+    )]
+    pub skip_placed: bool,
+    /// Like --skip-placed, but delete the media the destination holds
+    /// already from the sources
+    #[arg(
+        long,
+        conflicts_with_all = ["dry_run", "repair", "from_database"],
+        requires = "database"
+    )]
+    pub purge_ingested: bool,
+    /// Compare media with the copy the destination holds byte for byte
+    /// before --purge-ingested deletes it, rather than hashing the copy
+    #[arg(long, requires = "purge_ingested")]
+    pub paranoid: bool,
+    /// Let --purge-ingested delete media on the database's word alone,
+    /// without hashing the copy the destination holds
+    #[arg(long, requires = "purge_ingested", conflicts_with = "paranoid")]
+    pub trust_destination: bool,
+}
+
+impl OrganizeArgs {
+    /// The database --repair and --from-database read.
+    fn database(&self) -> &Path {
+        self.database.as_deref().unwrap_or(Path::new("deduper.db"))
+    }
+}
+
+pub fn run(args: &OrganizeArgs, output: &OutputArgs) -> Summary {
+    args.throttle.apply();
+    if args.repair {
+        return repair(args, output);
+    }
+    if args.from_database {
+        return from_database(args, output);
+    }
+    log_sources(&args.sources);
+    args.placement.log_destinations();
+    let library = match PhotosLibrary::open(&args.sources) {
+        Ok(library) => library,
+        Err(err) => {
+            error!("failed to read the Apple Photos library: {}", err);
+            return Summary::aborted();
+        }
+    };
+    warn_unread(&library);
+    let inspector = args.inspect.inspector().photos_library(Arc::new(library));
+    let Some(organizer) = args.placement.organizer() else {
+        return Summary::aborted();
+    };
+    let walk = WalkOptions {
+        follow_symlinks: args.follow_symlinks,
+        exclude: if args.skip_destination {
+            args.placement.excluded()
+        } else {
+            Vec::new()
+        },
+        ignore: args.ignore.rules(),
+    };
+    let dry_run = args.dry_run.then(plan::DryRun::default);
+    // a dry run places nothing, so there is nothing to resume
+    let journaled = match &args.database {
+        Some(database) if !args.dry_run => {
+            let Some(db) = open_database(database) else {
+                return Summary::aborted();
+            };
+            let parameters = journal::parameters(
+                args.sources
+                    .iter()
+                    .chain(args.placement.destinations())
+                    .map(PathBuf::as_path),
+            );
+            let Some(journal) = open_journal(&db, "organize", &parameters, args.resume) else {
+                return Summary::aborted();
+            };
+            Some((journal, db))
+        }
+        _ => None,
+    };
+    let placed = match &journaled {
+        Some((_, db)) if args.skip_placed || args.purge_ingested => {
+            let Some(mut placed) = Placed::load(db, &organizer, &inspector, args.purge_ingested)
+            else {
+                return Summary::aborted();
+            };
+            placed.verify = if args.paranoid {
+                Verify::Bytes
+            } else if args.trust_destination {
+                Verify::None
+            } else {
+                Verify::Hash
+            };
+            Some(placed)
+        }
+        _ => None,
+    };
+    // forgotten once the file is placed
+    let known_errors = match &journaled {
+        Some((_, db)) => match db.lock().errors() {
+            Ok(known_errors) => known_errors.into_iter().map(|known| known.path).collect(),
+            Err(err) => {
+                error!("failed to read the files that failed before: {}", err);
+                return Summary::aborted();
+            }
+        },
+        None => HashSet::new(),
+    };
+    // only the originals of a library are media
+    let walked = args
+        .sources
+        .iter()
+        .map(|source| photoslibrary::walked(source))
+        .collect::<Vec<_>>();
+    let walked_files = walk_files(&walked, &walk).filter_map(|entry| {
+        let path = entry.ok()?;
+        let hash = journaled
+            .as_ref()
+            .and_then(|(_, db)| db.read().find_file(&path).ok().flatten())
+            .map(|file| file.hash);
+        Some((path, hash))
+    });
+    let db = journaled.as_ref().map(|(_, db)| db);
+    let Some(organizer) = check_space(args, organizer, db, walked_files) else {
+        return Summary::aborted();
+    };
+    let progress = Arc::new(Progress::new(output, || walk_files(&walked, &walk).count()));
+    let organizer = organizer.events(progress.clone());
+    thread_pool(args.jobs).install(|| {
+        walk_files(&walked, &walk).par_bridge().for_each(|entry| {
+            let path = match entry {
+                Ok(path) => path,
+                Err(err) => return progress.walk_failed(err),
+            };
+            match &journaled {
+                Some((journal, _)) if journal.is_done(&path) => progress.advance(),
+                Some((journal, db)) => {
+                    let records = Some((db, UndoLog::of_run(journal.run_id())));
+                    if organize_file(
+                        &path,
+                        &inspector,
+                        &organizer,
+                        records,
+                        placed.as_ref(),
+                        None,
+                        &progress,
+                    ) {
+                        if known_errors.contains(&path) {
+                            errors::forget(db, &path, &progress);
+                        }
+                        record_journal(journal, db, &path, &progress);
+                    }
+                }
+                None => {
+                    organize_file(
+                        &path,
+                        &inspector,
+                        &organizer,
+                        None,
+                        None,
+                        dry_run.as_ref(),
+                        &progress,
+                    );
+                }
+            }
+        });
+    });
+    progress.finish();
+    if let Some((journal, db)) = journaled {
+        finish_journal(journal, &db);
+    }
+    if let Some(dry_run) = dry_run {
+        write_plan(dry_run, args.dry_run_json.as_deref());
+    }
+    progress.summary()
+}
+
+/// Checks that placing `files`, with their hashes where they are recorded,
+/// fits in the destinations, and trims the run to a budget with
+/// `--when-full trim`. Destinations on one filesystem are checked, and
+/// budgeted, together. `None` when the run must not start.
+fn check_space(
+    args: &OrganizeArgs,
+    mut organizer: Organizer,
+    db: Option<&DB>,
+    files: impl Iterator<Item = (PathBuf, Option<String>)>,
+) -> Option<Organizer> {
+    if args.dry_run
+        || args.space.when_full == WhenFull::Ignore
+        || !matches!(
+            organizer.strategy(),
+            LinkStrategy::Copy | LinkStrategy::Move
+        )
+    {
+        return Some(organizer);
+    }
+    let placed = match db.map(|db| {
+        db.lock()
+            .find_placed_hashes(&organizer.destinations(), args.inspect.hash_algo.name())
+    }) {
+        Some(Ok(placed)) => placed,
+        Some(Err(err)) => {
+            warn!("failed to read placed files: {}", err);
+            HashMap::new()
+        }
+        None => HashMap::new(),
+    };
+    let required = required_space(files, &organizer, &placed);
+    let mut file_systems: Vec<(Vec<PathBuf>, u64)> = Vec::new();
+    for (root, required) in required {
+        match file_systems
+            .iter_mut()
+            .find(|(roots, _)| space::same_filesystem(&roots[0], &root))
+        {
+            Some((roots, total)) => {
+                roots.push(root);
+                *total += required;
+            }
+            None => file_systems.push((vec![root], required)),
+        }
+    }
+    for (roots, required) in file_systems {
+        if let Some(budget) = args.space.check(&roots[0], required)? {
+            for root in roots {
+                organizer = organizer.budget(root, budget.clone());
+            }
+        }
+    }
+    Some(organizer)
+}
+
+/// Bytes placing `files` writes to each local destination root: the size
+/// of every file the strategy copies there, leaving out files whose
+/// contents the destinations hold already according to `placed`, and all
+/// but one file of every hash. The root of a file is that of its category,
+/// guessed from its name.
+fn required_space(
+    files: impl Iterator<Item = (PathBuf, Option<String>)>,
+    organizer: &Organizer,
+    placed: &HashMap<String, PathBuf>,
+) -> HashMap<PathBuf, u64> {
+    let mut counted = HashSet::new();
+    let mut required = HashMap::new();
+    for (path, hash) in files {
+        if let Some(hash) = hash {
+            if placed.contains_key(&hash) || !counted.insert(hash) {
+                continue;
+            }
+        }
+        let category = Category::of(&extractor::extract_mimetype(&path))
+            .map(Category::name)
+            .unwrap_or_default();
+        let root = organizer.destination_of(category);
+        if rclone::remote_path(root).is_some() || !space::writes(organizer.strategy(), &path, root)
+        {
+            continue;
+        }
+        if let Ok(metadata) = fs::metadata(&path) {
+            *required.entry(root.to_owned()).or_default() += metadata.len();
+        }
+    }
+    required
+}
+
+/// Prints the actions of a dry run, or writes them to `json_path`.
+fn write_plan(dry_run: plan::DryRun, json_path: Option<&Path>) {
+    let actions = dry_run.into_actions();
+    match json_path {
+        Some(json_path) => {
+            if let Err(err) = plan::write_json(&actions, json_path) {
+                error!(
+                    "failed to write dry run plan to {}: {}",
+                    json_path.to_string_lossy(),
+                    err
+                );
+            }
+        }
+        None => plan::print_actions(&actions),
+    }
+}
+
+/// Places one file, recording where it went in `db` when the file was
+/// scanned before and logging moves to its undo log. With `placed`, the
+/// file is scanned into `db` first and not placed again when the
+/// destination holds its contents.
+fn organize_file(
+    path: &Path,
+    inspector: &Inspector,
+    organizer: &Organizer,
+    db: Option<(&DB, UndoLog)>,
+    placed: Option<&Placed>,
+    dry_run: Option<&plan::DryRun>,
+    progress: &Progress,
+) -> bool {
+    // placed along with their media
+    if organizer.skips(path) {
+        progress.advance();
+        return true;
+    }
+    let inspected = match (placed, db) {
+        (Some(placed), Some((db, _))) => placed.scan(path, db),
+        _ => inspector.inspect(path),
+    };
+    let media = match inspected {
+        Ok(media) => media,
+        Err(DeduperError::TimestampMissing) => {
+            return organize_unknown(path, inspector, organizer, db, dry_run, progress);
+        }
+        Err(err) => {
+            let db = db.map(|(db, _)| db);
+            return failed(path, Inspector::stage_of(&err), &err, db, progress);
+        }
+    };
+    print_timestamp_source(progress, &media);
+    if let (Some(placed), Some((db, undo))) = (placed, db) {
+        if let Some(dest_path) = placed.find(path, &media) {
+            return placed.ingested(path, &media, dest_path, db, undo, progress);
+        }
+    }
+    place_media(path, &media, organizer, db, dry_run, progress)
+}
+
+/// The contents the destination holds already, by hash, for
+/// `--skip-placed` and `--purge-ingested`.
+struct Placed {
+    scanner: Scanner,
+    dest_paths: HashMap<String, PathBuf>,
+    purge: bool,
+    verify: Verify,
+}
+
+/// How media is checked against its copy before it is purged, so a stale
+/// row or a damaged copy cannot lose it.
+#[derive(Clone, Copy)]
+enum Verify {
+    /// The copy is taken as recorded.
+    None,
+    /// The copy has the size of the media and hashes to its hash.
+    Hash,
+    /// The copy has the same bytes, which also rules out a hash collision.
+    Bytes,
+}
+
+impl Placed {
+    /// Reads what the destination holds from `db`; problems are logged and
+    /// give `None`.
+    fn load(db: &DB, organizer: &Organizer, inspector: &Inspector, purge: bool) -> Option<Self> {
+        let dest_paths = db
+            .read()
+            .find_placed_hashes(&organizer.destinations(), inspector.algorithm().name());
+        let dest_paths = match dest_paths {
+            Ok(dest_paths) => dest_paths,
+            Err(err) => {
+                error!("failed to read what the destination holds: {}", err);
+                return None;
+            }
+        };
+        info!(
+            "the destination holds {} recorded contents",
+            dest_paths.len()
+        );
+        Some(Self {
+            scanner: Scanner::new(inspector.clone()).volumes(Volumes::detect()),
+            dest_paths,
+            purge,
+            verify: Verify::Hash,
+        })
+    }
+
+    /// Records `path` in `db` as `scan` does, so media added now is known
+    /// to the next run once placed.
+    fn scan(&self, path: &Path, db: &DB) -> Result<Media> {
+        match self.scanner.scan_file(path, db)? {
+            ScanOutcome::Recorded(media) => Ok(media),
+            ScanOutcome::Unchanged(file) => Media::recorded(&file),
+        }
+    }
+
+    /// Where the destination holds the contents of `media`, unless that is
+    /// `path` itself or a symlink to it. A destination file that is gone
+    /// holds nothing; one on an rclone remote is asked for.
+    fn find(&self, path: &Path, media: &Media) -> Option<&Path> {
+        let dest_path = self.dest_paths.get(&media.hash.digest)?;
+        if let Some(remote) = rclone::remote_path(dest_path) {
+            return rclone::size(remote)
+                .ok()
+                .flatten()
+                .map(|_| dest_path.as_path());
+        }
+        let target = platform::canonicalize(dest_path).ok()?;
+        (platform::canonicalize(path).ok()? != target).then_some(dest_path.as_path())
+    }
+
+    /// Leaves media the destination holds at `dest_path` alone, or with
+    /// `--purge-ingested` deletes it and its row, logging the deletion so
+    /// `undo` can copy it back from `dest_path`. Media whose copy fails
+    /// [`Verify`] is kept.
+    fn ingested(
+        &self,
+        path: &Path,
+        media: &Media,
+        dest_path: &Path,
+        db: &DB,
+        undo: UndoLog,
+        progress: &Progress,
+    ) -> bool {
+        if !self.purge {
+            progress.debug(format!(
+                "skipped {}, already placed at {}",
+                path.to_string_lossy(),
+                dest_path.to_string_lossy()
+            ));
+            progress.unchanged(&media.hash.digest);
+            return true;
+        }
+        let verified = match self.verify {
+            Verify::None => Ok(()),
+            Verify::Hash => verify_hash(path, media, dest_path),
+            Verify::Bytes if rclone::remote_path(dest_path).is_some() => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "--paranoid compares bytes only with local copies, not rclone remotes",
+            )
+            .into()),
+            Verify::Bytes => match hasher::same_contents(path, dest_path) {
+                Ok(true) => Ok(()),
+                Ok(false) => Err(DeduperError::ContentsDiffer(
+                    path.to_owned(),
+                    dest_path.to_owned(),
+                )),
+                Err(err) => Err(err.into()),
+            },
+        };
+        if let Err(err) = verified {
+            return failed(path, FileError::ORGANIZE, &err, Some(db), progress);
+        }
+        if let Err(err) = fs::remove_file(path) {
+            return failed(path, FileError::ORGANIZE, &err.into(), Some(db), progress);
+        }
+        let db = db.lock();
+        if let Err(err) = db.delete_file(path) {
+            progress.warn(format!(
+                "failed to forget {}: {}",
+                path.to_string_lossy(),
+                err
+            ));
+        }
+        let logged = undo.record(
+            &db,
+            UndoAction::Deleted,
+            path,
+            Some(dest_path),
+            &media.hash.digest,
+            media.hash.algorithm.name(),
+        );
+        if let Err(err) = logged {
+            progress.warn(format!(
+                "failed to log the deletion of {}: {}",
+                path.to_string_lossy(),
+                err
+            ));
+        }
+        progress.info(format!(
+            "deleted {}, already placed at {}",
+            path.to_string_lossy(),
+            dest_path.to_string_lossy()
+        ));
+        progress.record(&media.hash.digest, 0);
+        true
+    }
+}
+
+/// Whether `dest_path` is still a copy of `media` at `path`: the same size
+/// and, hashed again, the same hash. A copy on an rclone remote is
+/// streamed to be hashed.
+fn verify_hash(path: &Path, media: &Media, dest_path: &Path) -> Result<()> {
+    let mismatch = || DeduperError::HashMismatch(dest_path.to_owned());
+    let remote = rclone::remote_path(dest_path);
+    let dest_size = match remote {
+        Some(remote) => rclone::size(remote)?,
+        None => Some(fs::metadata(dest_path)?.len()),
+    };
+    if dest_size != Some(fs::metadata(path)?.len()) {
+        return Err(mismatch());
+    }
+    let hash = match remote {
+        Some(remote) => rclone::file_hash(remote, media.hash.algorithm)?,
+        None => hasher::file_hash(dest_path, media.hash.algorithm)?,
+    };
+    if !hash.matches(&media.hash.digest) {
+        return Err(mismatch());
+    }
+    Ok(())
+}
+
+/// Places `media` found at `path` with its sidecars, or records where it
+/// would go on a dry run.
+fn place_media(
+    path: &Path,
+    media: &Media,
+    organizer: &Organizer,
+    db: Option<(&DB, UndoLog)>,
+    dry_run: Option<&plan::DryRun>,
+    progress: &Progress,
+) -> bool {
+    if let Some(dry_run) = dry_run {
+        progress.record(&media.hash.digest, media.size);
+        let destination = organizer.destination_for(media);
+        dry_run.record(path, destination.clone());
+        record_sidecars(dry_run, organizer, path, &media.mime_type, &destination);
+        return true;
+    }
+    match organizer.place(media) {
+        Ok(placement) if placement.existed => {
+            progress.debug(format!(
+                "{} already holds {}",
+                placement.path.to_string_lossy(),
+                path.to_string_lossy()
+            ));
+            if let Some((db, _)) = db {
+                record_dest_path(db, path, &placement.path, progress);
+            }
+            place_sidecars(
+                organizer,
+                path,
+                &media.mime_type,
+                &placement.path,
+                db,
+                progress,
+            );
+            progress.record(&media.hash.digest, media.size);
+        }
+        Ok(placement) => {
+            let destination = &placement.path;
+            if let Some(taken) = &placement.taken {
+                let db = db.map(|(db, _)| db);
+                note_conflict(db, path, taken, destination, &media.hash.digest, progress);
+            }
+            if let Some((db, undo)) = db {
+                record_dest_path(db, path, destination, progress);
+                if organizer.strategy() == LinkStrategy::Move {
+                    log_move(db, undo, path, &media.hash, destination, progress);
+                }
+            }
+            place_sidecars(organizer, path, &media.mime_type, destination, db, progress);
+            progress.record(&media.hash.digest, media.size)
+        }
+        Err(err) => {
+            let db = db.map(|(db, _)| db);
+            return failed(path, FileError::ORGANIZE, &err, db, progress);
+        }
+    }
+    true
+}
+
+/// Logs that `path`, of the contents `hash`, was moved to `destination`,
+/// so `undo` can move it back.
+pub(super) fn log_move(
+    db: &DB,
+    undo: UndoLog,
+    path: &Path,
+    hash: &FileHash,
+    destination: &Path,
+    progress: &Progress,
+) {
+    let logged = undo.record(
+        &db.lock(),
+        UndoAction::Moved,
+        path,
+        Some(destination),
+        &hash.digest,
+        hash.algorithm.name(),
+    );
+    if let Err(err) = logged {
+        progress.warn(format!(
+            "failed to log the move of {}: {}",
+            path.to_string_lossy(),
+            err
+        ));
+    }
+}
+
+/// Reports that `stage` failed on `path` with `err`, recording the failure
+/// in `db` if any, and returns `false`.
+fn failed(
+    path: &Path,
+    stage: &str,
+    err: &DeduperError,
+    db: Option<&DB>,
+    progress: &Progress,
+) -> bool {
+    if let Some(db) = db {
+        errors::record(db, stage, path, None, err, scanner::stat(path), progress);
+    }
+    progress.fail(path, err);
+    false
+}
+
+/// Media that no source could date goes to the unknown directory rather
+/// than being dropped; a move is logged to the undo log with `db`.
+pub(super) fn organize_unknown(
+    path: &Path,
+    inspector: &Inspector,
+    organizer: &Organizer,
+    db: Option<(&DB, UndoLog)>,
+    dry_run: Option<&plan::DryRun>,
+    progress: &Progress,
+) -> bool {
+    let hashed = fs::metadata(path).and_then(|metadata| {
+        Ok((
+            hasher::file_hash(path, inspector.algorithm())?,
+            metadata.len(),
+        ))
+    });
+    let (hash, size) = match hashed {
+        Ok(hashed) => hashed,
+        Err(err) => {
+            let db = db.map(|(db, _)| db);
+            return failed(path, "hash", &err.into(), db, progress);
+        }
+    };
+    progress.debug(format!(
+        "no timestamp for {}, placing it with the unknown files",
+        path.to_string_lossy()
+    ));
+
+    let mime_type = extractor::extract_mimetype(path);
+    if let Some(dry_run) = dry_run {
+        progress.record(&hash.digest, size);
+        let destination = organizer.unknown_destination_for(path, &hash);
+        dry_run.record(path, destination.clone());
+        record_sidecars(dry_run, organizer, path, &mime_type, &destination);
+        return true;
+    }
+    match organizer.place_unknown(path, &hash) {
+        Ok(placement) => {
+            if !placement.existed {
+                if let Some(taken) = &placement.taken {
+                    let db = db.map(|(db, _)| db);
+                    note_conflict(db, path, taken, &placement.path, &hash.digest, progress);
+                }
+                if let (Some((db, undo)), LinkStrategy::Move) = (db, organizer.strategy()) {
+                    log_move(db, undo, path, &hash, &placement.path, progress);
+                }
+            }
+            place_sidecars(organizer, path, &mime_type, &placement.path, db, progress);
+            progress.record(&hash.digest, size)
+        }
+        Err(err) => {
+            let db = db.map(|(db, _)| db);
+            return failed(path, FileError::ORGANIZE, &err, db, progress);
+        }
+    }
+    true
+}
+
+/// Places the sidecars of `path`, which went to `destination`, next to it,
+/// logging those moved to the undo log with `db`.
+pub(super) fn place_sidecars(
+    organizer: &Organizer,
+    path: &Path,
+    mime_type: &Mime,
+    destination: &Path,
+    db: Option<(&DB, UndoLog)>,
+    progress: &Progress,
+) {
+    match organizer.place_sidecars(path, mime_type, destination) {
+        Ok(placed) => {
+            if let (Some((db, undo)), LinkStrategy::Move) = (db, organizer.strategy()) {
+                log_moves(db, undo, &placed, progress);
+            }
+        }
+        Err(err) => progress.warn(format!(
+            "failed to place sidecars of {}: {}",
+            path.to_string_lossy(),
+            err
+        )),
+    }
+}
+
+/// Logs the moves of files that are not media themselves, such as
+/// sidecars, each hashed at where it went.
+pub(super) fn log_moves(db: &DB, undo: UndoLog, moves: &[(PathBuf, PathBuf)], progress: &Progress) {
+    for (path, destination) in moves {
+        match hasher::file_hash(destination, HashAlgorithm::default()) {
+            Ok(hash) => log_move(db, undo, path, &hash, destination, progress),
+            Err(err) => progress.warn(format!(
+                "failed to log the move of {}: {}",
+                path.to_string_lossy(),
+                err
+            )),
+        }
+    }
+}
+
+fn record_sidecars(
+    dry_run: &plan::DryRun,
+    organizer: &Organizer,
+    path: &Path,
+    mime_type: &Mime,
+    destination: &Path,
+) {
+    for (sidecar, sidecar_dest) in organizer.sidecar_destinations(path, mime_type, destination) {
+        dry_run.record(&sidecar, sidecar_dest);
+    }
+}
+
+pub(super) fn record_dest_path(db: &DB, path: &Path, destination: &Path, progress: &Progress) {
+    let recorded = db.lock().set_dest_path(path, destination);
+    if let Err(err) = recorded {
+        progress.warn(format!(
+            "failed to record destination of {}: {}",
+            path.to_string_lossy(),
+            err
+        ));
+    }
+}
+
+/// Warns that `path` went to `destination` because `taken`, the name the
+/// layout gave it, holds other contents, and records the conflict in `db`
+/// for `conflicts list`.
+pub(super) fn note_conflict(
+    db: Option<&DB>,
+    path: &Path,
+    taken: &Path,
+    destination: &Path,
+    hash: &str,
+    progress: &Progress,
+) {
+    progress.warn(format!(
+        "{} holds other contents than {}, placed it at {}",
+        taken.to_string_lossy(),
+        path.to_string_lossy(),
+        destination.to_string_lossy()
+    ));
+    let Some(db) = db else {
+        return;
+    };
+    let conflict = Conflict {
+        path: destination.to_owned(),
+        source: path.to_owned(),
+        taken: taken.to_owned(),
+        hash: hash.to_owned(),
+        at: Utc::now().timestamp(),
+    };
+    if let Err(err) = db.lock().record_conflict(&conflict) {
+        progress.warn(format!(
+            "failed to record the conflict of {}: {}",
+            path.to_string_lossy(),
+            err
+        ));
+    }
+}
+
+/// Lays out the destination from the rows of earlier scans alone, so a
+/// new layout or destination does not need the sources hashed again.
+fn from_database(args: &OrganizeArgs, output: &OutputArgs) -> Summary {
+    args.placement.log_destinations();
+    let Some(organizer) = args.placement.organizer() else {
+        return Summary::aborted();
+    };
+    let Some(db) = open_database(args.database()) else {
+        return Summary::aborted();
+    };
+    let files = match db.lock().find_unique_files() {
+        Ok(files) => files,
+        Err(err) => {
+            error!("failed to read recorded files: {}", err);
+            return Summary::aborted();
+        }
+    };
+    let undo = match UndoLog::start(&db.lock(), "organize") {
+        Ok(undo) => undo,
+        Err(err) => {
+            error!("failed to start the undo log: {}", err);
+            return Summary::aborted();
+        }
+    };
+    let included = files
+        .iter()
+        .filter(|file| {
+            Media::recorded(file).is_ok_and(|media| {
+                args.inspect
+                    .include_types
+                    .iter()
+                    .any(|category| category.name() == media.category)
+            })
+        })
+        .map(|file| (file.path.clone(), Some(file.hash.clone())));
+    let Some(organizer) = check_space(args, organizer, Some(&db), included) else {
+        return Summary::aborted();
+    };
+    let with_group = args.placement.layout().uses(|token| token == Token::Group);
+    let dry_run = args.dry_run.then(plan::DryRun::default);
+    let progress = Arc::new(Progress::new(output, || files.len()));
+    let organizer = organizer.events(progress.clone());
+    thread_pool(args.jobs).install(|| {
+        files.par_iter().for_each(|file| {
+            let path = file.path.as_path();
+            let mut media = match Media::recorded(file) {
+                Ok(media) => media,
+                Err(err) => return progress.fail(path, &err),
+            };
+            let included = args
+                .inspect
+                .include_types
+                .iter()
+                .any(|category| category.name() == media.category);
+            if !included || organizer.skips(path) {
+                return progress.advance();
+            }
+            if !path.exists() {
+                progress.warn(format!(
+                    "{} is gone since it was scanned, skipping it",
+                    file.path.to_string_lossy()
+                ));
+                return progress.advance();
+            }
+            if with_group {
+                media.group = group::group_of(path, &media.mime_type);
+            }
+            place_media(
+                path,
+                &media,
+                &organizer,
+                Some((&db, undo)),
+                dry_run.as_ref(),
+                &progress,
+            );
+        });
+    });
+    progress.finish();
+    if let Err(err) = undo.finish(&db.lock()) {
+        warn!("failed to finish the undo log: {}", err);
+    }
+    if let Some(dry_run) = dry_run {
+        write_plan(dry_run, args.dry_run_json.as_deref());
+    }
+    progress.summary()
+}
+
+/// Recreates the recorded links below the destination that are missing or
+/// point at nothing, from sources that are still there.
+fn repair(args: &OrganizeArgs, output: &OutputArgs) -> Summary {
+    let Some(organizer) = args.placement.organizer() else {
+        return Summary::aborted();
+    };
+    let Some(db) = open_database(args.database()) else {
+        return Summary::aborted();
+    };
+    let placements = match db.lock().find_placements() {
+        Ok(placements) => placements,
+        Err(err) => {
+            error!("failed to read destinations: {}", err);
+            return Summary::aborted();
+        }
+    };
+    let placements = placements
+        .into_iter()
+        .filter(|placement| organizer.root_of(&placement.dest_path).is_some())
+        .collect::<Vec<_>>();
+    let progress = Arc::new(Progress::new(output, || placements.len()));
+    let organizer = organizer.events(progress.clone());
+    let mut repaired = 0;
+    for placement in &placements {
+        let source = placement.path.as_path();
+        match organizer.repair(source, &placement.dest_path) {
+            Ok(true) => {
+                progress.info(format!(
+                    "relinked {}",
+                    placement.dest_path.to_string_lossy()
+                ));
+                repaired += 1;
+                progress.done();
+            }
+            Ok(false) => progress.advance(),
+            Err(err) => progress.fail(source, &err),
+        }
+    }
+    progress.clear();
+    println!(
+        "repaired {} of {} recorded links",
+        repaired,
+        placements.len()
+    );
+    progress.summary()
+}
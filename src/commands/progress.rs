@@ -0,0 +1,417 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Display,
+    fs::File,
+    io::{self, BufWriter},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+
+use clap::{ArgAction, Args};
+use deduper::{database, media::Media, DeduperError, EventSink};
+use indicatif::{
+    HumanBytes, HumanDuration, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle,
+};
+use serde::Serialize;
+use tracing::{debug, enabled, info, warn, Level};
+
+use super::logging::LogLevel;
+
+const TEMPLATE: &str =
+    "{spinner} [{elapsed_precise}] {wide_bar} {pos}/{len} files ({per_sec}, ETA {eta}) {msg}";
+
+/// Bar of a single long task, counted in per mille.
+const TASK_TEMPLATE: &str = "  {prefix:30!} {bar:40} {percent:>3}% {msg}";
+
+#[derive(Args)]
+pub struct OutputArgs {
+    /// Only print the final summary and warnings
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+    /// Also log every file that was skipped or needed attention; twice to
+    /// log everything
+    #[arg(short, long, action = ArgAction::Count, global = true, conflicts_with = "quiet")]
+    pub verbose: u8,
+    /// Log level, instead of the one `-v` or `--quiet` sets
+    #[arg(long, value_enum, global = true)]
+    pub log_level: Option<LogLevel>,
+    /// Append the log with timestamps to this file instead of standard error
+    #[arg(long, global = true, value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+    pub log_file: Option<PathBuf>,
+    /// Do not draw a progress bar
+    #[arg(long, global = true)]
+    pub no_progress: bool,
+    /// Write counts of processed, skipped, failed and duplicate files and
+    /// the exit status of the run as JSON to this file
+    #[arg(long, global = true, value_name = "PATH", value_hint = clap::ValueHint::FilePath)]
+    pub summary_json: Option<PathBuf>,
+}
+
+/// How a command ended, which is also its exit code. When several apply,
+/// failures win over duplicates, and duplicates over having nothing to do.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Status {
+    #[default]
+    Done = 0,
+    /// Some files failed; the others were processed
+    Failed = 1,
+    /// The command could not run, e.g. the database did not open
+    Aborted = 2,
+    /// Duplicates were found and left in place
+    Duplicates = 3,
+    /// There were no files to process
+    NothingToDo = 4,
+}
+
+impl From<Status> for ExitCode {
+    fn from(status: Status) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
+
+/// Counts of a run for `--summary-json`. Files of unsupported media are
+/// counted as skipped rather than failed.
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    pub status: Status,
+    pub processed: u64,
+    pub skipped: u64,
+    pub failed: u64,
+    pub duplicates: u64,
+    pub bytes_hashed: u64,
+    /// Files that were not processed, by kind of failure
+    pub failures: BTreeMap<&'static str, usize>,
+}
+
+impl Summary {
+    /// Summary of a command that could not run.
+    pub fn aborted() -> Self {
+        Self {
+            status: Status::Aborted,
+            ..Self::default()
+        }
+    }
+
+    /// Summary of a command that listed `rows`, which found nothing to do
+    /// when there were none.
+    pub fn listed(rows: usize) -> Self {
+        Self {
+            processed: rows as u64,
+            ..Self::default()
+        }
+        .settled()
+    }
+
+    /// Counts a file that failed with `err`.
+    pub fn fail(&mut self, err: &DeduperError) {
+        self.failed += 1;
+        *self.failures.entry(err.kind()).or_default() += 1;
+    }
+
+    /// Adds the counts of another step of the same run.
+    pub fn add(&mut self, other: Summary) {
+        self.processed += other.processed;
+        self.skipped += other.skipped;
+        self.failed += other.failed;
+        self.duplicates += other.duplicates;
+        self.bytes_hashed += other.bytes_hashed;
+        for (kind, count) in other.failures {
+            *self.failures.entry(kind).or_default() += count;
+        }
+    }
+
+    /// Sets the status the counts amount to.
+    pub fn settled(mut self) -> Self {
+        self.status = if self.failed > 0 {
+            Status::Failed
+        } else if self.duplicates > 0 {
+            Status::Duplicates
+        } else if self.processed == 0 {
+            Status::NothingToDo
+        } else {
+            Status::Done
+        };
+        self
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+}
+
+/// Progress bar and running totals of a pass over many files. Per-file
+/// messages are logged above the bar, and failures are tallied by kind so
+/// the pass can end with a summary instead of leaving them scattered.
+pub struct Progress {
+    bars: MultiProgress,
+    bar: ProgressBar,
+    bytes: AtomicU64,
+    processed: AtomicU64,
+    skipped: AtomicU64,
+    unsupported: AtomicU64,
+    hashes: Mutex<HashSet<String>>,
+    duplicates: AtomicU64,
+    failures: Mutex<BTreeMap<&'static str, usize>>,
+}
+
+impl Progress {
+    /// `count` pre-counts the files of the pass; it is only called when a
+    /// bar is drawn, so `--quiet` and `--no-progress` skip the extra walk.
+    pub fn new(output: &OutputArgs, count: impl FnOnce() -> usize) -> Self {
+        if output.quiet || output.no_progress {
+            return Self::hidden();
+        }
+        let bars = MultiProgress::new();
+        let bar = bars.add(
+            ProgressBar::new(count() as u64)
+                .with_style(ProgressStyle::with_template(TEMPLATE).expect("valid template")),
+        );
+        bar.enable_steady_tick(Duration::from_millis(200));
+        Self::with_bar(bars, bar)
+    }
+
+    /// Sets the length of the bar, for passes that learn it once started.
+    pub fn set_length(&self, len: usize) {
+        self.bar.set_length(len as u64);
+    }
+
+    /// Progress without a bar, for passes whose length is not known up
+    /// front or that never end.
+    pub fn hidden() -> Self {
+        Self::with_bar(
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden()),
+            ProgressBar::hidden(),
+        )
+    }
+
+    fn with_bar(bars: MultiProgress, bar: ProgressBar) -> Self {
+        Self {
+            bars,
+            bar,
+            bytes: AtomicU64::new(0),
+            processed: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+            unsupported: AtomicU64::new(0),
+            hashes: Mutex::new(HashSet::new()),
+            duplicates: AtomicU64::new(0),
+            failures: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Counts a finished file with content `hash`, of which `hashed_bytes`
+    /// were read in this pass (zero when the recorded hash was reused).
+    pub fn record(&self, hash: &str, hashed_bytes: u64) {
+        self.count_hash(hash);
+        self.hashed(hashed_bytes);
+    }
+
+    /// Counts a file with content `hash` that was left as recorded, such
+    /// as one unchanged since the last scan.
+    pub fn unchanged(&self, hash: &str) {
+        self.count_hash(hash);
+        self.bar.set_message(self.totals());
+        self.advance();
+    }
+
+    fn count_hash(&self, hash: &str) {
+        if !self.hashes.lock().unwrap().insert(hash.to_owned()) {
+            self.duplicates.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Counts a finished file of which `bytes` were hashed.
+    pub fn hashed(&self, bytes: u64) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.bar.set_message(self.totals());
+        self.done();
+    }
+
+    /// Counts a finished file that was worked on without being hashed.
+    pub fn done(&self) {
+        self.processed.fetch_add(1, Ordering::Relaxed);
+        self.bar.inc(1);
+    }
+
+    /// Counts a file that needed no work, e.g. one a resumed run already
+    /// did.
+    pub fn advance(&self) {
+        self.skipped.fetch_add(1, Ordering::Relaxed);
+        self.bar.inc(1);
+    }
+
+    /// Logs a per-file message above the bar, at debug level so a normal
+    /// run only shows the bar and the summary.
+    pub fn debug(&self, message: impl Display) {
+        if enabled!(Level::DEBUG) {
+            self.bars.suspend(|| debug!("{}", message));
+        }
+    }
+
+    /// Logs a per-file result the pass is run for, such as a relinked
+    /// symlink.
+    pub fn info(&self, message: impl Display) {
+        if enabled!(Level::INFO) {
+            self.bars.suspend(|| info!("{}", message));
+        }
+    }
+
+    /// Logs a problem with a file that the summary alone would not
+    /// explain, such as one that no longer matches its recorded hash.
+    pub fn warn(&self, message: impl Display) {
+        if enabled!(Level::WARN) {
+            self.bars.suspend(|| warn!("{}", message));
+        }
+    }
+
+    /// Runs `f` with the bars hidden, for asking a question on the terminal.
+    pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.bars.suspend(f)
+    }
+
+    /// A bar of its own for a long task within the pass, such as encoding
+    /// one video, counting to 1000. Finish it with `finish_and_clear`.
+    pub fn task(&self, name: &str) -> ProgressBar {
+        if self.bar.is_hidden() {
+            return ProgressBar::hidden();
+        }
+        self.bars.add(
+            ProgressBar::new(1000)
+                .with_style(ProgressStyle::with_template(TASK_TEMPLATE).expect("valid template"))
+                .with_prefix(name.to_owned()),
+        )
+    }
+
+    /// Explains why `path` was not processed and counts it.
+    pub fn fail(&self, path: &Path, err: &DeduperError) {
+        self.debug(format!("skipping {}: {}", path.to_string_lossy(), err));
+        if matches!(err, DeduperError::UnsupportedMedia(_)) {
+            self.unsupported.fetch_add(1, Ordering::Relaxed);
+        }
+        self.failed(err.kind());
+    }
+
+    /// Counts a file that failed with an error of `kind` the pass already
+    /// reported.
+    pub fn failed(&self, kind: &'static str) {
+        *self.failures.lock().unwrap().entry(kind).or_default() += 1;
+        self.bar.inc(1);
+    }
+
+    /// Reports an entry the walk could not read, or a symlink loop.
+    pub fn walk_failed(&self, err: walkdir::Error) {
+        let path = err.path().map(Path::to_owned).unwrap_or_default();
+        self.fail(&path, &io::Error::from(err).into());
+    }
+
+    /// Clears the bar without a summary, for passes that print their own.
+    pub fn clear(&self) {
+        self.bar.finish_and_clear();
+    }
+
+    /// Clears the bar and prints the totals of the pass.
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+        let elapsed = self.bar.elapsed();
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        println!(
+            "{} in {} ({}/s)",
+            self.totals(),
+            HumanDuration(elapsed),
+            HumanBytes((bytes as f64 / elapsed.as_secs_f64().max(0.001)) as u64)
+        );
+        let failures = self.failures.lock().unwrap();
+        if failures.is_empty() {
+            return;
+        }
+        println!(
+            "skipped {} files: {}",
+            failures.values().sum::<usize>(),
+            failures
+                .iter()
+                .map(|(kind, count)| format!("{} {}", count, kind))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+
+    /// The counts of the pass so far, and the status they amount to.
+    pub fn summary(&self) -> Summary {
+        let failures = self.failures.lock().unwrap().clone();
+        let unsupported = self.unsupported.load(Ordering::Relaxed);
+        Summary {
+            status: Status::Done,
+            processed: self.processed.load(Ordering::Relaxed),
+            skipped: self.skipped.load(Ordering::Relaxed) + unsupported,
+            failed: failures.values().sum::<usize>() as u64 - unsupported,
+            duplicates: self.duplicates.load(Ordering::Relaxed),
+            bytes_hashed: self.bytes.load(Ordering::Relaxed),
+            failures,
+        }
+        .settled()
+    }
+
+    fn totals(&self) -> String {
+        let hashed = format!("{} hashed", HumanBytes(self.bytes.load(Ordering::Relaxed)));
+        if self.hashes.lock().unwrap().is_empty() {
+            return hashed;
+        }
+        format!(
+            "{}, {} duplicates",
+            hashed,
+            self.duplicates.load(Ordering::Relaxed)
+        )
+    }
+}
+
+/// The command line prints what the library reports above the bar, and
+/// counts hashed files and failures in the summary.
+impl EventSink for Progress {
+    fn hashed(&self, media: &Media) {
+        super::print_timestamp_source(self, media);
+        self.record(&media.hash.digest, media.size);
+    }
+
+    fn duplicate_found(&self, original: &database::File, duplicate: &database::File) {
+        self.debug(format!(
+            "{} duplicates {}",
+            duplicate.path.to_string_lossy(),
+            original.path.to_string_lossy()
+        ));
+    }
+
+    fn removed(&self, original: &database::File, duplicate: &database::File) {
+        self.debug(format!(
+            "removed {}, a copy of {}",
+            duplicate.path.to_string_lossy(),
+            original.path.to_string_lossy()
+        ));
+    }
+
+    fn linked(&self, path: &Path, target: &Path) {
+        self.debug(format!(
+            "linked {} to {}",
+            path.to_string_lossy(),
+            target.to_string_lossy()
+        ));
+    }
+
+    fn transcoded(&self, path: &Path, before: u64, after: u64) {
+        self.info(format!(
+            "transcoded {}: {} -> {}",
+            path.to_string_lossy(),
+            HumanBytes(before),
+            HumanBytes(after)
+        ));
+    }
+
+    fn error(&self, path: &Path, err: &DeduperError) {
+        self.fail(path, err);
+    }
+}
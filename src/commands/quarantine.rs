@@ -0,0 +1,131 @@
+use std::path::PathBuf;
+
+use chrono::{Days, Local};
+use clap::{Args, Subcommand};
+use deduper::quarantine;
+use tracing::{error, info};
+
+use super::{confirm, progress::Summary};
+
+#[derive(Args)]
+pub struct QuarantineArgs {
+    #[command(subcommand)]
+    pub command: QuarantineCommand,
+}
+
+#[derive(Subcommand)]
+pub enum QuarantineCommand {
+    /// Delete the folders of the days the review period has passed for
+    Purge(PurgeArgs),
+}
+
+#[derive(Args)]
+pub struct PurgeArgs {
+    /// Quarantine directory `dedupe --quarantine` moved duplicates into
+    #[arg(value_hint = clap::ValueHint::DirPath)]
+    pub dir: PathBuf,
+    /// Review period: folders of days longer ago than this are deleted,
+    /// in days (`30d`) or weeks (`4w`)
+    #[arg(long, value_name = "AGE", default_value = "30d", value_parser = parse_age)]
+    pub older_than: u64,
+    /// Print the folders that would be deleted without deleting them
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Do not ask for confirmation before deleting
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+pub fn run(args: &QuarantineArgs) -> Summary {
+    match &args.command {
+        QuarantineCommand::Purge(args) => purge(args),
+    }
+}
+
+/// Counts every deleted folder as processed.
+fn purge(args: &PurgeArgs) -> Summary {
+    let today = Local::now().date_naive();
+    let Some(before) = today.checked_sub_days(Days::new(args.older_than)) else {
+        error!("--older-than reaches back too far");
+        return Summary::aborted();
+    };
+    let folders = match quarantine::expired(&args.dir, before) {
+        Ok(folders) => folders,
+        Err(err) => {
+            error!(
+                "failed to read the quarantine {}: {}",
+                args.dir.to_string_lossy(),
+                err
+            );
+            return Summary::aborted();
+        }
+    };
+    if folders.is_empty() {
+        info!("nothing quarantined before {}", before);
+        return Summary::default().settled();
+    }
+    let (files, bytes) = folders
+        .iter()
+        .map(|(_, folder)| quarantine::usage(folder))
+        .fold((0, 0), |(files, bytes), usage| {
+            (files + usage.0, bytes + usage.1)
+        });
+    if args.dry_run {
+        for (date, folder) in &folders {
+            println!("would delete {} ({})", folder.to_string_lossy(), date);
+        }
+        println!(
+            "would delete {} files ({} bytes) quarantined before {}",
+            files, bytes, before
+        );
+        return Summary::default().settled();
+    }
+    if !args.yes
+        && !confirm(&format!(
+            "permanently delete {} files ({} bytes) quarantined before {}?",
+            files, bytes, before
+        ))
+    {
+        info!("aborted");
+        return Summary::aborted();
+    }
+
+    let mut summary = Summary::default();
+    let (mut files, mut bytes) = (0, 0);
+    for (_, folder) in &folders {
+        match quarantine::purge(folder) {
+            Ok(usage) => {
+                info!("deleted {}", folder.to_string_lossy());
+                files += usage.0;
+                bytes += usage.1;
+                summary.processed += 1;
+            }
+            Err(err) => {
+                error!("failed to delete {}: {}", folder.to_string_lossy(), err);
+                summary.fail(&err.into());
+            }
+        }
+    }
+    println!(
+        "deleted {} files ({} bytes) quarantined before {}",
+        files, bytes, before
+    );
+    summary.settled()
+}
+
+/// Days of `30d` or `4w`; a bare number is days.
+fn parse_age(s: &str) -> Result<u64, String> {
+    let (number, unit) = match s.strip_suffix(['d', 'w']) {
+        Some(number) => (number, &s[number.len()..]),
+        None => (s, "d"),
+    };
+    let number = number
+        .parse::<u64>()
+        .map_err(|err| format!("invalid age {}: {}", s, err))?;
+    match unit {
+        "w" => number
+            .checked_mul(7)
+            .ok_or_else(|| format!("invalid age {}: too many weeks", s)),
+        _ => Ok(number),
+    }
+}
@@ -0,0 +1,162 @@
+use std::path::{Path, PathBuf};
+
+use clap::Args;
+use deduper::{database::DB, group, layout::Token, media::Media, rclone, undo::UndoLog};
+use tracing::{error, warn};
+
+use super::{
+    open_database,
+    organize::{log_move, log_moves, record_dest_path},
+    progress::{OutputArgs, Progress, Summary},
+    PlacementArgs,
+};
+
+#[derive(Args)]
+pub struct RelayoutArgs {
+    #[command(flatten)]
+    pub placement: PlacementArgs,
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    /// Only report which files would move
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// Moves the files `organize` placed in the destination to where the
+/// current layout puts them, going by the recorded rows rather than the
+/// sources, and removes the directories left empty. Every move is logged
+/// for `undo`. Files on rclone remotes are left where they are.
+pub fn run(args: &RelayoutArgs, output: &OutputArgs) -> Summary {
+    let Some(organizer) = args.placement.organizer() else {
+        return Summary::aborted();
+    };
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    let placements = match db.lock().find_placements() {
+        Ok(placements) => placements,
+        Err(err) => {
+            error!("failed to read destinations: {}", err);
+            return Summary::aborted();
+        }
+    };
+    let (remote, placements): (Vec<_>, Vec<_>) = placements
+        .into_iter()
+        .filter(|placement| organizer.root_of(&placement.dest_path).is_some())
+        .partition(|placement| rclone::remote_path(&placement.dest_path).is_some());
+    if !remote.is_empty() {
+        warn!(
+            "leaving {} files on rclone remotes where they are, as they cannot be moved atomically",
+            remote.len()
+        );
+    }
+    let with_group = args.placement.layout().uses(|token| token == Token::Group);
+    let undo = if args.dry_run {
+        None
+    } else {
+        match UndoLog::start(&db.lock(), "relayout") {
+            Ok(undo) => Some(undo),
+            Err(err) => {
+                error!("failed to start the undo log: {}", err);
+                return Summary::aborted();
+            }
+        }
+    };
+    let progress = Progress::new(output, || placements.len());
+    let (mut moved, mut pruned) = (0, 0);
+    for placement in &placements {
+        let dest_path = placement.dest_path.as_path();
+        if !dest_path.is_symlink() && !dest_path.exists() {
+            progress.debug(format!(
+                "{} is gone, skipping it",
+                placement.dest_path.to_string_lossy()
+            ));
+            progress.advance();
+            continue;
+        }
+        let Some(media) = recorded_media(&placement.path, with_group, &db, &progress) else {
+            continue;
+        };
+        if args.dry_run {
+            match organizer.relocation_for(&media, dest_path) {
+                Some(target) => {
+                    progress.info(format!(
+                        "would move {} -> {}",
+                        placement.dest_path.to_string_lossy(),
+                        target.to_string_lossy()
+                    ));
+                    moved += 1;
+                    progress.done();
+                }
+                None => progress.advance(),
+            }
+            continue;
+        }
+        match organizer.relocate(&media, dest_path) {
+            Ok(Some(relocation)) => {
+                let new_dest_path = &relocation.path;
+                progress.info(format!(
+                    "moved {} -> {}",
+                    placement.dest_path.to_string_lossy(),
+                    new_dest_path.to_string_lossy()
+                ));
+                moved += 1;
+                progress.done();
+                record_dest_path(&db, &placement.path, new_dest_path, &progress);
+                if let Some(undo) = undo {
+                    log_move(&db, undo, dest_path, &media.hash, new_dest_path, &progress);
+                    log_moves(&db, undo, &relocation.sidecars, &progress);
+                }
+                if let Some(dir) = dest_path.parent() {
+                    pruned += organizer.prune_empty_dirs(dir);
+                }
+            }
+            Ok(None) => progress.advance(),
+            Err(err) => progress.fail(dest_path, &err),
+        }
+    }
+    progress.clear();
+    if let Some(undo) = undo {
+        if let Err(err) = undo.finish(&db.lock()) {
+            warn!("failed to finish the undo log: {}", err);
+        }
+    }
+    if args.dry_run {
+        println!("would move {} of {} placed files", moved, placements.len());
+    } else {
+        println!(
+            "moved {} of {} placed files, removed {} empty directories",
+            moved,
+            placements.len(),
+            pruned
+        );
+    }
+    progress.summary()
+}
+
+/// The media the row of `path` describes, with its group when the layout
+/// needs it.
+fn recorded_media(path: &Path, with_group: bool, db: &DB, progress: &Progress) -> Option<Media> {
+    let file = match db.read().find_file(path) {
+        Ok(Some(file)) => file,
+        Ok(None) => {
+            progress.advance();
+            return None;
+        }
+        Err(err) => {
+            progress.fail(Path::new(path), &err.into());
+            return None;
+        }
+    };
+    let mut media = match Media::recorded(&file) {
+        Ok(media) => media,
+        Err(err) => {
+            progress.fail(Path::new(path), &err);
+            return None;
+        }
+    };
+    if with_group {
+        media.group = group::group_of(&media.path, &media.mime_type);
+    }
+    Some(media)
+}
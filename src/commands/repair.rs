@@ -0,0 +1,217 @@
+use std::{
+    fs,
+    io::{self, IsTerminal, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use clap::Args;
+use deduper::{database::DB, linker, Organizer};
+use tracing::info;
+use walkdir::WalkDir;
+
+use super::{
+    open_database,
+    progress::{OutputArgs, Progress, Summary},
+};
+
+#[derive(Args)]
+pub struct RepairArgs {
+    /// Destination tree whose dangling symlinks are repaired
+    #[arg(short, long, value_hint = clap::ValueHint::DirPath)]
+    pub destination: PathBuf,
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    /// Where a source moved, as OLD=NEW: a link into OLD is pointed at the
+    /// same path below NEW
+    #[arg(long = "map", value_name = "OLD=NEW")]
+    pub mappings: Vec<SourceMapping>,
+    /// Only report which links would be repaired
+    #[arg(long)]
+    pub dry_run: bool,
+}
+
+/// A source root that moved.
+#[derive(Debug, Clone)]
+pub struct SourceMapping {
+    from: PathBuf,
+    to: PathBuf,
+}
+
+impl SourceMapping {
+    /// `target` moved along with the root, if it was below it.
+    fn apply(&self, target: &Path) -> Option<PathBuf> {
+        let rest = target.strip_prefix(&self.from).ok()?;
+        Some(self.to.join(rest))
+    }
+}
+
+impl FromStr for SourceMapping {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            Some((from, to)) if !from.is_empty() && !to.is_empty() => Ok(Self {
+                from: PathBuf::from(from),
+                to: PathBuf::from(to),
+            }),
+            _ => Err(format!("'{}' is not OLD=NEW", s)),
+        }
+    }
+}
+
+/// A symlink in the destination whose target is gone.
+struct Dangling {
+    link: PathBuf,
+    target: PathBuf,
+}
+
+/// Points dangling symlinks of the destination at where their source is
+/// now: below a mapped root, or any recorded file with the hash the link
+/// is named with. Links neither resolves are offered for another mapping
+/// when run from a terminal.
+pub fn run(args: &RepairArgs, output: &OutputArgs) -> Summary {
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    let mut dangling = dangling_symlinks(&args.destination);
+    if dangling.is_empty() {
+        info!("no dangling symlinks");
+        return Summary::default().settled();
+    }
+    let total = dangling.len();
+    let progress = Progress::new(output, || total);
+    let mut mappings = args.mappings.clone();
+    let mut repaired = 0;
+    loop {
+        dangling.retain(|link| {
+            let Some(source) = resolve(link, &mappings, &db) else {
+                return true;
+            };
+            if relink(link, &source, args.dry_run, &db, &progress) {
+                repaired += 1;
+            }
+            false
+        });
+        if dangling.is_empty() || !io::stdin().is_terminal() {
+            break;
+        }
+        let Some(mapping) = progress.suspend(|| ask_mapping(&dangling)) else {
+            break;
+        };
+        mappings.push(mapping);
+    }
+    for link in &dangling {
+        progress.warn(format!(
+            "unresolved: {} -> {}",
+            link.link.to_string_lossy(),
+            link.target.to_string_lossy()
+        ));
+        progress.advance();
+    }
+    progress.clear();
+    println!(
+        "{} {} of {} dangling symlinks",
+        if args.dry_run {
+            "would repair"
+        } else {
+            "repaired"
+        },
+        repaired,
+        total
+    );
+    progress.summary()
+}
+
+fn dangling_symlinks(destination: &Path) -> Vec<Dangling> {
+    WalkDir::new(destination)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path_is_symlink() && !entry.path().exists())
+        .filter_map(|entry| {
+            let link = entry.into_path();
+            let target = fs::read_link(&link).ok()?;
+            // relative targets are relative to the directory of the link
+            let target = link.parent().unwrap_or(Path::new("")).join(target);
+            Some(Dangling { link, target })
+        })
+        .collect()
+}
+
+/// The existing file `link` should point at: its target below a mapped
+/// root, or else a recorded file with the hash of its old target or the
+/// short hash of its name.
+fn resolve(link: &Dangling, mappings: &[SourceMapping], db: &DB) -> Option<PathBuf> {
+    if let Some(source) = mappings
+        .iter()
+        .filter_map(|mapping| mapping.apply(&link.target))
+        .find(|source| source.exists())
+    {
+        return Some(source);
+    }
+    let db = db.read();
+    let files = match db.find_file(&link.target).ok()? {
+        Some(file) => db.find_files_by_hash(&file.hash),
+        None => db.find_files_by_short_hash(Organizer::hash_in_name(&link.link)?),
+    };
+    files
+        .ok()?
+        .into_iter()
+        .map(|file| file.path)
+        .find(|source| source.exists())
+}
+
+fn relink(link: &Dangling, source: &Path, dry_run: bool, db: &DB, progress: &Progress) -> bool {
+    let message = format!(
+        "{} -> {}",
+        link.link.to_string_lossy(),
+        source.to_string_lossy()
+    );
+    if dry_run {
+        progress.info(format!("would relink {}", message));
+        progress.done();
+        return true;
+    }
+    if let Err(err) = linker::relink(source, &link.link) {
+        progress.fail(&link.link, &err.into());
+        return false;
+    }
+    progress.info(format!("relinked {}", message));
+    progress.done();
+    let recorded = db.lock().set_dest_path(source, &link.link);
+    if let Err(err) = recorded {
+        progress.warn(format!(
+            "failed to record destination of {}: {}",
+            source.to_string_lossy(),
+            err
+        ));
+    }
+    true
+}
+
+/// Asks for the new root of a source the remaining links point into.
+fn ask_mapping(dangling: &[Dangling]) -> Option<SourceMapping> {
+    let example = &dangling[0];
+    println!(
+        "{} symlinks still dangle, e.g. {} -> {}",
+        dangling.len(),
+        example.link.to_string_lossy(),
+        example.target.to_string_lossy()
+    );
+    loop {
+        print!("where did their source move, as OLD=NEW (empty to stop)? ");
+        let _ = io::stdout().flush();
+        let mut answer = String::new();
+        if io::stdin().read_line(&mut answer).ok()? == 0 {
+            return None;
+        }
+        let answer = answer.trim();
+        if answer.is_empty() {
+            return None;
+        }
+        match answer.parse() {
+            Ok(mapping) => return Some(mapping),
+            Err(err) => println!("{}", err),
+        }
+    }
+}
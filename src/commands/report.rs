@@ -0,0 +1,310 @@
+use std::{io, path::PathBuf};
+
+use clap::{Args, ValueEnum};
+use deduper::{
+    csv,
+    database::{CameraStats, DirectoryStats, LabelStats, LockDB, MediaTypeStats, ResolutionStats},
+    dedupe, videohash,
+};
+use serde::Serialize;
+use tracing::error;
+
+use super::{open_database, progress::Summary};
+
+#[derive(Args)]
+pub struct ReportArgs {
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    #[arg(long, value_enum, default_value_t)]
+    pub format: ReportFormat,
+    /// Average differing bits per sampled frame below which fingerprinted
+    /// videos are listed as probable duplicates
+    #[arg(long, default_value_t = videohash::DEFAULT_DISTANCE)]
+    pub video_distance: f64,
+    /// Levels of directories below each scanned source to break the
+    /// wasted bytes down by (0 = per source)
+    #[arg(long, default_value_t = 1)]
+    pub directory_depth: usize,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// Human-readable summary, statistics and duplicate groups
+    #[default]
+    Table,
+    /// The whole report as a single JSON document
+    Json,
+    /// One row per file that belongs to a duplicate group
+    Csv,
+}
+
+#[derive(Serialize)]
+struct Report {
+    files: u64,
+    bytes: u64,
+    redundant_files: u64,
+    wasted_bytes: u64,
+    media_types: Vec<MediaTypeStats>,
+    cameras: Vec<CameraStats>,
+    /// Directories of the sources, the most wasted bytes first
+    directories: Vec<DirectoryStats>,
+    /// Footage by resolution class, duplicates counted once
+    resolutions: Vec<ResolutionStats>,
+    /// Read-only indexes such as old backup drives, which the other
+    /// figures leave out
+    indexes: Vec<LabelStats>,
+    duplicate_groups: Vec<ReportedGroup>,
+    /// Videos that look the same without being identical, such as
+    /// re-encodes and trimmed copies; every path of each hash is listed
+    probable_duplicates: Vec<Vec<String>>,
+}
+
+/// A [`DupGroup`](deduper::database::DupGroup) as reported, by path, with
+/// the copies in read-only indexes apart.
+#[derive(Serialize)]
+struct ReportedGroup {
+    hash: String,
+    size: u64,
+    media_type: String,
+    /// All paths are hard links of one file, so they waste no space
+    hard_linked: bool,
+    paths: Vec<String>,
+    /// Copies in read-only indexes, where the contents can be restored from
+    indexed: Vec<IndexedCopy>,
+}
+
+#[derive(Serialize)]
+struct IndexedCopy {
+    label: String,
+    path: String,
+}
+
+pub fn run(args: &ReportArgs) -> Summary {
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    let report = match build_report(&db.lock(), args) {
+        Ok(report) => report,
+        Err(err) => {
+            error!("failed to build report: {}", err);
+            return Summary::aborted();
+        }
+    };
+    let result = match args.format {
+        ReportFormat::Table => {
+            print_table(&report);
+            Ok(())
+        }
+        ReportFormat::Json => serde_json::to_writer_pretty(io::stdout(), &report)
+            .map(|()| println!())
+            .map_err(io::Error::from),
+        ReportFormat::Csv => write_csv(&report),
+    };
+    if let Err(err) = result {
+        error!("failed to write report: {}", err);
+        return Summary::aborted();
+    }
+    Summary::default()
+}
+
+fn build_report(db: &LockDB, args: &ReportArgs) -> rusqlite::Result<Report> {
+    let (files, bytes) = db.count_files()?;
+    let (redundant_files, wasted_bytes) = db.count_redundant_files()?;
+    let media_types = db.media_type_stats()?;
+    let cameras = db.camera_stats()?;
+    let directories = db.directory_stats(args.directory_depth)?;
+    let resolutions = db.resolution_stats()?;
+    let indexes = db.label_stats()?;
+    let mut duplicate_groups = Vec::new();
+    for group in db.duplicate_groups(0, usize::MAX)? {
+        let (indexed, files): (Vec<_>, Vec<_>) = group
+            .files
+            .into_iter()
+            .partition(|file| file.label.is_some());
+        duplicate_groups.push(ReportedGroup {
+            size: group.size,
+            media_type: files
+                .first()
+                .map(|file| file.media_type.clone())
+                .unwrap_or_default(),
+            hard_linked: dedupe::hard_linked(&files),
+            paths: files
+                .into_iter()
+                .map(|file| file.path.to_string_lossy().into_owned())
+                .collect(),
+            indexed: indexed
+                .into_iter()
+                .map(|file| IndexedCopy {
+                    label: file.label.unwrap_or_default(),
+                    path: file.path.to_string_lossy().into_owned(),
+                })
+                .collect(),
+            hash: group.hash,
+        });
+    }
+    let fingerprints = db
+        .video_fingerprints()?
+        .into_iter()
+        .map(|(hash, frames)| (frames, hash))
+        .collect();
+    let mut probable_duplicates = Vec::new();
+    for hashes in videohash::probable_duplicates(fingerprints, args.video_distance) {
+        let mut paths = Vec::new();
+        for hash in hashes {
+            paths.extend(
+                db.find_files_by_hash(&hash)?
+                    .into_iter()
+                    .map(|file| file.path.to_string_lossy().into_owned()),
+            );
+        }
+        probable_duplicates.push(paths);
+    }
+    Ok(Report {
+        files,
+        bytes,
+        redundant_files,
+        wasted_bytes,
+        media_types,
+        cameras,
+        directories,
+        resolutions,
+        indexes,
+        duplicate_groups,
+        probable_duplicates,
+    })
+}
+
+fn print_table(report: &Report) {
+    println!("files: {} ({} bytes)", report.files, report.bytes);
+    let hard_linked = report
+        .duplicate_groups
+        .iter()
+        .filter(|group| group.hard_linked)
+        .count();
+    println!(
+        "duplicate groups: {} ({} already hard-linked)",
+        report.duplicate_groups.len(),
+        hard_linked
+    );
+    println!(
+        "probable duplicate videos: {} groups",
+        report.probable_duplicates.len()
+    );
+    println!(
+        "redundant files: {} ({} bytes)",
+        report.redundant_files, report.wasted_bytes
+    );
+    println!();
+    println!(
+        "{:<24} {:>10} {:>16} {:>10} {:>16}",
+        "media type", "files", "bytes", "redundant", "wasted bytes"
+    );
+    for stats in &report.media_types {
+        println!(
+            "{:<24} {:>10} {:>16} {:>10} {:>16}",
+            stats.media_type, stats.files, stats.bytes, stats.redundant_files, stats.wasted_bytes
+        );
+    }
+    println!();
+    println!(
+        "{:<24} {:>10} {:>16} {:>10} {:>16}",
+        "camera", "files", "bytes", "redundant", "wasted bytes"
+    );
+    for stats in &report.cameras {
+        println!(
+            "{:<24} {:>10} {:>16} {:>10} {:>16}",
+            stats.camera, stats.files, stats.bytes, stats.redundant_files, stats.wasted_bytes
+        );
+    }
+    println!();
+    println!(
+        "{:<40} {:>10} {:>16} {:>10} {:>16} {:>6}",
+        "directory", "files", "bytes", "redundant", "wasted bytes", "share"
+    );
+    for stats in &report.directories {
+        // of all wasted bytes, so a directory holding most can go wholesale
+        let share = if report.wasted_bytes == 0 {
+            0.0
+        } else {
+            stats.wasted_bytes as f64 * 100.0 / report.wasted_bytes as f64
+        };
+        println!(
+            "{:<40} {:>10} {:>16} {:>10} {:>16} {:>5.1}%",
+            stats.directory,
+            stats.files,
+            stats.bytes,
+            stats.redundant_files,
+            stats.wasted_bytes,
+            share
+        );
+    }
+    if !report.resolutions.is_empty() {
+        println!();
+        println!(
+            "{:<24} {:>10} {:>16} {:>10}",
+            "video resolution", "files", "bytes", "hours"
+        );
+        for stats in &report.resolutions {
+            println!(
+                "{:<24} {:>10} {:>16} {:>10.1}",
+                stats.resolution, stats.files, stats.bytes, stats.hours
+            );
+        }
+    }
+    if !report.indexes.is_empty() {
+        println!();
+        println!(
+            "{:<24} {:>10} {:>16} {:>10} {:>10}",
+            "index", "files", "bytes", "local", "only here"
+        );
+        for stats in &report.indexes {
+            println!(
+                "{:<24} {:>10} {:>16} {:>10} {:>10}",
+                stats.label, stats.files, stats.bytes, stats.local_files, stats.only_files
+            );
+        }
+    }
+    // groups that still waste space first
+    let mut groups = report.duplicate_groups.iter().collect::<Vec<_>>();
+    groups.sort_by_key(|group| group.hard_linked);
+    for group in groups {
+        println!();
+        if group.hard_linked {
+            println!("{} ({} bytes, already hard-linked)", group.hash, group.size);
+        } else {
+            println!("{} ({} bytes each)", group.hash, group.size);
+        }
+        for path in &group.paths {
+            println!("\t{}", path);
+        }
+        for copy in &group.indexed {
+            println!("\talso on {}: {}", copy.label, copy.path);
+        }
+    }
+    for (index, paths) in report.probable_duplicates.iter().enumerate() {
+        println!();
+        println!("probable duplicate videos {}", index + 1);
+        for path in paths {
+            println!("\t{}", path);
+        }
+    }
+}
+
+fn write_csv(report: &Report) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    csv::write_row(
+        &mut stdout,
+        &["hash", "size", "media_type", "path", "hard_linked"],
+    )?;
+    for group in &report.duplicate_groups {
+        let size = group.size.to_string();
+        let hard_linked = group.hard_linked.to_string();
+        for path in &group.paths {
+            csv::write_row(
+                &mut stdout,
+                &[&group.hash, &size, &group.media_type, path, &hard_linked],
+            )?;
+        }
+    }
+    Ok(())
+}
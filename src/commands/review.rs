@@ -0,0 +1,455 @@
+use std::{
+    collections::HashMap,
+    env,
+    io::{self, Cursor, Stdout, Write},
+};
+
+use base64ct::{Base64, Encoding};
+use chrono::Utc;
+use deduper::{
+    database::{Decision, DupGroup, File, Resolution},
+    thumbnail::{self, ThumbnailCache},
+    Deduper,
+};
+use image::ImageFormat;
+use ratatui::{
+    backend::CrosstermBackend,
+    crossterm::{
+        cursor::MoveTo,
+        event::{self, Event, KeyCode, KeyEventKind},
+        execute, queue,
+        terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
+    },
+    layout::{Constraint, Layout, Rect},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, List, ListItem, ListState, Paragraph},
+    Frame, Terminal,
+};
+use tracing::{error, info};
+
+use super::{
+    dedupe::{self, DedupeArgs},
+    history::format_time,
+    progress::Summary,
+};
+
+const HELP: &str = "↑↓ file  ←→ group  o keep  d delete others  l link others  u undo  q quit";
+
+/// Walks through the duplicate groups in the terminal, recording what is
+/// decided for each as it is, then deletes or links the duplicates of the
+/// groups decided so. Decisions of earlier reviews are shown and can be
+/// changed. Duplicates of undecided groups count as left in place.
+pub fn run(deduper: &Deduper, mut groups: Vec<DupGroup>, args: &DedupeArgs) -> Summary {
+    if groups.is_empty() {
+        info!("no duplicate groups to review");
+        return Summary::default().settled();
+    }
+    let decisions = match deduper.db().decisions() {
+        Ok(decisions) => decisions,
+        Err(err) => {
+            error!("failed to read decisions: {}", err);
+            return Summary::aborted();
+        }
+    };
+    let mut review = Review {
+        deduper,
+        groups: &mut groups,
+        decisions: decisions
+            .into_iter()
+            .map(|decision| (decision.hash.clone(), decision))
+            .collect(),
+        group: 0,
+        files: ListState::default(),
+        status: String::new(),
+        thumbnails: args.thumbnail.cache(),
+    };
+    review.select_group(0);
+    let result = review.run();
+    let decisions = review.decisions;
+    if let Err(err) = result {
+        error!("failed to run the review: {}", err);
+        return Summary::aborted();
+    }
+
+    let decided = |resolution| {
+        groups
+            .iter()
+            .filter(|group| {
+                decisions
+                    .get(&group.hash)
+                    .is_some_and(|decision| decision.resolution == resolution)
+            })
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    let (delete, link) = (decided(Resolution::Delete), decided(Resolution::Link));
+    println!(
+        "{} of {} groups decided: {} to delete, {} to link",
+        groups
+            .iter()
+            .filter(|group| decisions.contains_key(&group.hash))
+            .count(),
+        groups.len(),
+        delete.len(),
+        link.len()
+    );
+    let mut summary = Summary {
+        duplicates: groups
+            .iter()
+            .filter(|group| !decisions.contains_key(&group.hash))
+            .map(|group| group.duplicates().count() as u64)
+            .sum(),
+        ..Summary::default()
+    };
+    if !delete.is_empty() {
+        summary.add(dedupe::delete_duplicates(deduper, &delete, args));
+    }
+    if !link.is_empty() {
+        summary.add(dedupe::link_duplicates(deduper, &link, args));
+    }
+    summary.settled()
+}
+
+struct Review<'a, 'db> {
+    deduper: &'a Deduper<'db>,
+    groups: &'a mut [DupGroup],
+    decisions: HashMap<String, Decision>,
+    group: usize,
+    files: ListState,
+    status: String,
+    thumbnails: Option<ThumbnailCache>,
+}
+
+impl Review<'_, '_> {
+    fn run(&mut self) -> io::Result<()> {
+        let protocol = ImageProtocol::detect();
+        let mut terminal = TerminalGuard::enter()?;
+        // the file whose thumbnail is on screen
+        let mut shown = None;
+        loop {
+            let selected = (self.group, self.selected());
+            if protocol == Some(ImageProtocol::Iterm2) && shown != Some(selected) {
+                // inline images are only wiped by redrawing every cell
+                terminal.0.clear()?;
+            }
+            let mut preview = Rect::default();
+            terminal.0.draw(|frame| preview = self.render(frame))?;
+            if let Some(protocol) = protocol.filter(|_| shown != Some(selected)) {
+                let out = terminal.0.backend_mut();
+                protocol.clear(out)?;
+                if let Some(thumbnail) =
+                    thumbnail(&self.current().files[selected.1], self.thumbnails.as_ref())
+                {
+                    protocol.draw(out, &thumbnail, preview)?;
+                }
+                shown = Some(selected);
+            }
+
+            let Event::Key(key) = event::read()? else {
+                // redraw everything, e.g. after a resize
+                terminal.0.clear()?;
+                shown = None;
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => break,
+                KeyCode::Up | KeyCode::Char('k') => self.select_file(-1),
+                KeyCode::Down | KeyCode::Char('j') => self.select_file(1),
+                KeyCode::Left | KeyCode::Char('p') => self.select_group(-1),
+                KeyCode::Right | KeyCode::Char('n') => self.select_group(1),
+                KeyCode::Char('o') | KeyCode::Enter => self.decide(Resolution::Keep),
+                KeyCode::Char('d') => self.decide(Resolution::Delete),
+                KeyCode::Char('l') => self.decide(Resolution::Link),
+                KeyCode::Char('u') => self.undo(),
+                _ => {}
+            }
+        }
+        if let Some(protocol) = protocol {
+            protocol.clear(terminal.0.backend_mut())?;
+        }
+        Ok(())
+    }
+
+    fn current(&self) -> &DupGroup {
+        &self.groups[self.group]
+    }
+
+    fn selected(&self) -> usize {
+        self.files.selected().unwrap_or(0)
+    }
+
+    fn select_file(&mut self, step: isize) {
+        let last = self.current().files.len() - 1;
+        let selected = self.selected().saturating_add_signed(step).min(last);
+        self.files.select(Some(selected));
+    }
+
+    fn select_group(&mut self, step: isize) {
+        let last = self.groups.len() - 1;
+        self.group = self.group.saturating_add_signed(step).min(last);
+        // start on the original, as that is what is kept by default
+        let original = self
+            .current()
+            .files
+            .iter()
+            .position(|file| file.original)
+            .unwrap_or(0);
+        self.files.select(Some(original));
+        self.status.clear();
+    }
+
+    /// Keeps the selected file as the original, records `resolution` for
+    /// the others and moves on to the next group.
+    fn decide(&mut self, resolution: Resolution) {
+        let group = &mut self.groups[self.group];
+        let kept = group.files[self.files.selected().unwrap_or(0)].path.clone();
+        let decision = Decision {
+            hash: group.hash.clone(),
+            keep_path: kept.clone(),
+            resolution,
+            decided_at: Utc::now().timestamp(),
+        };
+        let recorded = self
+            .deduper
+            .set_original(&mut group.files, &group.hash, &kept)
+            .map_err(|err| err.to_string())
+            .and_then(|_| {
+                self.deduper
+                    .db()
+                    .decide(&decision)
+                    .map_err(|err| err.to_string())
+            });
+        if let Err(err) = recorded {
+            self.status = format!("failed to record the decision: {}", err);
+            return;
+        }
+        self.decisions.insert(decision.hash.clone(), decision);
+        self.select_group(1);
+    }
+
+    fn undo(&mut self) {
+        let hash = self.current().hash.clone();
+        if let Err(err) = self.deduper.db().forget_decision(&hash) {
+            self.status = format!("failed to forget the decision: {}", err);
+            return;
+        }
+        self.decisions.remove(&hash);
+        self.status = "decision undone".to_owned();
+    }
+
+    /// Draws the review and returns the area left for the thumbnail.
+    fn render(&mut self, frame: &mut Frame) -> Rect {
+        let [header, body, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [list, side] =
+            Layout::horizontal([Constraint::Percentage(60), Constraint::Percentage(40)])
+                .areas(body);
+        let [details, preview] =
+            Layout::vertical([Constraint::Length(8), Constraint::Min(0)]).areas(side);
+
+        let group = &self.groups[self.group];
+        let decision = self
+            .decisions
+            .get(&group.hash)
+            .map_or("undecided", |decision| decision.resolution.as_str());
+        frame.render_widget(
+            Line::from(format!(
+                "group {} of {}  {}  {}",
+                self.group + 1,
+                self.groups.len(),
+                group.hash,
+                decision
+            )),
+            header,
+        );
+
+        let items = group
+            .files
+            .iter()
+            .map(|file| {
+                let marker = if file.original { "keep" } else { "    " };
+                ListItem::new(format!("{} {}", marker, file.path.to_string_lossy()))
+            })
+            .collect::<Vec<_>>();
+        frame.render_stateful_widget(
+            List::new(items)
+                .block(Block::bordered().title("files"))
+                .highlight_style(Style::new().add_modifier(Modifier::REVERSED)),
+            list,
+            &mut self.files,
+        );
+
+        let file = &group.files[self.files.selected().unwrap_or(0)];
+        frame.render_widget(
+            Paragraph::new(describe(file)).block(Block::bordered().title("details")),
+            details,
+        );
+        let preview_block = Block::bordered().title("preview");
+        let thumbnail = preview_block.inner(preview);
+        frame.render_widget(preview_block, preview);
+
+        let status = if self.status.is_empty() {
+            HELP
+        } else {
+            &self.status
+        };
+        frame.render_widget(Line::from(status), footer);
+        thumbnail
+    }
+}
+
+fn describe(file: &File) -> Vec<Line<'static>> {
+    let mut lines = vec![
+        Line::from(format!("size      {} bytes", file.size)),
+        Line::from(format!("captured  {}", format_time(file.created_at))),
+        Line::from(format!("modified  {}", format_time(file.modified_at))),
+        Line::from(format!("type      {}", file.media_type)),
+    ];
+    if let Ok((width, height)) = image::image_dimensions(&file.path) {
+        lines.push(Line::from(format!("pixels    {}x{}", width, height)));
+    }
+    let camera = [&file.camera_make, &file.camera_model]
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+    if !camera.is_empty() {
+        lines.push(Line::from(format!("camera    {}", camera.join(" "))));
+    }
+    lines
+}
+
+struct Thumbnail {
+    png: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+/// The cached thumbnail of the file, or one of the image at its path.
+/// Videos only have a cached one.
+fn thumbnail(file: &File, cache: Option<&ThumbnailCache>) -> Option<Thumbnail> {
+    let path = match cache.and_then(|cache| cache.get(&file.hash)) {
+        Some(cached) => cached,
+        None if file.media_type.starts_with("image/") => file.path.clone(),
+        None => return None,
+    };
+    let image = image::open(path)
+        .ok()?
+        .thumbnail(thumbnail::SIZE, thumbnail::SIZE);
+    let mut png = Cursor::new(Vec::new());
+    image.write_to(&mut png, ImageFormat::Png).ok()?;
+    Some(Thumbnail {
+        png: png.into_inner(),
+        width: image.width(),
+        height: image.height(),
+    })
+}
+
+/// Terminal graphics protocols thumbnails are drawn with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImageProtocol {
+    Kitty,
+    /// iTerm2 inline images, also understood by WezTerm
+    Iterm2,
+}
+
+impl ImageProtocol {
+    fn detect() -> Option<Self> {
+        if env::var_os("KITTY_WINDOW_ID").is_some()
+            || env::var("TERM").is_ok_and(|term| term.contains("kitty"))
+        {
+            return Some(Self::Kitty);
+        }
+        match env::var("TERM_PROGRAM").as_deref() {
+            Ok("iTerm.app") | Ok("WezTerm") => Some(Self::Iterm2),
+            _ => None,
+        }
+    }
+
+    /// Removes the images drawn so far, where they outlive redrawn text.
+    fn clear(self, out: &mut impl Write) -> io::Result<()> {
+        if self == Self::Kitty {
+            write!(out, "\x1b_Ga=d,d=A,q=2\x1b\\")?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Draws the thumbnail into the cells of `area`, scaled to fit either
+    /// its width or its height.
+    fn draw(self, out: &mut impl Write, thumbnail: &Thumbnail, area: Rect) -> io::Result<()> {
+        if area.width == 0 || area.height == 0 {
+            return Ok(());
+        }
+        // cells are about twice as high as they are wide
+        let fit_width =
+            thumbnail.width * area.height as u32 * 2 > thumbnail.height * area.width as u32;
+        let data = Base64::encode_string(&thumbnail.png);
+        queue!(out, MoveTo(area.x, area.y))?;
+        match self {
+            Self::Kitty => {
+                let fit = if fit_width {
+                    format!("c={}", area.width)
+                } else {
+                    format!("r={}", area.height)
+                };
+                // the payload goes in chunks of at most 4096 bytes
+                let chunks = data.as_bytes().chunks(4096).collect::<Vec<_>>();
+                for (index, chunk) in chunks.iter().enumerate() {
+                    let more = u8::from(index + 1 < chunks.len());
+                    if index == 0 {
+                        write!(out, "\x1b_Ga=T,f=100,q=2,C=1,{},m={};", fit, more)?;
+                    } else {
+                        write!(out, "\x1b_Gm={};", more)?;
+                    }
+                    out.write_all(chunk)?;
+                    write!(out, "\x1b\\")?;
+                }
+            }
+            Self::Iterm2 => {
+                let fit = if fit_width {
+                    format!("width={}", area.width)
+                } else {
+                    format!("height={}", area.height)
+                };
+                write!(
+                    out,
+                    "\x1b]1337;File=inline=1;{};preserveAspectRatio=1:{}\x07",
+                    fit, data
+                )?;
+            }
+        }
+        out.flush()
+    }
+}
+
+/// The terminal in raw mode on the alternate screen, restored when dropped,
+/// also when the review fails.
+struct TerminalGuard(Terminal<CrosstermBackend<Stdout>>);
+
+impl TerminalGuard {
+    fn enter() -> io::Result<Self> {
+        let terminal = Terminal::new(CrosstermBackend::new(io::stdout()))?;
+        enable_raw_mode()?;
+        let mut guard = Self(terminal);
+        execute!(guard.0.backend_mut(), EnterAlternateScreen)?;
+        guard.0.clear()?;
+        Ok(guard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = disable_raw_mode();
+        let _ = execute!(self.0.backend_mut(), LeaveAlternateScreen);
+        let _ = self.0.show_cursor();
+    }
+}
@@ -0,0 +1,229 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+use clap::{ArgGroup, Args};
+use deduper::{
+    database::DbOptions, media::WalkOptions, photoslibrary::PhotosLibrary, scan::FileOutcome,
+    scanner::ScanOutcome, transcoder::FfmpegTools, ScanBuilder,
+};
+use tracing::{error, info, warn};
+
+use super::{
+    log_sources, open_database_with,
+    progress::{OutputArgs, Progress, Summary},
+    warn_unread, IgnoreArgs, InspectArgs, ThrottleArgs, ThumbnailArgs,
+};
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("ffmpeg").args(["video_fingerprints", "thumbnails"]).multiple(true)))]
+pub struct ScanArgs {
+    /// Directories to scan, rclone:remote:path paths of any rclone remote,
+    /// or with the s3 feature s3://bucket/prefix URLs of objects to record
+    /// for comparison
+    #[arg(short, long, value_hint = clap::ValueHint::DirPath, num_args = 1.., required = true)]
+    pub sources: Vec<PathBuf>,
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    /// Number of worker threads used for hashing and extraction (0 = one per CPU)
+    #[arg(short, long, default_value_t = 0)]
+    pub jobs: usize,
+    /// Read this many files at once through an async pipeline, for SMB and
+    /// NFS mounts where every read waits on the network; tens of files hide
+    /// the latency far better than one per job. Needs a build with the
+    /// async feature
+    #[arg(long, value_name = "FILES", value_parser = clap::value_parser!(u32).range(1..))]
+    pub read_concurrency: Option<u32>,
+    #[command(flatten)]
+    pub throttle: ThrottleArgs,
+    #[command(flatten)]
+    pub inspect: InspectArgs,
+    /// Re-hash every file, even those whose size and mtime are unchanged
+    #[arg(long)]
+    pub force_rehash: bool,
+    /// Read the dates, places, cameras and formats of files whose size and
+    /// mtime are unchanged again, keeping their hashes, e.g. to fill in the
+    /// columns of rows recorded by an older version
+    #[arg(long, conflicts_with = "force_rehash")]
+    pub refresh_metadata: bool,
+    /// Follow symlinks to files and directories; symlink loops are reported
+    #[arg(long)]
+    pub follow_symlinks: bool,
+    #[command(flatten)]
+    pub ignore: IgnoreArgs,
+    /// Continue the last interrupted scan of the same sources, skipping the
+    /// files it already finished
+    #[arg(long)]
+    pub resume: bool,
+    /// Files recorded per database transaction; an interrupted scan loses at
+    /// most this many
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..))]
+    pub batch_size: u32,
+    /// Fingerprint sampled frames of videos with ffmpeg, so `report` lists
+    /// re-encoded or slightly trimmed copies as probable duplicates
+    #[arg(long)]
+    pub video_fingerprints: bool,
+    /// Store a thumbnail of every image, and with ffmpeg of every video,
+    /// for `dedupe --interactive` and `serve` to show
+    #[arg(long)]
+    pub thumbnails: bool,
+    #[command(flatten)]
+    pub thumbnail: ThumbnailArgs,
+    /// The ffmpeg executable, or the directory holding it, for
+    /// --video-fingerprints and --thumbnails; looked up on the PATH without it
+    #[arg(long, value_hint = clap::ValueHint::AnyPath, requires = "ffmpeg")]
+    pub ffmpeg_path: Option<PathBuf>,
+    /// Record the sources as a read-only index, such as an old backup
+    /// drive: their files are only compared against, never deleted, linked
+    /// or transcoded, and `report` tells which files they also hold
+    #[arg(long, requires = "label")]
+    pub index_only: bool,
+    /// Name of the index the sources are recorded as, e.g. backup2019
+    #[arg(long, requires = "index_only")]
+    pub label: Option<String>,
+}
+
+pub fn run(args: &ScanArgs, output: &OutputArgs) -> Summary {
+    args.throttle.apply();
+    log_sources(&args.sources);
+    info!("database: {}", args.database.to_string_lossy());
+    let tools = match (args.video_fingerprints || args.thumbnails)
+        .then(|| FfmpegTools::locate(args.ffmpeg_path.as_deref()))
+    {
+        None => None,
+        Some(Ok(tools)) => Some(tools),
+        // images still get their thumbnails
+        Some(Err(err)) if !args.video_fingerprints => {
+            warn!("{}; videos get no thumbnails", err);
+            None
+        }
+        Some(Err(err)) => {
+            error!("{}", err);
+            return Summary::aborted();
+        }
+    };
+    let thumbnails = match args.thumbnails.then(|| args.thumbnail.cache()) {
+        None => None,
+        Some(Some(cache)) => match fs::create_dir_all(cache.dir()) {
+            Ok(()) => Some(cache),
+            Err(err) => {
+                error!(
+                    "failed to create thumbnail directory {}: {}",
+                    cache.dir().to_string_lossy(),
+                    err
+                );
+                return Summary::aborted();
+            }
+        },
+        Some(None) => {
+            error!("could not locate the cache directory; pass --thumbnail-dir");
+            return Summary::aborted();
+        }
+    };
+    let options = DbOptions {
+        batch_size: args.batch_size as usize,
+        ..DbOptions::default()
+    };
+    let Some(db) = open_database_with(&args.database, options) else {
+        return Summary::aborted();
+    };
+    let library = match PhotosLibrary::open(&args.sources) {
+        Ok(library) => Arc::new(library),
+        Err(err) => {
+            error!("failed to read the Apple Photos library: {}", err);
+            return Summary::aborted();
+        }
+    };
+    warn_unread(&library);
+    if !library.is_empty() {
+        info!("{} assets in Apple Photos libraries", library.len());
+    }
+    // the scan counts its files before it starts
+    let progress = Arc::new(Progress::new(output, || 0));
+    let resumed = Arc::new(AtomicUsize::new(0));
+    let failed_before = Arc::new(AtomicUsize::new(0));
+    let mut scan = ScanBuilder::new()
+        .sources(args.sources.iter().cloned())
+        .inspector(args.inspect.inspector())
+        .threads(args.jobs)
+        .walk(WalkOptions {
+            follow_symlinks: args.follow_symlinks,
+            ignore: args.ignore.rules(),
+            ..WalkOptions::default()
+        })
+        .force_rehash(args.force_rehash)
+        .refresh_metadata(args.refresh_metadata)
+        .resume(args.resume)
+        .label(args.label.clone())
+        .photos_library(library)
+        .events(progress.clone())
+        .on_progress({
+            let progress = progress.clone();
+            let resumed = resumed.clone();
+            let failed_before = failed_before.clone();
+            move |file| {
+                progress.set_length(file.total);
+                // recorded and failed files are reported as events
+                match file.outcome {
+                    FileOutcome::Scanned(ScanOutcome::Unchanged(row)) => {
+                        progress.unchanged(&row.hash)
+                    }
+                    FileOutcome::Scanned(ScanOutcome::Recorded(_)) | FileOutcome::Failed(_) => {}
+                    FileOutcome::Resumed => {
+                        resumed.fetch_add(1, Ordering::Relaxed);
+                        progress.advance();
+                    }
+                    FileOutcome::FailedBefore => {
+                        progress.debug(format!(
+                            "skipping {}, it failed before and is unchanged",
+                            file.path.to_string_lossy()
+                        ));
+                        failed_before.fetch_add(1, Ordering::Relaxed);
+                        progress.advance();
+                    }
+                }
+            }
+        });
+    if let Some(files) = args.read_concurrency {
+        scan = scan.read_concurrency(files as usize);
+    }
+    if let Some(tools) = tools.clone().filter(|_| args.video_fingerprints) {
+        scan = scan.video_fingerprints(tools);
+    }
+    if let Some(mut cache) = thumbnails {
+        if let Some(tools) = tools {
+            cache = cache.videos(tools);
+        }
+        scan = scan.thumbnails(cache);
+    }
+    let counts = scan.run(&db);
+    progress.finish();
+    let counts = match counts {
+        Ok(counts) => counts,
+        Err(err) => {
+            error!("scan failed: {}", err);
+            return Summary::aborted();
+        }
+    };
+    println!(
+        "scanned {} files, {} unchanged, {} deleted",
+        counts.recorded, counts.unchanged, counts.deleted
+    );
+    let resumed = resumed.load(Ordering::Relaxed);
+    if resumed > 0 {
+        println!("skipped {} files the interrupted scan finished", resumed);
+    }
+    let failed_before = failed_before.load(Ordering::Relaxed);
+    if failed_before > 0 {
+        println!(
+            "skipped {} files that failed before and are unchanged; see `deduper errors list`",
+            failed_before
+        );
+    }
+    progress.summary()
+}
@@ -0,0 +1,447 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    fs,
+    hash::{BuildHasher, Hasher},
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+};
+
+use chrono::Utc;
+use clap::Args;
+use deduper::{
+    database::{Decision, File, LockDB, Resolution, DB},
+    hasher,
+    html::{self, escape},
+    platform,
+    thumbnail::ThumbnailCache,
+    Deduper,
+};
+use indicatif::HumanBytes;
+use tiny_http::{Header, Method, Request, Response, Server};
+use tracing::{error, info, warn};
+
+use super::{history::format_time, open_database, progress::Summary, ThumbnailArgs};
+
+/// Duplicate groups listed per page.
+const PAGE_SIZE: usize = 50;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;max-width:70em}\
+    table.chart{border-collapse:collapse;width:100%}\
+    table.chart td{padding:.2em .5em;white-space:nowrap}\
+    td.bar{width:60%}\
+    .bytes{background:#9bc;height:1em}\
+    .wasted{background:#c66;height:1em}\
+    section{border-top:1px solid #ccc;padding:.5em 0}\
+    ul{list-style:none;padding:0}\
+    img{max-height:6em;max-width:10em;vertical-align:middle;margin-right:.5em}\
+    .meta,.decided{color:#666}";
+
+#[derive(Args)]
+pub struct ServeArgs {
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    /// Address to listen on, which requests must name as their host besides
+    /// localhost; pages are served without authentication, so only listen
+    /// beyond localhost on a network you trust
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    pub listen: SocketAddr,
+    #[command(flatten)]
+    pub thumbnail: ThumbnailArgs,
+}
+
+/// Serves the statistics and duplicate groups of the database until
+/// interrupted. Decisions made in the browser are recorded like those of
+/// `dedupe --interactive`, which carries them out.
+pub fn run(args: &ServeArgs) -> Summary {
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    // every group gets an original, as decided or by the default policy
+    if let Err(err) = Deduper::new(db.lock()).mark_originals() {
+        error!("failed to mark original files: {}", err);
+        return Summary::aborted();
+    }
+    let server = match Server::http(args.listen) {
+        Ok(server) => server,
+        Err(err) => {
+            error!("failed to listen on {}: {}", args.listen, err);
+            return Summary::aborted();
+        }
+    };
+    info!("serving on http://{}", args.listen);
+    let site = Site {
+        db: &db,
+        listen: args.listen,
+        thumbnails: args.thumbnail.cache(),
+        token: form_token(),
+    };
+    for request in server.incoming_requests() {
+        if let Err(err) = respond(request, &site) {
+            warn!("failed to respond: {}", err);
+        }
+    }
+    Summary::default()
+}
+
+/// What the pages are served from.
+struct Site<'a> {
+    db: &'a DB,
+    /// Where the server listens; requests naming another host are refused
+    listen: SocketAddr,
+    thumbnails: Option<ThumbnailCache>,
+    /// Sent along with every form, so a page of another site cannot post
+    /// decisions from the browser
+    token: String,
+}
+
+/// A secret of this process for [`Site::token`], from the random keys
+/// the standard library seeds its hash maps with.
+fn form_token() -> String {
+    (0..2)
+        .map(|_| format!("{:016x}", RandomState::new().build_hasher().finish()))
+        .collect()
+}
+
+fn respond(mut request: Request, site: &Site) -> io::Result<()> {
+    // a page of another site whose name resolves to this address sends
+    // that name, and must neither read files nor post decisions
+    if !sent(&request, "Host").is_some_and(|host| html::known_host(site.listen, &host)) {
+        return request.respond(Response::from_string("unknown host").with_status_code(421));
+    }
+    let (db, thumbnails) = (site.db, site.thumbnails.as_ref());
+    let url = request.url().to_owned();
+    let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+    let query = html::decode_form(query)
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+    match (request.method().clone(), path) {
+        (Method::Get, "/") => {
+            let page = query
+                .get("page")
+                .and_then(|page| page.parse().ok())
+                .unwrap_or(0);
+            match render_index(&db.read(), page, thumbnails, &site.token) {
+                Ok(index) => request.respond(
+                    Response::from_string(index)
+                        .with_header(header("Content-Type", "text/html; charset=utf-8")),
+                ),
+                Err(err) => request.respond(
+                    Response::from_string(format!("failed to read the database: {}", err))
+                        .with_status_code(500),
+                ),
+            }
+        }
+        (Method::Get, "/file") => match query.get("path").and_then(|path| image(db, path)) {
+            Some((file, media_type)) => request.respond(
+                Response::from_file(file).with_header(header("Content-Type", &media_type)),
+            ),
+            None => request.respond(not_found()),
+        },
+        (Method::Get, "/thumbnail") => {
+            let Some(hash) = query.get("hash").filter(|hash| hasher::is_digest(hash)) else {
+                return request
+                    .respond(Response::from_string("not a recorded hash").with_status_code(400));
+            };
+            let thumbnail = thumbnails
+                .and_then(|thumbnails| thumbnails.get(hash))
+                .and_then(|path| fs::File::open(path).ok());
+            match thumbnail {
+                Some(file) => request.respond(
+                    Response::from_file(file).with_header(header("Content-Type", "image/jpeg")),
+                ),
+                None => request.respond(not_found()),
+            }
+        }
+        (Method::Post, "/decide" | "/undo") => {
+            // browsers send the origin of the page a form was posted from
+            let foreign = match (sent(&request, "Origin"), sent(&request, "Host")) {
+                (Some(origin), Some(host)) => origin != format!("http://{}", host),
+                (Some(_), None) => true,
+                (None, _) => false,
+            };
+            let mut body = String::new();
+            request.as_reader().read_to_string(&mut body)?;
+            let form = html::decode_form(&body)
+                .into_iter()
+                .collect::<HashMap<_, _>>();
+            if foreign || form.get("token") != Some(&site.token) {
+                return request.respond(
+                    Response::from_string("forms are only taken from deduper's own pages")
+                        .with_status_code(403),
+                );
+            }
+            let decided = if path == "/decide" {
+                decide(db, &form)
+            } else {
+                undo(db, &form)
+            };
+            match decided {
+                Ok(hash) => {
+                    // back to the group, on the page it is listed on
+                    let page = form
+                        .get("page")
+                        .and_then(|page| page.parse::<usize>().ok())
+                        .unwrap_or(0);
+                    let location = format!("/?page={}#{}", page, html::encode_component(&hash));
+                    request.respond(
+                        Response::from_string("")
+                            .with_status_code(303)
+                            .with_header(header("Location", &location)),
+                    )
+                }
+                Err(err) => request.respond(Response::from_string(err).with_status_code(400)),
+            }
+        }
+        _ => request.respond(not_found()),
+    }
+}
+
+fn header(name: &str, value: &str) -> Header {
+    Header::from_bytes(name.as_bytes(), value.as_bytes()).expect("ASCII header")
+}
+
+/// The value of the header `name` of `request`.
+fn sent(request: &Request, name: &'static str) -> Option<String> {
+    request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(name))
+        .map(|header| header.value.as_str().to_owned())
+}
+
+fn not_found() -> Response<io::Cursor<Vec<u8>>> {
+    Response::from_string("not found").with_status_code(404)
+}
+
+/// A recorded image for a preview, its path encoded by
+/// [`platform::encode_path`]; other paths are not served.
+fn image(db: &DB, path: &str) -> Option<(fs::File, String)> {
+    let file = db.read().find_file(&platform::decode_path(path)?).ok()??;
+    if !file.media_type.starts_with("image/") || !file.media_type.is_ascii() {
+        return None;
+    }
+    Some((fs::File::open(&file.path).ok()?, file.media_type))
+}
+
+/// Keeps the chosen file of a group as its original and records what
+/// becomes of the others. Returns the hash of the group.
+fn decide(db: &DB, form: &HashMap<String, String>) -> Result<String, String> {
+    let field = |name: &str| form.get(name).ok_or_else(|| format!("missing {}", name));
+    let (hash, kept) = (field("hash")?, field("keep")?);
+    // encoded, so a name that is not UTF-8 comes back as it is
+    let kept_path = platform::decode_path(kept).ok_or_else(|| format!("invalid path {}", kept))?;
+    let kept_path = kept_path.as_path();
+    let resolution = match field("resolution")?.as_str() {
+        "keep" => Resolution::Keep,
+        "delete" => Resolution::Delete,
+        "link" => Resolution::Link,
+        other => return Err(format!("unknown resolution {}", other)),
+    };
+    let deduper = Deduper::new(db.lock());
+    let mut files = deduper
+        .db()
+        .find_files_by_hash(hash)
+        .map_err(|err| err.to_string())?;
+    if !files.iter().any(|file| file.path == kept_path) {
+        return Err(format!(
+            "{} is not a file of group {}",
+            kept_path.to_string_lossy(),
+            hash
+        ));
+    }
+    deduper
+        .set_original(&mut files, hash, kept_path)
+        .map_err(|err| err.to_string())?;
+    let decision = Decision {
+        hash: hash.clone(),
+        keep_path: kept_path.to_owned(),
+        resolution,
+        decided_at: Utc::now().timestamp(),
+    };
+    deduper
+        .db()
+        .decide(&decision)
+        .map_err(|err| err.to_string())?;
+    Ok(decision.hash)
+}
+
+fn undo(db: &DB, form: &HashMap<String, String>) -> Result<String, String> {
+    let hash = form.get("hash").ok_or("missing hash")?;
+    db.lock()
+        .forget_decision(hash)
+        .map_err(|err| err.to_string())?;
+    Ok(hash.clone())
+}
+
+/// Totals, a chart of the space each media type takes and could free, and
+/// one page of duplicate groups.
+fn render_index(
+    db: &LockDB,
+    page: usize,
+    thumbnails: Option<&ThumbnailCache>,
+    token: &str,
+) -> rusqlite::Result<String> {
+    let (files, bytes) = db.count_files()?;
+    let (redundant_files, wasted_bytes) = db.count_redundant_files()?;
+    let media_types = db.media_type_stats()?;
+    let decisions = db
+        .decisions()?
+        .into_iter()
+        .map(|decision| (decision.hash.clone(), decision))
+        .collect::<HashMap<_, _>>();
+    let count = db.count_duplicate_groups()?;
+    let pages = count.div_ceil(PAGE_SIZE);
+    let page = page.min(pages.saturating_sub(1));
+
+    let mut out = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>deduper</title>\
+            <style>{}</style></head><body><h1>deduper</h1>",
+        STYLE
+    );
+    out.push_str(&format!(
+        "<p>{} files, {}; {} duplicate groups with {} redundant files, {} reclaimable; \
+            {} groups decided</p>",
+        files,
+        HumanBytes(bytes),
+        count,
+        redundant_files,
+        HumanBytes(wasted_bytes),
+        decisions.len()
+    ));
+
+    out.push_str("<h2>Storage by media type</h2><table class=\"chart\">");
+    let largest = media_types
+        .iter()
+        .map(|stats| stats.bytes)
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    for stats in &media_types {
+        out.push_str(&format!(
+            "<tr><td>{}</td><td class=\"bar\"><div class=\"bytes\" style=\"width:{:.1}%\">\
+                <div class=\"wasted\" style=\"width:{:.1}%\"></div></div></td>\
+                <td>{}, {} reclaimable</td></tr>",
+            escape(&stats.media_type),
+            stats.bytes as f64 * 100.0 / largest as f64,
+            stats.wasted_bytes as f64 * 100.0 / stats.bytes.max(1) as f64,
+            HumanBytes(stats.bytes),
+            HumanBytes(stats.wasted_bytes)
+        ));
+    }
+    out.push_str("</table><h2>Duplicate groups</h2>");
+
+    let pager = render_pager(page, pages);
+    out.push_str(&pager);
+    for group in db.duplicate_groups(page * PAGE_SIZE, PAGE_SIZE)? {
+        let hash = group.hash.as_str();
+        let thumbnail = thumbnails.and_then(|thumbnails| thumbnails.get(hash));
+        render_group(
+            &mut out,
+            hash,
+            &group.files,
+            decisions.get(hash),
+            thumbnail.is_some(),
+            page,
+            token,
+        );
+    }
+    out.push_str(&pager);
+    out.push_str("</body></html>");
+    Ok(out)
+}
+
+fn render_pager(page: usize, pages: usize) -> String {
+    if pages < 2 {
+        return String::new();
+    }
+    let mut pager = format!("<p>page {} of {}", page + 1, pages);
+    if page > 0 {
+        pager.push_str(&format!(" <a href=\"/?page={}\">previous</a>", page - 1));
+    }
+    if page + 1 < pages {
+        pager.push_str(&format!(" <a href=\"/?page={}\">next</a>", page + 1));
+    }
+    pager.push_str("</p>");
+    pager
+}
+
+/// A form choosing the file to keep, with a button for each resolution.
+/// Files are previewed by the cached thumbnail of the group, or else images
+/// by themselves.
+fn render_group(
+    out: &mut String,
+    hash: &str,
+    files: &[File],
+    decision: Option<&Decision>,
+    thumbnail: bool,
+    page: usize,
+    token: &str,
+) {
+    let Some(first) = files.first() else {
+        return;
+    };
+    let hidden = format!(
+        "<input type=\"hidden\" name=\"hash\" value=\"{}\">\
+            <input type=\"hidden\" name=\"page\" value=\"{}\">\
+            <input type=\"hidden\" name=\"token\" value=\"{}\">",
+        escape(hash),
+        page,
+        token
+    );
+    out.push_str(&format!(
+        "<section id=\"{}\"><h3>{} copies of {} {}</h3>",
+        escape(hash),
+        files.len(),
+        HumanBytes(first.size),
+        escape(&first.media_type)
+    ));
+    if let Some(decision) = decision {
+        out.push_str(&format!(
+            "<p class=\"decided\">decided on {}: {}, keeping {}</p>",
+            format_time(decision.decided_at),
+            decision.resolution.as_str(),
+            escape(&decision.keep_path.to_string_lossy())
+        ));
+    }
+    out.push_str(&format!(
+        "<form method=\"post\" action=\"/decide\">{}<ul>",
+        hidden
+    ));
+    for file in files {
+        let preview = if thumbnail {
+            format!(
+                "<img loading=\"lazy\" alt=\"\" src=\"/thumbnail?hash={}\">",
+                html::encode_component(hash)
+            )
+        } else if file.media_type.starts_with("image/") {
+            format!(
+                "<img loading=\"lazy\" alt=\"\" src=\"/file?path={}\">",
+                html::encode_component(&platform::encode_path(&file.path))
+            )
+        } else {
+            String::new()
+        };
+        out.push_str(&format!(
+            "<li><label><input type=\"radio\" name=\"keep\" value=\"{}\"{}> {}{} \
+                <span class=\"meta\">{}</span></label></li>",
+            escape(&platform::encode_path(&file.path)),
+            if file.original { " checked" } else { "" },
+            preview,
+            escape(&file.path.to_string_lossy()),
+            format_time(file.created_at)
+        ));
+    }
+    out.push_str(
+        "</ul><button name=\"resolution\" value=\"keep\">keep selected</button> \
+            <button name=\"resolution\" value=\"delete\">delete others</button> \
+            <button name=\"resolution\" value=\"link\">link others</button></form>",
+    );
+    if decision.is_some() {
+        out.push_str(&format!(
+            "<form method=\"post\" action=\"/undo\">{}<button>undo</button></form>",
+            hidden
+        ));
+    }
+    out.push_str("</section>");
+}
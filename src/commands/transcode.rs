@@ -0,0 +1,658 @@
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use clap::{Args, ValueEnum};
+use deduper::{
+    database::{self, UndoAction},
+    extractor,
+    hasher::{self, FileHash, HashAlgorithm},
+    scanner,
+    space::{self, Budget},
+    transcoder::{self, FfmpegTools, ImageTarget, Limits, TranscodeProfile},
+    undo::UndoLog,
+    EventSink,
+};
+use indicatif::HumanDuration;
+use rayon::prelude::*;
+use tracing::{error, info, warn};
+
+use super::{
+    errors, open_database,
+    progress::{OutputArgs, Progress, Summary},
+    thread_pool, SpaceArgs,
+};
+
+#[derive(Args)]
+pub struct TranscodeArgs {
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    /// Which original media to re-encode
+    #[arg(long, value_enum, default_value_t)]
+    pub media: TranscodeMedia,
+    /// Video encoding profile: archive-av1, compat-h264, hevc-hw or a JSON
+    /// file with codec, crf, preset, audio, container and extra_args
+    #[arg(long, default_value = "archive-av1", value_parser = parse_profile)]
+    pub profile: TranscodeProfile,
+    /// Quality of re-encoded JPEGs and of AVIF conversions, 1 to 100
+    #[arg(long, default_value_t = 85, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub quality: u8,
+    /// Convert PNGs to this format; PNGs are left alone without it
+    #[arg(long, value_enum)]
+    pub png_to: Option<ImageTarget>,
+    /// Skip videos below this bitrate in kbit/s
+    #[arg(long)]
+    pub min_bitrate: Option<u64>,
+    /// Skip videos spending fewer bits per pixel per frame, e.g. 0.05
+    #[arg(long)]
+    pub min_bits_per_pixel: Option<f64>,
+    /// Skip videos already in these codecs, as ffprobe names them
+    #[arg(long, value_delimiter = ',', default_value = "av1,hevc")]
+    pub skip_codecs: Vec<String>,
+    /// How many more times a file that failed is tried on later runs
+    #[arg(long, default_value_t = 2)]
+    pub retries: u32,
+    /// Number of files re-encoded at once (0 = one per CPU)
+    #[arg(short, long, default_value_t = 1)]
+    pub jobs: usize,
+    /// Threads each ffmpeg process encodes with
+    #[arg(long)]
+    pub threads: Option<u32>,
+    /// Niceness ffmpeg runs at, e.g. 19 to only use otherwise idle CPU
+    #[arg(long, allow_negative_numbers = true)]
+    pub nice: Option<i32>,
+    /// Run ffmpeg in the idle I/O class, like ionice -c 3 (Linux)
+    #[arg(long)]
+    pub idle_io: bool,
+    /// Do not start another file while the one minute load average is above this
+    #[arg(long)]
+    pub max_load: Option<f64>,
+    /// The ffmpeg executable, or the directory holding ffmpeg and ffprobe;
+    /// looked up on the PATH without it
+    #[arg(long, value_hint = clap::ValueHint::AnyPath)]
+    pub ffmpeg_path: Option<PathBuf>,
+    #[command(flatten)]
+    pub space: SpaceArgs,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum TranscodeMedia {
+    /// Videos, to AV1
+    #[default]
+    Videos,
+    /// JPEGs at --quality and PNGs to --png-to
+    Images,
+    All,
+}
+
+pub fn run(args: &TranscodeArgs, output: &OutputArgs) -> Summary {
+    let tools = match FfmpegTools::locate(args.ffmpeg_path.as_deref()) {
+        Ok(tools) => tools,
+        // images are re-encoded in process
+        Err(_) if args.media == TranscodeMedia::Images => FfmpegTools::default(),
+        Err(err) => {
+            error!("{}", err);
+            return Summary::aborted();
+        }
+    };
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    let files = match queue_files(args, &db) {
+        Ok(files) => files,
+        Err(err) => {
+            error!("failed to queue files to transcode: {}", err);
+            return Summary::aborted();
+        }
+    };
+    if files.is_empty() {
+        info!("nothing to transcode; run dedupe first to mark original files");
+    }
+    let undo = match UndoLog::start(&db.lock(), "transcode") {
+        Ok(undo) => undo,
+        Err(err) => {
+            error!("failed to start the undo log: {}", err);
+            return Summary::aborted();
+        }
+    };
+    let limits = Limits {
+        threads: args.threads,
+        nice: args.nice,
+        idle_io: args.idle_io,
+    };
+    let pool = thread_pool(args.jobs);
+    let Some(budgets) = check_space(args, &files, pool.current_num_threads()) else {
+        return Summary::aborted();
+    };
+    let progress = Progress::new(output, || files.len());
+    pool.install(|| {
+        files.par_iter().for_each(|file| {
+            if let Some(max_load) = args.max_load {
+                transcoder::wait_for_load(max_load);
+            }
+            // the output replaces the file, so its size is only held while
+            // both exist
+            let budget = (!budgets.is_empty())
+                .then(|| budgets.get(&file_system(&file.path)))
+                .flatten();
+            if budget.is_some_and(|budget| !budget.take(file.size)) {
+                progress.debug(format!(
+                    "leaving {} for a later run, its output does not fit",
+                    file.path.to_string_lossy()
+                ));
+                return progress.advance();
+            }
+            if transcode_queued(file, args, &tools, limits, &db, undo, &progress) {
+                progress.done();
+            } else {
+                progress.failed("transcode");
+            }
+            if let Some(budget) = budget {
+                budget.give_back(file.size);
+            }
+        })
+    });
+    progress.clear();
+    if let Err(err) = undo.finish(&db.lock()) {
+        warn!("failed to finish the undo log: {}", err);
+    }
+    let summary = progress.summary();
+    if summary.failed > 0 {
+        warn!(
+            "{} files failed; they are retried on the next run up to {} times",
+            summary.failed, args.retries
+        );
+    }
+    summary
+}
+
+/// Checks that every filesystem holding files to re-encode has room for the
+/// outputs of the `jobs` largest of them, as each output sits next to its
+/// source until it replaces it. With `--when-full trim` gives the budget of
+/// each filesystem that lacks it, by [`file_system`]; `None` when the batch
+/// must not start.
+fn check_space(
+    args: &TranscodeArgs,
+    files: &[database::File],
+    jobs: usize,
+) -> Option<HashMap<PathBuf, Arc<Budget>>> {
+    let mut sizes = HashMap::<PathBuf, Vec<u64>>::new();
+    let mut roots = HashMap::new();
+    for file in files {
+        let dir = file.path.parent().unwrap_or(&file.path);
+        let root = roots.entry(dir).or_insert_with(|| file_system(&file.path));
+        sizes.entry(root.clone()).or_default().push(file.size);
+    }
+    let mut budgets = HashMap::new();
+    for (root, mut sizes) in sizes {
+        sizes.sort_unstable_by(|a, b| b.cmp(a));
+        let required = sizes.iter().take(jobs.max(1)).sum();
+        if let Some(budget) = args.space.check(&root, required)? {
+            budgets.insert(root, budget);
+        }
+    }
+    Some(budgets)
+}
+
+/// The directory standing for the filesystem of `path` in
+/// [`check_space`]: the topmost ancestor on the same one, i.e. its mount
+/// point.
+fn file_system(path: &Path) -> PathBuf {
+    let parent = path.parent().unwrap_or(path);
+    parent
+        .ancestors()
+        .take_while(|ancestor| {
+            !ancestor.as_os_str().is_empty() && space::same_filesystem(ancestor, parent)
+        })
+        .last()
+        .unwrap_or(parent)
+        .to_owned()
+}
+
+/// Re-encodes one queued file and records the outcome in the queue.
+/// Returns false when it failed.
+fn transcode_queued(
+    file: &database::File,
+    args: &TranscodeArgs,
+    tools: &FfmpegTools,
+    limits: Limits,
+    db: &database::DB,
+    undo: UndoLog,
+    progress: &Progress,
+) -> bool {
+    if let Err(err) = db.lock().start_transcode(&file.path) {
+        progress.warn(format!(
+            "failed to record {}: {}",
+            file.path.to_string_lossy(),
+            err
+        ));
+        return false;
+    }
+    let result = if !file.media_type.starts_with("video/") {
+        optimize_image(file, args, db, undo, progress)
+    } else if let Some(reason) = already_efficient(file, args, tools) {
+        progress.debug(format!(
+            "skipping {}: {}",
+            file.path.to_string_lossy(),
+            reason
+        ));
+        db.lock()
+            .mark_skipped(&file.path)
+            .map_err(|err| format!("failed to record {}: {}", file.path.to_string_lossy(), err))
+    } else {
+        transcode_file(file, &args.profile, tools, limits, db, undo, progress)
+    };
+    match &result {
+        Ok(()) => errors::forget(db, &file.path, progress),
+        Err(err) => {
+            progress.warn(err);
+            errors::record_transcode(db, &file.path, err, progress);
+            remove_partial_outputs(&file.path);
+        }
+    }
+    let ok = result.is_ok();
+    if let Err(err) = db
+        .lock()
+        .finish_transcode(&file.path, result.err().as_deref())
+    {
+        progress.warn(format!(
+            "failed to record {}: {}",
+            file.path.to_string_lossy(),
+            err
+        ));
+    }
+    ok
+}
+
+/// Why re-encoding the video `file` is not worth it under the thresholds in
+/// `args`, if it is not. Videos ffprobe cannot read are encoded regardless.
+fn already_efficient(
+    file: &database::File,
+    args: &TranscodeArgs,
+    tools: &FfmpegTools,
+) -> Option<String> {
+    let info = transcoder::probe_video(tools, &file.path)?;
+    if args
+        .skip_codecs
+        .iter()
+        .any(|codec| codec.eq_ignore_ascii_case(&info.codec))
+    {
+        return Some(format!("already {}", info.codec));
+    }
+    if let (Some(min_bitrate), Some(bit_rate)) = (args.min_bitrate, info.bit_rate) {
+        if bit_rate < min_bitrate.saturating_mul(1000) {
+            return Some(format!("{} kbit/s", bit_rate / 1000));
+        }
+    }
+    if let (Some(min_bits), Some(bits)) = (args.min_bits_per_pixel, info.bits_per_pixel()) {
+        if bits < min_bits {
+            return Some(format!("{:.3} bits per pixel", bits));
+        }
+    }
+    None
+}
+
+/// Adds the unoptimized originals to the transcode queue and returns the
+/// files due: new ones and failed ones with retries left. Files a crashed
+/// run left in progress are queued again after removing their partial
+/// output.
+fn queue_files(args: &TranscodeArgs, db: &database::DB) -> rusqlite::Result<Vec<database::File>> {
+    let db = db.lock();
+    for path in db.requeue_interrupted_transcodes()? {
+        remove_partial_outputs(Path::new(&path));
+    }
+    let mut files = Vec::new();
+    if args.media != TranscodeMedia::Images {
+        files.extend(db.find_unoptimized_videos()?);
+    }
+    if args.media != TranscodeMedia::Videos {
+        files.extend(
+            db.find_unoptimized_images()?
+                .into_iter()
+                .filter(|file| file.media_type != "image/png" || args.png_to.is_some()),
+        );
+    }
+    for file in &files {
+        db.enqueue_transcode(&file.path)?;
+    }
+    let due = db.due_transcodes(args.retries + 1)?;
+    Ok(files
+        .into_iter()
+        .filter(|file| due.contains(&file.path))
+        .collect())
+}
+
+/// Encodes next to the source and swaps the result in only once ffmpeg
+/// succeeded, the result checks out and is smaller, so a failed encode
+/// never touches the original. A profile with another container replaces
+/// the source with a file of that extension.
+fn transcode_file(
+    file: &database::File,
+    profile: &TranscodeProfile,
+    tools: &FfmpegTools,
+    limits: Limits,
+    db: &database::DB,
+    undo: UndoLog,
+    progress: &Progress,
+) -> Result<(), String> {
+    let path = file.path.as_path();
+    let new_path = match &profile.container {
+        Some(container) => path.with_extension(container),
+        None => path.to_owned(),
+    };
+    let media_type = (new_path != path).then(|| {
+        mime_guess::from_path(&new_path)
+            .first_or_octet_stream()
+            .to_string()
+    });
+    let temp_path = prepare(file, &new_path)?;
+
+    progress.debug(format!("transcoding {}", file.path.to_string_lossy()));
+    let bar = progress.task(&path.file_name().unwrap_or_default().to_string_lossy());
+    let encoded = transcoder::transcode(tools, path, &temp_path, profile, limits, |encode| {
+        if let Some(fraction) = encode.fraction() {
+            bar.set_position((fraction * 1000.0) as u64);
+        }
+        bar.set_message(format!(
+            "{:.0} fps, {:.1}x, ETA {}",
+            encode.fps.unwrap_or_default(),
+            encode.speed.unwrap_or_default(),
+            encode
+                .eta()
+                .map_or("unknown".to_owned(), |eta| HumanDuration(eta).to_string())
+        ));
+    });
+    let verified = encoded
+        .map_err(|err| {
+            format!(
+                "failed to transcode {}: {}",
+                file.path.to_string_lossy(),
+                err
+            )
+        })
+        .and_then(|()| {
+            bar.set_message("verifying");
+            transcoder::verify_transcode(tools, path, &temp_path, profile, limits)
+                .map_err(|err| reject(file, &temp_path, err))
+        })
+        .and_then(|()| {
+            bar.set_message("restoring metadata");
+            transcoder::restore_capture_tags(tools, path, &temp_path)
+                .map_err(|err| reject(file, &temp_path, err))
+        });
+    bar.finish_and_clear();
+    let restored = verified?;
+    if !restored.is_empty() {
+        progress.debug(format!(
+            "restored {} of {}",
+            restored.join(", "),
+            file.path.to_string_lossy()
+        ));
+    }
+    if !keep_smaller(file, &temp_path, db, progress)? {
+        return Ok(());
+    }
+    swap_in(
+        file,
+        &temp_path,
+        &new_path,
+        media_type.as_deref(),
+        db,
+        undo,
+        progress,
+    )
+}
+
+/// Re-encodes a JPEG in place or converts a PNG next to it, like
+/// [`transcode_file`].
+fn optimize_image(
+    file: &database::File,
+    args: &TranscodeArgs,
+    db: &database::DB,
+    undo: UndoLog,
+    progress: &Progress,
+) -> Result<(), String> {
+    let path = file.path.as_path();
+    let (target, new_path) = match (file.media_type.as_str(), args.png_to) {
+        ("image/png", Some(target)) => (Some(target), path.with_extension(target.extension())),
+        _ => (None, path.to_owned()),
+    };
+    let temp_path = prepare(file, &new_path)?;
+
+    progress.debug(format!("optimizing {}", file.path.to_string_lossy()));
+    let optimized = match target {
+        Some(target) => transcoder::convert_png(path, &temp_path, target, args.quality),
+        None => transcoder::optimize_jpeg(path, &temp_path, args.quality),
+    };
+    optimized
+        .map_err(|err| {
+            format!(
+                "failed to optimize {}: {}",
+                file.path.to_string_lossy(),
+                err
+            )
+        })
+        .and_then(|()| {
+            transcoder::verify_image(path, &temp_path).map_err(|err| reject(file, &temp_path, err))
+        })?;
+    if !keep_smaller(file, &temp_path, db, progress)? {
+        return Ok(());
+    }
+    swap_in(
+        file,
+        &temp_path,
+        &new_path,
+        target.map(ImageTarget::mime_type),
+        db,
+        undo,
+        progress,
+    )
+}
+
+/// Moves an output that failed verification aside as
+/// `name.rejected.<extension>`, where it survives the cleanup of partial
+/// outputs for inspection, and describes the failure.
+fn reject(file: &database::File, temp_path: &Path, err: std::io::Error) -> String {
+    let kept = sibling_path(&file.path, "rejected", temp_path.extension())
+        .filter(|rejected| fs::rename(temp_path, rejected).is_ok());
+    match kept {
+        Some(rejected) => format!(
+            "rejected transcode of {}: {}; kept it at {}",
+            file.path.to_string_lossy(),
+            err,
+            rejected.to_string_lossy()
+        ),
+        None => format!(
+            "rejected transcode of {}: {}",
+            file.path.to_string_lossy(),
+            err
+        ),
+    }
+}
+
+/// Discards the output at `temp_path` when it is not smaller than `file`,
+/// which is common for clips already in an efficient codec, and marks the
+/// file skipped so it is not tried again. Returns whether the output is
+/// kept.
+fn keep_smaller(
+    file: &database::File,
+    temp_path: &Path,
+    db: &database::DB,
+    progress: &Progress,
+) -> Result<bool, String> {
+    let size = fs::metadata(temp_path)
+        .map_err(|err| {
+            format!(
+                "failed to read re-encoded {}: {}",
+                file.path.to_string_lossy(),
+                err
+            )
+        })?
+        .len();
+    if size < file.size {
+        return Ok(true);
+    }
+    progress.debug(format!(
+        "keeping {}: re-encoded it is {} bytes, the original {}",
+        file.path.to_string_lossy(),
+        size,
+        file.size
+    ));
+    let _ = fs::remove_file(temp_path);
+    db.lock()
+        .mark_skipped(&file.path)
+        .map_err(|err| format!("failed to record {}: {}", file.path.to_string_lossy(), err))?;
+    Ok(false)
+}
+
+/// The temporary output for re-encoding `file` to `new_path`, refusing to
+/// overwrite another file when the extension changes.
+fn prepare(file: &database::File, new_path: &Path) -> Result<PathBuf, String> {
+    let path = file.path.as_path();
+    if new_path != path && new_path.exists() {
+        return Err(format!(
+            "not re-encoding {}: {} already exists",
+            file.path.to_string_lossy(),
+            new_path.to_string_lossy()
+        ));
+    }
+    temp_path(path, new_path.extension())
+        .ok_or_else(|| format!("{} has no file name", file.path.to_string_lossy()))
+}
+
+/// Moves the finished output over `new_path`, removes the source when the
+/// format changed and records the new contents, logging the replacement to
+/// `undo`, which restores the old contents from another recorded copy
+/// only; `media_type` is set for a changed format.
+fn swap_in(
+    file: &database::File,
+    temp_path: &Path,
+    new_path: &Path,
+    media_type: Option<&str>,
+    db: &database::DB,
+    undo: UndoLog,
+    progress: &Progress,
+) -> Result<(), String> {
+    // on disk before it takes the name, so a crash cannot leave it truncated
+    fs::OpenOptions::new()
+        .write(true)
+        .open(temp_path)
+        .and_then(|output| output.sync_all())
+        .and_then(|()| fs::rename(temp_path, new_path))
+        .map_err(|err| format!("failed to replace {}: {}", new_path.to_string_lossy(), err))?;
+    let path = file.path.as_path();
+    if new_path != path {
+        if let Err(err) = fs::remove_file(path) {
+            progress.warn(format!(
+                "failed to remove re-encoded {}: {}",
+                file.path.to_string_lossy(),
+                err
+            ));
+        }
+    }
+    let logged = undo.record(
+        &db.lock(),
+        UndoAction::Transcoded,
+        &file.path,
+        Some(new_path),
+        &file.hash,
+        &file.hash_algorithm,
+    );
+    if let Err(err) = logged {
+        progress.warn(format!(
+            "failed to log the re-encoding of {}: {}",
+            file.path.to_string_lossy(),
+            err
+        ));
+    }
+    let (hash, size, modified_at) = rehash(file, new_path)?;
+    progress.transcoded(new_path, file.size, size);
+    // the encode may have scaled the frame and changed the codec
+    let mime = media_type
+        .unwrap_or(&file.media_type)
+        .parse()
+        .unwrap_or(mime_guess::mime::APPLICATION_OCTET_STREAM);
+    let format = extractor::extract_format(new_path, &mime);
+    let encoded = database::Encoded {
+        hash: &hash.digest,
+        size,
+        modified_at,
+        format: &format,
+    };
+    match media_type {
+        Some(media_type) => db
+            .lock()
+            .mark_converted(&file.path, new_path, media_type, &encoded),
+        None => db.lock().mark_optimized(&file.path, &encoded),
+    }
+    .map_err(|err| format!("failed to record {}: {}", new_path.to_string_lossy(), err))
+}
+
+/// Removes the temporary outputs of `path` a failed or interrupted encode
+/// left behind.
+fn remove_partial_outputs(path: &Path) {
+    let (Some(parent), Some(file_name)) = (path.parent(), path.file_name()) else {
+        return;
+    };
+    let mut prefix = file_name.to_os_string();
+    prefix.push(".transcode");
+    let prefix = prefix.to_string_lossy().into_owned();
+    let Ok(entries) = fs::read_dir(parent) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// `name.transcode.<extension>` next to `path`, so the encoder still picks
+/// the format from the extension.
+fn temp_path(path: &Path, extension: Option<&OsStr>) -> Option<PathBuf> {
+    sibling_path(path, "transcode", extension)
+}
+
+/// `name.<tag>.<extension>` next to `path`.
+fn sibling_path(path: &Path, tag: &str, extension: Option<&OsStr>) -> Option<PathBuf> {
+    let mut name = path.file_name()?.to_os_string();
+    name.push(".");
+    name.push(tag);
+    if let Some(ext) = extension {
+        name.push(".");
+        name.push(ext);
+    }
+    Some(path.with_file_name(name))
+}
+
+/// Hash, size and mtime of the re-encoded file at `path`, with the hash
+/// algorithm `file` was recorded with.
+fn rehash(file: &database::File, path: &Path) -> Result<(FileHash, u64, i64), String> {
+    let algorithm = file
+        .hash_algorithm
+        .parse()
+        .unwrap_or(HashAlgorithm::default());
+    let (Ok(hash), Ok(metadata)) = (hasher::file_hash(path, algorithm), fs::metadata(path)) else {
+        return Err(format!("failed to re-hash {}", path.to_string_lossy()));
+    };
+    Ok((hash, metadata.len(), scanner::modified_at(&metadata)))
+}
+
+/// A built-in preset by name, or else a JSON profile file.
+fn parse_profile(s: &str) -> Result<TranscodeProfile, String> {
+    if let Some(profile) = TranscodeProfile::preset(s) {
+        return Ok(profile);
+    }
+    TranscodeProfile::load(Path::new(s)).map_err(|err| {
+        format!(
+            "{} is neither a preset ({}) nor a readable profile file: {}",
+            s,
+            transcoder::PRESETS.join(", "),
+            err
+        )
+    })
+}
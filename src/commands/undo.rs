@@ -0,0 +1,125 @@
+use std::path::PathBuf;
+
+use clap::{ArgGroup, Args};
+use deduper::{database::UndoRun, undo};
+use tracing::{error, info};
+
+use super::{confirm, history::format_time, open_database, progress::Summary};
+
+#[derive(Args)]
+#[command(group(ArgGroup::new("run").required(true).args(["last", "run_id", "list"])))]
+pub struct UndoArgs {
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    /// Roll back the latest run with actions left to undo
+    #[arg(long)]
+    pub last: bool,
+    /// Roll back the run with this id, as listed by --list
+    #[arg(long, value_name = "RUN")]
+    pub run_id: Option<i64>,
+    /// List the runs with actions left to undo
+    #[arg(long)]
+    pub list: bool,
+    /// Print what would be undone without touching any file
+    #[arg(long)]
+    pub dry_run: bool,
+    /// Do not ask for confirmation before rolling back
+    #[arg(short, long)]
+    pub yes: bool,
+}
+
+/// Reverses the logged actions of a run, latest first.
+pub fn run(args: &UndoArgs) -> Summary {
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    let db = db.lock();
+    let runs = match db.undo_runs() {
+        Ok(runs) => runs,
+        Err(err) => {
+            error!("failed to read the undo log: {}", err);
+            return Summary::aborted();
+        }
+    };
+    if args.list {
+        print_runs(&runs);
+        return Summary::listed(runs.len());
+    }
+    let run_id = match args.run_id.or_else(|| runs.first().map(|run| run.run_id)) {
+        Some(run_id) => run_id,
+        None => {
+            info!("nothing left to undo");
+            return Summary::default().settled();
+        }
+    };
+    let entries = match db.undo_entries(run_id) {
+        Ok(entries) => entries,
+        Err(err) => {
+            error!("failed to read the undo log of run {}: {}", run_id, err);
+            return Summary::aborted();
+        }
+    };
+    if entries.is_empty() {
+        info!("nothing left to undo in run {}", run_id);
+        return Summary::default().settled();
+    }
+
+    if args.dry_run {
+        for entry in &entries {
+            println!(
+                "would undo {} {}",
+                entry.action.as_str(),
+                entry.path.to_string_lossy()
+            );
+        }
+        return Summary::default().settled();
+    }
+    if !args.yes
+        && !confirm(&format!(
+            "undo {} actions of run {}?",
+            entries.len(),
+            run_id
+        ))
+    {
+        info!("aborted");
+        return Summary::aborted();
+    }
+    let mut summary = Summary::default();
+    for entry in &entries {
+        match undo::undo(&db, entry) {
+            Ok(()) => {
+                info!("restored {}", entry.path.to_string_lossy());
+                summary.processed += 1;
+            }
+            Err(err) => {
+                error!(
+                    "failed to undo {} {}: {}",
+                    entry.action.as_str(),
+                    entry.path.to_string_lossy(),
+                    err
+                );
+                summary.fail(&err);
+            }
+        }
+    }
+    println!(
+        "undid {} of {} actions of run {}",
+        summary.processed,
+        entries.len(),
+        run_id
+    );
+    summary.settled()
+}
+
+fn print_runs(runs: &[UndoRun]) {
+    println!("{:>6} {:<19} {:>8}  command", "run", "started", "actions");
+    for run in runs {
+        println!(
+            "{:>6} {:<19} {:>8}  {}",
+            run.run_id,
+            format_time(run.started_at),
+            run.actions,
+            run.command
+        );
+    }
+}
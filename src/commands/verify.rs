@@ -0,0 +1,241 @@
+use std::{
+    collections::hash_map::RandomState,
+    fs,
+    hash::BuildHasher,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use clap::Args;
+use deduper::{
+    database::{File, Placement},
+    hasher::{self, HashAlgorithm},
+    volume::Volumes,
+};
+use rayon::prelude::*;
+use tracing::{error, info};
+
+use super::{
+    open_database,
+    progress::{OutputArgs, Progress, Summary},
+    thread_pool, ThrottleArgs,
+};
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    /// Number of worker threads used for hashing (0 = one per CPU)
+    #[arg(short, long, default_value_t = 0)]
+    pub jobs: usize,
+    #[command(flatten)]
+    pub throttle: ThrottleArgs,
+    /// Only re-hash a random share of the files, e.g. 5%, for a spot check
+    #[arg(long, value_name = "PERCENT", value_parser = parse_percentage)]
+    pub sample: Option<f64>,
+}
+
+/// Exits with 1 when a file is corrupt, missing or unreadable or a link
+/// `organize` recorded is broken, with 2 when the database cannot be read,
+/// and with 4 when nothing is recorded. Files on a drive that is not
+/// mounted are skipped rather than missing, and files on a drive mounted
+/// somewhere else now are checked there and their rows moved along.
+pub fn run(args: &VerifyArgs, output: &OutputArgs) -> Summary {
+    args.throttle.apply();
+    let Some(db) = open_database(&args.database) else {
+        return Summary::aborted();
+    };
+    let mut files = match db.lock().all_files() {
+        // files merged from other machines are checked there
+        Ok(files) => files
+            .into_iter()
+            .filter(|file| file.host.is_none())
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            error!("failed to read files: {}", err);
+            return Summary::aborted();
+        }
+    };
+    let placements = match db.lock().find_placements() {
+        Ok(placements) => placements,
+        Err(err) => {
+            error!("failed to read destinations: {}", err);
+            return Summary::aborted();
+        }
+    };
+    if let Some(percentage) = args.sample {
+        let recorded = files.len();
+        // a per-process random order, so every run checks different files
+        let order = RandomState::new();
+        files.sort_by_cached_key(|file| order.hash_one(&file.path));
+        files.truncate((recorded as f64 * percentage / 100.0).ceil() as usize);
+        info!("sampling {} of {} files", files.len(), recorded);
+    }
+    let volumes = Volumes::detect();
+    let unmounted = AtomicUsize::new(0);
+    let relocated = AtomicUsize::new(0);
+    let progress = Progress::new(output, || files.len());
+    let failures = thread_pool(args.jobs).install(|| {
+        files
+            .par_iter()
+            .filter(|file| {
+                let path = match whereabouts(file, &volumes) {
+                    Whereabouts::Recorded => file.path.clone(),
+                    Whereabouts::Remounted(path) => path,
+                    Whereabouts::Unmounted(volume) => {
+                        progress.debug(format!(
+                            "not mounted: {} on {}",
+                            file.path.to_string_lossy(),
+                            volume
+                        ));
+                        unmounted.fetch_add(1, Ordering::Relaxed);
+                        progress.advance();
+                        return false;
+                    }
+                };
+                let Ok(algorithm) = file.hash_algorithm.parse::<HashAlgorithm>() else {
+                    progress.warn(format!(
+                        "unknown hash algorithm {} for {}",
+                        file.hash_algorithm,
+                        file.path.to_string_lossy()
+                    ));
+                    progress.advance();
+                    return true;
+                };
+                let hash = hasher::file_hash(&path, algorithm);
+                progress.hashed(if hash.is_ok() { file.size } else { 0 });
+                match hash {
+                    Ok(hash) if hash.matches(&file.hash) => {
+                        if path != file.path {
+                            progress.info(format!(
+                                "remounted: {} -> {}",
+                                file.path.to_string_lossy(),
+                                path.to_string_lossy()
+                            ));
+                            if let Err(err) =
+                                db.lock().relocate_file(&file.path, &path, Some(&file.hash))
+                            {
+                                progress.warn(format!(
+                                    "failed to record {} at {}: {}",
+                                    file.path.to_string_lossy(),
+                                    path.to_string_lossy(),
+                                    err
+                                ));
+                            }
+                            relocated.fetch_add(1, Ordering::Relaxed);
+                        }
+                        false
+                    }
+                    Ok(_) => {
+                        progress.warn(format!("hash mismatch: {}", file.path.to_string_lossy()));
+                        true
+                    }
+                    Err(err) if err.kind() == ErrorKind::NotFound => {
+                        progress.warn(format!("missing: {}", file.path.to_string_lossy()));
+                        true
+                    }
+                    Err(err) => {
+                        progress.warn(format!(
+                            "failed to get file hash for {}: {}",
+                            file.path.to_string_lossy(),
+                            err
+                        ));
+                        true
+                    }
+                }
+            })
+            .count()
+    });
+    let broken = broken_links(&placements, &progress);
+    progress.finish();
+    println!(
+        "verified {} files, {} failed, {} of {} links broken",
+        files.len(),
+        failures,
+        broken,
+        placements.len()
+    );
+    let unmounted = unmounted.into_inner();
+    if unmounted > 0 {
+        println!("{} files are on drives that are not mounted", unmounted);
+    }
+    let relocated = relocated.into_inner();
+    if relocated > 0 {
+        println!(
+            "{} files were found where their drive is mounted now",
+            relocated
+        );
+    }
+    let mut summary = progress.summary();
+    summary.failed = (failures + broken) as u64;
+    summary.settled()
+}
+
+enum Whereabouts {
+    /// At the recorded path, or gone from a drive that is still mounted
+    Recorded,
+    /// On its drive, which is mounted somewhere else now
+    Remounted(PathBuf),
+    /// On a drive that is not mounted
+    Unmounted(String),
+}
+
+/// Where to look for `file`, which is only looked up on its volume when
+/// nothing is at the recorded path.
+fn whereabouts(file: &File, volumes: &Volumes) -> Whereabouts {
+    let is_gone = |path: &Path| matches!(fs::symlink_metadata(path), Err(err) if err.kind() == ErrorKind::NotFound);
+    let (Some(volume), Some(volume_path)) = (&file.volume, &file.volume_path) else {
+        return Whereabouts::Recorded;
+    };
+    if !is_gone(&file.path) {
+        return Whereabouts::Recorded;
+    }
+    if !volumes.is_mounted(volume) {
+        return Whereabouts::Unmounted(volume.clone());
+    }
+    match volumes.resolve(volume, volume_path) {
+        Some(path) if !is_gone(&path) => Whereabouts::Remounted(path),
+        _ => Whereabouts::Recorded,
+    }
+}
+
+/// Counts the placements missing from the destination, or whose symlink
+/// points at a source that is gone.
+fn broken_links(placements: &[Placement], progress: &Progress) -> usize {
+    placements
+        .iter()
+        .filter(|placement| {
+            let dest_path = placement.dest_path.as_path();
+            if dest_path.exists() {
+                return false;
+            }
+            if fs::symlink_metadata(dest_path).is_ok() {
+                progress.warn(format!(
+                    "broken symlink: {} -> {}",
+                    placement.dest_path.to_string_lossy(),
+                    placement.path.to_string_lossy()
+                ));
+            } else {
+                progress.warn(format!(
+                    "missing link: {}",
+                    placement.dest_path.to_string_lossy()
+                ));
+            }
+            true
+        })
+        .count()
+}
+
+/// Parses a share of files such as `5%` or `5`.
+fn parse_percentage(s: &str) -> Result<f64, String> {
+    let percentage = s
+        .trim_end_matches('%')
+        .parse::<f64>()
+        .map_err(|err| format!("invalid percentage {}: {}", s, err))?;
+    if percentage > 0.0 && percentage <= 100.0 {
+        Ok(percentage)
+    } else {
+        Err(format!("percentage {} is not between 0 and 100", s))
+    }
+}
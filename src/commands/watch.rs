@@ -0,0 +1,270 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use clap::Args;
+use deduper::{
+    database::DB,
+    extractor, ignore,
+    linker::LinkStrategy,
+    media::{Inspector, WalkOptions},
+    scanner::{ScanOutcome, Scanner},
+    sidecar,
+    undo::UndoLog,
+    volume::Volumes,
+    watcher::SourceWatcher,
+    DeduperError, EventSink, Organizer,
+};
+use tracing::{error, info};
+
+use super::{
+    log_sources, open_database,
+    organize::{log_move, note_conflict, organize_unknown, place_sidecars, record_dest_path},
+    print_timestamp_source,
+    progress::{Progress, Summary},
+    IgnoreArgs, InspectArgs, PlacementArgs, ThrottleArgs,
+};
+
+#[derive(Args)]
+pub struct WatchArgs {
+    #[arg(short, long, value_hint = clap::ValueHint::DirPath, num_args = 1.., required = true)]
+    pub sources: Vec<PathBuf>,
+    #[command(flatten)]
+    pub placement: PlacementArgs,
+    #[arg(long, value_hint = clap::ValueHint::FilePath, default_value = "deduper.db")]
+    pub database: PathBuf,
+    #[command(flatten)]
+    pub inspect: InspectArgs,
+    #[command(flatten)]
+    pub throttle: ThrottleArgs,
+    #[command(flatten)]
+    pub ignore: IgnoreArgs,
+    /// Seconds a file has to go unchanged before it is processed
+    #[arg(long, default_value_t = 5)]
+    pub settle: u64,
+}
+
+/// Only returns when it cannot start watching.
+pub fn run(args: &WatchArgs) -> Summary {
+    let progress = Arc::new(Progress::hidden());
+    let Some(ingester) = Ingester::new(args, "watch", progress.clone()) else {
+        return Summary::aborted();
+    };
+    let mut watcher = match SourceWatcher::new(&args.sources, Duration::from_secs(args.settle)) {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            error!("failed to watch sources: {}", err);
+            return Summary::aborted();
+        }
+    };
+    info!("watching for new media, press Ctrl-C to stop");
+    loop {
+        for path in watcher.poll(Duration::from_secs(60)) {
+            match path {
+                Ok(path) => {
+                    ingester.ingest(&path, &progress);
+                }
+                Err(err) => progress.warn(err),
+            }
+        }
+    }
+}
+
+/// Records new or modified files in the database and places them into the
+/// destination.
+pub(super) struct Ingester {
+    scanner: Scanner,
+    inspector: Inspector,
+    organizer: Organizer,
+    db: DB,
+    /// The run moves are logged under for `undo`
+    undo: UndoLog,
+    excluded: Vec<PathBuf>,
+    sources: Vec<PathBuf>,
+    ignore: Option<ignore::Rules>,
+}
+
+impl Ingester {
+    /// Opens the database and builds the organizer, logging moves under a
+    /// run of `command` and reporting links placed to `sink`; problems are
+    /// logged and give `None`.
+    pub(super) fn new(args: &WatchArgs, command: &str, sink: Arc<dyn EventSink>) -> Option<Self> {
+        log_sources(&args.sources);
+        args.throttle.apply();
+        args.placement.log_destinations();
+        info!("database: {}", args.database.to_string_lossy());
+        let db = open_database(&args.database)?;
+        let organizer = args.placement.organizer()?.events(sink);
+        let inspector = args.inspect.inspector();
+        let undo = match UndoLog::start(&db.lock(), command) {
+            Ok(undo) => undo,
+            Err(err) => {
+                error!("failed to start the undo log: {}", err);
+                return None;
+            }
+        };
+        Some(Self {
+            scanner: Scanner::new(inspector.clone()).volumes(Volumes::detect()),
+            inspector,
+            organizer,
+            db,
+            undo,
+            excluded: args.placement.excluded(),
+            sources: args.sources.clone(),
+            ignore: args.ignore.rules(),
+        })
+    }
+
+    /// How to walk the sources for files to ingest.
+    pub(super) fn walk_options(&self) -> WalkOptions {
+        WalkOptions {
+            exclude: self.excluded.clone(),
+            ignore: self.ignore.clone(),
+            ..WalkOptions::default()
+        }
+    }
+
+    /// Whether `.deduperignore` files, or the global ignore file, leave
+    /// `path` out of its source, the innermost one it is below.
+    fn is_ignored(&self, path: &Path) -> bool {
+        let Some(global) = &self.ignore else {
+            return false;
+        };
+        self.sources
+            .iter()
+            .filter(|source| path.starts_with(source))
+            .max_by_key(|source| source.components().count())
+            .is_some_and(|source| ignore::is_ignored(source, path, global.clone()))
+    }
+
+    /// Ingests one file, reporting when its contents are already known.
+    /// Files in the destination, and those ignore files leave out, are
+    /// left alone. Returns false when the file
+    /// failed.
+    pub(super) fn ingest(&self, path: &Path, progress: &Progress) -> bool {
+        if self.excluded.iter().any(|dir| path.starts_with(dir)) || self.is_ignored(path) {
+            return true;
+        }
+        if self.organizer.skips(path) {
+            self.ingest_sidecar(path, progress);
+            return true;
+        }
+        let media = match self.scanner.scan_file(path, &self.db) {
+            Ok(ScanOutcome::Recorded(media)) => media,
+            // size and mtime match the database, an earlier event handled it
+            Ok(ScanOutcome::Unchanged(_)) => return true,
+            Err(DeduperError::TimestampMissing) => {
+                return organize_unknown(
+                    path,
+                    &self.inspector,
+                    &self.organizer,
+                    Some((&self.db, self.undo)),
+                    None,
+                    progress,
+                );
+            }
+            Err(err) => {
+                progress.fail(path, &err);
+                return false;
+            }
+        };
+        print_timestamp_source(progress, &media);
+        progress.record(&media.hash.digest, media.size);
+
+        let known = self.db.read().find_files_by_hash(&media.hash.digest);
+        match known {
+            Ok(files) => {
+                if let Some(original) = files.iter().find(|file| file.path != path) {
+                    progress.info(format!(
+                        "{} duplicates {}",
+                        path.to_string_lossy(),
+                        original.path.to_string_lossy()
+                    ));
+                }
+            }
+            Err(err) => {
+                progress.fail(path, &err.into());
+                return false;
+            }
+        }
+        match self.organizer.place(&media) {
+            // a duplicate lands on the same name as its original
+            Ok(placement) if placement.existed => {
+                record_dest_path(&self.db, path, &placement.path, progress);
+                place_sidecars(
+                    &self.organizer,
+                    path,
+                    &media.mime_type,
+                    &placement.path,
+                    Some((&self.db, self.undo)),
+                    progress,
+                );
+            }
+            Ok(placement) => {
+                let destination = &placement.path;
+                if let Some(taken) = &placement.taken {
+                    note_conflict(
+                        Some(&self.db),
+                        path,
+                        taken,
+                        destination,
+                        &media.hash.digest,
+                        progress,
+                    );
+                }
+                record_dest_path(&self.db, path, destination, progress);
+                if self.organizer.strategy() == LinkStrategy::Move {
+                    log_move(
+                        &self.db,
+                        self.undo,
+                        path,
+                        &media.hash,
+                        destination,
+                        progress,
+                    );
+                }
+                place_sidecars(
+                    &self.organizer,
+                    path,
+                    &media.mime_type,
+                    destination,
+                    Some((&self.db, self.undo)),
+                    progress,
+                );
+                progress.info(format!(
+                    "placed {} at {}",
+                    path.to_string_lossy(),
+                    destination.to_string_lossy()
+                ))
+            }
+            Err(err) => {
+                progress.fail(path, &err);
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Sidecars are often written after their media, say an edit saved
+    /// later, so one showing up next to media that was placed already
+    /// follows it there.
+    fn ingest_sidecar(&self, path: &Path, progress: &Progress) {
+        for media_path in sidecar::media_for(path) {
+            let dest_path = self.db.read().find_dest_path(&media_path);
+            match dest_path {
+                Ok(Some(dest_path)) => place_sidecars(
+                    &self.organizer,
+                    &media_path,
+                    &extractor::extract_mimetype(&media_path),
+                    &dest_path,
+                    Some((&self.db, self.undo)),
+                    progress,
+                ),
+                Ok(None) => {}
+                Err(err) => progress.fail(path, &err.into()),
+            }
+        }
+    }
+}
@@ -0,0 +1,680 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+use crate::extractor::Format;
+
+/// Largest `moov` box or Matroska `Info` element read into memory.
+const MAX_HEADER_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Seconds from the MP4 epoch, 1904-01-01, to the unix epoch.
+const MP4_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// Seconds from the unix epoch to the Matroska epoch, 2001-01-01.
+const MATROSKA_EPOCH: i64 = 978_307_200;
+
+const EBML_HEADER: u32 = 0x1A45_DFA3;
+const MATROSKA_SEGMENT: u32 = 0x1853_8067;
+const MATROSKA_INFO: u32 = 0x1549_A966;
+const MATROSKA_CLUSTER: u32 = 0x1F43_B675;
+const MATROSKA_DATE_UTC: u32 = 0x4461;
+
+/// FLAC metadata block holding the Vorbis comments.
+const FLAC_VORBIS_COMMENT: u8 = 4;
+
+/// Reads the metadata tags of an MP4/QuickTime, Matroska, MP3 or FLAC file
+/// without libav. Keys match what ffmpeg reports: `creation_time`,
+/// `location`, the `com.apple.quicktime.*`/`com.android.*` keys, `date`
+/// for the recording date of ID3 and MP4 audio tags, and the Vorbis
+/// comments of FLAC as they are named in the file. Other formats give no
+/// tags.
+pub fn read_tags(path: &Path) -> io::Result<HashMap<String, String>> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut magic = [0; 8];
+    if file.read_exact(&mut magic).is_err() {
+        return Ok(HashMap::new());
+    }
+    file.seek(SeekFrom::Start(0))?;
+    if u32::from_be_bytes([magic[0], magic[1], magic[2], magic[3]]) == EBML_HEADER {
+        read_matroska_tags(&mut file)
+    } else if magic.starts_with(b"ID3") {
+        read_id3_tags(&mut file)
+    } else if magic.starts_with(b"fLaC") {
+        read_flac_tags(&mut file)
+    } else if matches!(&magic[4..], b"ftyp" | b"moov" | b"mdat" | b"free" | b"wide") {
+        read_mp4_tags(&mut file)
+    } else {
+        Ok(HashMap::new())
+    }
+}
+
+fn read_mp4_tags(file: &mut (impl Read + Seek)) -> io::Result<HashMap<String, String>> {
+    let mut tags = HashMap::new();
+    if let Some(moov) = read_moov(file)? {
+        parse_moov(&moov, &mut tags);
+    }
+    Ok(tags)
+}
+
+/// The payload of the `moov` box. mdat is usually the bulk of the file, so
+/// top-level boxes are skipped by seeking and only moov is read.
+fn read_moov(file: &mut (impl Read + Seek)) -> io::Result<Option<Vec<u8>>> {
+    while let Some((kind, size)) = read_box_header(file)? {
+        if &kind != b"moov" {
+            match size {
+                Some(size) => file.seek(SeekFrom::Current(size as i64))?,
+                None => break,
+            };
+            continue;
+        }
+        let size = size.unwrap_or(MAX_HEADER_SIZE).min(MAX_HEADER_SIZE);
+        let mut moov = Vec::new();
+        file.take(size).read_to_end(&mut moov)?;
+        return Ok(Some(moov));
+    }
+    Ok(None)
+}
+
+/// Reads the length of an MP4/QuickTime file and the frame size and codec
+/// of its first video track without libav. Other formats give no format.
+pub fn read_format(path: &Path) -> io::Result<Format> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut magic = [0; 8];
+    if file.read_exact(&mut magic).is_err()
+        || !matches!(&magic[4..], b"ftyp" | b"moov" | b"mdat" | b"free" | b"wide")
+    {
+        return Ok(Format::default());
+    }
+    file.seek(SeekFrom::Start(0))?;
+    Ok(read_moov(&mut file)?
+        .map(|moov| parse_format(&moov))
+        .unwrap_or_default())
+}
+
+fn parse_format(moov: &[u8]) -> Format {
+    let mut format = Format::default();
+    for (kind, payload) in boxes(moov) {
+        match &kind {
+            b"mvhd" => format.duration = mvhd_duration(payload),
+            b"trak" if format.codec.is_none() => {
+                let Some(mdia) = child(payload, b"mdia") else {
+                    continue;
+                };
+                // handler type after version, flags and pre-defined
+                if child(mdia, b"hdlr").and_then(|hdlr| hdlr.get(8..12)) != Some(b"vide") {
+                    continue;
+                }
+                // the sample entry format after version, flags, entry count
+                // and the entry size
+                format.codec = child(mdia, b"minf")
+                    .and_then(|minf| child(minf, b"stbl"))
+                    .and_then(|stbl| child(stbl, b"stsd"))
+                    .and_then(|stsd| stsd.get(12..16))
+                    .map(codec_name);
+                // 16.16 fixed-point width and height end the track header
+                if let Some(size) = child(payload, b"tkhd")
+                    .and_then(|tkhd| tkhd.get(tkhd.len().checked_sub(8)?..))
+                {
+                    let fixed = |bytes: &[u8]| u32::from_be_bytes(bytes.try_into().unwrap()) >> 16;
+                    format.width = Some(fixed(&size[..4])).filter(|width| *width > 0);
+                    format.height = Some(fixed(&size[4..])).filter(|height| *height > 0);
+                }
+            }
+            _ => {}
+        }
+    }
+    format
+}
+
+/// The payload of the first child box of `kind`.
+fn child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    boxes(data)
+        .find(|(child, _)| child == kind)
+        .map(|(_, payload)| payload)
+}
+
+fn mvhd_duration(mvhd: &[u8]) -> Option<f64> {
+    let (timescale, duration) = match mvhd.first()? {
+        0 => (
+            u32::from_be_bytes(mvhd.get(12..16)?.try_into().ok()?),
+            u64::from(u32::from_be_bytes(mvhd.get(16..20)?.try_into().ok()?)),
+        ),
+        _ => (
+            u32::from_be_bytes(mvhd.get(20..24)?.try_into().ok()?),
+            u64::from_be_bytes(mvhd.get(24..32)?.try_into().ok()?),
+        ),
+    };
+    (timescale > 0 && duration > 0).then(|| duration as f64 / f64::from(timescale))
+}
+
+/// ffmpeg's name of the codec of an MP4 sample entry.
+fn codec_name(fourcc: &[u8]) -> String {
+    match fourcc {
+        b"avc1" | b"avc3" => "h264",
+        b"hvc1" | b"hev1" => "hevc",
+        b"av01" => "av1",
+        b"vp09" => "vp9",
+        b"vp08" => "vp8",
+        b"mp4v" => "mpeg4",
+        b"apch" | b"apcn" | b"apcs" | b"apco" | b"ap4h" | b"ap4x" => "prores",
+        b"jpeg" | b"mjpa" => "mjpeg",
+        _ => return string(fourcc),
+    }
+    .to_owned()
+}
+
+/// Box type and payload size; `None` size extends to the end of the file.
+fn read_box_header(file: &mut impl Read) -> io::Result<Option<([u8; 4], Option<u64>)>> {
+    let mut header = [0; 8];
+    match file.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let kind = [header[4], header[5], header[6], header[7]];
+    let size = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+        0 => None,
+        1 => {
+            let mut large = [0; 8];
+            file.read_exact(&mut large)?;
+            Some(u64::from_be_bytes(large).saturating_sub(16))
+        }
+        size => Some(u64::from(size).saturating_sub(8)),
+    };
+    Ok(Some((kind, size)))
+}
+
+/// Child boxes of an in-memory box payload.
+fn boxes(mut data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    std::iter::from_fn(move || {
+        let size = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+        let kind = data.get(4..8)?.try_into().ok()?;
+        let (payload, rest) = match size {
+            0 => (data.get(8..)?, &data[data.len()..]),
+            size => (data.get(8..size)?, data.get(size..)?),
+        };
+        data = rest;
+        Some((kind, payload))
+    })
+}
+
+fn parse_moov(moov: &[u8], tags: &mut HashMap<String, String>) {
+    for (kind, payload) in boxes(moov) {
+        match &kind {
+            b"mvhd" => {
+                if let Some(creation_time) = mvhd_creation_time(payload) {
+                    tags.insert(
+                        "creation_time".to_owned(),
+                        creation_time.format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string(),
+                    );
+                }
+            }
+            b"udta" => {
+                for (kind, payload) in boxes(payload) {
+                    match &kind {
+                        b"\xA9xyz" => {
+                            // 16-bit length and language code, then the ISO 6709 string
+                            if let Some(location) = payload.get(4..) {
+                                tags.insert("location".to_owned(), string(location));
+                            }
+                        }
+                        // iTunes-style tags of M4A audio
+                        b"meta" => parse_quicktime_meta(payload, tags),
+                        _ => {}
+                    }
+                }
+            }
+            b"meta" => parse_quicktime_meta(payload, tags),
+            _ => {}
+        }
+    }
+}
+
+fn mvhd_creation_time(mvhd: &[u8]) -> Option<DateTime<Utc>> {
+    let seconds = match mvhd.first()? {
+        0 => u64::from(u32::from_be_bytes(mvhd.get(4..8)?.try_into().ok()?)),
+        _ => u64::from_be_bytes(mvhd.get(4..12)?.try_into().ok()?),
+    };
+    if seconds == 0 {
+        return None;
+    }
+    DateTime::from_timestamp(seconds as i64 - MP4_EPOCH_OFFSET, 0)
+}
+
+/// QuickTime metadata: a `keys` box naming every entry and an `ilst` box
+/// whose children are typed by the one-based index of their key. iTunes
+/// metadata has no `keys` and types the children by their tag instead, of
+/// which the `©day` recording date is kept.
+fn parse_quicktime_meta(meta: &[u8], tags: &mut HashMap<String, String>) {
+    // the ISO flavour of meta carries version and flags before its children
+    let meta = match meta.get(4..8) {
+        Some(b"hdlr") => meta,
+        _ => meta.get(4..).unwrap_or_default(),
+    };
+    let mut keys = Vec::new();
+    for (kind, payload) in boxes(meta) {
+        match &kind {
+            b"keys" => {
+                let mut entries = payload.get(8..).unwrap_or_default();
+                while let Some(size) = entries.get(..4) {
+                    let size = u32::from_be_bytes(size.try_into().unwrap()) as usize;
+                    let Some(key) = entries.get(8..size) else {
+                        break;
+                    };
+                    keys.push(string(key));
+                    entries = &entries[size..];
+                }
+            }
+            b"ilst" => {
+                for (kind, item) in boxes(payload) {
+                    let index = u32::from_be_bytes(kind) as usize;
+                    let key = match index.checked_sub(1).and_then(|index| keys.get(index)) {
+                        Some(key) => key.clone(),
+                        None if &kind == b"\xA9day" => "date".to_owned(),
+                        None => continue,
+                    };
+                    let value = boxes(item)
+                        .find(|(kind, _)| kind == b"data")
+                        .and_then(|(_, data)| data.get(8..));
+                    if let Some(value) = value {
+                        tags.insert(key, string(value));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn read_matroska_tags(file: &mut (impl Read + Seek)) -> io::Result<HashMap<String, String>> {
+    let mut tags = HashMap::new();
+    while let Some((id, size)) = read_element_header(file)? {
+        match id {
+            // descend into the segment; its size is often unknown while recording
+            MATROSKA_SEGMENT => {}
+            MATROSKA_INFO => {
+                let size = size.unwrap_or(MAX_HEADER_SIZE).min(MAX_HEADER_SIZE);
+                let mut info = Vec::new();
+                file.take(size).read_to_end(&mut info)?;
+                if let Some(date) = matroska_date(&info) {
+                    tags.insert(
+                        "creation_time".to_owned(),
+                        date.format("%Y-%m-%dT%H:%M:%S%.6fZ").to_string(),
+                    );
+                }
+                break;
+            }
+            // media data starts, Info comes before it
+            MATROSKA_CLUSTER => break,
+            _ => match size {
+                Some(size) => {
+                    file.seek(SeekFrom::Current(size as i64))?;
+                }
+                None => break,
+            },
+        }
+    }
+    Ok(tags)
+}
+
+/// Element ID with its marker bits and payload size; `None` is the
+/// reserved unknown size.
+fn read_element_header(file: &mut impl Read) -> io::Result<Option<(u32, Option<u64>)>> {
+    let Some((id, _)) = read_vint(file)? else {
+        return Ok(None);
+    };
+    let Some((size, width)) = read_vint(file)? else {
+        return Ok(None);
+    };
+    let marker = 1u64 << (7 * width);
+    let size = size & (marker - 1);
+    Ok(Some((id as u32, (size != marker - 1).then_some(size))))
+}
+
+/// A variable length integer, raw including its length marker, and its
+/// width in bytes.
+fn read_vint(file: &mut impl Read) -> io::Result<Option<(u64, u32)>> {
+    let mut first = [0; 1];
+    match file.read_exact(&mut first) {
+        Ok(()) => {}
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let width = first[0].leading_zeros() + 1;
+    if width > 8 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid EBML variable length integer",
+        ));
+    }
+    let mut value = u64::from(first[0]);
+    for _ in 1..width {
+        file.read_exact(&mut first)?;
+        value = (value << 8) | u64::from(first[0]);
+    }
+    Ok(Some((value, width)))
+}
+
+fn matroska_date(mut info: &[u8]) -> Option<DateTime<Utc>> {
+    while !info.is_empty() {
+        let (id, size) = read_element_header(&mut info).ok()??;
+        let size = size? as usize;
+        let payload = info.get(..size)?;
+        if id == MATROSKA_DATE_UTC {
+            let nanoseconds = i64::from_be_bytes(payload.try_into().ok()?);
+            return DateTime::from_timestamp(MATROSKA_EPOCH, 0)?
+                .checked_add_signed(TimeDelta::nanoseconds(nanoseconds));
+        }
+        info = &info[size..];
+    }
+    None
+}
+
+/// ID3v2 tags at the start of an MP3. The recording date is `TDRC` in
+/// version 2.4, and spread over the year, `DDMM` and `HHMM` of `TYER`,
+/// `TDAT` and `TIME` before; like ffmpeg, those are merged into one `date`.
+fn read_id3_tags(file: &mut impl Read) -> io::Result<HashMap<String, String>> {
+    let mut header = [0; 10];
+    file.read_exact(&mut header)?;
+    let version = header[3];
+    let size = syncsafe(&header[6..10]);
+    let mut tag = Vec::new();
+    file.take(u64::from(size).min(MAX_HEADER_SIZE))
+        .read_to_end(&mut tag)?;
+    // version 2.2 has three-letter frame IDs and three-byte sizes
+    let (id_len, size_len, header_len) = if version == 2 { (3, 3, 6) } else { (4, 4, 10) };
+    let mut frames = tag.as_slice();
+    if header[5] & 0x40 != 0 && version > 2 {
+        let extended = frames.get(..4).map_or(0, |size| match version {
+            3 => u32::from_be_bytes(size.try_into().unwrap()) + 4,
+            _ => syncsafe(size),
+        });
+        frames = frames.get(extended as usize..).unwrap_or_default();
+    }
+    let mut text = HashMap::new();
+    while let Some(frame_header) = frames.get(..header_len) {
+        let id = &frame_header[..id_len];
+        if id[0] == 0 {
+            // padding
+            break;
+        }
+        let size = &frame_header[id_len..id_len + size_len];
+        let size = match version {
+            4 => syncsafe(size),
+            _ => size.iter().fold(0, |size, &b| (size << 8) | u32::from(b)),
+        } as usize;
+        let Some(payload) = frames.get(header_len..header_len + size) else {
+            break;
+        };
+        // text frames only, not the likes of embedded cover art
+        if let Some(value) = id.starts_with(b"T").then(|| id3_text(payload)).flatten() {
+            text.insert(String::from_utf8_lossy(id).into_owned(), value);
+        }
+        frames = &frames[header_len + size..];
+    }
+
+    let mut tags = HashMap::new();
+    let get = |ids: [&str; 2]| ids.iter().find_map(|id| text.get(*id));
+    if let Some(date) = text.get("TDRC") {
+        tags.insert("date".to_owned(), date.clone());
+    } else if let Some(year) = get(["TYER", "TYE"]) {
+        let mut date = year.clone();
+        if let Some(day) = get(["TDAT", "TDA"]).filter(|day| day.len() == 4) {
+            date.push_str(&format!("-{}-{}", &day[2..], &day[..2]));
+            if let Some(time) = get(["TIME", "TIM"]).filter(|time| time.len() == 4) {
+                date.push_str(&format!(" {}:{}", &time[..2], &time[2..]));
+            }
+        }
+        tags.insert("date".to_owned(), date);
+    }
+    Ok(tags)
+}
+
+/// 28-bit integer stored in the low seven bits of four bytes.
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0, |value, &b| (value << 7) | u32::from(b & 0x7F))
+}
+
+/// The value of an ID3 text frame after its encoding
+/// byte: Latin-1, UTF-16 with a byte order mark, UTF-16BE or UTF-8.
+fn id3_text(payload: &[u8]) -> Option<String> {
+    let (&encoding, text) = payload.split_first()?;
+    let text = match encoding {
+        0 => text.iter().map(|&b| char::from(b)).collect(),
+        1 | 2 => {
+            let (big_endian, text) = match text {
+                [0xFF, 0xFE, rest @ ..] => (false, rest),
+                [0xFE, 0xFF, rest @ ..] => (true, rest),
+                _ => (encoding == 2, text),
+            };
+            let units = text
+                .chunks_exact(2)
+                .map(|unit| match big_endian {
+                    true => u16::from_be_bytes([unit[0], unit[1]]),
+                    false => u16::from_le_bytes([unit[0], unit[1]]),
+                })
+                .collect::<Vec<_>>();
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(text).into_owned(),
+    };
+    let text = text.trim_end_matches('\0').trim();
+    (!text.is_empty()).then(|| text.to_owned())
+}
+
+/// Vorbis comments of a FLAC file, `DATE` among them, keyed as written.
+fn read_flac_tags(file: &mut impl Read) -> io::Result<HashMap<String, String>> {
+    let mut tags = HashMap::new();
+    let mut magic = [0; 4];
+    file.read_exact(&mut magic)?;
+    loop {
+        let mut header = [0; 4];
+        file.read_exact(&mut header)?;
+        let last = header[0] & 0x80 != 0;
+        let size = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+        let mut block = Vec::new();
+        file.take(u64::from(size)).read_to_end(&mut block)?;
+        if header[0] & 0x7F == FLAC_VORBIS_COMMENT {
+            parse_vorbis_comments(&block, &mut tags);
+            break;
+        }
+        if last {
+            break;
+        }
+    }
+    Ok(tags)
+}
+
+/// Vendor string, then a count of `KEY=value` comments, with little-endian
+/// lengths.
+fn parse_vorbis_comments(mut block: &[u8], tags: &mut HashMap<String, String>) {
+    fn next_u32(data: &mut &[u8]) -> Option<usize> {
+        let value = u32::from_le_bytes(data.get(..4)?.try_into().ok()?);
+        *data = &data[4..];
+        Some(value as usize)
+    }
+    fn next_string<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+        let len = next_u32(data)?;
+        let value = data.get(..len)?;
+        *data = &data[len..];
+        Some(value)
+    }
+    let Some(count) = next_string(&mut block).and_then(|_vendor| next_u32(&mut block)) else {
+        return;
+    };
+    for _ in 0..count {
+        let Some(comment) = next_string(&mut block) else {
+            return;
+        };
+        if let Some((key, value)) = String::from_utf8_lossy(comment).split_once('=') {
+            tags.insert(key.to_owned(), value.trim().to_owned());
+        }
+    }
+}
+
+fn string(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches('\0')
+        .trim()
+        .to_owned()
+}
+
+#[test]
+fn test_read_tags() {
+    fn mp4_box(kind: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    let mut mvhd = vec![0; 4];
+    mvhd.extend_from_slice(&((1_693_608_581 + MP4_EPOCH_OFFSET) as u32).to_be_bytes());
+    let key = b"com.apple.quicktime.make";
+    let mut keys = vec![0, 0, 0, 0, 0, 0, 0, 1];
+    keys.extend_from_slice(&((key.len() + 8) as u32).to_be_bytes());
+    keys.extend_from_slice(b"mdta");
+    keys.extend_from_slice(key);
+    let data = mp4_box(b"data", b"\0\0\0\x01\0\0\0\0Apple");
+    let meta = [
+        mp4_box(b"hdlr", &[0; 8]),
+        mp4_box(b"keys", &keys),
+        mp4_box(b"ilst", &mp4_box(&1u32.to_be_bytes(), &data)),
+    ]
+    .concat();
+    let udta = mp4_box(b"\xA9xyz", b"\0\x12\x15\xC7+35.6895+139.6917/");
+    let moov = [
+        mp4_box(b"mvhd", &mvhd),
+        mp4_box(b"udta", &udta),
+        mp4_box(b"meta", &meta),
+    ]
+    .concat();
+    let file = [
+        mp4_box(b"ftyp", b"isom"),
+        mp4_box(b"mdat", &[0; 32]),
+        mp4_box(b"moov", &moov),
+    ]
+    .concat();
+
+    let path = std::env::temp_dir().join(format!("deduper-container-{}.mp4", std::process::id()));
+    std::fs::write(&path, file).unwrap();
+    let tags = read_tags(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        Some("2023-09-01T22:49:41.000000Z"),
+        tags.get("creation_time").map(String::as_str)
+    );
+    assert_eq!(
+        Some("+35.6895+139.6917/"),
+        tags.get("location").map(String::as_str)
+    );
+    assert_eq!(
+        Some("Apple"),
+        tags.get("com.apple.quicktime.make").map(String::as_str)
+    );
+}
+
+#[test]
+fn test_read_format() {
+    fn mp4_box(kind: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(payload);
+        data
+    }
+    let trak = |handler: &[u8], fourcc: &[u8], width: u32, height: u32| {
+        let mut tkhd = vec![0; 76];
+        tkhd.extend_from_slice(&(width << 16).to_be_bytes());
+        tkhd.extend_from_slice(&(height << 16).to_be_bytes());
+        let hdlr = [&[0; 8], handler, &[0; 12]].concat();
+        let stsd = [&[0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 16], fourcc, &[0; 8]].concat();
+        let stbl = mp4_box(b"stbl", &mp4_box(b"stsd", &stsd));
+        let mdia = [mp4_box(b"hdlr", &hdlr), mp4_box(b"minf", &stbl)].concat();
+        mp4_box(
+            b"trak",
+            &[mp4_box(b"tkhd", &tkhd), mp4_box(b"mdia", &mdia)].concat(),
+        )
+    };
+
+    let mut mvhd = vec![0; 12];
+    mvhd.extend_from_slice(&600u32.to_be_bytes());
+    mvhd.extend_from_slice(&9000u32.to_be_bytes());
+    let moov = [
+        mp4_box(b"mvhd", &mvhd),
+        trak(b"soun", b"mp4a", 0, 0),
+        trak(b"vide", b"hvc1", 3840, 2160),
+    ]
+    .concat();
+    let file = [mp4_box(b"ftyp", b"qt  "), mp4_box(b"moov", &moov)].concat();
+
+    let path = std::env::temp_dir().join(format!("deduper-format-{}.mov", std::process::id()));
+    std::fs::write(&path, file).unwrap();
+    let format = read_format(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(
+        Format {
+            width: Some(3840),
+            height: Some(2160),
+            duration: Some(15.0),
+            codec: Some("hevc".to_owned()),
+        },
+        format
+    );
+}
+
+#[test]
+fn test_read_audio_tags() {
+    let read = |name: &str, data: &[u8]| {
+        let path = std::env::temp_dir().join(format!("deduper-{}-{}", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        let tags = read_tags(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        tags.get("date").or_else(|| tags.get("DATE")).cloned()
+    };
+
+    let id3_frame = |id: &[u8], text: &[u8]| {
+        let mut frame = id.to_vec();
+        frame.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 0]);
+        frame.extend_from_slice(text);
+        frame
+    };
+    let frames = [
+        id3_frame(b"TYER", b"2019"),
+        id3_frame(b"TDAT", b"0305"),
+        id3_frame(b"TIME", b"1230"),
+        vec![0; 16],
+    ]
+    .concat();
+    let mut mp3 = b"ID3\x03\0\0".to_vec();
+    mp3.extend(
+        (0..4)
+            .rev()
+            .map(|shift| (frames.len() >> (7 * shift)) as u8 & 0x7F),
+    );
+    mp3.extend_from_slice(&frames);
+    mp3.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x00]);
+    assert_eq!(Some("2019-05-03 12:30".to_owned()), read("a.mp3", &mp3));
+
+    let vorbis_string = |value: &[u8]| [&(value.len() as u32).to_le_bytes()[..], value].concat();
+    let comments = [
+        vorbis_string(b"reference libFLAC"),
+        1u32.to_le_bytes().to_vec(),
+        vorbis_string(b"DATE=2021-07-04"),
+    ]
+    .concat();
+    let mut flac = b"fLaC".to_vec();
+    flac.extend_from_slice(&[0, 0, 0, 34]);
+    flac.extend_from_slice(&[0; 34]);
+    flac.push(0x80 | FLAC_VORBIS_COMMENT);
+    flac.extend_from_slice(&(comments.len() as u32).to_be_bytes()[1..]);
+    flac.extend_from_slice(&comments);
+    assert_eq!(Some("2021-07-04".to_owned()), read("a.flac", &flac));
+}
@@ -0,0 +1,104 @@
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// A command sent to the daemon over its control socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Request {
+    /// Stop processing new files; they are queued until resumed
+    Pause,
+    /// Process queued and new files again
+    Resume,
+    /// Report what the daemon is doing
+    Status,
+    /// Walk all sources once, picking up files the watcher missed
+    Scan,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Status {
+    pub paused: bool,
+    pub scanning: bool,
+    /// Files waiting while the daemon is paused
+    pub queued: usize,
+    pub ingested: u64,
+    pub failed: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Response {
+    Ok,
+    Status(Status),
+    Error(String),
+}
+
+/// How long the daemon waits on a client to send its request or take the
+/// response, so a stalled client cannot hold up the socket.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Sends `request` to the daemon listening on `socket` and waits for its
+/// response. Requests and responses are single lines of JSON.
+pub fn send(socket: &Path, request: Request) -> io::Result<Response> {
+    let mut stream = UnixStream::connect(socket)?;
+    write_line(&mut stream, &request)?;
+    let mut line = String::new();
+    if BufReader::new(stream).read_line(&mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "daemon closed the connection",
+        ));
+    }
+    Ok(serde_json::from_str(&line)?)
+}
+
+/// Reads the request of a connection accepted by the daemon and answers it
+/// with `handle`. A client that sends nothing for `CLIENT_TIMEOUT` is
+/// given up on with `ErrorKind::WouldBlock` or `ErrorKind::TimedOut`.
+pub fn serve(stream: UnixStream, handle: impl FnOnce(Request) -> Response) -> io::Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let response = match serde_json::from_str(&line) {
+        Ok(request) => handle(request),
+        Err(err) => Response::Error(format!("invalid request: {}", err)),
+    };
+    write_line(&mut &stream, &response)
+}
+
+fn write_line(writer: &mut impl Write, value: &impl Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    writer.write_all(&line)
+}
+
+#[test]
+fn test_send() {
+    let socket = std::env::temp_dir().join(format!("deduper-control-{}.sock", std::process::id()));
+    let listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+    let daemon = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        serve(stream, |request| match request {
+            Request::Status => Response::Status(Status {
+                queued: 2,
+                ..Status::default()
+            }),
+            _ => Response::Ok,
+        })
+        .unwrap();
+    });
+    let response = send(&socket, Request::Status).unwrap();
+    daemon.join().unwrap();
+    std::fs::remove_file(&socket).unwrap();
+    assert!(matches!(
+        response,
+        Response::Status(Status { queued: 2, .. })
+    ));
+}
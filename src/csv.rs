@@ -1,31 +1,476 @@
-#[derive(Debug)]
-pub struct CsvRow {
-    pub path: String,
-    pub hash: String,
-    pub size: u64,
-    pub media_type: String,
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    mem,
+    path::PathBuf,
+    str::FromStr,
+};
+
+use thiserror::Error;
+
+use crate::{database::File, platform};
+
+/// Columns of an exported `files` table, in the order they are written.
+pub const FILE_COLUMNS: [&str; 27] = [
+    "path",
+    "hash",
+    "hash_algorithm",
+    "size",
+    "media_type",
+    "created_at",
+    "modified_at",
+    "original",
+    "optimized",
+    "phash",
+    "utc_offset",
+    "latitude",
+    "longitude",
+    "camera_make",
+    "camera_model",
+    "lens_model",
+    "host",
+    "volume",
+    "volume_path",
+    "dev",
+    "inode",
+    "source",
+    "width",
+    "height",
+    "duration",
+    "codec",
+    "label",
+];
+
+/// Columns of [`FILE_COLUMNS`] that exports from before they were written
+/// lack. Reading such a file leaves them out of [`Files::columns`].
+const LATER_COLUMNS: [&str; 11] = [
+    "host",
+    "volume",
+    "volume_path",
+    "dev",
+    "inode",
+    "source",
+    "width",
+    "height",
+    "duration",
+    "codec",
+    "label",
+];
+
+/// Columns written after [`FILE_COLUMNS`] with the bytes of a path that is
+/// not UTF-8, as [`platform::encode_path`] encodes them, and empty for
+/// others. `path` and `volume_path` then hold the name with replacement
+/// characters, for reading.
+const PATH_BYTES_COLUMNS: [&str; 2] = ["path_bytes", "volume_path_bytes"];
+
+#[derive(Debug, Error)]
+pub enum CsvError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("line {line}: quoted field is never closed")]
+    Unterminated { line: usize },
+    #[error("no {0} column in the header")]
+    MissingColumn(&'static str),
+    #[error("line {line}: {found} fields where the header has {expected}")]
+    FieldCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    #[error("line {line}: invalid {column} '{value}'")]
+    InvalidField {
+        line: usize,
+        column: &'static str,
+        value: String,
+    },
+}
+
+/// Writes one RFC 4180 record, quoting fields that contain a comma, quote
+/// or line break.
+pub fn write_row(writer: &mut impl Write, fields: &[&str]) -> io::Result<()> {
+    let line = fields
+        .iter()
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    writeln!(writer, "{}", line)
+}
+
+/// Reads RFC 4180 records: fields may be quoted, a doubled quote inside
+/// quotes stands for one, and quoted fields may span lines. Blank lines
+/// are skipped.
+pub struct Reader<R> {
+    reader: R,
+    lines_read: usize,
+    line: usize,
 }
 
-impl From<String> for CsvRow {
-    fn from(value: String) -> Self {
-        let tokens = value.split(',').collect::<Vec<&str>>();
+impl<R: BufRead> Reader<R> {
+    pub fn new(reader: R) -> Self {
         Self {
-            path: tokens[0].trim_matches('"').to_owned(),
-            hash: tokens[1].trim_matches('"').to_string(),
-            size: tokens[2].trim_matches('"').parse().unwrap(),
-            media_type: tokens[3].trim_matches('"').to_string(),
+            reader,
+            lines_read: 0,
+            line: 0,
         }
     }
+
+    /// Line the last record read started on, counting from 1.
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    /// The next record, or `None` at the end of the input.
+    pub fn read_record(&mut self) -> Result<Option<Vec<String>>, CsvError> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            self.lines_read += 1;
+            if !line.trim_end_matches(['\r', '\n']).is_empty() {
+                break;
+            }
+        }
+        self.line = self.lines_read;
+
+        let mut fields = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        loop {
+            let mut chars = line.chars().peekable();
+            while let Some(c) = chars.next() {
+                match (quoted, c) {
+                    (true, '"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    (true, '"') => quoted = false,
+                    (true, c) => field.push(c),
+                    (false, '"') if field.is_empty() => quoted = true,
+                    (false, ',') => fields.push(mem::take(&mut field)),
+                    (false, '\r' | '\n') => {}
+                    (false, c) => field.push(c),
+                }
+            }
+            if !quoted {
+                break;
+            }
+            // the line break belongs to the quoted field
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(CsvError::Unterminated { line: self.line });
+            }
+            self.lines_read += 1;
+        }
+        fields.push(field);
+        Ok(Some(fields))
+    }
 }
 
-pub fn parse_csv(
-    path: &str,
-) -> std::iter::Map<
-    std::io::Lines<std::io::BufReader<std::fs::File>>,
-    impl FnMut(Result<String, std::io::Error>) -> CsvRow,
-> {
-    use std::fs::File;
-    use std::io::{BufRead, BufReader};
-    let csv = BufReader::new(File::open(path).unwrap());
-    csv.lines().map(|line| CsvRow::from(line.unwrap()))
+/// Writes the header row of [`write_file`]: [`FILE_COLUMNS`] and the raw
+/// bytes of paths.
+pub fn write_header(writer: &mut impl Write) -> io::Result<()> {
+    write_row(writer, &[&FILE_COLUMNS[..], &PATH_BYTES_COLUMNS].concat())
+}
+
+/// Writes `files` with a header row.
+pub fn write_files(writer: &mut impl Write, files: &[File]) -> io::Result<()> {
+    write_header(writer)?;
+    for file in files {
+        write_file(writer, file)?;
+    }
+    Ok(())
+}
+
+/// Writes one file in the order of [`write_header`]. Missing optional
+/// values are empty fields.
+pub fn write_file(writer: &mut impl Write, file: &File) -> io::Result<()> {
+    let optional = |value: Option<String>| value.unwrap_or_default();
+    let bytes = |path: Option<&PathBuf>| {
+        optional(
+            path.filter(|path| path.to_str().is_none())
+                .map(|path| platform::encode_path(path)),
+        )
+    };
+    let fields = [
+        file.path.to_string_lossy().into_owned(),
+        file.hash.clone(),
+        file.hash_algorithm.clone(),
+        file.size.to_string(),
+        file.media_type.clone(),
+        file.created_at.to_string(),
+        file.modified_at.to_string(),
+        file.original.to_string(),
+        file.optimized.as_str().to_owned(),
+        optional(file.phash.map(|phash| phash.to_string())),
+        file.utc_offset.to_string(),
+        optional(file.latitude.map(|latitude| latitude.to_string())),
+        optional(file.longitude.map(|longitude| longitude.to_string())),
+        optional(file.camera_make.clone()),
+        optional(file.camera_model.clone()),
+        optional(file.lens_model.clone()),
+        optional(file.host.clone()),
+        optional(file.volume.clone()),
+        optional(
+            file.volume_path
+                .as_ref()
+                .map(|path| path.to_string_lossy().into_owned()),
+        ),
+        optional(file.dev.map(|dev| dev.to_string())),
+        optional(file.inode.map(|inode| inode.to_string())),
+        optional(file.source.clone()),
+        optional(file.width.map(|width| width.to_string())),
+        optional(file.height.map(|height| height.to_string())),
+        optional(file.duration.map(|duration| duration.to_string())),
+        optional(file.codec.clone()),
+        optional(file.label.clone()),
+        bytes(Some(&file.path)),
+        bytes(file.volume_path.as_ref()),
+    ];
+    let fields = fields.iter().map(String::as_str).collect::<Vec<_>>();
+    write_row(writer, &fields)
+}
+
+/// Files read by [`read_files`], with the columns the input had.
+pub struct Files {
+    pub files: Vec<File>,
+    pub columns: Vec<&'static str>,
+}
+
+/// Reads files written by [`write_files`]. The header names the columns,
+/// which may come in any order; every column of [`FILE_COLUMNS`] has to be
+/// there except [`LATER_COLUMNS`], which read as empty when missing. The
+/// raw bytes of a path take precedence over its text.
+pub fn read_files(reader: impl BufRead) -> Result<Files, CsvError> {
+    let mut reader = Reader::new(reader);
+    let header = reader.read_record()?.unwrap_or_default();
+    let positions = FILE_COLUMNS
+        .iter()
+        .filter_map(
+            |&column| match header.iter().position(|name| name == column) {
+                Some(position) => Some(Ok((column, position))),
+                None if LATER_COLUMNS.contains(&column) => None,
+                None => Some(Err(CsvError::MissingColumn(column))),
+            },
+        )
+        .chain(PATH_BYTES_COLUMNS.iter().filter_map(|&column| {
+            let position = header.iter().position(|name| name == column)?;
+            Some(Ok((column, position)))
+        }))
+        .collect::<Result<HashMap<_, _>, _>>()?;
+
+    let mut files = Vec::new();
+    while let Some(record) = reader.read_record()? {
+        let line = reader.line();
+        if record.len() != header.len() {
+            return Err(CsvError::FieldCount {
+                line,
+                expected: header.len(),
+                found: record.len(),
+            });
+        }
+        let record = Record {
+            fields: &record,
+            positions: &positions,
+            line,
+        };
+        files.push(File {
+            path: record
+                .path("path_bytes")?
+                .unwrap_or_else(|| PathBuf::from(record.text("path"))),
+            hash: record.text("hash"),
+            hash_algorithm: record.text("hash_algorithm"),
+            size: record.parse("size")?,
+            media_type: record.text("media_type"),
+            created_at: record.parse("created_at")?,
+            modified_at: record.parse("modified_at")?,
+            original: record.parse("original")?,
+            optimized: record.parse("optimized")?,
+            phash: record.parse_optional("phash")?,
+            utc_offset: record.parse("utc_offset")?,
+            latitude: record.parse_optional("latitude")?,
+            longitude: record.parse_optional("longitude")?,
+            camera_make: record.optional("camera_make"),
+            camera_model: record.optional("camera_model"),
+            lens_model: record.optional("lens_model"),
+            host: record.optional("host"),
+            volume: record.optional("volume"),
+            volume_path: record
+                .path("volume_path_bytes")?
+                .or_else(|| record.optional("volume_path").map(PathBuf::from)),
+            dev: record.parse_optional("dev")?,
+            inode: record.parse_optional("inode")?,
+            source: record.optional("source"),
+            width: record.parse_optional("width")?,
+            height: record.parse_optional("height")?,
+            duration: record.parse_optional("duration")?,
+            codec: record.optional("codec"),
+            label: record.optional("label"),
+        });
+    }
+    let columns = FILE_COLUMNS
+        .into_iter()
+        .filter(|column| positions.contains_key(column))
+        .collect();
+    Ok(Files { files, columns })
+}
+
+/// Fields of a record looked up by column name.
+struct Record<'a> {
+    fields: &'a [String],
+    positions: &'a HashMap<&'static str, usize>,
+    line: usize,
+}
+
+impl Record<'_> {
+    fn text(&self, column: &'static str) -> String {
+        self.fields[self.positions[column]].clone()
+    }
+
+    /// The field, or `None` when it is empty or the column is missing.
+    fn optional(&self, column: &'static str) -> Option<String> {
+        let position = self.positions.get(column)?;
+        Some(self.fields[*position].clone()).filter(|value| !value.is_empty())
+    }
+
+    /// The path whose bytes the field holds, if it is not empty.
+    fn path(&self, column: &'static str) -> Result<Option<PathBuf>, CsvError> {
+        let Some(value) = self.optional(column) else {
+            return Ok(None);
+        };
+        platform::decode_path(&value)
+            .map(Some)
+            .ok_or(CsvError::InvalidField {
+                line: self.line,
+                column,
+                value,
+            })
+    }
+
+    fn parse<T: FromStr>(&self, column: &'static str) -> Result<T, CsvError> {
+        let value = &self.fields[self.positions[column]];
+        value.parse().map_err(|_| CsvError::InvalidField {
+            line: self.line,
+            column,
+            value: value.clone(),
+        })
+    }
+
+    fn parse_optional<T: FromStr>(&self, column: &'static str) -> Result<Option<T>, CsvError> {
+        self.optional(column)
+            .map(|_| self.parse(column))
+            .transpose()
+    }
+}
+
+#[test]
+fn test_write_row() {
+    let mut out = Vec::new();
+    write_row(&mut out, &["plain", "a,b", "say \"hi\""]).unwrap();
+    assert_eq!(
+        "plain,\"a,b\",\"say \"\"hi\"\"\"\n",
+        String::from_utf8(out).unwrap()
+    );
+}
+
+#[test]
+fn test_read_record() {
+    let input = "plain,\"a,b\",\"say \"\"hi\"\"\"\r\n\n\"two\nlines\",\n\"open";
+    let mut reader = Reader::new(input.as_bytes());
+    assert_eq!(
+        Some(vec![
+            "plain".to_owned(),
+            "a,b".to_owned(),
+            "say \"hi\"".to_owned()
+        ]),
+        reader.read_record().unwrap()
+    );
+    assert_eq!(
+        Some(vec!["two\nlines".to_owned(), String::new()]),
+        reader.read_record().unwrap()
+    );
+    assert_eq!(3, reader.line());
+    assert!(matches!(
+        reader.read_record(),
+        Err(CsvError::Unterminated { line: 5 })
+    ));
+}
+
+#[test]
+fn test_files_round_trip() {
+    let file = File {
+        path: PathBuf::from("/photos/a, \"b\"\nc.jpg"),
+        hash: "abc".to_owned(),
+        hash_algorithm: "blake3".to_owned(),
+        size: 42,
+        media_type: "image/jpeg".to_owned(),
+        created_at: 1_700_000_000,
+        modified_at: 1_700_000_100,
+        original: true,
+        optimized: crate::database::Optimized::Skipped,
+        phash: Some(u64::MAX),
+        utc_offset: -3600,
+        latitude: Some(48.8584),
+        longitude: Some(2.2945),
+        camera_make: Some("Canon".to_owned()),
+        camera_model: Some("EOS R5".to_owned()),
+        lens_model: Some("RF 24-105mm".to_owned()),
+        host: Some("nas".to_owned()),
+        volume: Some("8c2f-11aa".to_owned()),
+        volume_path: Some(PathBuf::from("photos/a.jpg")),
+        dev: Some(u64::MAX),
+        inode: Some(1234),
+        source: Some("takeout".to_owned()),
+        width: Some(8192),
+        height: Some(5464),
+        duration: Some(12.5),
+        codec: Some("hevc".to_owned()),
+        label: Some("backup".to_owned()),
+    };
+    let mut out = Vec::new();
+    write_files(&mut out, std::slice::from_ref(&file)).unwrap();
+    let read = read_files(out.as_slice()).unwrap();
+    assert_eq!(FILE_COLUMNS.to_vec(), read.columns);
+    assert_eq!(1, read.files.len());
+    assert_eq!(format!("{:?}", file), format!("{:?}", read.files[0]));
+
+    // exports from before the later columns still read
+    let header = FILE_COLUMNS[..16].join(",");
+    let old = format!(
+        "{}\n/a.jpg,abc,blake3,1,image/jpeg,0,0,true,false,,0,,,,,\n",
+        header
+    );
+    let read = read_files(old.as_bytes()).unwrap();
+    assert_eq!(FILE_COLUMNS[..16].to_vec(), read.columns);
+    assert_eq!(None, read.files[0].host);
+
+    let invalid = "path,hash\n/a.jpg,abc\n";
+    assert!(matches!(
+        read_files(invalid.as_bytes()),
+        Err(CsvError::MissingColumn("hash_algorithm"))
+    ));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_utf8_paths() {
+    // "café.jpg" in Latin-1
+    let path = crate::platform::path_from_bytes(b"/photos/caf\xe9.jpg");
+    let out = "path,hash,hash_algorithm,size,media_type,created_at,modified_at,original,optimized,phash,utc_offset,latitude,longitude,camera_make,camera_model,lens_model,path_bytes\n\
+        /photos/caf\u{fffd}.jpg,abc,blake3,1,image/jpeg,0,0,true,false,,0,,,,,,/photos/caf%E9.jpg\n";
+    let read = read_files(out.as_bytes()).unwrap();
+    assert_eq!(path, read.files[0].path);
+    assert!(!read.columns.contains(&"path_bytes"));
+
+    let mut written = Vec::new();
+    write_files(&mut written, &read.files).unwrap();
+    let reread = read_files(written.as_slice()).unwrap();
+    assert_eq!(path, reread.files[0].path);
 }
@@ -0,0 +1,3434 @@
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Deref,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex, MutexGuard,
+    },
+    time::Duration,
+};
+
+use rusqlite::{
+    params,
+    types::{FromSql, FromSqlError, FromSqlResult, ToSqlOutput, Value, ValueRef},
+    Connection, OpenFlags, Row, ToSql,
+};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::{
+    error::{DeduperError, Result},
+    extractor::Format,
+    photoslibrary, platform, videohash,
+};
+
+const CREATE_FILES_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS files (
+        path TEXT PRIMARY KEY,
+        hash TEXT NOT NULL,
+        hash_algorithm TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        media_type TEXT NOT NULL,
+        created_at INTEGER NOT NULL,
+        modified_at INTEGER NOT NULL,
+        original BOOLEAN NOT NULL DEFAULT FALSE,
+        optimized BOOLEAN NOT NULL DEFAULT FALSE,
+        phash INTEGER,
+        utc_offset INTEGER NOT NULL DEFAULT 0,
+        latitude REAL,
+        longitude REAL,
+        camera_make TEXT,
+        camera_model TEXT,
+        lens_model TEXT
+    );
+    CREATE INDEX IF NOT EXISTS files_hash ON files (hash);
+";
+
+/// Runs of a command over a set of sources, and the paths each has
+/// finished so an interrupted run can be resumed. `parameters` identifies
+/// the sources and destination a run was started with.
+const CREATE_JOURNAL_TABLES: &str = "
+    CREATE TABLE IF NOT EXISTS runs (
+        id INTEGER PRIMARY KEY,
+        command TEXT NOT NULL,
+        parameters TEXT NOT NULL,
+        started_at INTEGER NOT NULL,
+        finished_at INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS journal (
+        run_id INTEGER NOT NULL REFERENCES runs (id),
+        path TEXT NOT NULL,
+        PRIMARY KEY (run_id, path)
+    );
+";
+
+/// Re-encodes of original files, so a batch that takes days survives
+/// crashes. `state` is one of `pending`, `in-progress`, `done` or
+/// `failed`; `attempts` counts the encodes started.
+const CREATE_TRANSCODE_QUEUE_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS transcode_queue (
+        path TEXT PRIMARY KEY,
+        state TEXT NOT NULL DEFAULT 'pending',
+        attempts INTEGER NOT NULL DEFAULT 0,
+        error TEXT,
+        updated_at INTEGER NOT NULL
+    );
+";
+
+/// Scans with what they found, and what happened to each file over time.
+/// `sources` holds the scanned paths one per line; `event` is one of
+/// `added`, `moved`, `rehashed`, `deleted` or `transcoded`, and `detail`
+/// the previous path of a move or the previous hash of a rehash.
+const CREATE_HISTORY_TABLES: &str = "
+    CREATE TABLE IF NOT EXISTS scans (
+        id INTEGER PRIMARY KEY,
+        sources TEXT NOT NULL,
+        started_at INTEGER NOT NULL,
+        finished_at INTEGER,
+        recorded INTEGER NOT NULL DEFAULT 0,
+        unchanged INTEGER NOT NULL DEFAULT 0,
+        failed INTEGER NOT NULL DEFAULT 0,
+        deleted INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE TABLE IF NOT EXISTS file_events (
+        id INTEGER PRIMARY KEY,
+        scan_id INTEGER REFERENCES scans (id),
+        path TEXT NOT NULL,
+        event TEXT NOT NULL,
+        hash TEXT,
+        detail TEXT,
+        at INTEGER NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS file_events_path ON file_events (path);
+    CREATE INDEX IF NOT EXISTS file_events_at ON file_events (at);
+";
+
+/// Where `organize` placed each file in the destination tree.
+const ADD_DEST_PATH_COLUMN: &str = "ALTER TABLE files ADD COLUMN dest_path TEXT";
+
+/// What was decided for duplicate groups under `dedupe --interactive`.
+const CREATE_DECISIONS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS decisions (
+        hash TEXT PRIMARY KEY,
+        keep_path TEXT NOT NULL,
+        resolution TEXT NOT NULL,
+        decided_at INTEGER NOT NULL
+    )
+";
+
+/// Frame hash sequences of videos by content hash, see
+/// [`crate::videohash`].
+const CREATE_VIDEO_FINGERPRINTS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS video_fingerprints (
+        hash TEXT PRIMARY KEY,
+        frames BLOB NOT NULL
+    )
+";
+
+/// The machine a file was scanned on when it was merged in from another
+/// database by `db merge`; NULL for files scanned on this one.
+const ADD_HOST_COLUMN: &str = "
+    ALTER TABLE files ADD COLUMN host TEXT;
+    CREATE INDEX IF NOT EXISTS files_host ON files (host);
+";
+
+/// The filesystem a file is on, as `UUID=...` or `LABEL=...`, and its
+/// path from the root of that filesystem, so it can be found again when
+/// the drive is mounted elsewhere; see [`crate::volume`].
+const ADD_VOLUME_COLUMNS: &str = "
+    ALTER TABLE files ADD COLUMN volume TEXT;
+    ALTER TABLE files ADD COLUMN volume_path TEXT;
+";
+
+/// The device and inode of a file, shared by all its hard links, so links
+/// are hashed once and reported apart from copies.
+const ADD_FILE_ID_COLUMNS: &str = "
+    ALTER TABLE files ADD COLUMN dev INTEGER;
+    ALTER TABLE files ADD COLUMN inode INTEGER;
+    CREATE INDEX IF NOT EXISTS files_inode ON files (inode);
+";
+
+/// What destructive commands did to files, so a run can be rolled back.
+/// `action` is one of `deleted`, `trashed`, `linked`, `moved` or
+/// `transcoded`; `target` is the original a deleted or linked file
+/// duplicated, where a trashed or moved file went, or the re-encoded file.
+/// `hash` is the contents `path` held before.
+const CREATE_UNDO_LOG_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS undo_log (
+        id INTEGER PRIMARY KEY,
+        run_id INTEGER NOT NULL REFERENCES runs (id),
+        action TEXT NOT NULL,
+        path TEXT NOT NULL,
+        target TEXT,
+        hash TEXT NOT NULL,
+        hash_algorithm TEXT NOT NULL,
+        at INTEGER NOT NULL,
+        undone_at INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS undo_log_run ON undo_log (run_id);
+";
+
+/// The app a file was saved from, see [`crate::extractor::MediaSource`].
+const ADD_SOURCE_COLUMN: &str = "ALTER TABLE files ADD COLUMN source TEXT";
+
+/// Frame size of images and videos, and length and codec of videos, see
+/// [`crate::extractor::Format`].
+const ADD_FORMAT_COLUMNS: [&str; 4] = [
+    "ALTER TABLE files ADD COLUMN width INTEGER",
+    "ALTER TABLE files ADD COLUMN height INTEGER",
+    "ALTER TABLE files ADD COLUMN duration REAL",
+    "ALTER TABLE files ADD COLUMN codec TEXT",
+];
+
+/// Files a scan failed on, with the size and mtime they failed at, so an
+/// unchanged file is not tried again until `errors retry`, and those
+/// `organize` or `transcode` failed on. `stage` is the step that failed,
+/// see [`crate::media::Inspector::stage_of`], or the command, and `kind`
+/// that of the error.
+const CREATE_ERRORS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS errors (
+        path TEXT PRIMARY KEY,
+        stage TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        message TEXT NOT NULL,
+        size INTEGER,
+        modified_at INTEGER,
+        attempts INTEGER NOT NULL DEFAULT 1,
+        at INTEGER NOT NULL
+    )
+";
+
+/// What the database of an Apple Photos library says about the files
+/// scanned from it, besides their capture time and place.
+const CREATE_LIBRARY_TABLES: &str = "
+    CREATE TABLE IF NOT EXISTS library_assets (
+        path TEXT PRIMARY KEY,
+        original_name TEXT,
+        favorite BOOLEAN NOT NULL DEFAULT FALSE
+    );
+    CREATE TABLE IF NOT EXISTS library_albums (
+        path TEXT NOT NULL,
+        album TEXT NOT NULL,
+        PRIMARY KEY (path, album)
+    );
+";
+
+/// The read-only index a file was recorded in by `scan --index-only`,
+/// such as an old backup drive; NULL for files of the collection itself.
+/// A failure keeps the label of its scan so `errors retry` records the
+/// file in the same index.
+const ADD_LABEL_COLUMNS: &str = "
+    ALTER TABLE files ADD COLUMN label TEXT;
+    CREATE INDEX IF NOT EXISTS files_label ON files (label);
+    ALTER TABLE errors ADD COLUMN label TEXT;
+";
+
+/// Roots of the sources in order of preference, set with `dedupe
+/// --prefer-source`, so files below an earlier root are kept as originals
+/// over their copies elsewhere, whichever was captured first. Each `root`
+/// ends in a path separator, so it only matches whole directory names.
+const CREATE_SOURCE_PRIORITY_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS source_priority (
+        root TEXT PRIMARY KEY,
+        position INTEGER NOT NULL
+    )
+";
+
+/// Files placed under a numbered name because the name the layout gave
+/// them, `taken`, already held other contents, kept for review with
+/// `conflicts list`. `path` is where the file of `source` went.
+const CREATE_CONFLICTS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS conflicts (
+        path TEXT PRIMARY KEY,
+        source TEXT NOT NULL,
+        taken TEXT NOT NULL,
+        hash TEXT NOT NULL,
+        at INTEGER NOT NULL
+    )
+";
+
+/// Schema changes in the order they were made. A database whose
+/// `user_version` pragma is n has the first n applied; each runs in its own
+/// transaction. Released migrations are never edited, only appended to.
+const MIGRATIONS: &[&[&str]] = &[
+    // 1: the schema as of the first versioned release
+    &[
+        CREATE_FILES_TABLE,
+        CREATE_JOURNAL_TABLES,
+        CREATE_TRANSCODE_QUEUE_TABLE,
+    ],
+    // 2: scan sessions and the file history
+    &[CREATE_HISTORY_TABLES],
+    // 3: destination paths
+    &[ADD_DEST_PATH_COLUMN],
+    // 4: reviewed duplicate groups
+    &[CREATE_DECISIONS_TABLE],
+    // 5: video fingerprints
+    &[CREATE_VIDEO_FINGERPRINTS_TABLE],
+    // 6: files of other machines
+    &[ADD_HOST_COLUMN],
+    // 7: volumes of removable drives
+    &[ADD_VOLUME_COLUMNS],
+    // 8: hard links
+    &[ADD_FILE_ID_COLUMNS],
+    // 9: undo log
+    &[CREATE_UNDO_LOG_TABLE],
+    // 10: favorites and albums of Apple Photos libraries
+    &[CREATE_LIBRARY_TABLES],
+    // 11: the app a file was saved from
+    &[ADD_SOURCE_COLUMN],
+    // 12: frame size, length and codec
+    &ADD_FORMAT_COLUMNS,
+    // 13: files that failed
+    &[CREATE_ERRORS_TABLE],
+    // 14: read-only indexes
+    &[ADD_LABEL_COLUMNS],
+    // 15: preferred sources
+    &[CREATE_SOURCE_PRIORITY_TABLE],
+    // 16: placement conflicts
+    &[CREATE_CONFLICTS_TABLE],
+];
+
+/// Columns added to `files` before the schema was versioned. Databases
+/// from then have `user_version` 0 and get whichever of these they lack
+/// before migration 1 runs.
+const UNVERSIONED_COLUMNS: [(&str, &str); 8] = [
+    ("optimized", "BOOLEAN NOT NULL DEFAULT FALSE"),
+    ("phash", "INTEGER"),
+    ("utc_offset", "INTEGER NOT NULL DEFAULT 0"),
+    ("latitude", "REAL"),
+    ("longitude", "REAL"),
+    ("camera_make", "TEXT"),
+    ("camera_model", "TEXT"),
+    ("lens_model", "TEXT"),
+];
+
+/// Queues a file, or queues it again when it was re-encoded before but is
+/// unoptimized now.
+const ENQUEUE_TRANSCODE: &str = "
+    INSERT INTO transcode_queue (path, updated_at) VALUES (?1, CAST(strftime('%s', 'now') AS INTEGER))
+    ON CONFLICT (path) DO UPDATE SET state = 'pending', attempts = 0, error = NULL,
+        updated_at = excluded.updated_at
+    WHERE state = 'done'
+";
+
+const FIND_UNFINISHED_RUN: &str = "
+    SELECT id FROM runs
+    WHERE command = ?1 AND parameters = ?2 AND finished_at IS NULL
+    ORDER BY id DESC
+    LIMIT 1
+";
+
+const LOG_EVENT: &str = "
+    INSERT INTO file_events (scan_id, path, event, hash, detail, at)
+    VALUES (?1, ?2, ?3, ?4, ?5, CAST(strftime('%s', 'now') AS INTEGER))
+";
+
+const LOG_UNDO: &str = "
+    INSERT INTO undo_log (run_id, action, path, target, hash, hash_algorithm, at)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, CAST(strftime('%s', 'now') AS INTEGER))
+";
+
+/// Runs with actions left to undo, latest first.
+const FIND_UNDO_RUNS: &str = "
+    SELECT runs.id, runs.command, runs.started_at, COUNT(*)
+    FROM undo_log JOIN runs ON runs.id = undo_log.run_id
+    WHERE undo_log.undone_at IS NULL
+    GROUP BY runs.id
+    ORDER BY runs.id DESC
+";
+
+/// The tables archived with the files by [`crate::archive`] and the rows
+/// taken from each: what was decided, placed under another name, failed
+/// and can be undone, which sources are preferred, and the runs the undo
+/// log belongs to. Library rows go with their files.
+const ARCHIVED_TABLES: [(&str, &str); 6] = [
+    ("decisions", "SELECT * FROM decisions ORDER BY hash"),
+    ("conflicts", "SELECT * FROM conflicts ORDER BY path"),
+    ("errors", "SELECT * FROM errors ORDER BY path"),
+    (
+        "source_priority",
+        "SELECT * FROM source_priority ORDER BY position",
+    ),
+    (
+        "runs",
+        "SELECT * FROM runs WHERE id IN (SELECT run_id FROM undo_log) ORDER BY id",
+    ),
+    ("undo_log", "SELECT * FROM undo_log ORDER BY id"),
+];
+
+/// Events from the start of a scan on, including those of later commands.
+const FIND_EVENTS_SINCE: &str = "
+    SELECT scan_id, path, event, hash, detail, at FROM file_events
+    WHERE at >= (SELECT started_at FROM scans WHERE id = ?1)
+    ORDER BY id
+";
+
+const FILE_COLUMNS: &str = "path, hash, hash_algorithm, size, media_type, created_at, \
+    modified_at, original, optimized, phash, utc_offset, latitude, longitude, camera_make, \
+    camera_model, lens_model, host, volume, volume_path, dev, inode, source, width, height, \
+    duration, codec, label";
+
+const UPSERT_FILE: &str = "
+    INSERT INTO files (path, hash, hash_algorithm, size, media_type, created_at, modified_at, phash,
+        utc_offset, latitude, longitude, camera_make, camera_model, lens_model, volume, volume_path,
+        dev, inode, source, width, height, duration, codec, label)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19,
+        ?20, ?21, ?22, ?23, ?24)
+    ON CONFLICT (path) DO UPDATE SET
+        hash = excluded.hash,
+        hash_algorithm = excluded.hash_algorithm,
+        size = excluded.size,
+        media_type = excluded.media_type,
+        created_at = excluded.created_at,
+        modified_at = excluded.modified_at,
+        phash = excluded.phash,
+        utc_offset = excluded.utc_offset,
+        latitude = excluded.latitude,
+        longitude = excluded.longitude,
+        camera_make = excluded.camera_make,
+        camera_model = excluded.camera_model,
+        lens_model = excluded.lens_model,
+        volume = excluded.volume,
+        volume_path = excluded.volume_path,
+        dev = excluded.dev,
+        inode = excluded.inode,
+        source = excluded.source,
+        width = excluded.width,
+        height = excluded.height,
+        duration = excluded.duration,
+        codec = excluded.codec,
+        label = excluded.label
+";
+
+/// Inserts a whole row as it is, unlike [`UPSERT_FILE`] which leaves the
+/// `original` and `optimized` flags of a rescanned file alone. The columns
+/// an existing row takes from it are appended by [`LockDB::import`].
+const IMPORT_FILE: &str = "
+    INSERT INTO files (path, hash, hash_algorithm, size, media_type, created_at,
+        modified_at, original, optimized, phash, utc_offset, latitude, longitude, camera_make,
+        camera_model, lens_model, host, volume, volume_path, dev, inode, source, width, height,
+        duration, codec, label, dest_path)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19,
+        ?20, ?21, ?22, ?23, ?24, ?25, ?26, ?27, ?28)
+    ON CONFLICT (path) DO
+";
+
+/// Keeps the earliest capture of every hash as the original, one merged
+/// from another machine, on a remote source or of a read-only index only
+/// when there is no other.
+const MARK_ORIGINAL_FILES: &str = "
+    UPDATE files SET original = path IN (
+        SELECT path FROM (
+            SELECT path, ROW_NUMBER() OVER (
+                PARTITION BY hash
+                ORDER BY host IS NOT NULL,
+                    substr(path, 1, 5) = 's3://' OR substr(path, 1, 7) = 'rclone:',
+                    label IS NOT NULL,
+                    preference IS NULL, preference, created_at, path
+            ) AS rank
+            FROM (
+                SELECT path, hash, host, label, created_at, (
+                    SELECT MIN(position) FROM source_priority
+                    WHERE substr(CAST(files.path AS BLOB), 1, length(CAST(root AS BLOB)))
+                        = CAST(root AS BLOB)
+                ) AS preference
+                FROM files
+            )
+        )
+        WHERE rank = 1
+    )
+";
+
+/// One file of each hash scanned on this machine outside read-only
+/// indexes, the original where one
+/// is marked, in capture order; the `WHERE` clause after [`FILE_COLUMNS`].
+const FIND_UNIQUE_FILES_ORDERED: &str = "
+    WHERE path IN (
+        SELECT path FROM (
+            SELECT path, ROW_NUMBER() OVER (
+                PARTITION BY hash ORDER BY original DESC, created_at, path
+            ) AS rank
+            FROM files
+            WHERE host IS NULL AND label IS NULL
+        )
+        WHERE rank = 1
+    )
+    ORDER BY created_at, path
+";
+
+/// Copies beyond the original of each hash. Hard links of a file counted
+/// already take no space of their own and are left out, as are files of
+/// read-only indexes, which are kept on purpose.
+const COUNT_REDUNDANT_FILES: &str = "
+    SELECT COUNT(*), COALESCE(SUM(size), 0) FROM (
+        SELECT size, ROW_NUMBER() OVER (PARTITION BY hash ORDER BY created_at, path) AS rank,
+            inode IS NULL OR ROW_NUMBER() OVER (
+                PARTITION BY hash, dev, inode ORDER BY created_at, path
+            ) = 1 AS own_space
+        FROM files
+        WHERE label IS NULL
+    )
+    WHERE rank > 1 AND own_space
+";
+
+/// Like [`COUNT_REDUNDANT_FILES`], per media type.
+const MEDIA_TYPE_STATS: &str = "
+    SELECT media_type, COUNT(*), SUM(size), SUM(rank > 1 AND own_space),
+        SUM(CASE WHEN rank > 1 AND own_space THEN size ELSE 0 END)
+    FROM (
+        SELECT media_type, size, ROW_NUMBER() OVER (PARTITION BY hash ORDER BY created_at, path) AS rank,
+            inode IS NULL OR ROW_NUMBER() OVER (
+                PARTITION BY hash, dev, inode ORDER BY created_at, path
+            ) = 1 AS own_space
+        FROM files
+        WHERE label IS NULL
+    )
+    GROUP BY media_type
+    ORDER BY media_type
+";
+
+/// Like [`MEDIA_TYPE_STATS`], grouped by camera. Models usually repeat the
+/// make ("Canon EOS 5D"), in which case the make is not prefixed again.
+const CAMERA_STATS: &str = "
+    SELECT camera, COUNT(*), SUM(size), SUM(rank > 1 AND own_space),
+        SUM(CASE WHEN rank > 1 AND own_space THEN size ELSE 0 END)
+    FROM (
+        SELECT
+            CASE
+                WHEN camera_model IS NULL THEN COALESCE(camera_make, 'unknown')
+                WHEN camera_make IS NULL OR camera_model LIKE camera_make || '%' THEN camera_model
+                ELSE camera_make || ' ' || camera_model
+            END AS camera,
+            size,
+            ROW_NUMBER() OVER (PARTITION BY hash ORDER BY created_at, path) AS rank,
+            inode IS NULL OR ROW_NUMBER() OVER (
+                PARTITION BY hash, dev, inode ORDER BY created_at, path
+            ) = 1 AS own_space
+        FROM files
+        WHERE label IS NULL
+    )
+    GROUP BY camera
+    ORDER BY camera
+";
+
+/// Videos of each hash by resolution class, largest first; the classes
+/// are those of [`crate::extractor::RESOLUTIONS`].
+const RESOLUTION_STATS: &str = "
+    SELECT
+        CASE
+            WHEN short_side >= 4320 THEN '8K'
+            WHEN short_side >= 2160 THEN '4K'
+            WHEN short_side >= 1440 THEN '1440p'
+            WHEN short_side >= 1080 THEN '1080p'
+            WHEN short_side >= 720 THEN '720p'
+            ELSE 'SD'
+        END AS resolution,
+        COUNT(*), SUM(size), COALESCE(SUM(duration), 0) / 3600.0
+    FROM (
+        SELECT MIN(width, height) AS short_side, size, duration,
+            ROW_NUMBER() OVER (PARTITION BY hash ORDER BY created_at, path) AS rank
+        FROM files
+        WHERE media_type LIKE 'video/%' AND width > 0 AND height > 0 AND label IS NULL
+    )
+    WHERE rank = 1
+    GROUP BY resolution
+    ORDER BY MAX(short_side) DESC
+";
+
+/// Every file with its size and whether it is redundant as in
+/// [`COUNT_REDUNDANT_FILES`], for [`LockDB::directory_stats`]. The copy
+/// marked original is the one kept, as identical copies mostly share their
+/// capture time and the first path would be kept otherwise.
+const REDUNDANT_BY_PATH: &str = "
+    SELECT path, size, rank > 1 AND own_space FROM (
+        SELECT path, size, ROW_NUMBER() OVER (
+                PARTITION BY hash ORDER BY original DESC, created_at, path
+            ) AS rank,
+            inode IS NULL OR ROW_NUMBER() OVER (
+                PARTITION BY hash, dev, inode ORDER BY created_at, path
+            ) = 1 AS own_space
+        FROM files
+        WHERE label IS NULL
+    )
+";
+
+/// Files per read-only index, see [`LabelStats`].
+const LABEL_STATS: &str = "
+    SELECT label, COUNT(*), SUM(size),
+        SUM(hash IN (SELECT hash FROM files WHERE label IS NULL)),
+        SUM(NOT EXISTS (
+            SELECT 1 FROM files AS other
+            WHERE other.hash = indexed.hash
+                AND (other.label IS NULL OR other.label != indexed.label)
+        ))
+    FROM files AS indexed
+    WHERE label IS NOT NULL
+    GROUP BY label
+    ORDER BY label
+";
+
+/// Hashes with more than one file outside read-only indexes.
+const FIND_IDENTICAL_SIGNS: &str = "SELECT hash FROM files WHERE label IS NULL \
+    GROUP BY hash HAVING COUNT(*) > 1 ORDER BY hash";
+
+/// The files of a page of [`FIND_IDENTICAL_SIGNS`], ?1 hashes from the
+/// ?2nd on; the `WHERE` clause after [`FILE_COLUMNS`].
+const FIND_DUPLICATE_GROUPS: &str = "
+    WHERE hash IN (
+        SELECT hash FROM files WHERE label IS NULL
+        GROUP BY hash HAVING COUNT(*) > 1 ORDER BY hash
+        LIMIT ?1 OFFSET ?2
+    )
+    ORDER BY hash, created_at, path
+";
+
+const FIND_CROSS_HOST_SIGNS: &str = "SELECT hash FROM files GROUP BY hash \
+    HAVING COUNT(DISTINCT COALESCE(host, '')) > 1 ORDER BY hash";
+
+/// Copies the files the database attached as `other` scanned itself as
+/// those of host ?1, their paths prefixed with it. Files it merged from
+/// elsewhere are left out; paths stored as BLOBs stay BLOBs.
+const MERGE_FILES: &str = "
+    INSERT INTO files (path, hash, hash_algorithm, size, media_type, created_at, modified_at,
+        optimized, phash, utc_offset, latitude, longitude, camera_make, camera_model, lens_model,
+        source, width, height, duration, codec, host)
+    SELECT CASE typeof(path)
+            WHEN 'blob' THEN CAST(?1 || ':' || path AS BLOB)
+            ELSE ?1 || ':' || path
+        END,
+        hash, hash_algorithm, size, media_type, created_at, modified_at,
+        optimized, phash, utc_offset, latitude, longitude, camera_make, camera_model, lens_model,
+        source, width, height, duration, codec, ?1
+    FROM other.files WHERE host IS NULL
+    ON CONFLICT (path) DO NOTHING
+";
+
+const MERGE_VIDEO_FINGERPRINTS: &str = "INSERT OR IGNORE INTO video_fingerprints (hash, frames) \
+    SELECT hash, frames FROM other.video_fingerprints";
+
+// `optimized = FALSE` rather than `NOT optimized`, which is true for 'skipped'
+const FIND_UNOPTIMIZED_VIDEOS: &str = "WHERE media_type LIKE 'video/%' AND original \
+    AND optimized = FALSE AND host IS NULL AND label IS NULL ORDER BY path";
+
+const FIND_UNOPTIMIZED_IMAGES: &str = "WHERE media_type IN ('image/jpeg', 'image/png') \
+    AND original AND optimized = FALSE AND host IS NULL AND label IS NULL ORDER BY path";
+
+/// A row of the `files` table. Timestamps are unix seconds; `created_at` is
+/// the extracted capture time and `modified_at` the filesystem mtime.
+/// `utc_offset` is the offset in seconds east of UTC the capture time was
+/// recorded at, and `latitude`/`longitude` the capture position in degrees.
+/// `phash` is the perceptual hash of images, stored bit-for-bit as INTEGER.
+/// `host` names the machine of a file merged from another database, whose
+/// path starts with `host:` and does not exist here. `volume` and
+/// `volume_path` locate the file on its filesystem independently of the
+/// mount point, for files on filesystems with a UUID or label. `dev` and
+/// `inode` identify the file on its filesystem, the same for all its hard
+/// links, stored bit-for-bit as INTEGER. `label` names the read-only index
+/// a file was recorded in, such as an old backup drive, which is never
+/// written to; its files are only compared against.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct File {
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    pub hash: String,
+    pub hash_algorithm: String,
+    pub size: u64,
+    pub media_type: String,
+    pub created_at: i64,
+    pub modified_at: i64,
+    pub original: bool,
+    pub optimized: Optimized,
+    pub phash: Option<u64>,
+    pub utc_offset: i32,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens_model: Option<String>,
+    pub host: Option<String>,
+    pub volume: Option<String>,
+    #[serde(serialize_with = "serialize_option_path")]
+    pub volume_path: Option<PathBuf>,
+    pub dev: Option<u64>,
+    pub inode: Option<u64>,
+    /// The app the file was saved from, e.g. `WhatsApp`
+    pub source: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Length of a video in seconds
+    pub duration: Option<f64>,
+    /// ffmpeg's name of the codec of a video
+    pub codec: Option<String>,
+    pub label: Option<String>,
+}
+
+impl File {
+    fn from_row(row: &Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            path: row.get::<_, StoredPath>(0)?.0,
+            hash: row.get(1)?,
+            hash_algorithm: row.get(2)?,
+            size: row.get(3)?,
+            media_type: row.get(4)?,
+            created_at: row.get(5)?,
+            modified_at: row.get(6)?,
+            original: row.get(7)?,
+            optimized: row.get(8)?,
+            phash: row.get::<_, Option<i64>>(9)?.map(|phash| phash as u64),
+            utc_offset: row.get(10)?,
+            latitude: row.get(11)?,
+            longitude: row.get(12)?,
+            camera_make: row.get(13)?,
+            camera_model: row.get(14)?,
+            lens_model: row.get(15)?,
+            host: row.get(16)?,
+            volume: row.get(17)?,
+            volume_path: row.get::<_, Option<StoredPath>>(18)?.map(|path| path.0),
+            dev: row.get::<_, Option<i64>>(19)?.map(|dev| dev as u64),
+            inode: row.get::<_, Option<i64>>(20)?.map(|inode| inode as u64),
+            source: row.get(21)?,
+            width: row.get(22)?,
+            height: row.get(23)?,
+            duration: row.get(24)?,
+            codec: row.get(25)?,
+            label: row.get(26)?,
+        })
+    }
+}
+
+#[cfg(test)]
+impl File {
+    /// A one-byte JPEG of `hash` at `path`, captured at the epoch, with
+    /// every optional column empty; tests set what they need on top with
+    /// struct update syntax.
+    pub(crate) fn test(path: impl Into<PathBuf>, hash: &str) -> Self {
+        Self {
+            path: path.into(),
+            hash: hash.to_owned(),
+            hash_algorithm: "blake3".to_owned(),
+            size: 1,
+            media_type: "image/jpeg".to_owned(),
+            created_at: 0,
+            modified_at: 0,
+            original: false,
+            optimized: Optimized::No,
+            phash: None,
+            utc_offset: 0,
+            latitude: None,
+            longitude: None,
+            camera_make: None,
+            camera_model: None,
+            lens_model: None,
+            host: None,
+            volume: None,
+            volume_path: None,
+            dev: None,
+            inode: None,
+            source: None,
+            width: None,
+            height: None,
+            duration: None,
+            codec: None,
+            label: None,
+        }
+    }
+}
+
+/// A path as a query parameter: TEXT when it is valid UTF-8, and otherwise
+/// a BLOB of its bytes, so a name in another encoding is stored as it is on
+/// disk rather than with replacement characters.
+struct SqlPath<'a>(&'a Path);
+
+impl ToSql for SqlPath<'_> {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self.0.to_str() {
+            Some(path) => ToSqlOutput::Borrowed(ValueRef::Text(path.as_bytes())),
+            None => ToSqlOutput::Owned(Value::Blob(
+                platform::os_bytes(self.0.as_os_str()).into_owned(),
+            )),
+        })
+    }
+}
+
+/// A path column written from a [`SqlPath`].
+struct StoredPath(PathBuf);
+
+impl FromSql for StoredPath {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Text(bytes) | ValueRef::Blob(bytes) => {
+                Ok(Self(platform::path_from_bytes(bytes)))
+            }
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+/// Paths go into JSON as text, with replacement characters for bytes that
+/// are not UTF-8.
+fn serialize_path<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+pub(crate) fn serialize_option_path<S: Serializer>(
+    path: &Option<PathBuf>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match path {
+        Some(path) => serialize_path(path, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// What `transcode` did with a file. The `optimized` column holds FALSE,
+/// TRUE, or 'skipped' when re-encoding did not make the file smaller.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Optimized {
+    #[default]
+    No,
+    Yes,
+    Skipped,
+}
+
+impl FromSql for Optimized {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        if let ValueRef::Text(b"skipped") = value {
+            return Ok(Self::Skipped);
+        }
+        Ok(if value.as_i64()? != 0 {
+            Self::Yes
+        } else {
+            Self::No
+        })
+    }
+}
+
+/// As in CSV exports: `"false"`, `"true"` or `"skipped"`.
+impl Serialize for Optimized {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Optimized {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+impl ToSql for Optimized {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            Self::No => ToSqlOutput::from(0),
+            Self::Yes => ToSqlOutput::from(1),
+            Self::Skipped => ToSqlOutput::Borrowed(ValueRef::Text(b"skipped")),
+        })
+    }
+}
+
+impl Optimized {
+    /// `false`, `true` or `skipped`, as exported.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::No => "false",
+            Self::Yes => "true",
+            Self::Skipped => "skipped",
+        }
+    }
+}
+
+impl FromStr for Optimized {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "false" => Ok(Self::No),
+            "true" => Ok(Self::Yes),
+            "skipped" => Ok(Self::Skipped),
+            _ => Err(format!("'{}' is not false, true or skipped", s)),
+        }
+    }
+}
+
+/// The file `transcode` wrote, for [`LockDB::mark_optimized`] and
+/// [`LockDB::mark_converted`].
+#[derive(Debug, Clone, Copy)]
+pub struct Encoded<'a> {
+    pub hash: &'a str,
+    pub size: u64,
+    pub modified_at: i64,
+    pub format: &'a Format,
+}
+
+/// What an Apple Photos library said about a scanned file, as recorded in
+/// `library_assets` and `library_albums`.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LibraryAsset {
+    pub original_name: Option<String>,
+    pub favorite: bool,
+    pub albums: Vec<String>,
+}
+
+impl From<&photoslibrary::Asset> for LibraryAsset {
+    fn from(asset: &photoslibrary::Asset) -> Self {
+        Self {
+            original_name: asset.original_name.clone(),
+            favorite: asset.favorite,
+            albums: asset.albums.clone(),
+        }
+    }
+}
+
+/// A recorded file and where `organize` placed it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    pub path: PathBuf,
+    pub dest_path: PathBuf,
+}
+
+/// A row of one of the tables archived besides `files`, column by column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableRow {
+    pub table: String,
+    pub columns: Vec<(String, Value)>,
+}
+
+/// What happened to a file, as logged in `file_events`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileEvent {
+    /// A scan recorded a path it had not seen before
+    Added,
+    /// A scan found a recorded file at a new path, its old one gone
+    Moved,
+    /// A scan hashed a recorded file again, as its size or mtime changed
+    Rehashed,
+    /// A scan found a recorded file gone and forgot it
+    Deleted,
+    /// `transcode` replaced the file with a re-encoded one
+    Transcoded,
+}
+
+impl FileEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Added => "added",
+            Self::Moved => "moved",
+            Self::Rehashed => "rehashed",
+            Self::Deleted => "deleted",
+            Self::Transcoded => "transcoded",
+        }
+    }
+}
+
+impl FromSql for FileEvent {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "added" => Ok(Self::Added),
+            "moved" => Ok(Self::Moved),
+            "rehashed" => Ok(Self::Rehashed),
+            "deleted" => Ok(Self::Deleted),
+            "transcoded" => Ok(Self::Transcoded),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for FileEvent {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Borrowed(ValueRef::Text(
+            self.as_str().as_bytes(),
+        )))
+    }
+}
+
+/// What to do with the duplicates of a reviewed group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Resolution {
+    /// Only keep the chosen file as the original
+    Keep,
+    /// Remove every other file of the group
+    Delete,
+    /// Replace every other file of the group with a link to the chosen one
+    Link,
+}
+
+impl Resolution {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Keep => "keep",
+            Self::Delete => "delete",
+            Self::Link => "link",
+        }
+    }
+}
+
+impl FromSql for Resolution {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "keep" => Ok(Self::Keep),
+            "delete" => Ok(Self::Delete),
+            "link" => Ok(Self::Link),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for Resolution {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Borrowed(ValueRef::Text(
+            self.as_str().as_bytes(),
+        )))
+    }
+}
+
+/// A row of `decisions`: the file kept of the group of `hash` and what
+/// becomes of the others.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Decision {
+    pub hash: String,
+    #[serde(serialize_with = "serialize_path")]
+    pub keep_path: PathBuf,
+    pub resolution: Resolution,
+    pub decided_at: i64,
+}
+
+/// A row of `conflicts`: a file that went to `path` because `taken` held
+/// other contents.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Conflict {
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    #[serde(serialize_with = "serialize_path")]
+    pub source: PathBuf,
+    #[serde(serialize_with = "serialize_path")]
+    pub taken: PathBuf,
+    pub hash: String,
+    pub at: i64,
+}
+
+/// A row of `errors`: why a file could not be recorded, placed or
+/// re-encoded.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileError {
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    pub stage: String,
+    pub kind: String,
+    pub message: String,
+    /// Size and mtime of the file when it failed, where it could be read
+    pub size: Option<u64>,
+    pub modified_at: Option<i64>,
+    /// How many times in a row it failed
+    pub attempts: u32,
+    pub at: i64,
+    /// The read-only index the file was scanned into, see [`File::label`]
+    pub label: Option<String>,
+}
+
+impl FileError {
+    /// The stages of the commands other than `scan`, whose failures a scan
+    /// neither skips nor forgets.
+    pub const ORGANIZE: &'static str = "organize";
+    pub const TRANSCODE: &'static str = "transcode";
+
+    /// Whether the file failed to be scanned, which a scan or `errors
+    /// retry` tries again.
+    pub fn is_scan_failure(&self) -> bool {
+        self.stage != Self::ORGANIZE && self.stage != Self::TRANSCODE
+    }
+}
+
+/// A row of `file_events`; `scan_id` is `None` for events of other
+/// commands than `scan`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub scan_id: Option<i64>,
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    pub event: FileEvent,
+    pub hash: Option<String>,
+    pub detail: Option<String>,
+    pub at: i64,
+}
+
+/// What a destructive command did to a file, as logged in `undo_log`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UndoAction {
+    /// Removed as a duplicate of `target`
+    Deleted,
+    /// Moved into the trash at `target`
+    Trashed,
+    /// Moved into the quarantine at `target`
+    Quarantined,
+    /// Replaced with a link to `target`
+    Linked,
+    /// Moved to `target`
+    Moved,
+    /// Replaced with its re-encoding at `target`
+    Transcoded,
+}
+
+impl UndoAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Deleted => "deleted",
+            Self::Trashed => "trashed",
+            Self::Quarantined => "quarantined",
+            Self::Linked => "linked",
+            Self::Moved => "moved",
+            Self::Transcoded => "transcoded",
+        }
+    }
+}
+
+impl FromSql for UndoAction {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value.as_str()? {
+            "deleted" => Ok(Self::Deleted),
+            "trashed" => Ok(Self::Trashed),
+            "quarantined" => Ok(Self::Quarantined),
+            "linked" => Ok(Self::Linked),
+            "moved" => Ok(Self::Moved),
+            "transcoded" => Ok(Self::Transcoded),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for UndoAction {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Borrowed(ValueRef::Text(
+            self.as_str().as_bytes(),
+        )))
+    }
+}
+
+/// A row of `undo_log` that was not undone yet.
+#[derive(Debug, Clone, Serialize)]
+pub struct UndoEntry {
+    pub id: i64,
+    pub run_id: i64,
+    pub action: UndoAction,
+    #[serde(serialize_with = "serialize_path")]
+    pub path: PathBuf,
+    #[serde(serialize_with = "serialize_option_path")]
+    pub target: Option<PathBuf>,
+    pub hash: String,
+    pub hash_algorithm: String,
+    pub at: i64,
+}
+
+/// A run of a command with actions that can still be undone.
+#[derive(Debug, Clone, Serialize)]
+pub struct UndoRun {
+    pub run_id: i64,
+    pub command: String,
+    pub started_at: i64,
+    pub actions: u64,
+}
+
+/// Files a scan went through, by what became of them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ScanCounts {
+    /// Hashed and recorded
+    pub recorded: u64,
+    /// Skipped as their size and mtime matched the recorded row
+    pub unchanged: u64,
+    /// Could not be read or inspected
+    pub failed: u64,
+    /// Recorded under the sources but gone, and forgotten
+    pub deleted: u64,
+}
+
+/// A row of `scans`; `finished_at` is `None` while it runs or when it was
+/// interrupted.
+#[derive(Debug, Clone, Serialize)]
+pub struct Scan {
+    pub id: i64,
+    pub sources: Vec<String>,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+    pub counts: ScanCounts,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MediaTypeStats {
+    pub media_type: String,
+    pub files: u64,
+    pub bytes: u64,
+    pub redundant_files: u64,
+    pub wasted_bytes: u64,
+}
+
+/// Videos of one resolution class, each content counted once.
+#[derive(Debug, Serialize)]
+pub struct ResolutionStats {
+    pub resolution: String,
+    pub files: u64,
+    pub bytes: u64,
+    pub hours: f64,
+}
+
+/// Files below a directory of the scanned sources, see
+/// [`LockDB::directory_stats`].
+#[derive(Debug, Serialize)]
+pub struct DirectoryStats {
+    pub directory: String,
+    pub files: u64,
+    pub bytes: u64,
+    pub redundant_files: u64,
+    pub wasted_bytes: u64,
+}
+
+/// The files of a hash shared by more than one file outside read-only
+/// indexes, copies in those indexes included, in capture order.
+#[derive(Debug, Clone, Serialize)]
+pub struct DupGroup {
+    pub hash: String,
+    pub size: u64,
+    pub files: Vec<File>,
+}
+
+impl DupGroup {
+    pub fn original(&self) -> Option<&File> {
+        self.files.iter().find(|file| file.original)
+    }
+
+    pub fn duplicates(&self) -> impl Iterator<Item = &File> {
+        self.files.iter().filter(|file| !file.original)
+    }
+}
+
+/// Files of one read-only index. `local_files` also lie in the collection
+/// and `only_files` nowhere else recorded, so they would have to be
+/// restored from it.
+#[derive(Debug, Serialize)]
+pub struct LabelStats {
+    pub label: String,
+    pub files: u64,
+    pub bytes: u64,
+    pub local_files: u64,
+    pub only_files: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CameraStats {
+    pub camera: String,
+    pub files: u64,
+    pub bytes: u64,
+    pub redundant_files: u64,
+    pub wasted_bytes: u64,
+}
+
+/// The directory `path` counts towards in [`LockDB::directory_stats`]: the
+/// first of `roots` holding it, or the first directory of its path, and
+/// `depth` more levels of its directories below that.
+fn directory_of(path: &Path, roots: &[PathBuf], depth: usize) -> PathBuf {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let (mut directory, below) = match roots.iter().find(|root| dir.starts_with(root)) {
+        Some(root) => (
+            root.clone(),
+            dir.strip_prefix(root).unwrap_or(Path::new("")),
+        ),
+        None => {
+            let mut components = dir.components();
+            let mut first = PathBuf::new();
+            // the root of an absolute path, then its first directory
+            for component in components.by_ref() {
+                first.push(component);
+                if matches!(component, Component::Normal(_)) {
+                    break;
+                }
+            }
+            (first, components.as_path())
+        }
+    };
+    directory.extend(below.components().take(depth));
+    if directory.as_os_str().is_empty() {
+        directory.push(".");
+    }
+    directory
+}
+
+/// Applies the migrations `conn` has not seen yet. A database written by a
+/// newer deduper is refused rather than misread.
+fn migrate(conn: &mut Connection) -> Result<()> {
+    let version = conn.pragma_query_value(None, "user_version", |row| row.get::<_, i64>(0))?;
+    let version = version as usize;
+    if version > MIGRATIONS.len() {
+        return Err(DeduperError::SchemaTooNew {
+            version,
+            supported: MIGRATIONS.len(),
+        });
+    }
+    if version == 0 {
+        add_unversioned_columns(conn)?;
+    }
+    for (applied, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        for statements in *migration {
+            tx.execute_batch(statements)?;
+        }
+        tx.pragma_update(None, "user_version", applied as i64 + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
+
+/// Adds the [`UNVERSIONED_COLUMNS`] an existing `files` table lacks.
+fn add_unversioned_columns(conn: &Connection) -> rusqlite::Result<()> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_table_info('files')")?;
+    let columns = stmt
+        .query_map(params![], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<HashSet<_>>>()?;
+    if columns.is_empty() {
+        // a new database, which migration 1 creates in full
+        return Ok(());
+    }
+    for (column, definition) in UNVERSIONED_COLUMNS {
+        if !columns.contains(column) {
+            conn.execute_batch(&format!(
+                "ALTER TABLE files ADD COLUMN {} {}",
+                column, definition
+            ))?;
+        }
+    }
+    Ok(())
+}
+
+/// How a [`DB`] trades durability for write speed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DbOptions {
+    /// Write-ahead logging, so readers do not block the writer and commits
+    /// only append to the log
+    pub wal: bool,
+    /// `synchronous = NORMAL`, which with WAL skips the sync on every commit
+    /// and can only lose the last commits on power loss, never corrupt the
+    /// database
+    pub synchronous_normal: bool,
+    /// Number of file and journal writes grouped into one transaction; 1
+    /// commits each on its own. Batched writes only become durable with
+    /// [`DB::flush`].
+    pub batch_size: usize,
+}
+
+impl Default for DbOptions {
+    fn default() -> Self {
+        Self {
+            wal: true,
+            synchronous_normal: true,
+            batch_size: 1,
+        }
+    }
+}
+
+/// Writes made in the open batch transaction.
+struct Batch {
+    size: usize,
+    uncommitted: AtomicUsize,
+}
+
+/// How long a connection waits for another one's lock before giving up
+/// with `SQLITE_BUSY`.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Read-only connections to the database file, opened as threads need them
+/// and kept for reuse. With WAL they read while the writer writes.
+struct Pool {
+    path: PathBuf,
+    idle: Mutex<Vec<Connection>>,
+}
+
+impl Pool {
+    fn get(&self) -> rusqlite::Result<PooledConnection<'_>> {
+        let conn = match self.idle.lock().unwrap().pop() {
+            Some(conn) => conn,
+            None => {
+                let conn = Connection::open_with_flags(
+                    &self.path,
+                    OpenFlags::SQLITE_OPEN_READ_ONLY
+                        | OpenFlags::SQLITE_OPEN_URI
+                        | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+                )?;
+                conn.busy_timeout(BUSY_TIMEOUT)?;
+                conn
+            }
+        };
+        Ok(PooledConnection {
+            conn: Some(conn),
+            pool: self,
+        })
+    }
+}
+
+/// A reader taken from the [`Pool`], which gets it back when dropped.
+struct PooledConnection<'a> {
+    conn: Option<Connection>,
+    pool: &'a Pool,
+}
+
+impl Drop for PooledConnection<'_> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.pool.idle.lock().unwrap().push(conn);
+        }
+    }
+}
+
+/// The connection a [`LockDB`] runs its statements on.
+enum Handle<'a> {
+    Writer(MutexGuard<'a, Connection>),
+    Reader(PooledConnection<'a>),
+}
+
+impl Deref for Handle<'_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        match self {
+            Handle::Writer(conn) => conn,
+            Handle::Reader(pooled) => pooled.conn.as_ref().unwrap(),
+        }
+    }
+}
+
+/// The database, with one writer connection that writes are serialized on
+/// and a [`Pool`] of readers for lookups from many threads.
+pub struct DB {
+    conn: Mutex<Connection>,
+    readers: Pool,
+    batch: Batch,
+}
+
+pub struct LockDB<'a>(Handle<'a>, &'a Batch);
+
+impl DB {
+    /// Opens or creates the database at `path` with the default
+    /// [`DbOptions`] and brings its schema up to date.
+    pub fn new(path: &Path) -> Result<Self> {
+        Self::new_with_options(path, DbOptions::default())
+    }
+
+    pub fn new_with_options(path: &Path, options: DbOptions) -> Result<Self> {
+        let mut conn = Connection::open(path)?;
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        if options.wal {
+            // answers with the mode it ended up in, which stays the rollback
+            // journal on filesystems without shared memory
+            conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| {
+                row.get::<_, String>(0)
+            })?;
+        }
+        if options.synchronous_normal {
+            conn.pragma_update(None, "synchronous", "NORMAL")?;
+        }
+        migrate(&mut conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+            readers: Pool {
+                path: path.to_owned(),
+                idle: Mutex::new(Vec::new()),
+            },
+            batch: Batch {
+                size: options.batch_size.max(1),
+                uncommitted: AtomicUsize::new(0),
+            },
+        })
+    }
+
+    /// The writer connection, held until the [`LockDB`] is dropped.
+    pub fn lock(&self) -> LockDB<'_> {
+        LockDB(Handle::Writer(self.conn.lock().unwrap()), &self.batch)
+    }
+
+    /// A read-only connection that does not wait for the writer, for
+    /// lookups that may run on many threads at once. Writes through it
+    /// fail, and it does not see the writes of an unflushed batch. Falls
+    /// back to the writer when no reader can be opened, as with an
+    /// in-memory database.
+    pub fn read(&self) -> LockDB<'_> {
+        match self.readers.get() {
+            Ok(reader) => LockDB(Handle::Reader(reader), &self.batch),
+            Err(_) => self.lock(),
+        }
+    }
+
+    /// Commits the writes of the open batch, also when an earlier commit
+    /// of it failed.
+    pub fn flush(&self) -> rusqlite::Result<()> {
+        let db = self.lock();
+        db.1.uncommitted.store(0, Ordering::Relaxed);
+        if !db.0.is_autocommit() {
+            db.0.execute_batch("COMMIT")?;
+        }
+        Ok(())
+    }
+}
+
+/// Commits what is left of a batch; call [`DB::flush`] to see errors.
+impl Drop for DB {
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
+
+impl LockDB<'_> {
+    /// Runs a file or journal write in the batch transaction, committing
+    /// every [`DbOptions::batch_size`] writes.
+    fn batched<T>(&self, write: impl FnOnce() -> rusqlite::Result<T>) -> rusqlite::Result<T> {
+        if self.1.size == 1 {
+            return write();
+        }
+        if self.0.is_autocommit() {
+            self.0.execute_batch("BEGIN")?;
+        }
+        let result = match write() {
+            Ok(result) => result,
+            Err(err) => {
+                // a batch without writes is not left open
+                if self.1.uncommitted.load(Ordering::Relaxed) == 0 {
+                    let _ = self.0.execute_batch("ROLLBACK");
+                }
+                return Err(err);
+            }
+        };
+        if self.1.uncommitted.fetch_add(1, Ordering::Relaxed) + 1 >= self.1.size {
+            self.1.uncommitted.store(0, Ordering::Relaxed);
+            self.0.execute_batch("COMMIT")?;
+        }
+        Ok(result)
+    }
+
+    pub fn upsert_file(&self, file: &File) -> rusqlite::Result<()> {
+        self.batched(|| self.upsert(file))
+    }
+
+    fn upsert(&self, file: &File) -> rusqlite::Result<()> {
+        self.0.prepare_cached(UPSERT_FILE)?.execute(params![
+            SqlPath(&file.path),
+            file.hash,
+            file.hash_algorithm,
+            file.size,
+            file.media_type,
+            file.created_at,
+            file.modified_at,
+            file.phash.map(|phash| phash as i64),
+            file.utc_offset,
+            file.latitude,
+            file.longitude,
+            file.camera_make,
+            file.camera_model,
+            file.lens_model,
+            file.volume,
+            file.volume_path.as_deref().map(SqlPath),
+            file.dev.map(|dev| dev as i64),
+            file.inode.map(|inode| inode as i64),
+            file.source,
+            file.width,
+            file.height,
+            file.duration,
+            file.codec,
+            file.label,
+        ])?;
+        Ok(())
+    }
+
+    /// Calls `visit` with every file in path order, one row at a time
+    /// rather than loading the whole table.
+    pub fn for_each_file<E: From<rusqlite::Error>>(
+        &self,
+        mut visit: impl FnMut(File) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut stmt = self
+            .0
+            .prepare(&format!("SELECT {} FROM files ORDER BY path", FILE_COLUMNS))?;
+        for file in stmt.query_map(params![], File::from_row)? {
+            visit(file?)?;
+        }
+        Ok(())
+    }
+
+    /// Inserts `files` in one transaction. A row with the same path takes
+    /// the named `columns` of the file and keeps the rest, so an import
+    /// lacking a column does not clear it.
+    pub fn import_files(&self, files: &[File], columns: &[&str]) -> rusqlite::Result<()> {
+        self.import(files.iter().map(|file| (file, None)), columns)
+    }
+
+    /// Like [`LockDB::import_files`] with every column, each file with where
+    /// `organize` placed it.
+    pub fn restore_files<'f>(
+        &self,
+        files: impl Iterator<Item = (&'f File, Option<&'f Path>)>,
+    ) -> rusqlite::Result<()> {
+        let columns = FILE_COLUMNS
+            .split(',')
+            .map(str::trim)
+            .chain(["dest_path"])
+            .collect::<Vec<_>>();
+        self.import(files, &columns)
+    }
+
+    /// Calls `visit` with every row of the tables archived besides
+    /// `files`, table by table.
+    pub fn for_each_archived_row<E: From<rusqlite::Error>>(
+        &self,
+        mut visit: impl FnMut(TableRow) -> Result<(), E>,
+    ) -> Result<(), E> {
+        for (table, query) in ARCHIVED_TABLES {
+            let mut stmt = self.0.prepare(query)?;
+            let names = stmt
+                .column_names()
+                .into_iter()
+                .map(str::to_owned)
+                .collect::<Vec<_>>();
+            let mut rows = stmt.query(params![])?;
+            while let Some(row) = rows.next()? {
+                let columns = names
+                    .iter()
+                    .enumerate()
+                    .map(|(index, name)| Ok((name.clone(), row.get(index)?)))
+                    .collect::<rusqlite::Result<_>>()?;
+                visit(TableRow {
+                    table: table.to_owned(),
+                    columns,
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Inserts rows [`LockDB::for_each_archived_row`] visited in one
+    /// transaction, replacing those with the same key. Runs are numbered on
+    /// from those recorded already, and their undo entries with them, so
+    /// the runs of the archive can be undone as they were.
+    pub fn restore_rows<'r>(
+        &self,
+        rows: impl Iterator<Item = &'r TableRow>,
+    ) -> rusqlite::Result<()> {
+        let tx = self.0.unchecked_transaction()?;
+        let run_offset: i64 =
+            tx.query_row("SELECT COALESCE(MAX(id), 0) FROM runs", params![], |row| {
+                row.get(0)
+            })?;
+        for row in rows {
+            let Some((table, _)) = ARCHIVED_TABLES
+                .iter()
+                .find(|(table, _)| *table == row.table)
+            else {
+                return Err(rusqlite::Error::InvalidParameterName(row.table.clone()));
+            };
+            let mut names = Vec::new();
+            let mut values = Vec::new();
+            for (name, value) in &row.columns {
+                let value = match (*table, name.as_str(), value) {
+                    ("runs", "id", Value::Integer(id))
+                    | ("undo_log", "run_id", Value::Integer(id)) => Value::Integer(id + run_offset),
+                    ("undo_log", "id", _) => continue,
+                    _ => value.clone(),
+                };
+                names.push(format!("\"{}\"", name.replace('"', "\"\"")));
+                values.push(value);
+            }
+            tx.execute(
+                &format!(
+                    "INSERT OR REPLACE INTO {} ({}) VALUES ({})",
+                    table,
+                    names.join(", "),
+                    vec!["?"; values.len()].join(", ")
+                ),
+                rusqlite::params_from_iter(values),
+            )?;
+        }
+        tx.commit()
+    }
+
+    fn import<'f>(
+        &self,
+        files: impl Iterator<Item = (&'f File, Option<&'f Path>)>,
+        columns: &[&str],
+    ) -> rusqlite::Result<()> {
+        // the path is what conflicts, so it is always the same
+        let updates = columns
+            .iter()
+            .filter(|&&column| column != "path")
+            .map(|column| format!("{column} = excluded.{column}"))
+            .collect::<Vec<_>>();
+        let sql = if updates.is_empty() {
+            format!("{} NOTHING", IMPORT_FILE)
+        } else {
+            format!("{} UPDATE SET {}", IMPORT_FILE, updates.join(", "))
+        };
+        let tx = self.0.unchecked_transaction()?;
+        for (file, dest_path) in files {
+            tx.execute(
+                &sql,
+                params![
+                    SqlPath(&file.path),
+                    file.hash,
+                    file.hash_algorithm,
+                    file.size,
+                    file.media_type,
+                    file.created_at,
+                    file.modified_at,
+                    file.original,
+                    file.optimized,
+                    file.phash.map(|phash| phash as i64),
+                    file.utc_offset,
+                    file.latitude,
+                    file.longitude,
+                    file.camera_make,
+                    file.camera_model,
+                    file.lens_model,
+                    file.host,
+                    file.volume,
+                    file.volume_path.as_deref().map(SqlPath),
+                    file.dev.map(|dev| dev as i64),
+                    file.inode.map(|inode| inode as i64),
+                    file.source,
+                    file.width,
+                    file.height,
+                    file.duration,
+                    file.codec,
+                    file.label,
+                    dest_path.map(SqlPath),
+                ],
+            )?;
+        }
+        tx.commit()
+    }
+
+    pub fn all_files(&self) -> rusqlite::Result<Vec<File>> {
+        self.select_files("ORDER BY path", params![])
+    }
+
+    pub fn find_file(&self, path: &Path) -> rusqlite::Result<Option<File>> {
+        self.select_files("WHERE path = ?1", params![SqlPath(path)])
+            .map(|files| files.into_iter().next())
+    }
+
+    /// A recorded file with the device and inode `id` at another path than
+    /// `path`, i.e. another hard link of it unless the inode was reused.
+    pub fn find_hard_link(&self, id: (u64, u64), path: &Path) -> rusqlite::Result<Option<File>> {
+        self.select_files(
+            "WHERE dev = ?1 AND inode = ?2 AND path != ?3 AND host IS NULL LIMIT 1",
+            params![id.0 as i64, id.1 as i64, SqlPath(path)],
+        )
+        .map(|files| files.into_iter().next())
+    }
+
+    /// Records the device and inode of `path` where they changed without
+    /// its contents, e.g. after it was replaced with a hard link.
+    pub fn set_file_id(&self, path: &Path, id: (u64, u64)) -> rusqlite::Result<()> {
+        self.batched(|| {
+            self.0.execute(
+                "UPDATE files SET dev = ?2, inode = ?3 WHERE path = ?1",
+                params![SqlPath(path), id.0 as i64, id.1 as i64],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// One file of every hash to place, see [`FIND_UNIQUE_FILES_ORDERED`].
+    pub fn find_unique_files(&self) -> rusqlite::Result<Vec<File>> {
+        self.select_files(FIND_UNIQUE_FILES_ORDERED, params![])
+    }
+
+    pub fn find_files_by_hash(&self, hash: &str) -> rusqlite::Result<Vec<File>> {
+        self.select_files("WHERE hash = ?1 ORDER BY created_at, path", params![hash])
+    }
+
+    /// Files whose digest starts with `short`, the form file names carry.
+    pub fn find_files_by_short_hash(&self, short: &str) -> rusqlite::Result<Vec<File>> {
+        self.select_files(
+            "WHERE substr(hash, 1, length(?1)) = ?1 ORDER BY created_at, path",
+            params![short],
+        )
+    }
+
+    pub fn find_perceptually_hashed(&self) -> rusqlite::Result<Vec<File>> {
+        self.select_files(
+            "WHERE phash IS NOT NULL ORDER BY created_at, path",
+            params![],
+        )
+    }
+
+    pub fn find_unoptimized_videos(&self) -> rusqlite::Result<Vec<File>> {
+        self.select_files(FIND_UNOPTIMIZED_VIDEOS, params![])
+    }
+
+    /// Hashes shared by more than one file.
+    pub fn find_identical_signs(&self) -> rusqlite::Result<Vec<String>> {
+        let mut stmt = self.0.prepare(FIND_IDENTICAL_SIGNS)?;
+        let signs = stmt.query_map(params![], |row| row.get(0))?;
+        signs.collect()
+    }
+
+    /// Up to `limit` groups of identical files, skipping the first
+    /// `offset`, in hash order so pages of a large database line up.
+    pub fn duplicate_groups(&self, offset: usize, limit: usize) -> rusqlite::Result<Vec<DupGroup>> {
+        // a negative limit is none
+        let limit = i64::try_from(limit).unwrap_or(-1);
+        let files = self.select_files(FIND_DUPLICATE_GROUPS, params![limit, offset as i64])?;
+        let mut groups: Vec<DupGroup> = Vec::new();
+        for file in files {
+            match groups.last_mut() {
+                Some(group) if group.hash == file.hash => group.files.push(file),
+                _ => groups.push(DupGroup {
+                    hash: file.hash.clone(),
+                    size: file.size,
+                    files: vec![file],
+                }),
+            }
+        }
+        Ok(groups)
+    }
+
+    /// How many groups [`LockDB::duplicate_groups`] pages through.
+    pub fn count_duplicate_groups(&self) -> rusqlite::Result<usize> {
+        self.0.query_row(
+            &format!("SELECT COUNT(*) FROM ({})", FIND_IDENTICAL_SIGNS),
+            params![],
+            |row| row.get(0),
+        )
+    }
+
+    /// Hashes of files on more than one machine, see [`File::host`].
+    pub fn find_cross_host_signs(&self) -> rusqlite::Result<Vec<String>> {
+        let mut stmt = self.0.prepare(FIND_CROSS_HOST_SIGNS)?;
+        let signs = stmt.query_map(params![], |row| row.get(0))?;
+        signs.collect()
+    }
+
+    /// Copies in the files the database at `other` scanned as those of
+    /// `host`, replacing what an earlier merge of `host` brought in, along
+    /// with its video fingerprints. `other` has to be at the current
+    /// schema. Returns the number of files now recorded for `host`.
+    pub fn merge(&self, other: &Path, host: &str) -> rusqlite::Result<u64> {
+        // attaching is not allowed within a transaction
+        self.0.execute(
+            "ATTACH DATABASE ?1 AS other",
+            params![other.to_string_lossy().as_ref()],
+        )?;
+        let merged = self.0.unchecked_transaction().and_then(|tx| {
+            tx.execute("DELETE FROM files WHERE host = ?1", params![host])?;
+            tx.execute(MERGE_FILES, params![host])?;
+            tx.execute(MERGE_VIDEO_FINGERPRINTS, params![])?;
+            tx.commit()
+        });
+        let detached = self.0.execute("DETACH DATABASE other", params![]);
+        merged?;
+        detached?;
+        self.0.query_row(
+            "SELECT COUNT(*) FROM files WHERE host = ?1",
+            params![host],
+            |row| row.get(0),
+        )
+    }
+
+    /// Hash algorithms of the files of `host`, or of this machine.
+    pub fn hash_algorithms(&self, host: Option<&str>) -> rusqlite::Result<Vec<String>> {
+        let mut stmt = self.0.prepare(
+            "SELECT DISTINCT hash_algorithm FROM files WHERE host IS ?1 ORDER BY hash_algorithm",
+        )?;
+        let algorithms = stmt.query_map(params![host], |row| row.get(0))?;
+        algorithms.collect()
+    }
+
+    /// Number and total size of files that are not the original of their hash.
+    pub fn count_redundant_files(&self) -> rusqlite::Result<(u64, u64)> {
+        self.0.query_row(COUNT_REDUNDANT_FILES, params![], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+    }
+
+    /// Number and total size of files outside read-only indexes.
+    pub fn count_files(&self) -> rusqlite::Result<(u64, u64)> {
+        self.0.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(size), 0) FROM files WHERE label IS NULL",
+            params![],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+    }
+
+    pub fn media_type_stats(&self) -> rusqlite::Result<Vec<MediaTypeStats>> {
+        let mut stmt = self.0.prepare(MEDIA_TYPE_STATS)?;
+        let stats = stmt.query_map(params![], |row| {
+            Ok(MediaTypeStats {
+                media_type: row.get(0)?,
+                files: row.get(1)?,
+                bytes: row.get(2)?,
+                redundant_files: row.get(3)?,
+                wasted_bytes: row.get(4)?,
+            })
+        })?;
+        stats.collect()
+    }
+
+    pub fn camera_stats(&self) -> rusqlite::Result<Vec<CameraStats>> {
+        let mut stmt = self.0.prepare(CAMERA_STATS)?;
+        let stats = stmt.query_map(params![], |row| {
+            Ok(CameraStats {
+                camera: row.get(0)?,
+                files: row.get(1)?,
+                bytes: row.get(2)?,
+                redundant_files: row.get(3)?,
+                wasted_bytes: row.get(4)?,
+            })
+        })?;
+        stats.collect()
+    }
+
+    pub fn label_stats(&self) -> rusqlite::Result<Vec<LabelStats>> {
+        let mut stmt = self.0.prepare(LABEL_STATS)?;
+        let stats = stmt.query_map(params![], |row| {
+            Ok(LabelStats {
+                label: row.get(0)?,
+                files: row.get(1)?,
+                bytes: row.get(2)?,
+                local_files: row.get(3)?,
+                only_files: row.get(4)?,
+            })
+        })?;
+        stats.collect()
+    }
+
+    pub fn resolution_stats(&self) -> rusqlite::Result<Vec<ResolutionStats>> {
+        let mut stmt = self.0.prepare(RESOLUTION_STATS)?;
+        let stats = stmt.query_map(params![], |row| {
+            Ok(ResolutionStats {
+                resolution: row.get(0)?,
+                files: row.get(1)?,
+                bytes: row.get(2)?,
+                hours: row.get(3)?,
+            })
+        })?;
+        stats.collect()
+    }
+
+    /// Like [`LockDB::media_type_stats`], per directory of the scanned
+    /// sources, the most wasted bytes first: a file counts towards the
+    /// source root it was scanned below, or with `depth` towards the
+    /// directory that many levels further down. Files below no recorded
+    /// source, such as merged or imported ones, count towards the first
+    /// directory of their path.
+    pub fn directory_stats(&self, depth: usize) -> rusqlite::Result<Vec<DirectoryStats>> {
+        let mut roots = self
+            .scans()?
+            .into_iter()
+            .flat_map(|scan| scan.sources)
+            .map(PathBuf::from)
+            .collect::<Vec<_>>();
+        // the innermost of nested sources wins
+        roots.sort_by(|a, b| {
+            b.components()
+                .count()
+                .cmp(&a.components().count())
+                .then_with(|| a.cmp(b))
+        });
+        roots.dedup();
+        let mut stmt = self.0.prepare(REDUNDANT_BY_PATH)?;
+        let rows = stmt.query_map(params![], |row| {
+            Ok((
+                row.get::<_, StoredPath>(0)?.0,
+                row.get::<_, u64>(1)?,
+                row.get::<_, bool>(2)?,
+            ))
+        })?;
+        let mut directories = HashMap::<PathBuf, DirectoryStats>::new();
+        for row in rows {
+            let (path, size, redundant) = row?;
+            let directory = directory_of(&path, &roots, depth);
+            let stats = directories
+                .entry(directory)
+                .or_insert_with_key(|directory| DirectoryStats {
+                    directory: directory.to_string_lossy().into_owned(),
+                    files: 0,
+                    bytes: 0,
+                    redundant_files: 0,
+                    wasted_bytes: 0,
+                });
+            stats.files += 1;
+            stats.bytes += size;
+            if redundant {
+                stats.redundant_files += 1;
+                stats.wasted_bytes += size;
+            }
+        }
+        let mut stats = directories.into_values().collect::<Vec<_>>();
+        stats.sort_by(|a, b| {
+            b.wasted_bytes
+                .cmp(&a.wasted_bytes)
+                .then_with(|| a.directory.cmp(&b.directory))
+        });
+        Ok(stats)
+    }
+
+    pub fn mark_original_files(&self) -> rusqlite::Result<()> {
+        self.0.execute(MARK_ORIGINAL_FILES, params![])?;
+        Ok(())
+    }
+
+    /// The roots set with [`LockDB::set_source_priority`], most preferred
+    /// first, each ending in a path separator.
+    pub fn source_priority(&self) -> rusqlite::Result<Vec<PathBuf>> {
+        let mut stmt = self
+            .0
+            .prepare("SELECT root FROM source_priority ORDER BY position")?;
+        let roots = stmt.query_map(params![], |row| Ok(row.get::<_, StoredPath>(0)?.0))?;
+        roots.collect()
+    }
+
+    /// Prefers files below `roots`, the earliest first, as originals from
+    /// now on, replacing the roots set before; no roots prefer none.
+    pub fn set_source_priority(&self, roots: &[PathBuf]) -> rusqlite::Result<()> {
+        let tx = self.0.unchecked_transaction()?;
+        tx.execute("DELETE FROM source_priority", params![])?;
+        for (position, root) in roots.iter().enumerate() {
+            let mut root = root.as_os_str().to_owned();
+            if !root.to_string_lossy().ends_with(std::path::is_separator) {
+                root.push(std::path::MAIN_SEPARATOR_STR);
+            }
+            tx.execute(
+                "INSERT OR IGNORE INTO source_priority (root, position) VALUES (?1, ?2)",
+                params![SqlPath(Path::new(&root)), position],
+            )?;
+        }
+        tx.commit()
+    }
+
+    /// Makes `path` the one original among the files of `hash`.
+    pub fn set_original(&self, hash: &str, path: &Path) -> rusqlite::Result<()> {
+        self.0.execute(
+            "UPDATE files SET original = (path = ?2) WHERE hash = ?1",
+            params![hash, SqlPath(path)],
+        )?;
+        Ok(())
+    }
+
+    /// Records `decision`, replacing an earlier one for its group.
+    pub fn decide(&self, decision: &Decision) -> rusqlite::Result<()> {
+        self.0.execute(
+            "INSERT OR REPLACE INTO decisions (hash, keep_path, resolution, decided_at) \
+                VALUES (?1, ?2, ?3, ?4)",
+            params![
+                decision.hash,
+                SqlPath(&decision.keep_path),
+                decision.resolution,
+                decision.decided_at
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn forget_decision(&self, hash: &str) -> rusqlite::Result<()> {
+        self.0
+            .execute("DELETE FROM decisions WHERE hash = ?1", params![hash])?;
+        Ok(())
+    }
+
+    pub fn decisions(&self) -> rusqlite::Result<Vec<Decision>> {
+        let mut stmt = self
+            .0
+            .prepare("SELECT hash, keep_path, resolution, decided_at FROM decisions")?;
+        let decisions = stmt.query_map(params![], |row| {
+            Ok(Decision {
+                hash: row.get(0)?,
+                keep_path: row.get::<_, StoredPath>(1)?.0,
+                resolution: row.get(2)?,
+                decided_at: row.get(3)?,
+            })
+        })?;
+        decisions.collect()
+    }
+
+    /// Records that `error.path` failed, counting the attempt when it
+    /// failed before; `error.attempts` is ignored.
+    pub fn record_error(&self, error: &FileError) -> rusqlite::Result<()> {
+        self.0.execute(
+            "INSERT INTO errors (path, stage, kind, message, size, modified_at, at, label) \
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8) \
+                ON CONFLICT (path) DO UPDATE SET stage = excluded.stage, kind = excluded.kind, \
+                    message = excluded.message, size = excluded.size, \
+                    modified_at = excluded.modified_at, attempts = attempts + 1, at = excluded.at, \
+                    label = excluded.label",
+            params![
+                SqlPath(&error.path),
+                error.stage,
+                error.kind,
+                error.message,
+                error.size,
+                error.modified_at,
+                error.at,
+                error.label
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Forgets that `path` failed and returns whether it had.
+    pub fn forget_error(&self, path: &Path) -> rusqlite::Result<bool> {
+        let forgotten = self
+            .0
+            .execute("DELETE FROM errors WHERE path = ?1", params![SqlPath(path)])?;
+        Ok(forgotten > 0)
+    }
+
+    /// Every recorded failure, by path.
+    pub fn errors(&self) -> rusqlite::Result<Vec<FileError>> {
+        let mut stmt = self.0.prepare(
+            "SELECT path, stage, kind, message, size, modified_at, attempts, at, label FROM errors \
+                ORDER BY path",
+        )?;
+        let errors = stmt.query_map(params![], |row| {
+            Ok(FileError {
+                path: row.get::<_, StoredPath>(0)?.0,
+                stage: row.get(1)?,
+                kind: row.get(2)?,
+                message: row.get(3)?,
+                size: row.get(4)?,
+                modified_at: row.get(5)?,
+                attempts: row.get(6)?,
+                at: row.get(7)?,
+                label: row.get(8)?,
+            })
+        })?;
+        errors.collect()
+    }
+
+    /// Records that the file of `conflict.source` was placed at
+    /// `conflict.path` instead of the name another file holds.
+    pub fn record_conflict(&self, conflict: &Conflict) -> rusqlite::Result<()> {
+        self.0.execute(
+            "INSERT OR REPLACE INTO conflicts (path, source, taken, hash, at) \
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                SqlPath(&conflict.path),
+                SqlPath(&conflict.source),
+                SqlPath(&conflict.taken),
+                conflict.hash,
+                conflict.at
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Forgets the conflict of the file placed at `path` and returns
+    /// whether there was one.
+    pub fn forget_conflict(&self, path: &Path) -> rusqlite::Result<bool> {
+        let forgotten = self.0.execute(
+            "DELETE FROM conflicts WHERE path = ?1",
+            params![SqlPath(path)],
+        )?;
+        Ok(forgotten > 0)
+    }
+
+    /// Every recorded conflict, by the name that was taken.
+    pub fn conflicts(&self) -> rusqlite::Result<Vec<Conflict>> {
+        let mut stmt = self
+            .0
+            .prepare("SELECT path, source, taken, hash, at FROM conflicts ORDER BY taken, path")?;
+        let conflicts = stmt.query_map(params![], |row| {
+            Ok(Conflict {
+                path: row.get::<_, StoredPath>(0)?.0,
+                source: row.get::<_, StoredPath>(1)?.0,
+                taken: row.get::<_, StoredPath>(2)?.0,
+                hash: row.get(3)?,
+                at: row.get(4)?,
+            })
+        })?;
+        conflicts.collect()
+    }
+
+    pub fn set_video_fingerprint(&self, hash: &str, frames: &[u64]) -> rusqlite::Result<()> {
+        self.0.execute(
+            "INSERT OR REPLACE INTO video_fingerprints (hash, frames) VALUES (?1, ?2)",
+            params![hash, videohash::to_bytes(frames)],
+        )?;
+        Ok(())
+    }
+
+    pub fn has_video_fingerprint(&self, hash: &str) -> rusqlite::Result<bool> {
+        self.0.query_row(
+            "SELECT EXISTS (SELECT 1 FROM video_fingerprints WHERE hash = ?1)",
+            params![hash],
+            |row| row.get(0),
+        )
+    }
+
+    /// Fingerprints of the videos still recorded, by hash in the order of
+    /// their earliest capture.
+    pub fn video_fingerprints(&self) -> rusqlite::Result<Vec<(String, Vec<u64>)>> {
+        let mut stmt = self.0.prepare(
+            "SELECT v.hash, v.frames FROM video_fingerprints v \
+                JOIN (SELECT hash, MIN(created_at) AS created_at FROM files GROUP BY hash) f \
+                ON f.hash = v.hash \
+                ORDER BY f.created_at, v.hash",
+        )?;
+        let fingerprints = stmt.query_map(params![], |row| {
+            Ok((
+                row.get(0)?,
+                videohash::from_bytes(&row.get::<_, Vec<u8>>(1)?),
+            ))
+        })?;
+        fingerprints.collect()
+    }
+
+    /// Forgets the file at `path`, with what its library said about it.
+    pub fn delete_file(&self, path: &Path) -> rusqlite::Result<()> {
+        let tx = self.0.unchecked_transaction()?;
+        for table in ["files", "library_assets", "library_albums"] {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE path = ?1", table),
+                params![SqlPath(path)],
+            )?;
+        }
+        tx.commit()
+    }
+
+    /// Records the favorite flag, name on import and albums `asset` has in
+    /// its Apple Photos library for the file scanned at `path`.
+    pub fn record_library_asset(&self, path: &Path, asset: &LibraryAsset) -> rusqlite::Result<()> {
+        self.batched(|| {
+            self.0
+                .prepare_cached(
+                    "INSERT OR REPLACE INTO library_assets (path, original_name, favorite) \
+                        VALUES (?1, ?2, ?3)",
+                )?
+                .execute(params![SqlPath(path), asset.original_name, asset.favorite])?;
+            self.0
+                .prepare_cached("DELETE FROM library_albums WHERE path = ?1")?
+                .execute(params![SqlPath(path)])?;
+            let mut insert = self.0.prepare_cached(
+                "INSERT OR IGNORE INTO library_albums (path, album) VALUES (?1, ?2)",
+            )?;
+            for album in &asset.albums {
+                insert.execute(params![SqlPath(path), album])?;
+            }
+            Ok(())
+        })
+    }
+
+    /// What the libraries of scanned files said about them, by path.
+    pub fn find_library_assets(&self) -> rusqlite::Result<HashMap<PathBuf, LibraryAsset>> {
+        let mut stmt = self
+            .0
+            .prepare("SELECT path, original_name, favorite FROM library_assets")?;
+        let rows = stmt.query_map(params![], |row| {
+            let asset = LibraryAsset {
+                original_name: row.get(1)?,
+                favorite: row.get(2)?,
+                albums: Vec::new(),
+            };
+            Ok((row.get::<_, StoredPath>(0)?.0, asset))
+        })?;
+        let mut assets = rows.collect::<rusqlite::Result<HashMap<_, _>>>()?;
+        let mut stmt = self
+            .0
+            .prepare("SELECT path, album FROM library_albums ORDER BY path, album")?;
+        let rows = stmt.query_map(params![], |row| {
+            Ok((row.get::<_, StoredPath>(0)?.0, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (path, album) = row?;
+            if let Some(asset) = assets.get_mut(&path) {
+                asset.albums.push(album);
+            }
+        }
+        Ok(assets)
+    }
+
+    /// Moves the row of `path` to `new_path`, replacing a row recorded
+    /// there, as when its drive is mounted elsewhere, and logs the move.
+    pub fn relocate_file(
+        &self,
+        path: &Path,
+        new_path: &Path,
+        hash: Option<&str>,
+    ) -> rusqlite::Result<()> {
+        self.0.execute(
+            "UPDATE OR REPLACE files SET path = ?2 WHERE path = ?1",
+            params![SqlPath(path), SqlPath(new_path)],
+        )?;
+        self.0.execute(
+            "UPDATE decisions SET keep_path = ?2 WHERE keep_path = ?1",
+            params![SqlPath(path), SqlPath(new_path)],
+        )?;
+        for table in ["library_assets", "library_albums"] {
+            self.0.execute(
+                &format!("DELETE FROM {} WHERE path = ?2 AND ?1 != ?2", table),
+                params![SqlPath(path), SqlPath(new_path)],
+            )?;
+            self.0.execute(
+                &format!("UPDATE {} SET path = ?2 WHERE path = ?1", table),
+                params![SqlPath(path), SqlPath(new_path)],
+            )?;
+        }
+        self.log_event(
+            None,
+            new_path,
+            FileEvent::Moved,
+            hash,
+            Some(&path.to_string_lossy()),
+        )
+    }
+
+    /// Records where `path` was placed in the destination tree. Files that
+    /// were never scanned have no row and are left out.
+    pub fn set_dest_path(&self, path: &Path, dest_path: &Path) -> rusqlite::Result<()> {
+        self.0.execute(
+            "UPDATE files SET dest_path = ?2 WHERE path = ?1",
+            params![SqlPath(path), SqlPath(dest_path)],
+        )?;
+        Ok(())
+    }
+
+    /// Records that what was placed at `dest_path` is at `path` now, as
+    /// when a move is undone: a file back at its source is placed nowhere,
+    /// one back at an earlier destination is placed there.
+    pub fn move_dest_path(&self, dest_path: &Path, path: &Path) -> rusqlite::Result<()> {
+        self.0.execute(
+            "UPDATE files SET dest_path = CASE WHEN path = ?2 THEN NULL ELSE ?2 END \
+             WHERE dest_path = ?1",
+            params![SqlPath(dest_path), SqlPath(path)],
+        )?;
+        Ok(())
+    }
+
+    /// Where `path` was placed, if it was scanned and placed.
+    pub fn find_dest_path(&self, path: &Path) -> rusqlite::Result<Option<PathBuf>> {
+        let mut stmt = self
+            .0
+            .prepare("SELECT dest_path FROM files WHERE path = ?1 AND dest_path IS NOT NULL")?;
+        let dest_paths = stmt.query_map(params![SqlPath(path)], |row| {
+            Ok(row.get::<_, StoredPath>(0)?.0)
+        })?;
+        dest_paths
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map(|dest_paths| dest_paths.into_iter().next())
+    }
+
+    /// One file of each hash placed below `destination`, or placed anywhere
+    /// without one, with where it lies there, in capture order. Files
+    /// scanned inside the destination count as placed at their own path.
+    pub fn find_placed_files(
+        &self,
+        destination: Option<&Path>,
+    ) -> rusqlite::Result<Vec<(File, PathBuf)>> {
+        let mut stmt = self.0.prepare(&format!(
+            "SELECT {}, dest_path FROM files WHERE host IS NULL ORDER BY created_at, path",
+            FILE_COLUMNS
+        ))?;
+        let rows = stmt.query_map(params![], |row| {
+            Ok((
+                File::from_row(row)?,
+                row.get::<_, Option<StoredPath>>("dest_path")?
+                    .map(|path| path.0),
+            ))
+        })?;
+        let mut hashes = HashSet::new();
+        let mut placed = Vec::new();
+        for row in rows {
+            let (file, dest_path) = row?;
+            let placed_at = match destination {
+                Some(destination) => dest_path
+                    .into_iter()
+                    .chain([file.path.clone()])
+                    .find(|path| path.starts_with(destination)),
+                None => dest_path,
+            };
+            if let Some(placed_at) = placed_at {
+                if hashes.insert(file.hash.clone()) {
+                    placed.push((file, placed_at));
+                }
+            }
+        }
+        Ok(placed)
+    }
+
+    pub fn find_placements(&self) -> rusqlite::Result<Vec<Placement>> {
+        let mut stmt = self.0.prepare(
+            "SELECT path, dest_path FROM files WHERE dest_path IS NOT NULL ORDER BY path",
+        )?;
+        let placements = stmt.query_map(params![], |row| {
+            Ok(Placement {
+                path: row.get::<_, StoredPath>(0)?.0,
+                dest_path: row.get::<_, StoredPath>(1)?.0,
+            })
+        })?;
+        placements.collect()
+    }
+
+    /// Where the contents of each hash of `algorithm` are in one of
+    /// `destinations`: the placement of a file placed there, or the file
+    /// itself when it was scanned inside one.
+    pub fn find_placed_hashes(
+        &self,
+        destinations: &[&Path],
+        algorithm: &str,
+    ) -> rusqlite::Result<HashMap<String, PathBuf>> {
+        let mut stmt = self.0.prepare(
+            "SELECT hash, path, dest_path FROM files \
+                WHERE hash_algorithm = ?1 AND host IS NULL ORDER BY path",
+        )?;
+        let rows = stmt.query_map(params![algorithm], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, StoredPath>(1)?.0,
+                row.get::<_, Option<StoredPath>>(2)?.map(|path| path.0),
+            ))
+        })?;
+        let mut placed = HashMap::new();
+        for row in rows {
+            let (hash, path, dest_path) = row?;
+            let in_destination = dest_path.into_iter().chain([path]).find(|path| {
+                destinations
+                    .iter()
+                    .any(|destination| path.starts_with(destination))
+            });
+            if let Some(path) = in_destination {
+                placed.entry(hash).or_insert(path);
+            }
+        }
+        Ok(placed)
+    }
+
+    pub fn find_unoptimized_images(&self) -> rusqlite::Result<Vec<File>> {
+        self.select_files(FIND_UNOPTIMIZED_IMAGES, params![])
+    }
+
+    /// Records the re-encoded contents of a file, with the mtime it was
+    /// written at so the next scan does not hash it again, and logs a
+    /// [`FileEvent::Transcoded`].
+    pub fn mark_optimized(&self, path: &Path, encoded: &Encoded) -> rusqlite::Result<()> {
+        self.0.execute(
+            "UPDATE files SET optimized = TRUE, hash = ?2, size = ?3, modified_at = ?4, \
+                width = ?5, height = ?6, duration = ?7, codec = ?8 WHERE path = ?1",
+            params![
+                SqlPath(path),
+                encoded.hash,
+                encoded.size,
+                encoded.modified_at,
+                encoded.format.width,
+                encoded.format.height,
+                encoded.format.duration,
+                encoded.format.codec
+            ],
+        )?;
+        self.log_event(None, path, FileEvent::Transcoded, Some(encoded.hash), None)
+    }
+
+    /// Records that re-encoding `path` did not make it smaller, so it is
+    /// left as it is and not tried again.
+    pub fn mark_skipped(&self, path: &Path) -> rusqlite::Result<()> {
+        self.0.execute(
+            "UPDATE files SET optimized = 'skipped' WHERE path = ?1",
+            params![SqlPath(path)],
+        )?;
+        Ok(())
+    }
+
+    /// Like [`LockDB::mark_optimized`] for a file converted to another
+    /// format, which moves it to `new_path`.
+    pub fn mark_converted(
+        &self,
+        path: &Path,
+        new_path: &Path,
+        media_type: &str,
+        encoded: &Encoded,
+    ) -> rusqlite::Result<()> {
+        self.0.execute(
+            "UPDATE files SET optimized = TRUE, path = ?2, media_type = ?3, hash = ?4, size = ?5, \
+                modified_at = ?6, width = ?7, height = ?8, duration = ?9, codec = ?10 \
+                WHERE path = ?1",
+            params![
+                SqlPath(path),
+                SqlPath(new_path),
+                media_type,
+                encoded.hash,
+                encoded.size,
+                encoded.modified_at,
+                encoded.format.width,
+                encoded.format.height,
+                encoded.format.duration,
+                encoded.format.codec
+            ],
+        )?;
+        self.log_event(
+            None,
+            new_path,
+            FileEvent::Transcoded,
+            Some(encoded.hash),
+            Some(&path.to_string_lossy()),
+        )
+    }
+
+    /// Starts a run and returns its id.
+    pub fn start_run(
+        &self,
+        command: &str,
+        parameters: &str,
+        started_at: i64,
+    ) -> rusqlite::Result<i64> {
+        self.0.execute(
+            "INSERT INTO runs (command, parameters, started_at) VALUES (?1, ?2, ?3)",
+            params![command, parameters, started_at],
+        )?;
+        Ok(self.0.last_insert_rowid())
+    }
+
+    /// The latest run of `command` with the same parameters that never finished.
+    pub fn find_unfinished_run(
+        &self,
+        command: &str,
+        parameters: &str,
+    ) -> rusqlite::Result<Option<i64>> {
+        let mut stmt = self.0.prepare(FIND_UNFINISHED_RUN)?;
+        let mut ids = stmt.query_map(params![command, parameters], |row| row.get(0))?;
+        ids.next().transpose()
+    }
+
+    pub fn journaled_paths(&self, run_id: i64) -> rusqlite::Result<Vec<PathBuf>> {
+        let mut stmt = self
+            .0
+            .prepare("SELECT path FROM journal WHERE run_id = ?1")?;
+        let paths = stmt.query_map(params![run_id], |row| Ok(row.get::<_, StoredPath>(0)?.0))?;
+        paths.collect()
+    }
+
+    pub fn journal_path(&self, run_id: i64, path: &Path) -> rusqlite::Result<()> {
+        self.batched(|| {
+            self.0
+                .prepare_cached("INSERT OR IGNORE INTO journal (run_id, path) VALUES (?1, ?2)")?
+                .execute(params![run_id, SqlPath(path)])?;
+            Ok(())
+        })
+    }
+
+    /// Marks a run finished; its journal is no longer needed.
+    pub fn finish_run(&self, run_id: i64, finished_at: i64) -> rusqlite::Result<()> {
+        self.0.execute(
+            "UPDATE runs SET finished_at = ?2 WHERE id = ?1",
+            params![run_id, finished_at],
+        )?;
+        self.0
+            .execute("DELETE FROM journal WHERE run_id = ?1", params![run_id])?;
+        Ok(())
+    }
+
+    /// Starts a scan of `sources` and returns its id.
+    pub fn start_scan(&self, sources: &[String], started_at: i64) -> rusqlite::Result<i64> {
+        self.0.execute(
+            "INSERT INTO scans (sources, started_at) VALUES (?1, ?2)",
+            params![sources.join("\n"), started_at],
+        )?;
+        Ok(self.0.last_insert_rowid())
+    }
+
+    pub fn finish_scan(
+        &self,
+        scan_id: i64,
+        finished_at: i64,
+        counts: &ScanCounts,
+    ) -> rusqlite::Result<()> {
+        self.0.execute(
+            "UPDATE scans SET finished_at = ?2, recorded = ?3, unchanged = ?4, failed = ?5, \
+                deleted = ?6 WHERE id = ?1",
+            params![
+                scan_id,
+                finished_at,
+                counts.recorded,
+                counts.unchanged,
+                counts.failed,
+                counts.deleted
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// All scans, the latest first.
+    pub fn scans(&self) -> rusqlite::Result<Vec<Scan>> {
+        let mut stmt = self.0.prepare(
+            "SELECT id, sources, started_at, finished_at, recorded, unchanged, failed, deleted \
+                FROM scans ORDER BY id DESC",
+        )?;
+        let scans = stmt.query_map(params![], |row| {
+            Ok(Scan {
+                id: row.get(0)?,
+                sources: row
+                    .get::<_, String>(1)?
+                    .lines()
+                    .map(str::to_owned)
+                    .collect(),
+                started_at: row.get(2)?,
+                finished_at: row.get(3)?,
+                counts: ScanCounts {
+                    recorded: row.get(4)?,
+                    unchanged: row.get(5)?,
+                    failed: row.get(6)?,
+                    deleted: row.get(7)?,
+                },
+            })
+        })?;
+        scans.collect()
+    }
+
+    /// Appends to the history of `path`; `scan_id` is the scan it happened
+    /// in, if any.
+    pub fn log_event(
+        &self,
+        scan_id: Option<i64>,
+        path: &Path,
+        event: FileEvent,
+        hash: Option<&str>,
+        detail: Option<&str>,
+    ) -> rusqlite::Result<()> {
+        self.batched(|| {
+            self.0.prepare_cached(LOG_EVENT)?.execute(params![
+                scan_id,
+                SqlPath(path),
+                event,
+                hash,
+                detail
+            ])?;
+            Ok(())
+        })
+    }
+
+    /// Records what run `run_id` did to `path`, which held the contents
+    /// `hash`, so [`crate::undo`] can reverse it.
+    pub fn log_undo(
+        &self,
+        run_id: i64,
+        action: UndoAction,
+        path: &Path,
+        target: Option<&Path>,
+        hash: &str,
+        hash_algorithm: &str,
+    ) -> rusqlite::Result<()> {
+        self.0.prepare_cached(LOG_UNDO)?.execute(params![
+            run_id,
+            action,
+            SqlPath(path),
+            target.map(SqlPath),
+            hash,
+            hash_algorithm
+        ])?;
+        Ok(())
+    }
+
+    pub fn undo_runs(&self) -> rusqlite::Result<Vec<UndoRun>> {
+        let mut stmt = self.0.prepare(FIND_UNDO_RUNS)?;
+        let runs = stmt.query_map(params![], |row| {
+            Ok(UndoRun {
+                run_id: row.get(0)?,
+                command: row.get(1)?,
+                started_at: row.get(2)?,
+                actions: row.get(3)?,
+            })
+        })?;
+        runs.collect()
+    }
+
+    /// The actions of run `run_id` not undone yet, latest first, the order
+    /// they are undone in.
+    pub fn undo_entries(&self, run_id: i64) -> rusqlite::Result<Vec<UndoEntry>> {
+        let mut stmt = self.0.prepare(
+            "SELECT id, run_id, action, path, target, hash, hash_algorithm, at FROM undo_log
+            WHERE run_id = ?1 AND undone_at IS NULL
+            ORDER BY id DESC",
+        )?;
+        let entries = stmt.query_map(params![run_id], |row| {
+            Ok(UndoEntry {
+                id: row.get(0)?,
+                run_id: row.get(1)?,
+                action: row.get(2)?,
+                path: row.get::<_, StoredPath>(3)?.0,
+                target: row.get::<_, Option<StoredPath>>(4)?.map(|path| path.0),
+                hash: row.get(5)?,
+                hash_algorithm: row.get(6)?,
+                at: row.get(7)?,
+            })
+        })?;
+        entries.collect()
+    }
+
+    pub fn mark_undone(&self, id: i64) -> rusqlite::Result<()> {
+        self.0.execute(
+            "UPDATE undo_log SET undone_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE id = ?1",
+            params![id],
+        )?;
+        Ok(())
+    }
+
+    /// What happened to files from the start of scan `scan_id` on, oldest
+    /// first.
+    pub fn events_since(&self, scan_id: i64) -> rusqlite::Result<Vec<Event>> {
+        let mut stmt = self.0.prepare(FIND_EVENTS_SINCE)?;
+        let events = stmt.query_map(params![scan_id], |row| {
+            Ok(Event {
+                scan_id: row.get(0)?,
+                path: row.get::<_, StoredPath>(1)?.0,
+                event: row.get(2)?,
+                hash: row.get(3)?,
+                detail: row.get(4)?,
+                at: row.get(5)?,
+            })
+        })?;
+        events.collect()
+    }
+
+    /// Puts files a crashed run left in progress back to pending and
+    /// returns their paths.
+    pub fn requeue_interrupted_transcodes(&self) -> rusqlite::Result<Vec<PathBuf>> {
+        let mut stmt = self
+            .0
+            .prepare("SELECT path FROM transcode_queue WHERE state = 'in-progress'")?;
+        let paths = stmt
+            .query_map(params![], |row| Ok(row.get::<_, StoredPath>(0)?.0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        self.0.execute(
+            "UPDATE transcode_queue SET state = 'pending' WHERE state = 'in-progress'",
+            params![],
+        )?;
+        Ok(paths)
+    }
+
+    pub fn enqueue_transcode(&self, path: &Path) -> rusqlite::Result<()> {
+        self.0.execute(ENQUEUE_TRANSCODE, params![SqlPath(path)])?;
+        Ok(())
+    }
+
+    /// Paths that are pending, or failed after fewer than `max_attempts`.
+    pub fn due_transcodes(&self, max_attempts: u32) -> rusqlite::Result<HashSet<PathBuf>> {
+        let mut stmt = self.0.prepare(
+            "SELECT path FROM transcode_queue \
+                WHERE state = 'pending' OR (state = 'failed' AND attempts < ?1)",
+        )?;
+        let paths = stmt.query_map(params![max_attempts], |row| {
+            Ok(row.get::<_, StoredPath>(0)?.0)
+        })?;
+        paths.collect()
+    }
+
+    pub fn start_transcode(&self, path: &Path) -> rusqlite::Result<()> {
+        self.0.execute(
+            "UPDATE transcode_queue SET state = 'in-progress', attempts = attempts + 1, \
+                updated_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE path = ?1",
+            params![SqlPath(path)],
+        )?;
+        Ok(())
+    }
+
+    /// Marks a queued file done, or failed with `error`.
+    pub fn finish_transcode(&self, path: &Path, error: Option<&str>) -> rusqlite::Result<()> {
+        self.0.execute(
+            "UPDATE transcode_queue SET state = ?2, error = ?3, \
+                updated_at = CAST(strftime('%s', 'now') AS INTEGER) WHERE path = ?1",
+            params![
+                SqlPath(path),
+                if error.is_some() { "failed" } else { "done" },
+                error
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn select_files(
+        &self,
+        clause: &str,
+        params: &[&dyn rusqlite::ToSql],
+    ) -> rusqlite::Result<Vec<File>> {
+        let mut stmt = self
+            .0
+            .prepare_cached(&format!("SELECT {} FROM files {}", FILE_COLUMNS, clause))?;
+        let files = stmt.query_map(params, File::from_row)?;
+        files.collect()
+    }
+}
+
+#[test]
+fn test_migrate_unversioned_database() {
+    let path = std::env::temp_dir().join(format!("deduper-migrate-{}.db", std::process::id()));
+    let conn = Connection::open(&path).unwrap();
+    // files as the first releases created it
+    conn.execute_batch(
+        "CREATE TABLE files (
+            path TEXT PRIMARY KEY,
+            hash TEXT NOT NULL,
+            hash_algorithm TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            media_type TEXT NOT NULL,
+            created_at INTEGER NOT NULL,
+            modified_at INTEGER NOT NULL,
+            original BOOLEAN NOT NULL DEFAULT FALSE
+        );
+        INSERT INTO files VALUES ('/a.jpg', 'abc', 'blake3', 1, 'image/jpeg', 0, 0, TRUE);",
+    )
+    .unwrap();
+    drop(conn);
+
+    let db = DB::new(&path).unwrap();
+    let file = db.lock().find_file(Path::new("/a.jpg")).unwrap().unwrap();
+    assert!(file.original);
+    assert_eq!(Optimized::No, file.optimized);
+    assert_eq!(None, file.camera_make);
+    db.lock().enqueue_transcode(Path::new("/a.jpg")).unwrap();
+    drop(db);
+    // an up-to-date database is left alone
+    drop(DB::new(&path).unwrap());
+
+    let conn = Connection::open(&path).unwrap();
+    conn.pragma_update(None, "user_version", MIGRATIONS.len() as i64 + 1)
+        .unwrap();
+    drop(conn);
+    assert!(matches!(
+        DB::new(&path),
+        Err(DeduperError::SchemaTooNew { .. })
+    ));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_read_pool() {
+    let path = std::env::temp_dir().join(format!("deduper-pool-{}.db", std::process::id()));
+    let db = DB::new(&path).unwrap();
+    db.lock().enqueue_transcode(Path::new("/a.mp4")).unwrap();
+    assert!(db
+        .read()
+        .due_transcodes(3)
+        .unwrap()
+        .contains(Path::new("/a.mp4")));
+    // readers cannot write
+    assert!(db.read().enqueue_transcode(Path::new("/b.mp4")).is_err());
+    // and go back to the pool for the next lookup
+    assert_eq!(1, db.readers.idle.lock().unwrap().len());
+    drop(db);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_file_history() {
+    let path = std::env::temp_dir().join(format!("deduper-history-{}.db", std::process::id()));
+    let db = DB::new(&path).unwrap();
+    let scan_id = db.lock().start_scan(&["/photos".to_owned()], 0).unwrap();
+    db.lock()
+        .log_event(
+            Some(scan_id),
+            Path::new("/photos/a.jpg"),
+            FileEvent::Added,
+            Some("abc"),
+            None,
+        )
+        .unwrap();
+    db.lock()
+        .log_event(
+            None,
+            Path::new("/photos/a.jpg"),
+            FileEvent::Transcoded,
+            Some("def"),
+            None,
+        )
+        .unwrap();
+    let counts = ScanCounts {
+        recorded: 1,
+        ..ScanCounts::default()
+    };
+    db.lock().finish_scan(scan_id, 1, &counts).unwrap();
+
+    let scans = db.lock().scans().unwrap();
+    assert_eq!(vec!["/photos".to_owned()], scans[0].sources);
+    assert_eq!(counts, scans[0].counts);
+    let events = db.lock().events_since(scan_id).unwrap();
+    assert_eq!(
+        vec![FileEvent::Added, FileEvent::Transcoded],
+        events.iter().map(|event| event.event).collect::<Vec<_>>()
+    );
+    assert_eq!(Some(scan_id), events[0].scan_id);
+    drop(db);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_decisions() {
+    let path = std::env::temp_dir().join(format!("deduper-decisions-{}.db", std::process::id()));
+    let db = DB::new(&path).unwrap();
+    let decision = Decision {
+        hash: "abc".to_owned(),
+        keep_path: PathBuf::from("/photos/a.jpg"),
+        resolution: Resolution::Keep,
+        decided_at: 1,
+    };
+    db.lock().decide(&decision).unwrap();
+    let changed = Decision {
+        resolution: Resolution::Delete,
+        ..decision
+    };
+    db.lock().decide(&changed).unwrap();
+    assert_eq!(vec![changed], db.lock().decisions().unwrap());
+    db.lock().forget_decision("abc").unwrap();
+    assert!(db.lock().decisions().unwrap().is_empty());
+    drop(db);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_merge() {
+    let dir = std::env::temp_dir();
+    let path = dir.join(format!("deduper-merge-{}.db", std::process::id()));
+    let other_path = dir.join(format!("deduper-merge-nas-{}.db", std::process::id()));
+    let db = DB::new(&path).unwrap();
+    db.lock()
+        .upsert_file(&File::test("/photos/a.jpg", "abc"))
+        .unwrap();
+    let other = DB::new(&other_path).unwrap();
+    // taken earlier, yet the copy on this machine stays the original
+    other
+        .lock()
+        .upsert_file(&File {
+            created_at: -1,
+            ..File::test("/photos/a.jpg", "abc")
+        })
+        .unwrap();
+    other
+        .lock()
+        .upsert_file(&File::test("/photos/b.jpg", "def"))
+        .unwrap();
+
+    assert_eq!(2, db.lock().merge(&other_path, "nas").unwrap());
+    let merged = db
+        .lock()
+        .find_file(Path::new("nas:/photos/a.jpg"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(Some("nas"), merged.host.as_deref());
+    db.lock().mark_original_files().unwrap();
+    let local = db
+        .lock()
+        .find_file(Path::new("/photos/a.jpg"))
+        .unwrap()
+        .unwrap();
+    assert!(local.original);
+    assert!(
+        !db.lock()
+            .find_file(Path::new("nas:/photos/a.jpg"))
+            .unwrap()
+            .unwrap()
+            .original
+    );
+    assert_eq!(
+        vec!["abc".to_owned()],
+        db.lock().find_cross_host_signs().unwrap()
+    );
+    // merging again replaces what the first merge brought in
+    other
+        .lock()
+        .delete_file(Path::new("/photos/a.jpg"))
+        .unwrap();
+    assert_eq!(1, db.lock().merge(&other_path, "nas").unwrap());
+    assert!(db.lock().find_cross_host_signs().unwrap().is_empty());
+    assert_eq!(2, db.lock().count_files().unwrap().0);
+    drop((db, other));
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&other_path).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_utf8_path() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let path = std::env::temp_dir().join(format!("deduper-non-utf8-{}.db", std::process::id()));
+    let latin1 = Path::new(OsStr::from_bytes(b"/photos/caf\xe9.jpg"));
+    let ToSqlOutput::Owned(Value::Blob(bytes)) = SqlPath(latin1).to_sql().unwrap() else {
+        panic!("a path that is not UTF-8 is bound as a BLOB");
+    };
+    assert_eq!(b"/photos/caf\xe9.jpg", bytes.as_slice());
+    assert!(matches!(
+        SqlPath(Path::new("/photos/café.jpg")).to_sql().unwrap(),
+        ToSqlOutput::Borrowed(ValueRef::Text(_))
+    ));
+
+    let file = File::test(latin1, "abc");
+    let db = DB::new(&path).unwrap();
+    db.lock().upsert_file(&file).unwrap();
+    let found = db.lock().find_file(latin1).unwrap().unwrap();
+    assert_eq!(latin1, found.path);
+    // the lossy spelling is another file
+    assert!(db
+        .lock()
+        .find_file(Path::new("/photos/caf\u{FFFD}.jpg"))
+        .unwrap()
+        .is_none());
+    drop(db);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_find_placed_hashes() {
+    let path = std::env::temp_dir().join(format!("deduper-placed-{}.db", std::process::id()));
+    let database = DB::new(&path).unwrap();
+    let db = database.lock();
+    db.upsert_file(&File::test("/sd/a.jpg", "abc")).unwrap();
+    db.set_dest_path(Path::new("/sd/a.jpg"), Path::new("/library/2023/a.jpg"))
+        .unwrap();
+    // scanned inside the destination
+    db.upsert_file(&File::test("/library/2022/b.jpg", "def"))
+        .unwrap();
+    // placed into an older destination
+    db.upsert_file(&File::test("/sd/c.jpg", "ghi")).unwrap();
+    db.set_dest_path(Path::new("/sd/c.jpg"), Path::new("/old/c.jpg"))
+        .unwrap();
+    db.upsert_file(&File::test("/sd/d.jpg", "jkl")).unwrap();
+
+    let placed = db
+        .find_placed_hashes(&[Path::new("/library")], "blake3")
+        .unwrap();
+    assert_eq!(2, placed.len());
+    assert_eq!(Path::new("/library/2023/a.jpg"), placed["abc"]);
+    assert_eq!(Path::new("/library/2022/b.jpg"), placed["def"]);
+    assert!(db
+        .find_placed_hashes(&[Path::new("/library")], "sha256")
+        .unwrap()
+        .is_empty());
+    drop(db);
+    drop(database);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_resolution_stats() {
+    let path = std::env::temp_dir().join(format!("deduper-resolution-{}.db", std::process::id()));
+    let video = |path: &str, hash: &str, width: u32, height: u32| File {
+        size: 10,
+        media_type: "video/mp4".to_owned(),
+        width: Some(width),
+        height: Some(height),
+        duration: Some(1800.0),
+        codec: Some("hevc".to_owned()),
+        ..File::test(path, hash)
+    };
+    let database = DB::new(&path).unwrap();
+    let db = database.lock();
+    db.upsert_file(&video("/a.mp4", "abc", 3840, 2160)).unwrap();
+    // a copy is not footage of its own
+    db.upsert_file(&video("/copy/a.mp4", "abc", 3840, 2160))
+        .unwrap();
+    db.upsert_file(&video("/b.mp4", "def", 2160, 3840)).unwrap();
+    db.upsert_file(&video("/c.mp4", "ghi", 1920, 1080)).unwrap();
+
+    let stats = db.resolution_stats().unwrap();
+    assert_eq!(2, stats.len());
+    assert_eq!("4K", stats[0].resolution);
+    assert_eq!(2, stats[0].files);
+    assert_eq!(1.0, stats[0].hours);
+    assert_eq!("1080p", stats[1].resolution);
+    assert_eq!(0.5, stats[1].hours);
+    drop(db);
+    drop(database);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_find_placed_files() {
+    let path = std::env::temp_dir().join(format!("deduper-placed-files-{}.db", std::process::id()));
+    let image = |path: &str, hash: &str, created_at: i64| File {
+        size: 10,
+        created_at,
+        ..File::test(path, hash)
+    };
+    let database = DB::new(&path).unwrap();
+    let db = database.lock();
+    db.upsert_file(&image("/src/b.jpg", "def", 20)).unwrap();
+    db.upsert_file(&image("/src/a.jpg", "abc", 10)).unwrap();
+    db.upsert_file(&image("/src/copy/a.jpg", "abc", 10))
+        .unwrap();
+    db.upsert_file(&image("/src/c.jpg", "ghi", 30)).unwrap();
+    db.upsert_file(&image("/library/d.jpg", "jkl", 40)).unwrap();
+    db.set_dest_path(Path::new("/src/a.jpg"), Path::new("/library/a.jpg"))
+        .unwrap();
+    db.set_dest_path(Path::new("/src/copy/a.jpg"), Path::new("/library/a.jpg"))
+        .unwrap();
+    db.set_dest_path(Path::new("/src/b.jpg"), Path::new("/library/b.jpg"))
+        .unwrap();
+    db.set_dest_path(Path::new("/src/c.jpg"), Path::new("/elsewhere/c.jpg"))
+        .unwrap();
+
+    let placed = db.find_placed_files(Some(Path::new("/library"))).unwrap();
+    let dest_paths = placed
+        .iter()
+        .map(|(_, dest_path)| dest_path.to_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec!["/library/a.jpg", "/library/b.jpg", "/library/d.jpg"],
+        dest_paths
+    );
+    // d.jpg was scanned in place, not placed
+    assert_eq!(3, db.find_placed_files(None).unwrap().len());
+    drop(db);
+    drop(database);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_directory_stats() {
+    let path = std::env::temp_dir().join(format!("deduper-directories-{}.db", std::process::id()));
+    let image = |path: &str, hash: &str, created_at: i64| File {
+        size: 10,
+        created_at,
+        ..File::test(path, hash)
+    };
+    let database = DB::new(&path).unwrap();
+    let db = database.lock();
+    db.start_scan(&["/photos".to_owned(), "/backup".to_owned()], 0)
+        .unwrap();
+    db.upsert_file(&image("/photos/2023/a.jpg", "abc", 10))
+        .unwrap();
+    db.upsert_file(&image("/photos/2023/b.jpg", "def", 10))
+        .unwrap();
+    db.upsert_file(&image("/backup/old-laptop/a.jpg", "abc", 10))
+        .unwrap();
+    db.upsert_file(&image("/backup/old-laptop/b.jpg", "def", 10))
+        .unwrap();
+    db.upsert_file(&image("/backup/phone/b.jpg", "def", 10))
+        .unwrap();
+    db.upsert_file(&image("/elsewhere/x/c.jpg", "ghi", 10))
+        .unwrap();
+
+    // the copies share their capture time, and /backup sorts first
+    db.set_original("abc", Path::new("/photos/2023/a.jpg"))
+        .unwrap();
+    db.set_original("def", Path::new("/photos/2023/b.jpg"))
+        .unwrap();
+
+    let stats = db.directory_stats(0).unwrap();
+    let rows = stats
+        .iter()
+        .map(|stats| (stats.directory.as_str(), stats.files, stats.wasted_bytes))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![("/backup", 3, 30), ("/elsewhere", 1, 0), ("/photos", 2, 0)],
+        rows
+    );
+    let stats = db.directory_stats(1).unwrap();
+    assert_eq!("/backup/old-laptop", stats[0].directory);
+    assert_eq!(20, stats[0].wasted_bytes);
+    assert_eq!("/backup/phone", stats[1].directory);
+    assert_eq!("/elsewhere/x", stats[2].directory);
+    drop(db);
+    drop(database);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_failed_batch_write() {
+    let path = std::env::temp_dir().join(format!("deduper-batch-{}.db", std::process::id()));
+    let options = DbOptions {
+        batch_size: 10,
+        ..DbOptions::default()
+    };
+    let database = DB::new_with_options(&path, options).unwrap();
+    let db = database.lock();
+    assert!(db
+        .batched(|| Err::<(), _>(rusqlite::Error::InvalidQuery))
+        .is_err());
+    assert!(db.0.is_autocommit());
+    db.batched(|| Ok(())).unwrap();
+    assert!(db
+        .batched(|| Err::<(), _>(rusqlite::Error::InvalidQuery))
+        .is_err());
+    assert!(!db.0.is_autocommit());
+    drop(db);
+    database.flush().unwrap();
+    assert!(database.lock().0.is_autocommit());
+    drop(database);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_errors() {
+    let path = std::env::temp_dir().join(format!("deduper-errors-{}.db", std::process::id()));
+    let database = DB::new(&path).unwrap();
+    let db = database.lock();
+    let error = FileError {
+        path: PathBuf::from("/photos/broken.jpg"),
+        stage: "scan".to_owned(),
+        kind: "exif".to_owned(),
+        message: "exif error: truncated".to_owned(),
+        size: Some(10),
+        modified_at: Some(100),
+        attempts: 1,
+        at: 1000,
+        label: None,
+    };
+    db.record_error(&error).unwrap();
+    db.record_error(&FileError {
+        at: 2000,
+        ..error.clone()
+    })
+    .unwrap();
+    let errors = db.errors().unwrap();
+    assert_eq!(1, errors.len());
+    assert_eq!(2, errors[0].attempts);
+    assert_eq!(2000, errors[0].at);
+    assert!(db.forget_error(&error.path).unwrap());
+    assert!(!db.forget_error(&error.path).unwrap());
+    assert!(db.errors().unwrap().is_empty());
+    drop(db);
+    drop(database);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_conflicts() {
+    let path = std::env::temp_dir().join(format!("deduper-conflicts-{}.db", std::process::id()));
+    let database = DB::new(&path).unwrap();
+    let db = database.lock();
+    let conflict = Conflict {
+        path: PathBuf::from("/dest/2001/a_abc.1.png"),
+        source: PathBuf::from("/phone/a.png"),
+        taken: PathBuf::from("/dest/2001/a_abc.png"),
+        hash: "abcdef".to_owned(),
+        at: 1000,
+    };
+    db.record_conflict(&conflict).unwrap();
+    db.record_conflict(&Conflict {
+        at: 2000,
+        ..conflict.clone()
+    })
+    .unwrap();
+    let conflicts = db.conflicts().unwrap();
+    assert_eq!(1, conflicts.len());
+    assert_eq!(2000, conflicts[0].at);
+    assert_eq!(conflict.taken, conflicts[0].taken);
+    assert!(db.forget_conflict(&conflict.path).unwrap());
+    assert!(!db.forget_conflict(&conflict.path).unwrap());
+    assert!(db.conflicts().unwrap().is_empty());
+    drop(db);
+    drop(database);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_labels() {
+    let path = std::env::temp_dir().join(format!("deduper-labels-{}.db", std::process::id()));
+    let image = |path: &str, hash: &str, created_at: i64, label: Option<&str>| File {
+        size: 10,
+        created_at,
+        label: label.map(str::to_owned),
+        ..File::test(path, hash)
+    };
+    let database = DB::new(&path).unwrap();
+    let db = database.lock();
+    // the backup holds the earlier capture of a, yet the local copy is kept
+    db.upsert_file(&image("/photos/a.jpg", "abc", 20, None))
+        .unwrap();
+    db.upsert_file(&image("/backup/a.jpg", "abc", 10, Some("backup2019")))
+        .unwrap();
+    db.upsert_file(&image("/backup/b.jpg", "def", 10, Some("backup2019")))
+        .unwrap();
+    db.mark_original_files().unwrap();
+    let original = |path: &str| db.find_file(Path::new(path)).unwrap().unwrap().original;
+    assert!(original("/photos/a.jpg"));
+    assert!(!original("/backup/a.jpg"));
+    assert!(original("/backup/b.jpg"));
+
+    assert_eq!((1, 10), db.count_files().unwrap());
+    assert_eq!((0, 0), db.count_redundant_files().unwrap());
+    assert!(db.find_identical_signs().unwrap().is_empty());
+    let unique = db.find_unique_files().unwrap();
+    assert_eq!(
+        vec![PathBuf::from("/photos/a.jpg")],
+        unique.into_iter().map(|file| file.path).collect::<Vec<_>>()
+    );
+    let stats = db.label_stats().unwrap();
+    assert_eq!(1, stats.len());
+    assert_eq!(
+        ("backup2019", 2, 20, 1, 1),
+        (
+            stats[0].label.as_str(),
+            stats[0].files,
+            stats[0].bytes,
+            stats[0].local_files,
+            stats[0].only_files
+        )
+    );
+    drop(db);
+    drop(database);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_source_priority() {
+    let path = std::env::temp_dir().join(format!("deduper-priority-{}.db", std::process::id()));
+    let image = |path: &str, created_at: i64| File {
+        size: 10,
+        created_at,
+        ..File::test(path, "abc")
+    };
+    let database = DB::new(&path).unwrap();
+    let db = database.lock();
+    db.upsert_file(&image("/phone/a.jpg", 10)).unwrap();
+    db.upsert_file(&image("/photos-old/a.jpg", 5)).unwrap();
+    db.upsert_file(&image("/photos/2020/a.jpg", 20)).unwrap();
+    let original = || {
+        db.mark_original_files().unwrap();
+        db.find_files_by_hash("abc")
+            .unwrap()
+            .into_iter()
+            .find(|file| file.original)
+            .unwrap()
+            .path
+    };
+    assert_eq!(PathBuf::from("/photos-old/a.jpg"), original());
+
+    // a prefix of a directory name is no match
+    db.set_source_priority(&[PathBuf::from("/photos"), PathBuf::from("/phone/")])
+        .unwrap();
+    assert_eq!(
+        vec![PathBuf::from("/photos/"), PathBuf::from("/phone/")],
+        db.source_priority().unwrap()
+    );
+    assert_eq!(PathBuf::from("/photos/2020/a.jpg"), original());
+    db.set_source_priority(&[PathBuf::from("/phone")]).unwrap();
+    assert_eq!(PathBuf::from("/phone/a.jpg"), original());
+    db.set_source_priority(&[]).unwrap();
+    assert_eq!(PathBuf::from("/photos-old/a.jpg"), original());
+    drop(db);
+    drop(database);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_duplicate_groups() {
+    let path = std::env::temp_dir().join(format!("deduper-groups-{}.db", std::process::id()));
+    let image = |path: &str, hash: &str, label: Option<&str>| File {
+        size: hash.len() as u64,
+        label: label.map(str::to_owned),
+        ..File::test(path, hash)
+    };
+    let database = DB::new(&path).unwrap();
+    let db = database.lock();
+    for file in [
+        image("/a/1.jpg", "a", None),
+        image("/b/1.jpg", "a", None),
+        image("/backup/1.jpg", "a", Some("backup")),
+        // one local copy and one in an index is no group
+        image("/a/2.jpg", "bb", None),
+        image("/backup/2.jpg", "bb", Some("backup")),
+        image("/a/3.jpg", "ccc", None),
+        image("/b/3.jpg", "ccc", None),
+        image("/a/4.jpg", "dddd", None),
+        image("/b/4.jpg", "dddd", None),
+        image("/c/4.jpg", "dddd", None),
+    ] {
+        db.upsert_file(&file).unwrap();
+    }
+    assert_eq!(3, db.count_duplicate_groups().unwrap());
+    let page = |offset, limit| {
+        db.duplicate_groups(offset, limit)
+            .unwrap()
+            .into_iter()
+            .map(|group| (group.hash, group.size, group.files.len()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        vec![("a".to_owned(), 1, 3), ("ccc".to_owned(), 3, 2)],
+        page(0, 2)
+    );
+    assert_eq!(vec![("dddd".to_owned(), 4, 3)], page(2, 2));
+    assert!(page(3, 2).is_empty());
+    assert_eq!(3, page(0, usize::MAX).len());
+    drop(db);
+    drop(database);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_import_files() {
+    let path = std::env::temp_dir().join(format!("deduper-import-{}.db", std::process::id()));
+    let file = File {
+        size: 10,
+        created_at: 10,
+        width: Some(640),
+        height: Some(480),
+        label: Some("backup".to_owned()),
+        ..File::test("/src/a.jpg", "abc")
+    };
+    let database = DB::new(&path).unwrap();
+    let db = database.lock();
+    db.upsert_file(&file).unwrap();
+    db.set_dest_path(&file.path, Path::new("/library/a.jpg"))
+        .unwrap();
+
+    // an export without the later columns, which also never has dest_path
+    let imported = File {
+        original: true,
+        width: None,
+        height: None,
+        label: None,
+        ..file.clone()
+    };
+    let columns = ["path", "hash", "original"];
+    db.import_files(std::slice::from_ref(&imported), &columns)
+        .unwrap();
+    let found = db.find_file(&file.path).unwrap().unwrap();
+    assert!(found.original);
+    assert_eq!((Some(640), Some(480)), (found.width, found.height));
+    assert_eq!(Some("backup".to_owned()), found.label);
+    let placed = db.find_placed_files(None).unwrap();
+    assert_eq!(PathBuf::from("/library/a.jpg"), placed[0].1);
+
+    db.import_files(std::slice::from_ref(&imported), &["path", "label"])
+        .unwrap();
+    assert_eq!(None, db.find_file(&file.path).unwrap().unwrap().label);
+    drop(db);
+    drop(database);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_library_assets() {
+    let path = std::env::temp_dir().join(format!("deduper-library-{}.db", std::process::id()));
+    let image = |path: &str| File {
+        size: 10,
+        ..File::test(path, "abc")
+    };
+    let asset = LibraryAsset {
+        original_name: Some("IMG_0001.JPG".to_owned()),
+        favorite: true,
+        albums: vec!["Paris".to_owned()],
+    };
+    let database = DB::new(&path).unwrap();
+    let db = database.lock();
+    db.upsert_file(&image("/a.jpg")).unwrap();
+    db.upsert_file(&image("/b.jpg")).unwrap();
+    db.record_library_asset(Path::new("/a.jpg"), &asset)
+        .unwrap();
+    db.record_library_asset(Path::new("/b.jpg"), &LibraryAsset::default())
+        .unwrap();
+
+    // the row replaced by a move takes its library rows along
+    db.relocate_file(Path::new("/a.jpg"), Path::new("/b.jpg"), None)
+        .unwrap();
+    let assets = db.find_library_assets().unwrap();
+    assert_eq!(1, assets.len());
+    assert_eq!(Some(&asset), assets.get(Path::new("/b.jpg")));
+
+    db.delete_file(Path::new("/b.jpg")).unwrap();
+    assert!(db.find_library_assets().unwrap().is_empty());
+    let albums: i64 =
+        db.0.query_row("SELECT COUNT(*) FROM library_albums", [], |row| row.get(0))
+            .unwrap();
+    assert_eq!(0, albums);
+    drop(db);
+    drop(database);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,583 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use regex::Regex;
+
+use crate::{
+    database::{DupGroup, File, LockDB, UndoAction},
+    error::{DeduperError, Result},
+    events::{EventSink, Events},
+    hasher::{self, HashAlgorithm},
+    linker::{self, LinkStrategy},
+    phash, platform, quarantine, source, trash,
+    undo::UndoLog,
+};
+
+/// Whether `files` are all hard links of one inode, so they take the space
+/// of one file already and removing duplicates would free nothing.
+pub fn hard_linked(files: &[File]) -> bool {
+    let Some(first) = files.first() else {
+        return false;
+    };
+    first.inode.is_some()
+        && files
+            .iter()
+            .all(|file| (file.dev, file.inode) == (first.dev, first.inode))
+}
+
+/// Bytes per pixel of a photo saved without visible recompression; JPEGs
+/// straight from phones and cameras are around 0.3.
+const REFERENCE_BYTES_PER_PIXEL: f64 = 0.25;
+
+/// Bytes per pixel per second of a video encoded without visible loss.
+const REFERENCE_BYTES_PER_PIXEL_SECOND: f64 = 0.5;
+
+/// Share of the best quality in a fuzzy group below which a copy is never
+/// kept, see [`KeepPolicy::choose_similar`].
+const MIN_QUALITY_RATIO: f64 = 0.5;
+
+/// How much picture a file holds: its pixels, discounted when it spends
+/// fewer bytes on them than [`REFERENCE_BYTES_PER_PIXEL`] (or, for videos,
+/// [`REFERENCE_BYTES_PER_PIXEL_SECOND`]), as recompressed copies do. The
+/// frame size is the scanned one, or else read from the image. `None` when
+/// the size is unknown.
+pub fn quality(file: &File) -> Option<f64> {
+    let (width, height) = file
+        .width
+        .zip(file.height)
+        .or_else(|| image::image_dimensions(&file.path).ok())?;
+    let pixels = f64::from(width) * f64::from(height);
+    if pixels == 0.0 {
+        return None;
+    }
+    let bytes_per_pixel = file.size as f64 / pixels;
+    let density = match file.duration.filter(|duration| *duration > 0.0) {
+        Some(duration) => bytes_per_pixel / duration / REFERENCE_BYTES_PER_PIXEL_SECOND,
+        None => bytes_per_pixel / REFERENCE_BYTES_PER_PIXEL,
+    };
+    Some(pixels * density.min(1.0))
+}
+
+/// Which file of a group is kept as the original. Ties, and files no rule
+/// ranks, fall back to the earliest capture, then the path. Files of
+/// read-only indexes, see [`File::label`], are only kept when the group has
+/// no other, and files below preferred sources, see
+/// [`LockDB::source_priority`], before the policy is asked.
+#[derive(Debug, Clone, Default)]
+pub enum KeepPolicy {
+    /// The earliest capture
+    #[default]
+    Oldest,
+    /// The latest capture
+    Newest,
+    /// The file with the shortest path
+    ShortestPath,
+    /// The image or video with the most pixels, as scanned or else read
+    /// from the image. Copies of one hash share their pixels, so this only
+    /// tells apart the files of fuzzy groups.
+    LargestResolution,
+    /// The file of the best [`quality`]
+    BestQuality,
+    /// The file matching the earliest of these patterns
+    PathPriority(Vec<Regex>),
+}
+
+impl KeepPolicy {
+    /// The file of `files` this policy keeps, among those below the
+    /// earliest of the `preferred` roots any of them is below.
+    pub fn choose<'f>(&self, preferred: &[PathBuf], files: &'f [File]) -> Option<&'f File> {
+        self.choose_among(preferred, files.iter())
+    }
+
+    fn choose_among<'f>(
+        &self,
+        preferred: &[PathBuf],
+        files: impl Iterator<Item = &'f File>,
+    ) -> Option<&'f File> {
+        files
+            .map(|file| {
+                let preference = preferred
+                    .iter()
+                    .position(|root| file.path.starts_with(root))
+                    .unwrap_or(preferred.len());
+                let key = (
+                    file.host.is_some(),
+                    source::is_remote(&file.path),
+                    file.label.is_some(),
+                    preference,
+                    self.rank(file),
+                    file.created_at,
+                );
+                ((key, &file.path), file)
+            })
+            .min_by(|(a, _), (b, _)| a.cmp(b))
+            .map(|(_, file)| file)
+    }
+
+    /// The file of `files`, a fuzzy group of differing copies, this policy
+    /// keeps among those of at least [`MIN_QUALITY_RATIO`] of the best
+    /// [`quality`], so a shrunken or recompressed copy is never kept over
+    /// the full one whatever its capture time, path or source.
+    pub fn choose_similar<'f>(&self, preferred: &[PathBuf], files: &'f [File]) -> Option<&'f File> {
+        let qualities = files.iter().map(quality).collect::<Vec<_>>();
+        let best = qualities
+            .iter()
+            .flatten()
+            .fold(0.0, |best: f64, quality| best.max(*quality));
+        self.choose_among(
+            preferred,
+            files
+                .iter()
+                .zip(qualities)
+                .filter(|(_, quality)| quality.unwrap_or(0.0) >= best * MIN_QUALITY_RATIO)
+                .map(|(file, _)| file),
+        )
+    }
+
+    /// Lower ranks are kept first.
+    fn rank(&self, file: &File) -> i64 {
+        let path = file.path.as_path();
+        match self {
+            KeepPolicy::Oldest => 0,
+            KeepPolicy::Newest => -file.created_at,
+            KeepPolicy::ShortestPath => file.path.to_string_lossy().chars().count() as i64,
+            KeepPolicy::LargestResolution => file
+                .width
+                .zip(file.height)
+                .or_else(|| image::image_dimensions(path).ok())
+                .map(|(width, height)| -(width as i64 * height as i64))
+                .unwrap_or(0),
+            KeepPolicy::BestQuality => quality(file).map_or(0, |quality| -(quality as i64)),
+            KeepPolicy::PathPriority(patterns) => patterns
+                .iter()
+                .position(|pattern| pattern.is_match(&file.path.to_string_lossy()))
+                .unwrap_or(patterns.len()) as i64,
+        }
+    }
+}
+
+/// How a duplicate is disposed of by [`Deduper::remove`].
+#[derive(Debug, Clone)]
+pub enum Removal {
+    Delete,
+    /// Move into a freedesktop.org style trash directory
+    Trash(PathBuf),
+    /// Move into today's folder of a quarantine directory
+    Quarantine(PathBuf),
+}
+
+/// Resolves duplicate groups recorded in the database.
+pub struct Deduper<'a> {
+    db: LockDB<'a>,
+    compare_bytes: bool,
+    keep: KeepPolicy,
+    undo: Option<UndoLog>,
+    events: Events,
+}
+
+impl<'a> Deduper<'a> {
+    pub fn new(db: LockDB<'a>) -> Self {
+        Self {
+            db,
+            compare_bytes: false,
+            keep: KeepPolicy::default(),
+            undo: None,
+            events: Events::default(),
+        }
+    }
+
+    /// Compare each duplicate with its original byte for byte before
+    /// removing or replacing it, rather than trusting equal hashes.
+    pub fn compare_bytes(mut self, compare_bytes: bool) -> Self {
+        self.compare_bytes = compare_bytes;
+        self
+    }
+
+    /// Which file of each group [`Deduper::mark_originals`] keeps.
+    pub fn keep(mut self, keep: KeepPolicy) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /// Log every removed or replaced duplicate to `undo`, so the run can be
+    /// rolled back with [`crate::undo::undo`].
+    pub fn undo_log(mut self, undo: UndoLog) -> Self {
+        self.undo = Some(undo);
+        self
+    }
+
+    /// Report duplicates found, removed and linked to `sink`.
+    pub fn events(mut self, sink: Arc<dyn EventSink>) -> Self {
+        self.events = Events::new(sink);
+        self
+    }
+
+    pub fn keep_policy(&self) -> &KeepPolicy {
+        &self.keep
+    }
+
+    pub fn db(&self) -> &LockDB<'a> {
+        &self.db
+    }
+
+    /// Marks the original of every hash as the [`KeepPolicy`] chooses, or
+    /// as decided in an earlier review, and returns the groups that have
+    /// more than one file.
+    pub fn mark_originals(&self) -> Result<Vec<DupGroup>> {
+        // the earliest capture, and the original of every single file
+        self.db.mark_original_files()?;
+        let decided = self
+            .db
+            .decisions()?
+            .into_iter()
+            .map(|decision| (decision.hash, decision.keep_path))
+            .collect::<HashMap<_, _>>();
+        let preferred = self.db.source_priority()?;
+        let mut groups = Vec::new();
+        for mut group in self.db.duplicate_groups(0, usize::MAX)? {
+            let kept = match decided.get(&group.hash) {
+                Some(kept) if group.files.iter().any(|file| &file.path == kept) => {
+                    Some(kept.clone())
+                }
+                _ if matches!(self.keep, KeepPolicy::Oldest) => None,
+                _ => self
+                    .keep
+                    .choose(&preferred, &group.files)
+                    .map(|file| file.path.clone()),
+            };
+            if let Some(kept) = kept {
+                self.set_original(&mut group.files, &group.hash, &kept)?;
+            }
+            if let Some(original) = group.original() {
+                for duplicate in group.duplicates() {
+                    self.events.duplicate_found(original, duplicate);
+                }
+            }
+            groups.push(group);
+        }
+        Ok(groups)
+    }
+
+    /// Makes `kept` the original of `files`, all of `hash`.
+    pub fn set_original(&self, files: &mut [File], hash: &str, kept: &Path) -> Result<()> {
+        self.db.set_original(hash, kept)?;
+        for file in files {
+            file.original = file.path == kept;
+        }
+        Ok(())
+    }
+
+    /// Groups images whose perceptual hashes differ by at most `distance` bits.
+    pub fn similar_images(&self, distance: u32) -> Result<Vec<Vec<File>>> {
+        let files = self.db.find_perceptually_hashed()?;
+        Ok(phash::group_by_distance(
+            files
+                .into_iter()
+                .filter_map(|file| Some((file.phash?, file)))
+                .collect(),
+            distance,
+        ))
+    }
+
+    /// Removes `duplicate` and forgets it in the database. Both files are
+    /// re-hashed first, as either may have changed since the scan. Returns
+    /// `false`, keeping the duplicate, when `original` is no longer on disk,
+    /// so a group is never left without any copy, when both paths are the
+    /// same file, as one seen through a followed symlink is, and when the
+    /// duplicate lies in a remote source or a read-only index, which are only
+    /// recorded for comparison.
+    pub fn remove(&self, original: &File, duplicate: &File, removal: &Removal) -> Result<bool> {
+        if source::is_remote(&duplicate.path) || duplicate.label.is_some() {
+            return Ok(false);
+        }
+        let Ok(original_meta) = fs::metadata(&original.path) else {
+            return Ok(false);
+        };
+        if platform::same_file(&original_meta, &fs::metadata(&duplicate.path)?) {
+            return Ok(false);
+        }
+        verify(&original.path, duplicate)?;
+        verify(&duplicate.path, duplicate)?;
+        self.check_contents(original, duplicate)?;
+        let (action, target) = match removal {
+            Removal::Delete => {
+                fs::remove_file(&duplicate.path)?;
+                (UndoAction::Deleted, original.path.clone())
+            }
+            Removal::Trash(dir) => {
+                let trashed = trash::trash(&duplicate.path, dir)?;
+                (UndoAction::Trashed, trashed)
+            }
+            Removal::Quarantine(dir) => {
+                let quarantined = quarantine::quarantine(&duplicate.path, dir)?;
+                (UndoAction::Quarantined, quarantined)
+            }
+        };
+        self.db.delete_file(&duplicate.path)?;
+        self.log_undo(action, duplicate, &target)?;
+        self.events.removed(original, duplicate);
+        Ok(true)
+    }
+
+    /// Swaps `duplicate` for a link to `original`. The link is built next to
+    /// the duplicate and renamed over it, so the path is never missing, and
+    /// both files are re-hashed before and after the swap. Returns `false`
+    /// when the duplicate already is a link or lies in a remote source or a
+    /// read-only index.
+    pub fn link(&self, original: &File, duplicate: &File, strategy: LinkStrategy) -> Result<bool> {
+        let duplicate_path = duplicate.path.as_path();
+        if source::is_remote(duplicate_path) || duplicate.label.is_some() {
+            return Ok(false);
+        }
+        let original_path = platform::canonicalize(&original.path)?;
+        let verify = |path: &Path| verify(path, duplicate);
+
+        let original_meta = fs::metadata(&original_path)?;
+        let duplicate_meta = fs::symlink_metadata(duplicate_path)?;
+        if duplicate_meta.file_type().is_symlink()
+            || platform::same_file(&original_meta, &duplicate_meta)
+        {
+            return Ok(false);
+        }
+        verify(&original_path)?;
+        verify(duplicate_path)?;
+        self.check_contents(original, duplicate)?;
+
+        let mut temp_name = OsString::from(".");
+        temp_name.push(duplicate_path.file_name().unwrap_or_default());
+        temp_name.push(".deduper-link");
+        let temp_path = duplicate_path.with_file_name(temp_name);
+        linker::place(strategy, &original_path, &temp_path)?;
+        if let Err(err) = verify(&temp_path) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(err);
+        }
+        if let Err(err) = fs::rename(&temp_path, duplicate_path) {
+            let _ = fs::remove_file(&temp_path);
+            return Err(err.into());
+        }
+        verify(duplicate_path)?;
+        self.log_undo(UndoAction::Linked, duplicate, &original.path)?;
+        self.events.linked(duplicate_path, &original.path);
+        Ok(true)
+    }
+
+    fn log_undo(&self, action: UndoAction, duplicate: &File, target: &Path) -> Result<()> {
+        if let Some(undo) = &self.undo {
+            undo.record(
+                &self.db,
+                action,
+                &duplicate.path,
+                Some(target),
+                &duplicate.hash,
+                &duplicate.hash_algorithm,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// With [`Deduper::compare_bytes`], fails unless both files hold the
+    /// same bytes.
+    fn check_contents(&self, original: &File, duplicate: &File) -> Result<()> {
+        if self.compare_bytes && !hasher::same_contents(&original.path, &duplicate.path)? {
+            return Err(DeduperError::ContentsDiffer(
+                duplicate.path.clone(),
+                original.path.clone(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Fails unless the file at `path` still hashes to what was recorded for
+/// `duplicate`.
+fn verify(path: &Path, duplicate: &File) -> Result<()> {
+    let algorithm = duplicate
+        .hash_algorithm
+        .parse::<HashAlgorithm>()
+        .unwrap_or_default();
+    if hasher::file_hash(path, algorithm)?.matches(&duplicate.hash) {
+        Ok(())
+    } else {
+        Err(DeduperError::HashMismatch(path.to_owned()))
+    }
+}
+
+#[test]
+fn test_keep_policy() {
+    let file = |path: &str, created_at| File {
+        created_at,
+        modified_at: created_at,
+        ..File::test(path, "hash")
+    };
+    let files = [
+        file("/backup/phone/2020/a.jpg", 20),
+        file("/photos/a.jpg", 30),
+        file("/phone/DCIM/a.jpg", 10),
+    ];
+    let kept = |policy: KeepPolicy| policy.choose(&[], &files).unwrap().path.to_str().unwrap();
+    assert_eq!("/phone/DCIM/a.jpg", kept(KeepPolicy::Oldest));
+    assert_eq!("/photos/a.jpg", kept(KeepPolicy::Newest));
+    assert_eq!("/photos/a.jpg", kept(KeepPolicy::ShortestPath));
+    // a copy merged from another machine is only kept when there is no other
+    let merged = [
+        File {
+            host: Some("nas".to_owned()),
+            ..file("nas:/phone/DCIM/a.jpg", 0)
+        },
+        file("/photos/a.jpg", 30),
+    ];
+    assert_eq!(
+        "/photos/a.jpg",
+        KeepPolicy::Oldest
+            .choose(&[], &merged)
+            .unwrap()
+            .path
+            .to_str()
+            .unwrap()
+    );
+    let uploaded = [
+        file("s3://archive/phone/a.jpg", 0),
+        file("rclone:gdrive:Photos/a.jpg", 0),
+        file("/photos/a.jpg", 30),
+    ];
+    assert_eq!(
+        "/photos/a.jpg",
+        KeepPolicy::Oldest
+            .choose(&[], &uploaded)
+            .unwrap()
+            .path
+            .to_str()
+            .unwrap()
+    );
+    assert_eq!(
+        "/photos/a.jpg",
+        kept(KeepPolicy::PathPriority(vec![
+            Regex::new("^/photos/").unwrap(),
+            Regex::new("backup").unwrap()
+        ]))
+    );
+    // preferred sources come before the policy, which picks among them
+    let preferred = [PathBuf::from("/nowhere"), PathBuf::from("/backup")];
+    assert_eq!(
+        "/backup/phone/2020/a.jpg",
+        KeepPolicy::Newest
+            .choose(&preferred, &files)
+            .unwrap()
+            .path
+            .to_str()
+            .unwrap()
+    );
+    // no root matches a partial directory name, so the policy decides
+    assert_eq!(
+        "/phone/DCIM/a.jpg",
+        KeepPolicy::Oldest
+            .choose(&[PathBuf::from("/pho")], &files)
+            .unwrap()
+            .path
+            .to_str()
+            .unwrap()
+    );
+
+    let sized = |path: &str, created_at: i64, width: u32, size: u64| File {
+        width: Some(width),
+        height: Some(width * 3 / 4),
+        size,
+        ..file(path, created_at)
+    };
+    let similar = [
+        sized("/whatsapp/a.jpg", 10, 1600, 150_000),
+        sized("/phone/a.jpg", 20, 4000, 3_600_000),
+        // the same pixels saved again at low quality
+        sized("/export/a.jpg", 15, 4000, 600_000),
+    ];
+    let kept_similar = |policy: KeepPolicy| {
+        policy
+            .choose_similar(&[], &similar)
+            .unwrap()
+            .path
+            .to_str()
+            .unwrap()
+    };
+    assert_eq!("/phone/a.jpg", kept_similar(KeepPolicy::Oldest));
+    assert_eq!("/phone/a.jpg", kept_similar(KeepPolicy::BestQuality));
+    // a preferred source does not save a shrunken copy
+    assert_eq!(
+        "/phone/a.jpg",
+        KeepPolicy::Oldest
+            .choose_similar(&[PathBuf::from("/whatsapp")], &similar)
+            .unwrap()
+            .path
+            .to_str()
+            .unwrap()
+    );
+    // the same pixels tie, so the earlier capture is kept
+    assert_eq!(
+        "/export/a.jpg",
+        KeepPolicy::LargestResolution
+            .choose(&[], &similar)
+            .unwrap()
+            .path
+            .to_str()
+            .unwrap()
+    );
+
+    assert!(!hard_linked(&files));
+    let linked = files
+        .iter()
+        .map(|file| File {
+            dev: Some(1),
+            inode: Some(7),
+            source: None,
+            ..file.clone()
+        })
+        .collect::<Vec<_>>();
+    assert!(hard_linked(&linked));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_remove() {
+    use crate::database::DB;
+
+    let dir = std::env::temp_dir().join(format!("deduper-remove-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("photos")).unwrap();
+    fs::write(dir.join("photos/a.jpg"), b"a").unwrap();
+    fs::write(dir.join("b.jpg"), b"a").unwrap();
+    // a followed symlink records every file below it twice
+    std::os::unix::fs::symlink(dir.join("photos"), dir.join("linked")).unwrap();
+    let hash = hasher::file_hash(&dir.join("b.jpg"), HashAlgorithm::Blake3).unwrap();
+    let file = |path: &str| File::test(dir.join(path), &hash.digest);
+
+    let database = DB::new(&dir.join("deduper.db")).unwrap();
+    let deduper = Deduper::new(database.lock());
+    let original = file("photos/a.jpg");
+    assert!(!deduper
+        .remove(&original, &file("linked/a.jpg"), &Removal::Delete)
+        .unwrap());
+    assert!(dir.join("photos/a.jpg").exists());
+
+    // a copy edited since the scan is kept
+    fs::write(dir.join("b.jpg"), b"b").unwrap();
+    assert!(matches!(
+        deduper.remove(&original, &file("b.jpg"), &Removal::Delete),
+        Err(DeduperError::HashMismatch(_))
+    ));
+    assert!(dir.join("b.jpg").exists());
+
+    fs::write(dir.join("b.jpg"), b"a").unwrap();
+    assert!(deduper
+        .remove(&original, &file("b.jpg"), &Removal::Delete)
+        .unwrap());
+    assert!(!dir.join("b.jpg").exists());
+    assert!(dir.join("photos/a.jpg").exists());
+    drop(deduper);
+    drop(database);
+    fs::remove_dir_all(&dir).unwrap();
+}
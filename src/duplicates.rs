@@ -0,0 +1,136 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    io,
+    path::{Path, PathBuf},
+};
+
+use rayon::prelude::*;
+
+use crate::{
+    hasher::{self, HashAlgorithm, ReadBackend},
+    import::DuplicateSet,
+};
+
+/// Identical files found by [`find_duplicates`], and how many files each
+/// stage had to read.
+#[derive(Debug, Default)]
+pub struct Found {
+    pub sets: Vec<DuplicateSet>,
+    /// Files sharing their size with another, whose ends were hashed
+    pub partially_hashed: usize,
+    /// Files sharing their partial hash with another, hashed whole
+    pub fully_hashed: usize,
+}
+
+/// Finds the files of identical contents among `files`, given with their
+/// sizes, reading as little as possible: only files sharing a size get a
+/// [`hasher::partial_hash`], and only those sharing that as well are
+/// hashed whole, read through `backend`. Files that fail to read are
+/// passed to `on_error` and left out.
+pub fn find_duplicates(
+    files: Vec<(PathBuf, u64)>,
+    algorithm: HashAlgorithm,
+    backend: ReadBackend,
+    on_error: impl Fn(&Path, io::Error) + Sync,
+) -> Found {
+    let candidates = colliding(files.into_iter().map(|(path, size)| (size, path)));
+    let partially_hashed = candidates.len();
+    let hash = |hash: fn(&Path, HashAlgorithm, ReadBackend) -> io::Result<hasher::FileHash>| {
+        let on_error = &on_error;
+        move |path: PathBuf| match hash(&path, algorithm, backend) {
+            Ok(hash) => Some((hash, path)),
+            Err(err) => {
+                on_error(&path, err);
+                None
+            }
+        }
+    };
+
+    let candidates = colliding(
+        candidates
+            .into_par_iter()
+            .filter_map(hash(|path, algorithm, _| {
+                hasher::partial_hash(path, algorithm)
+            }))
+            .collect::<Vec<_>>(),
+    );
+    let fully_hashed = candidates.len();
+    let hashed = candidates
+        .into_par_iter()
+        .filter_map(hash(hasher::file_hash_with))
+        .collect::<Vec<_>>();
+
+    let mut sets = HashMap::<_, Vec<_>>::new();
+    for (hash, path) in hashed {
+        sets.entry(hash).or_default().push(path);
+    }
+    let mut sets = sets
+        .into_iter()
+        .filter(|(_, paths)| paths.len() > 1)
+        .map(|(hash, mut paths)| {
+            paths.sort();
+            DuplicateSet {
+                paths,
+                hash: Some(hash),
+            }
+        })
+        .collect::<Vec<_>>();
+    sets.sort_by(|a, b| a.paths.cmp(&b.paths));
+    Found {
+        sets,
+        partially_hashed,
+        fully_hashed,
+    }
+}
+
+/// The paths whose key another path shares.
+fn colliding<K: Hash + Eq>(keyed: impl IntoIterator<Item = (K, PathBuf)>) -> Vec<PathBuf> {
+    let mut groups = HashMap::<K, Vec<PathBuf>>::new();
+    for (key, path) in keyed {
+        groups.entry(key).or_default().push(path);
+    }
+    groups
+        .into_values()
+        .filter(|paths| paths.len() > 1)
+        .flatten()
+        .collect()
+}
+
+#[test]
+fn test_find_duplicates() {
+    let dir = std::env::temp_dir().join(format!("deduper-duplicates-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let size = 3 * hasher::PARTIAL_HASH_BYTES as usize;
+    let contents = vec![7; size];
+    let mut middle = contents.clone();
+    middle[size / 2] = 8;
+    let mut end = contents.clone();
+    end[size - 1] = 8;
+    let files = [
+        ("a", contents.clone()),
+        ("b", contents),
+        ("middle", middle),
+        ("end", end),
+        ("unique", vec![7; 10]),
+    ]
+    .into_iter()
+    .map(|(name, contents)| {
+        let path = dir.join(name);
+        std::fs::write(&path, &contents).unwrap();
+        (path, contents.len() as u64)
+    })
+    .collect();
+
+    let found = find_duplicates(
+        files,
+        HashAlgorithm::Sha256,
+        ReadBackend::Mmap,
+        |path, err| panic!("{}: {}", path.to_string_lossy(), err),
+    );
+    assert_eq!(4, found.partially_hashed);
+    assert_eq!(3, found.fully_hashed);
+    assert_eq!(1, found.sets.len());
+    assert_eq!(vec![dir.join("a"), dir.join("b")], found.sets[0].paths);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,54 @@
+use std::{io, path::PathBuf};
+
+#[cfg(feature = "ffmpeg")]
+use ffmpeg_next as ffmpeg;
+use mime_guess::Mime;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DeduperError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("exif error: {0}")]
+    Exif(#[from] exif::Error),
+    #[cfg(feature = "ffmpeg")]
+    #[error("ffmpeg error: {0}")]
+    Ffmpeg(#[from] ffmpeg::Error),
+    #[error("database error: {0}")]
+    Db(#[from] rusqlite::Error),
+    #[error("watch error: {0}")]
+    Watch(#[from] notify::Error),
+    #[error("'{0}' not supported")]
+    UnsupportedMedia(Mime),
+    #[error("no timestamp found")]
+    TimestampMissing,
+    #[error("contents of {} do not match the recorded hash", .0.to_string_lossy())]
+    HashMismatch(PathBuf),
+    #[error("{} differs from {} despite the same hash", .0.to_string_lossy(), .1.to_string_lossy())]
+    ContentsDiffer(PathBuf, PathBuf),
+    #[error(
+        "database schema version {version} is newer than the {supported} this deduper supports"
+    )]
+    SchemaTooNew { version: usize, supported: usize },
+}
+
+impl DeduperError {
+    /// Short stable name of the variant, for grouping failures in summaries.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DeduperError::Io(_) => "io",
+            DeduperError::Exif(_) => "exif",
+            #[cfg(feature = "ffmpeg")]
+            DeduperError::Ffmpeg(_) => "ffmpeg",
+            DeduperError::Db(_) => "database",
+            DeduperError::Watch(_) => "watch",
+            DeduperError::UnsupportedMedia(_) => "unsupported media",
+            DeduperError::TimestampMissing => "timestamp missing",
+            DeduperError::HashMismatch(_) => "hash mismatch",
+            DeduperError::ContentsDiffer(..) => "contents differ",
+            DeduperError::SchemaTooNew { .. } => "schema too new",
+        }
+    }
+}
+
+pub type Result<T, E = DeduperError> = std::result::Result<T, E>;
@@ -0,0 +1,89 @@
+//! Hooks into each stage of the pipeline, so what a run does can be shown
+//! by whoever drives it: the command line prints it, a GUI can draw it and
+//! a service can log it its own way.
+
+use std::{fmt, path::Path, sync::Arc};
+
+use crate::{database::File, error::DeduperError, media::Media};
+
+/// Receives what happens to files as it happens, from whichever thread
+/// handles them. Every method does nothing by default, so a sink only
+/// implements those it is interested in.
+pub trait EventSink: Send + Sync {
+    /// A walk of the sources found `path`, before it is read.
+    fn discovered(&self, _path: &Path) {}
+
+    /// `media` was hashed and recorded. Files left as recorded because they
+    /// are unchanged are not hashed again and not reported.
+    fn hashed(&self, _media: &Media) {}
+
+    /// `duplicate` holds the same contents as `original`, which is kept.
+    fn duplicate_found(&self, _original: &File, _duplicate: &File) {}
+
+    /// `duplicate` was removed, its contents kept in `original`.
+    fn removed(&self, _original: &File, _duplicate: &File) {}
+
+    /// `path` was made a link to `target`: a duplicate replaced by one, or
+    /// a file organized by linking it into the destination.
+    fn linked(&self, _path: &Path, _target: &Path) {}
+
+    /// `path` was re-encoded, shrinking from `before` to `after` bytes.
+    /// Reported by the `transcode` command once the re-encode replaced the
+    /// file; the functions of [`crate::transcoder`] only encode to a path
+    /// of the caller's choosing and report nothing.
+    fn transcoded(&self, _path: &Path, _before: u64, _after: u64) {}
+
+    /// Handling `path` failed with `err`.
+    fn error(&self, _path: &Path, _err: &DeduperError) {}
+}
+
+/// An optional [`EventSink`], for the scanners, dedupers and builders that
+/// report to one; reports go nowhere without a sink.
+#[derive(Clone, Default)]
+pub struct Events(Option<Arc<dyn EventSink>>);
+
+impl Events {
+    pub fn new(sink: Arc<dyn EventSink>) -> Self {
+        Self(Some(sink))
+    }
+
+    fn emit(&self, event: impl FnOnce(&dyn EventSink)) {
+        if let Some(sink) = &self.0 {
+            event(sink.as_ref());
+        }
+    }
+
+    pub fn discovered(&self, path: &Path) {
+        self.emit(|sink| sink.discovered(path));
+    }
+
+    pub fn hashed(&self, media: &Media) {
+        self.emit(|sink| sink.hashed(media));
+    }
+
+    pub fn duplicate_found(&self, original: &File, duplicate: &File) {
+        self.emit(|sink| sink.duplicate_found(original, duplicate));
+    }
+
+    pub fn removed(&self, original: &File, duplicate: &File) {
+        self.emit(|sink| sink.removed(original, duplicate));
+    }
+
+    pub fn linked(&self, path: &Path, target: &Path) {
+        self.emit(|sink| sink.linked(path, target));
+    }
+
+    pub fn transcoded(&self, path: &Path, before: u64, after: u64) {
+        self.emit(|sink| sink.transcoded(path, before, after));
+    }
+
+    pub fn error(&self, path: &Path, err: &DeduperError) {
+        self.emit(|sink| sink.error(path, err));
+    }
+}
+
+impl fmt::Debug for Events {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Events").field(&self.0.is_some()).finish()
+    }
+}
@@ -1,15 +1,27 @@
 use std::{
-    fs::{metadata, File},
-    io::BufReader,
-    path::Path,
+    collections::HashMap,
+    ffi::OsString,
+    fs::{self, metadata, File},
+    io::{BufReader, Read},
+    path::{Path, PathBuf},
+    str::FromStr,
     time::UNIX_EPOCH,
 };
 
-use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
-use exif::{In, Tag};
+use chrono::{
+    DateTime, Datelike, FixedOffset, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta,
+    TimeZone,
+};
+use exif::{Exif, In, Tag, Value};
 
+#[cfg(feature = "ffmpeg")]
 use ffmpeg_next as ffmpeg;
-use mime_guess::Mime;
+use mime_guess::{mime, Mime};
+
+use crate::{
+    error::{DeduperError, Result},
+    geo::Location,
+};
 
 // pub fn extract_timestamp(path: &str) -> DateTime<Local> {
 //     let mimetype = extract_mimetype(path);
@@ -30,65 +42,656 @@ use mime_guess::Mime;
 //     }
 // }
 
-pub fn extract_filesystem_timestamp(path: &Path) -> Option<DateTime<Local>> {
-    metadata(path)
+pub fn extract_filesystem_timestamp(path: &Path) -> Result<DateTime<FixedOffset>> {
+    let duration = metadata(path)?
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| DeduperError::TimestampMissing)?;
+    Local
+        .timestamp_opt(duration.as_secs() as i64, duration.subsec_nanos())
+        .single()
+        .map(|date_time| date_time.fixed_offset())
+        .ok_or(DeduperError::TimestampMissing)
+}
+
+/// Interprets a wall-clock time recorded without an offset as local time.
+fn in_local_timezone(date_time: NaiveDateTime) -> Option<DateTime<FixedOffset>> {
+    date_time
+        .and_local_timezone(Local)
+        .single()
+        .map(|date_time| date_time.fixed_offset())
+}
+
+/// EXIF capture time tags, each paired with the tag holding its UTC offset.
+const EXIF_DATE_TAGS: [(Tag, Tag); 3] = [
+    (Tag::DateTime, Tag::OffsetTime),
+    (Tag::DateTimeOriginal, Tag::OffsetTimeOriginal),
+    (Tag::DateTimeDigitized, Tag::OffsetTimeDigitized),
+];
+
+/// Camera RAW formats by extension. All of them are TIFF based, so
+/// [`extract_image_timestamp`] reads their EXIF like any other image.
+const RAW_TYPES: [(&str, &str); 6] = [
+    ("arw", "image/x-sony-arw"),
+    ("cr2", "image/x-canon-cr2"),
+    ("dng", "image/x-adobe-dng"),
+    ("nef", "image/x-nikon-nef"),
+    ("pef", "image/x-pentax-pef"),
+    ("srw", "image/x-samsung-srw"),
+];
+
+/// Documents and archives, which carry no capture date and are dated by
+/// their mtime. OpenDocument and Office Open XML formats are matched by
+/// [`DOCUMENT_PREFIXES`].
+const DOCUMENT_TYPES: [&str; 14] = [
+    "application/pdf",
+    "application/rtf",
+    "application/epub+zip",
+    "application/msword",
+    "application/vnd.ms-excel",
+    "application/vnd.ms-powerpoint",
+    "application/zip",
+    "application/x-tar",
+    "application/gzip",
+    "application/x-compressed",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/x-7z-compressed",
+    "application/x-rar-compressed",
+];
+
+const DOCUMENT_PREFIXES: [&str; 2] = [
+    "application/vnd.oasis.opendocument.",
+    "application/vnd.openxmlformats-officedocument.",
+];
+
+/// chrono formats searched for in file names, most specific first.
+pub const FILENAME_PATTERNS: [&str; 7] = [
+    // IMG_20190901_070202.jpg, PXL_20230901_224941123.mp4
+    "%Y%m%d_%H%M%S",
+    "%Y%m%d-%H%M%S",
+    // 2023-09-01-22-49-41-343.mp4
+    "%Y-%m-%d-%H-%M-%S",
+    "%Y-%m-%d_%H-%M-%S",
+    "%Y-%m-%d %H-%M-%S",
+    // Screenshot 2023-09-01 at 22.49.41.png
+    "%Y-%m-%d at %H.%M.%S",
+    // IMG-20190901-WA0001.jpg
+    "%Y%m%d-WA",
+];
+
+/// chrono formats of the dates in the names WhatsApp gives files, see
+/// [`MediaSource::WhatsApp`].
+pub const WHATSAPP_PATTERNS: [&str; 2] = [
+    // IMG-20230901-WA0012.jpg
+    "%Y%m%d-WA",
+    // WhatsApp Image 2023-09-01 at 22.49.41.jpeg
+    "%Y-%m-%d at %H.%M.%S",
+];
+
+/// Prefixes of the names WhatsApp on phones gives images, videos, audio,
+/// voice notes, stickers and documents, before `-YYYYMMDD-WA`.
+const WHATSAPP_PREFIXES: [&str; 6] = ["IMG", "VID", "AUD", "PTT", "STK", "DOC"];
+
+/// The app a file was saved from, when its name gives it away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaSource {
+    /// Sent or received in WhatsApp, which strips EXIF: named
+    /// `IMG-20230901-WA0012.jpg` on phones or
+    /// `WhatsApp Image 2023-09-01 at 22.49.41.jpeg` by WhatsApp Web
+    WhatsApp,
+}
+
+impl MediaSource {
+    /// How the source is written to the `source` column and layouts.
+    pub fn name(self) -> &'static str {
+        match self {
+            MediaSource::WhatsApp => "WhatsApp",
+        }
+    }
+
+    /// The source the name of `path` gives away.
+    pub fn of(path: &Path) -> Option<Self> {
+        let stem = path.file_stem()?.to_str()?;
+        let phone = stem.split_once('-').is_some_and(|(prefix, rest)| {
+            WHATSAPP_PREFIXES.contains(&prefix)
+                && rest.len() > 11
+                && rest.as_bytes()[..8].iter().all(u8::is_ascii_digit)
+                && rest[8..].starts_with("-WA")
+        });
+        (phone || stem.starts_with("WhatsApp ")).then_some(MediaSource::WhatsApp)
+    }
+}
+
+impl FromStr for MediaSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "WhatsApp" => Ok(MediaSource::WhatsApp),
+            _ => Err(format!("unknown media source {}", s)),
+        }
+    }
+}
+
+/// Dates a file WhatsApp saved by its name, see [`WHATSAPP_PATTERNS`].
+/// Phones only put the day in it, so the mtime, which is when the file was
+/// received, gives the time when it falls on that day.
+pub fn extract_whatsapp_timestamp(path: &Path) -> Result<DateTime<FixedOffset>> {
+    if MediaSource::of(path) != Some(MediaSource::WhatsApp) {
+        return Err(DeduperError::TimestampMissing);
+    }
+    let timestamp = extract_filename_timestamp(path, &WHATSAPP_PATTERNS)?;
+    if timestamp.time() != NaiveTime::MIN {
+        return Ok(timestamp);
+    }
+    Ok(extract_filesystem_timestamp(path)
         .ok()
-        .and_then(|metadata| metadata.modified().ok())
-        .and_then(|sys_time| sys_time.duration_since(UNIX_EPOCH).ok())
-        .and_then(|duration| {
-            Local
-                .timestamp_opt(duration.as_secs() as i64, duration.subsec_nanos())
-                .single()
+        .filter(|mtime| mtime.date_naive() == timestamp.date_naive())
+        .unwrap_or(timestamp))
+}
+
+/// XMP properties holding the capture date, in order of preference.
+const XMP_DATE_PROPERTIES: [&str; 2] = ["xmp:CreateDate", "photoshop:DateCreated"];
+
+/// How much of a file is searched for an embedded XMP packet; JPEG, PNG and
+/// TIFF writers put it in the header.
+const XMP_SEARCH_LIMIT: u64 = 256 * 1024;
+
+/// Reads the capture date from an XMP sidecar, `IMG_0001.xmp` as written by
+/// Lightroom or `IMG_0001.CR2.xmp` as written by darktable, and otherwise
+/// from an XMP packet embedded in the file itself.
+pub fn extract_xmp_timestamp(path: &Path) -> Result<DateTime<FixedOffset>> {
+    xmp_sidecars(path)
+        .iter()
+        .filter_map(|sidecar| fs::read_to_string(sidecar).ok())
+        .chain(embedded_xmp(path))
+        .find_map(|xmp| {
+            XMP_DATE_PROPERTIES
+                .iter()
+                .filter_map(|property| xmp_property(&xmp, property))
+                .find_map(parse_xmp_date)
         })
+        .ok_or(DeduperError::TimestampMissing)
 }
 
-pub fn extract_image_timestamp(path: &Path) -> Option<DateTime<Local>> {
+fn xmp_sidecars(path: &Path) -> [PathBuf; 2] {
+    let mut darktable = OsString::from(path.as_os_str());
+    darktable.push(".xmp");
+    [path.with_extension("xmp"), PathBuf::from(darktable)]
+}
+
+fn embedded_xmp(path: &Path) -> Option<String> {
+    let mut head = Vec::new();
     File::open(path)
-        .ok()
-        .map(|file| BufReader::new(file))
-        .and_then(|mut buf| {
-            let exif_reader = exif::Reader::new();
-            exif_reader.read_from_container(&mut buf).ok()
+        .ok()?
+        .take(XMP_SEARCH_LIMIT)
+        .read_to_end(&mut head)
+        .ok()?;
+    let head = String::from_utf8_lossy(&head);
+    let start = head.find("<x:xmpmeta")?;
+    let end = head[start..].find("</x:xmpmeta>")? + start;
+    Some(head[start..end].to_owned())
+}
+
+/// Value of `property` written either as an attribute of `rdf:Description`
+/// or as a child element.
+fn xmp_property<'a>(xmp: &'a str, property: &str) -> Option<&'a str> {
+    if let Some(start) = xmp.find(&format!("{}=", property)) {
+        let rest = &xmp[start + property.len() + 1..];
+        let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let value = &rest[1..];
+        return value.find(quote).map(|end| &value[..end]);
+    }
+    let start = xmp.find(&format!("<{}>", property))? + property.len() + 2;
+    let value = &xmp[start..];
+    value.find('<').map(|end| value[..end].trim())
+}
+
+/// XMP dates are ISO 8601 with optional seconds, fraction and offset, or
+/// just a date.
+fn parse_xmp_date(value: &str) -> Option<DateTime<FixedOffset>> {
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(value) {
+        return Some(date_time);
+    }
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%dT%H:%M"]
+        .into_iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_time(NaiveTime::MIN))
         })
-        .and_then(|exif_data| {
-            for tag in [Tag::DateTime, Tag::DateTimeOriginal, Tag::DateTimeDigitized] {
-                if let Some(field) = exif_data.get_field(tag, In::PRIMARY) {
-                    return Some(field.clone());
-                }
-            }
-            None
+        .and_then(in_local_timezone)
+}
+
+/// Reads the EXIF capture time. Its offset comes from the matching
+/// `OffsetTime*` tag, else from the GPS time, else the local timezone.
+pub fn extract_image_timestamp(path: &Path) -> Result<DateTime<FixedOffset>> {
+    let exif_data = read_exif(path)?;
+    let (field, offset_tag) = EXIF_DATE_TAGS
+        .into_iter()
+        .find_map(|(tag, offset_tag)| Some((exif_data.get_field(tag, In::PRIMARY)?, offset_tag)))
+        .ok_or(DeduperError::TimestampMissing)?;
+    let date_string = field.display_value().with_unit(field).to_string();
+    let date_time = ["%Y:%m:%d %H:%M:%S", "%Y-%m-%d %H:%M:%S"]
+        .into_iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(&date_string, format).ok())
+        .ok_or(DeduperError::TimestampMissing)?;
+    let offset = ascii_field(&exif_data, offset_tag)
+        .and_then(|offset| offset.parse::<FixedOffset>().ok())
+        .or_else(|| gps_offset(&exif_data, date_time));
+    match offset {
+        Some(offset) => offset.from_local_datetime(&date_time).single(),
+        None => in_local_timezone(date_time),
+    }
+    .ok_or(DeduperError::TimestampMissing)
+}
+
+/// The device that captured a file, as far as its metadata tells.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Camera {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub lens: Option<String>,
+}
+
+/// Reads the EXIF `Make`, `Model` and `LensModel` tags.
+pub fn extract_image_camera(path: &Path) -> Camera {
+    let Ok(exif_data) = read_exif(path) else {
+        return Camera::default();
+    };
+    Camera {
+        make: ascii_field(&exif_data, Tag::Make),
+        model: ascii_field(&exif_data, Tag::Model),
+        lens: ascii_field(&exif_data, Tag::LensModel),
+    }
+}
+
+/// Reads the device make and model iOS and Android write into the container.
+pub fn extract_video_camera(path: &Path) -> Camera {
+    let Ok(tags) = media_tags(path) else {
+        return Camera::default();
+    };
+    let field = |keys: [&str; 2]| {
+        keys.into_iter()
+            .find_map(|key| tags.get(key))
+            .map(|value| value.trim().to_owned())
+            .filter(|value| !value.is_empty())
+    };
+    Camera {
+        make: field(["com.apple.quicktime.make", "com.android.manufacturer"]),
+        model: field(["com.apple.quicktime.model", "com.android.model"]),
+        lens: None,
+    }
+}
+
+/// Resolution classes by the shorter side of the frame, from the largest;
+/// smaller frames are `SD`.
+pub const RESOLUTIONS: [(u32, &str); 5] = [
+    (4320, "8K"),
+    (2160, "4K"),
+    (1440, "1440p"),
+    (1080, "1080p"),
+    (720, "720p"),
+];
+
+/// Frame size of an image or video, and the length and codec of a video.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Format {
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// Length in seconds
+    pub duration: Option<f64>,
+    /// ffmpeg's name of the video codec, e.g. `h264`, `hevc` or `av1`
+    pub codec: Option<String>,
+}
+
+impl Format {
+    /// The [`RESOLUTIONS`] class of the frame, e.g. `4K` for 3840x2160 and
+    /// `1080p` for a portrait 1080x1920 video.
+    pub fn resolution(&self) -> Option<&'static str> {
+        let short_side = self.width?.min(self.height?);
+        if short_side == 0 {
+            return None;
+        }
+        Some(
+            RESOLUTIONS
+                .iter()
+                .find(|(side, _)| short_side >= *side)
+                .map_or("SD", |(_, name)| name),
+        )
+    }
+}
+
+/// The frame size of images and videos, and the length and codec of videos.
+pub fn extract_format(path: &Path, mime_type: &Mime) -> Format {
+    match mime_type.type_() {
+        mime::IMAGE => image::image_dimensions(path)
+            .map(|(width, height)| Format {
+                width: Some(width),
+                height: Some(height),
+                ..Format::default()
+            })
+            .unwrap_or_default(),
+        mime::VIDEO => extract_video_format(path).unwrap_or_default(),
+        _ => Format::default(),
+    }
+}
+
+/// Length of the container and frame size and codec of its best video
+/// stream as reported by libav.
+#[cfg(feature = "ffmpeg")]
+fn extract_video_format(path: &Path) -> Result<Format> {
+    ffmpeg::init()?;
+
+    let context = ffmpeg::format::input(path)?;
+    // in AV_TIME_BASE units, microseconds
+    let duration = Some(context.duration())
+        .filter(|duration| *duration > 0)
+        .map(|duration| duration as f64 / 1_000_000.0);
+    let Some(stream) = context.streams().best(ffmpeg::media::Type::Video) else {
+        return Ok(Format {
+            duration,
+            ..Format::default()
+        });
+    };
+    let codec = stream.parameters().id().name().to_owned();
+    let video = ffmpeg::codec::context::Context::from_parameters(stream.parameters())?
+        .decoder()
+        .video()?;
+    Ok(Format {
+        width: Some(video.width()).filter(|width| *width > 0),
+        height: Some(video.height()).filter(|height| *height > 0),
+        duration,
+        codec: Some(codec),
+    })
+}
+
+/// Length and video track of an MP4/QuickTime file as read by the built-in
+/// parser, used when built without the `ffmpeg` feature.
+#[cfg(not(feature = "ffmpeg"))]
+fn extract_video_format(path: &Path) -> Result<Format> {
+    Ok(crate::container::read_format(path)?)
+}
+
+/// Reads the EXIF GPS position, if the camera recorded one.
+pub fn extract_image_location(path: &Path) -> Option<Location> {
+    let exif_data = read_exif(path).ok()?;
+    let coordinate = |tag, reference_tag, negative| {
+        let Value::Rational(dms) = &exif_data.get_field(tag, In::PRIMARY)?.value else {
+            return None;
+        };
+        let degrees = dms
+            .iter()
+            .zip([1.0, 60.0, 3600.0])
+            .map(|(value, unit)| value.to_f64() / unit)
+            .sum::<f64>();
+        match ascii_field(&exif_data, reference_tag) {
+            Some(reference) if reference == negative => Some(-degrees),
+            _ => Some(degrees),
+        }
+    };
+    Location::new(
+        coordinate(Tag::GPSLatitude, Tag::GPSLatitudeRef, "S")?,
+        coordinate(Tag::GPSLongitude, Tag::GPSLongitudeRef, "W")?,
+    )
+}
+
+/// Apple maker note tag holding the identifier shared by the frames of a
+/// burst.
+const APPLE_BURST_UUID: u16 = 0x000b;
+
+/// Reads the burst identifier iPhones write into the EXIF maker note.
+pub fn extract_burst_id(path: &Path) -> Option<String> {
+    let exif_data = read_exif(path).ok()?;
+    let Value::Undefined(maker_note, _) = &exif_data.get_field(Tag::MakerNote, In::PRIMARY)?.value
+    else {
+        return None;
+    };
+    apple_maker_note_ascii(maker_note, APPLE_BURST_UUID)
+}
+
+/// An ASCII entry of an Apple maker note: `Apple iOS\0`, a version, a
+/// byte order mark and then an IFD whose offsets count from the start of
+/// the note.
+fn apple_maker_note_ascii(note: &[u8], tag: u16) -> Option<String> {
+    const ASCII: u16 = 2;
+    let header = note.get(..14)?;
+    if !header.starts_with(b"Apple iOS\0") {
+        return None;
+    }
+    let little_endian = &header[12..14] == b"II";
+    let u16_at = |at: usize| {
+        let bytes = note.get(at..at + 2)?.try_into().ok()?;
+        Some(if little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
         })
-        .map(|field| field.display_value().with_unit(&field).to_string())
-        .and_then(|date_string| {
-            for format in ["%Y:%m:%d %H:%M:%S", "%Y-%m-%d %H:%M:%S"] {
-                if let Ok(date_time) = NaiveDateTime::parse_from_str(&date_string, format) {
-                    return Some(date_time);
-                }
-            }
-            None
+    };
+    let u32_at = |at: usize| {
+        let bytes = note.get(at..at + 4)?.try_into().ok()?;
+        Some(if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
         })
-        .and_then(|date_time| date_time.and_local_timezone(Local).single())
+    };
+    let entries = u16_at(14)? as usize;
+    let entry = (0..entries)
+        .map(|index| 16 + 12 * index)
+        .find(|&entry| u16_at(entry) == Some(tag))?;
+    if u16_at(entry + 2)? != ASCII {
+        return None;
+    }
+    let len = u32_at(entry + 4)? as usize;
+    let value = if len <= 4 {
+        note.get(entry + 8..entry + 8 + len)?
+    } else {
+        let offset = u32_at(entry + 8)? as usize;
+        note.get(offset..offset.checked_add(len)?)?
+    };
+    let value = String::from_utf8_lossy(value)
+        .trim_end_matches('\0')
+        .trim()
+        .to_owned();
+    (!value.is_empty()).then_some(value)
 }
 
-pub fn extract_video_timestamp(path: &Path) -> Option<DateTime<Local>> {
-    ffmpeg::init().expect("could not initialize ffmpeg");
+fn read_exif(path: &Path) -> Result<Exif> {
+    let mut buf = BufReader::new(File::open(path)?);
+    Ok(exif::Reader::new().read_from_container(&mut buf)?)
+}
 
-    ffmpeg::format::input(path)
-        .ok()
-        .and_then(|context| {
-            context
-                .metadata()
-                .get("creation_time")
-                .map(|str| str.to_owned())
+fn ascii_field(exif_data: &Exif, tag: Tag) -> Option<String> {
+    match &exif_data.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values
+            .first()
+            .map(|value| String::from_utf8_lossy(value).trim().to_owned())
+            .filter(|value| !value.is_empty()),
+        _ => None,
+    }
+}
+
+/// GPS time is UTC, so its distance from the camera clock is the offset the
+/// camera was set to, rounded to a quarter hour to absorb clock drift.
+fn gps_offset(exif_data: &Exif, date_time: NaiveDateTime) -> Option<FixedOffset> {
+    let date =
+        NaiveDate::parse_from_str(&ascii_field(exif_data, Tag::GPSDateStamp)?, "%Y:%m:%d").ok()?;
+    let Value::Rational(hms) = &exif_data.get_field(Tag::GPSTimeStamp, In::PRIMARY)?.value else {
+        return None;
+    };
+    let seconds = hms
+        .iter()
+        .zip([3600.0, 60.0, 1.0])
+        .map(|(value, unit)| value.to_f64() * unit)
+        .sum::<f64>();
+    let utc = date.and_time(NaiveTime::MIN) + TimeDelta::milliseconds((seconds * 1000.0) as i64);
+    let quarters = ((date_time - utc).num_seconds() as f64 / 900.0).round() as i32;
+    if quarters.abs() > 14 * 4 {
+        return None;
+    }
+    FixedOffset::east_opt(quarters * 900)
+}
+
+/// Prefers the QuickTime creation date, which keeps the offset it was
+/// recorded at, over the container `creation_time`, which is UTC and is
+/// shown in the local timezone.
+pub fn extract_video_timestamp(path: &Path) -> Result<DateTime<FixedOffset>> {
+    container_timestamp(&media_tags(path)?)
+}
+
+fn container_timestamp(tags: &HashMap<String, String>) -> Result<DateTime<FixedOffset>> {
+    if let Some(date_time) = tags
+        .get("com.apple.quicktime.creationdate")
+        .and_then(|date| DateTime::parse_from_str(date.trim(), "%Y-%m-%dT%H:%M:%S%z").ok())
+    {
+        return Ok(date_time);
+    }
+    let date_string = tags
+        .get("creation_time")
+        .ok_or(DeduperError::TimestampMissing)?;
+    NaiveDateTime::parse_from_str(date_string.trim(), "%Y-%m-%dT%H:%M:%S%.fZ")
+        .map(|date_time| date_time.and_utc().with_timezone(&Local).fixed_offset())
+        .map_err(|_| DeduperError::TimestampMissing)
+}
+
+/// Reads the recording date of the ID3, Vorbis or MP4 tags of an audio
+/// file. A date without a time is midnight and a bare year New Year's Day,
+/// as albums are often tagged. Recordings without one, like voice memos,
+/// fall back to the container dates of videos.
+pub fn extract_audio_timestamp(path: &Path) -> Result<DateTime<FixedOffset>> {
+    let tags = media_tags(path)?;
+    tags.iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("date"))
+        .and_then(|(_, date)| parse_tag_date(date))
+        .map_or_else(|| container_timestamp(&tags), Ok)
+}
+
+/// `2019`, `2019-05`, `2019-05-03`, `2019-05-03 12:30` and RFC 3339 times,
+/// the dates taggers write.
+fn parse_tag_date(date: &str) -> Option<DateTime<FixedOffset>> {
+    let date = date.trim();
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(date) {
+        return Some(date_time);
+    }
+    let date_time = [
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(date, format).ok())
+    .or_else(|| {
+        let date = match date.len() {
+            4 => format!("{}-01-01", date),
+            7 => format!("{}-01", date),
+            _ => date.to_owned(),
+        };
+        NaiveDate::parse_from_str(&date, "%Y-%m-%d")
+            .ok()
+            .map(|date| date.and_time(NaiveTime::MIN))
+    })?;
+    in_local_timezone(date_time)
+}
+
+/// Reads the ISO 6709 position phones store in the container metadata.
+pub fn extract_video_location(path: &Path) -> Option<Location> {
+    let tags = media_tags(path).ok()?;
+    ["com.apple.quicktime.location.ISO6709", "location"]
+        .into_iter()
+        .find_map(|key| {
+            tags.get(key)
+                .and_then(|value| Location::from_iso6709(value))
         })
-        .and_then(|date_string| {
-            NaiveDateTime::parse_from_str(&date_string.trim(), "%Y-%m-%dT%H:%M:%S%.f%Z").ok()
+}
+
+/// Container metadata of a video or audio file as reported by libav.
+#[cfg(feature = "ffmpeg")]
+fn media_tags(path: &Path) -> Result<HashMap<String, String>> {
+    ffmpeg::init()?;
+
+    let context = ffmpeg::format::input(path)?;
+    let tags = context
+        .metadata()
+        .iter()
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect();
+    Ok(tags)
+}
+
+/// Container metadata of a video or audio file as read by the built-in
+/// parser, used when built without the `ffmpeg` feature.
+#[cfg(not(feature = "ffmpeg"))]
+fn media_tags(path: &Path) -> Result<HashMap<String, String>> {
+    Ok(crate::container::read_tags(path)?)
+}
+
+/// Finds the first of `patterns` anywhere in the file stem. Patterns without
+/// a time of day give midnight; matches inside a longer number or outside
+/// 1970 to next year are ignored.
+pub fn extract_filename_timestamp(
+    path: &Path,
+    patterns: &[impl AsRef<str>],
+) -> Result<DateTime<FixedOffset>> {
+    let stem = path
+        .file_stem()
+        .ok_or(DeduperError::TimestampMissing)?
+        .to_string_lossy();
+    patterns
+        .iter()
+        .find_map(|pattern| find_timestamp(&stem, pattern.as_ref()))
+        .and_then(in_local_timezone)
+        .ok_or(DeduperError::TimestampMissing)
+}
+
+fn find_timestamp(name: &str, pattern: &str) -> Option<NaiveDateTime> {
+    let years = 1970..=Local::now().year() + 1;
+    let mut previous = None;
+    name.char_indices()
+        .filter(|&(_, c)| {
+            let boundary = !previous.is_some_and(|p: char| p.is_ascii_digit());
+            previous = Some(c);
+            boundary && c.is_ascii_digit()
+        })
+        .find_map(|(start, _)| {
+            let rest = &name[start..];
+            NaiveDateTime::parse_and_remainder(rest, pattern)
+                .map(|(date_time, _)| date_time)
+                .or_else(|_| {
+                    NaiveDate::parse_and_remainder(rest, pattern)
+                        .map(|(date, _)| date.and_time(NaiveTime::MIN))
+                })
+                .ok()
+                .filter(|date_time| years.contains(&date_time.year()))
         })
-        .and_then(|date_time| date_time.and_local_timezone(Local).single())
 }
 
 pub fn extract_mimetype(path: &Path) -> Mime {
-    mime_guess::from_path(path).first_or_octet_stream()
+    let extension = path
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase());
+    RAW_TYPES
+        .iter()
+        .find(|(raw, _)| extension.as_deref() == Some(raw))
+        .and_then(|(_, mime_type)| mime_type.parse().ok())
+        .unwrap_or_else(|| mime_guess::from_path(path).first_or_octet_stream())
+}
+
+pub fn is_raw(mime_type: &Mime) -> bool {
+    RAW_TYPES
+        .iter()
+        .any(|(_, raw)| mime_type.essence_str() == *raw)
+}
+
+pub fn is_document(mime_type: &Mime) -> bool {
+    let essence = mime_type.essence_str();
+    DOCUMENT_TYPES.contains(&essence)
+        || DOCUMENT_PREFIXES
+            .iter()
+            .any(|prefix| essence.starts_with(prefix))
 }
 
 #[test]
@@ -110,3 +713,184 @@ fn test_extract_mimetype() {
         ))
     );
 }
+
+#[test]
+fn test_is_document() {
+    for name in [
+        "report.pdf",
+        "sheet.xlsx",
+        "talk.odp",
+        "backup.tar.gz",
+        "photos.zip",
+    ] {
+        assert!(is_document(&extract_mimetype(Path::new(name))), "{}", name);
+    }
+    assert!(!is_document(&extract_mimetype(Path::new("IMG_0001.jpg"))));
+    assert!(!is_document(&extract_mimetype(Path::new("notes.txt"))));
+}
+
+#[test]
+fn test_extract_raw_mimetype() {
+    let mime_type = extract_mimetype(Path::new("IMG_0001.CR2"));
+    assert_eq!("image/x-canon-cr2", mime_type);
+    assert!(is_raw(&mime_type));
+    assert!(!is_raw(&extract_mimetype(Path::new("IMG_0001.jpg"))));
+}
+
+#[test]
+fn test_apple_maker_note_ascii() {
+    let uuid = b"4B3E4C2A-1B2C-4D5E-8F90-123456789ABC\0";
+    let mut note = b"Apple iOS\0\0\x01MM".to_vec();
+    note.extend_from_slice(&1u16.to_be_bytes());
+    note.extend_from_slice(&APPLE_BURST_UUID.to_be_bytes());
+    note.extend_from_slice(&2u16.to_be_bytes());
+    note.extend_from_slice(&(uuid.len() as u32).to_be_bytes());
+    note.extend_from_slice(&32u32.to_be_bytes());
+    note.extend_from_slice(&0u32.to_be_bytes());
+    assert_eq!(32, note.len());
+    note.extend_from_slice(uuid);
+    assert_eq!(
+        Some("4B3E4C2A-1B2C-4D5E-8F90-123456789ABC".to_owned()),
+        apple_maker_note_ascii(&note, APPLE_BURST_UUID)
+    );
+    assert_eq!(None, apple_maker_note_ascii(&note, 0x0011));
+    assert_eq!(None, apple_maker_note_ascii(&note[..20], APPLE_BURST_UUID));
+}
+
+#[test]
+fn test_parse_tag_date() {
+    let date = |date: &str| parse_tag_date(date).map(|date| date.naive_local().to_string());
+    assert_eq!(Some("2019-01-01 00:00:00".to_owned()), date("2019"));
+    assert_eq!(Some("2019-05-01 00:00:00".to_owned()), date("2019-05"));
+    assert_eq!(
+        Some("2019-05-03 12:30:00".to_owned()),
+        date("2019-05-03 12:30")
+    );
+    assert_eq!(
+        Some("2019-05-03 12:30:15".to_owned()),
+        date("2019-05-03T12:30:15+02:00")
+    );
+    assert_eq!(None, date("unknown"));
+}
+
+#[test]
+fn test_extract_filename_timestamp() {
+    let timestamp = |name: &str| {
+        extract_filename_timestamp(Path::new(name), &FILENAME_PATTERNS)
+            .ok()
+            .map(|date_time| date_time.naive_local().to_string())
+    };
+    assert_eq!(
+        Some("2019-09-01 07:02:02"),
+        timestamp("IMG_20190901_070202.jpg").as_deref()
+    );
+    assert_eq!(
+        Some("2023-09-01 22:49:41"),
+        timestamp("2023-09-01-22-49-41-343.mp4").as_deref()
+    );
+    assert_eq!(
+        Some("2023-09-01 22:49:41"),
+        timestamp("Screenshot 2023-09-01 at 22.49.41.png").as_deref()
+    );
+    assert_eq!(
+        Some("2019-09-01 00:00:00"),
+        timestamp("IMG-20190901-WA0001.jpg").as_deref()
+    );
+    assert_eq!(None, timestamp("IMG_0001.jpg"));
+    assert_eq!(None, timestamp("120190901_070202.jpg"));
+}
+
+#[test]
+fn test_extract_whatsapp_timestamp() {
+    let dir = std::env::temp_dir().join(format!("deduper-whatsapp-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let received = dir.join("IMG-20190901-WA0001.jpg");
+    fs::write(&received, b"stripped").unwrap();
+    let mtime = in_local_timezone(
+        NaiveDate::from_ymd_opt(2019, 9, 1)
+            .unwrap()
+            .and_hms_opt(18, 30, 0)
+            .unwrap(),
+    )
+    .unwrap();
+    let set_mtime = |path: &Path, mtime: DateTime<FixedOffset>| {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(mtime.into())
+            .unwrap();
+    };
+    set_mtime(&received, mtime);
+    assert_eq!(mtime, extract_whatsapp_timestamp(&received).unwrap());
+    // forwarded or copied on another day
+    set_mtime(&received, mtime + TimeDelta::days(3));
+    assert_eq!(
+        "2019-09-01 00:00:00",
+        extract_whatsapp_timestamp(&received)
+            .unwrap()
+            .naive_local()
+            .to_string()
+    );
+
+    let web = dir.join("WhatsApp Image 2023-09-01 at 22.49.41.jpeg");
+    fs::write(&web, b"stripped").unwrap();
+    assert_eq!(
+        "2023-09-01 22:49:41",
+        extract_whatsapp_timestamp(&web)
+            .unwrap()
+            .naive_local()
+            .to_string()
+    );
+    assert_eq!(Some(MediaSource::WhatsApp), MediaSource::of(&web));
+    assert_eq!(
+        Some(MediaSource::WhatsApp),
+        MediaSource::of(Path::new("PTT-20230901-WA0003.opus"))
+    );
+    assert_eq!(None, MediaSource::of(Path::new("IMG_20190901_070202.jpg")));
+    assert_eq!(None, MediaSource::of(Path::new("IMG-2019-WA.jpg")));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_resolution() {
+    let format = |width, height| Format {
+        width: Some(width),
+        height: Some(height),
+        ..Format::default()
+    };
+    assert_eq!(Some("4K"), format(3840, 2160).resolution());
+    assert_eq!(Some("1080p"), format(1080, 1920).resolution());
+    assert_eq!(Some("720p"), format(1280, 720).resolution());
+    assert_eq!(Some("SD"), format(640, 480).resolution());
+    assert_eq!(None, format(0, 0).resolution());
+    assert_eq!(None, Format::default().resolution());
+}
+
+#[test]
+fn test_extract_xmp_timestamp() {
+    let dir = std::env::temp_dir().join(format!("deduper-xmp-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let raw = dir.join("IMG_0001.CR2");
+    fs::write(&raw, b"not really a raw file").unwrap();
+    assert!(extract_xmp_timestamp(&raw).is_err());
+
+    fs::write(
+        dir.join("IMG_0001.CR2.xmp"),
+        "<x:xmpmeta><rdf:RDF><rdf:Description>\
+         <photoshop:DateCreated>2019-09-01T07:02:02</photoshop:DateCreated>\
+         </rdf:Description></rdf:RDF></x:xmpmeta>",
+    )
+    .unwrap();
+    let timestamp = extract_xmp_timestamp(&raw).unwrap();
+    assert_eq!("2019-09-01 07:02:02", timestamp.naive_local().to_string());
+
+    fs::write(
+        dir.join("IMG_0001.xmp"),
+        "<x:xmpmeta><rdf:Description xmp:CreateDate='2020-01-02T03:04:05+09:00'/></x:xmpmeta>",
+    )
+    .unwrap();
+    let timestamp = extract_xmp_timestamp(&raw).unwrap();
+    assert_eq!("2020-01-02 03:04:05 +09:00", timestamp.to_string());
+    fs::remove_dir_all(&dir).unwrap();
+}
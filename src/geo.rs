@@ -0,0 +1,144 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Location {
+    pub fn new(latitude: f64, longitude: f64) -> Option<Self> {
+        ((-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude)).then_some(
+            Self {
+                latitude,
+                longitude,
+            },
+        )
+    }
+
+    /// Parses the ISO 6709 form phones write into video metadata, e.g.
+    /// `+35.6895+139.6917+040.000/`.
+    pub fn from_iso6709(s: &str) -> Option<Self> {
+        let s = s.trim().trim_end_matches('/');
+        let latitude_end = s.get(1..)?.find(['+', '-'])? + 1;
+        let rest = &s[latitude_end..];
+        let longitude_end = rest
+            .get(1..)?
+            .find(['+', '-'])
+            .map_or(rest.len(), |end| end + 1);
+        Self::new(
+            s[..latitude_end].parse().ok()?,
+            rest[..longitude_end].parse().ok()?,
+        )
+    }
+
+    /// Great-circle distance in kilometres.
+    pub fn distance_km(&self, other: &Location) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.longitude - self.longitude).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+
+    fn cell(&self) -> (i32, i32) {
+        (self.latitude.floor() as i32, self.longitude.floor() as i32)
+    }
+}
+
+/// A populated place from a geonames dump.
+#[derive(Debug, Clone)]
+pub struct Place {
+    pub city: String,
+    /// ISO 3166 country code
+    pub country: String,
+    pub location: Location,
+}
+
+/// Offline reverse geocoder over a geonames `cities*.txt` dump, with places
+/// bucketed into one-degree cells so a lookup only scans its neighbourhood.
+#[derive(Debug, Default)]
+pub struct Geocoder {
+    places: Vec<Place>,
+    cells: HashMap<(i32, i32), Vec<usize>>,
+}
+
+impl Geocoder {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
+
+    /// Reads the tab separated geonames format: name in the second column,
+    /// latitude and longitude in the fifth and sixth and the country code in
+    /// the ninth. Malformed lines are skipped.
+    pub fn from_reader(reader: impl BufRead) -> io::Result<Self> {
+        let mut geocoder = Self::default();
+        for line in reader.lines() {
+            let line = line?;
+            let columns = line.split('\t').collect::<Vec<_>>();
+            if columns.len() < 9 {
+                continue;
+            }
+            let Some(location) = columns[4]
+                .parse()
+                .ok()
+                .zip(columns[5].parse().ok())
+                .and_then(|(latitude, longitude)| Location::new(latitude, longitude))
+            else {
+                continue;
+            };
+            geocoder
+                .cells
+                .entry(location.cell())
+                .or_default()
+                .push(geocoder.places.len());
+            geocoder.places.push(Place {
+                city: columns[1].to_owned(),
+                country: columns[8].to_owned(),
+                location,
+            });
+        }
+        Ok(geocoder)
+    }
+
+    /// The closest place in the cells around `location`, roughly within
+    /// 100 km; none for the open sea or the wilderness.
+    pub fn nearest(&self, location: Location) -> Option<&Place> {
+        let (latitude, longitude) = location.cell();
+        (-1..=1)
+            .flat_map(|dlat| (-1..=1).map(move |dlon| (latitude + dlat, longitude + dlon)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .map(|&index| &self.places[index])
+            .min_by(|a, b| {
+                a.location
+                    .distance_km(&location)
+                    .total_cmp(&b.location.distance_km(&location))
+            })
+    }
+}
+
+#[test]
+fn test_geocoder() {
+    let dump = "1850147\tTokyo\tTokyo\t\t35.6895\t139.69171\tP\tPPLC\tJP\n\
+                1853909\tOsaka\tOsaka\t\t34.69374\t135.50218\tP\tPPLA\tJP\n\
+                broken line\n";
+    let geocoder = Geocoder::from_reader(dump.as_bytes()).unwrap();
+    let shinjuku = Location::from_iso6709("+35.6938+139.7034+040.000/").unwrap();
+    let place = geocoder.nearest(shinjuku).unwrap();
+    assert_eq!(
+        ("Tokyo", "JP"),
+        (place.city.as_str(), place.country.as_str())
+    );
+    assert!(geocoder
+        .nearest(Location::new(0.0, -30.0).unwrap())
+        .is_none());
+    assert_eq!(None, Location::from_iso6709("+95.0+10.0/"));
+}
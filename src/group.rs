@@ -0,0 +1,123 @@
+use std::path::{Path, PathBuf};
+
+use mime_guess::{mime, Mime};
+
+use crate::extractor;
+
+/// Extensions of the still of an iPhone live photo.
+const STILL_EXTENSIONS: [&str; 4] = ["heic", "heif", "jpg", "jpeg"];
+
+/// Extension of the motion half of a live photo, a short QuickTime movie
+/// next to the still with the same name.
+const MOTION_EXTENSION: &str = "mov";
+
+/// The still of the live photo `path` is the motion half of, if it is one.
+/// That movie is dated and filed with its still, so a pair is never split
+/// across categories or days.
+pub fn live_photo_still(path: &Path) -> Option<PathBuf> {
+    if !has_extension(path, &[MOTION_EXTENSION]) {
+        return None;
+    }
+    sibling(path, &STILL_EXTENSIONS)
+}
+
+/// Name of the live photo or burst the file at `path` belongs to: the
+/// shared file name of a live photo, the common part of Android burst
+/// names, or the burst identifier iPhones write into the EXIF maker note.
+pub fn group_of(path: &Path, mime_type: &Mime) -> Option<String> {
+    let stem = path.file_stem()?.to_str()?;
+    let live_photo = match mime_type.type_() {
+        mime::IMAGE if has_extension(path, &STILL_EXTENSIONS) => {
+            sibling(path, &[MOTION_EXTENSION]).is_some()
+        }
+        mime::VIDEO => live_photo_still(path).is_some(),
+        _ => false,
+    };
+    if live_photo {
+        return Some(stem.to_owned());
+    }
+    if mime_type.type_() != mime::IMAGE {
+        return None;
+    }
+    burst_in_name(stem).or_else(|| {
+        let burst = extractor::extract_burst_id(path)?;
+        // the identifier is a UUID; its first block tells bursts apart
+        let short = burst.split('-').next().unwrap_or(&burst);
+        Some(format!("Burst_{}", short))
+    })
+}
+
+/// The burst a file name tells, as Android cameras name the frames:
+/// `IMG_20230101_120000_BURST001_COVER.jpg` shares `IMG_20230101_120000`
+/// with the other frames, and Pixels number the frames up front, as in
+/// `00001IMG_00001_BURST20230101120000123.jpg`.
+fn burst_in_name(stem: &str) -> Option<String> {
+    let (prefix, rest) = stem.split_once("_BURST")?;
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if prefix
+        .split_once("IMG_")
+        .is_some_and(|(frame, index)| is_number(frame) && is_number(index))
+    {
+        let id = rest.bytes().take_while(u8::is_ascii_digit).count();
+        return (id > 0).then(|| format!("BURST{}", &rest[..id]));
+    }
+    (!prefix.is_empty()).then(|| prefix.to_owned())
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| extensions.contains(&ext.to_lowercase().as_str()))
+}
+
+/// An existing file next to `path` with the same stem and one of
+/// `extensions`, in lower or upper case.
+fn sibling(path: &Path, extensions: &[&str]) -> Option<PathBuf> {
+    extensions
+        .iter()
+        .flat_map(|ext| [ext.to_string(), ext.to_uppercase()])
+        .map(|ext| path.with_extension(ext))
+        .find(|sibling| sibling.is_file())
+}
+
+#[test]
+fn test_live_photo() {
+    let dir = std::env::temp_dir().join(format!("deduper-live-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for name in [
+        "IMG_1234.HEIC",
+        "IMG_1234.MOV",
+        "IMG_1235.MOV",
+        "IMG_1236.JPG",
+    ] {
+        std::fs::write(dir.join(name), name).unwrap();
+    }
+    let group = |name: &str| {
+        let path = dir.join(name);
+        group_of(&path, &extractor::extract_mimetype(&path))
+    };
+    assert_eq!(
+        Some(dir.join("IMG_1234.HEIC")),
+        live_photo_still(&dir.join("IMG_1234.MOV"))
+    );
+    assert_eq!(None, live_photo_still(&dir.join("IMG_1235.MOV")));
+    assert_eq!(Some("IMG_1234".to_owned()), group("IMG_1234.HEIC"));
+    assert_eq!(Some("IMG_1234".to_owned()), group("IMG_1234.MOV"));
+    assert_eq!(None, group("IMG_1235.MOV"));
+    assert_eq!(None, group("IMG_1236.JPG"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_burst_in_name() {
+    assert_eq!(
+        Some("IMG_20230101_120000".to_owned()),
+        burst_in_name("IMG_20230101_120000_BURST001_COVER")
+    );
+    assert_eq!(
+        Some("BURST20230101120000123".to_owned()),
+        burst_in_name("00001IMG_00001_BURST20230101120000123")
+    );
+    assert_eq!(None, burst_in_name("IMG_20230101_120000"));
+    assert_eq!(None, burst_in_name("_BURST001"));
+}
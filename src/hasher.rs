@@ -2,24 +2,472 @@ use base64ct::Base64UrlUnpadded;
 use base64ct::Encoding;
 use sha2::Digest;
 use sha2::Sha256;
+use std::fmt;
 use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
 use std::path::Path;
+use std::str::FromStr;
+use xxhash_rust::xxh3::Xxh3;
 
-pub fn file_hash(path: &Path) -> Option<String> {
-    let mut file = File::open(path).ok()?;
-    let mut sha256 = Sha256::new();
-    std::io::copy(&mut file, &mut sha256).ok()?;
-    let hash = sha256.finalize();
-    Some(Base64UrlUnpadded::encode_string(&hash[..16]))
+use crate::{platform, throttle};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, clap::ValueEnum)]
+pub enum HashAlgorithm {
+    #[default]
+    Blake3,
+    Sha256,
+    Xxh3,
+}
+
+/// Characters of [`FileHash::short`]: 16 bytes in unpadded base64url.
+pub const SHORT_DIGEST_LEN: usize = 22;
+
+impl HashAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Blake3 => "blake3",
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Xxh3 => "xxh3",
+        }
+    }
+
+    /// Characters of a full digest as recorded.
+    pub fn digest_len(&self) -> usize {
+        match self {
+            HashAlgorithm::Blake3 | HashAlgorithm::Sha256 => 43,
+            HashAlgorithm::Xxh3 => SHORT_DIGEST_LEN,
+        }
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        <Self as clap::ValueEnum>::from_str(s, true)
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileHash {
+    pub algorithm: HashAlgorithm,
+    pub digest: String,
+}
+
+impl FileHash {
+    /// The first 16 bytes of the digest, which file names carry.
+    pub fn short(&self) -> &str {
+        &self.digest[..self.digest.len().min(SHORT_DIGEST_LEN)]
+    }
+
+    /// Whether `recorded` is this digest. Databases from before full
+    /// digests were stored hold the short form.
+    pub fn matches(&self, recorded: &str) -> bool {
+        self.digest == recorded || self.short() == recorded
+    }
+}
+
+impl fmt::Display for FileHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.digest)
+    }
+}
+
+/// How [`file_hash_with`] reads a file.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReadBackend {
+    /// Reads of 64 KiB into a buffer
+    #[default]
+    Buffered,
+    /// Memory-maps the file and hashes it in place; Unix only
+    Mmap,
+    /// Keeps several large reads in flight through io_uring; Linux builds
+    /// with the `io-uring` feature only
+    IoUring,
+}
+
+pub fn file_hash(path: &Path, algorithm: HashAlgorithm) -> io::Result<FileHash> {
+    file_hash_with(path, algorithm, ReadBackend::Buffered)
+}
+
+/// Like [`file_hash`], reading through `backend`. Where the backend is not
+/// available, on this platform, build or kernel, the file is read buffered.
+pub fn file_hash_with(
+    path: &Path,
+    algorithm: HashAlgorithm,
+    backend: ReadBackend,
+) -> io::Result<FileHash> {
+    hash_with(algorithm, |update| {
+        let file = File::open(path)?;
+        match backend {
+            ReadBackend::Buffered => read_chunks(file, update),
+            ReadBackend::Mmap => read_mapped(file, update),
+            ReadBackend::IoUring => read_uring(file, update),
+        }
+    })
+}
+
+/// Like [`file_hash`], of whatever `reader` yields, e.g. a file streamed
+/// from a remote.
+pub fn reader_hash(reader: impl Read, algorithm: HashAlgorithm) -> io::Result<FileHash> {
+    hash_with(algorithm, |update| read_chunks(reader, update))
+}
+
+/// Bytes read at each end of a file by [`partial_hash`].
+pub const PARTIAL_HASH_BYTES: u64 = 64 * 1024;
+
+/// A hash of the length and the first and last [`PARTIAL_HASH_BYTES`] of
+/// the file at `path`. It tells most files of the same size apart without
+/// reading them whole, but equal partial hashes do not mean equal files.
+/// Files no longer than both ends are hashed whole.
+pub fn partial_hash(path: &Path, algorithm: HashAlgorithm) -> io::Result<FileHash> {
+    hash_with(algorithm, |update| {
+        let mut file = File::open(path)?;
+        let len = file.metadata()?.len();
+        update(&len.to_le_bytes());
+        if len <= 2 * PARTIAL_HASH_BYTES {
+            return read_chunks(file, update);
+        }
+        let mut buf = vec![0; PARTIAL_HASH_BYTES as usize];
+        file.read_exact(&mut buf)?;
+        throttle::consume(buf.len());
+        update(&buf);
+        file.seek(SeekFrom::End(-(PARTIAL_HASH_BYTES as i64)))?;
+        file.read_exact(&mut buf)?;
+        throttle::consume(buf.len());
+        update(&buf);
+        Ok(())
+    })
+}
+
+/// Hashes what `read` feeds to the update function it is given.
+fn hash_with(
+    algorithm: HashAlgorithm,
+    read: impl FnOnce(&mut dyn FnMut(&[u8])) -> io::Result<()>,
+) -> io::Result<FileHash> {
+    let mut hashing = Hashing::new(algorithm);
+    read(&mut |chunk| hashing.update(chunk))?;
+    Ok(hashing.finish())
+}
+
+/// Like [`file_hash`], reading through tokio so a task waiting on a slow
+/// mount holds no thread and many files can be read at once. Reads are
+/// throttled like those of [`file_hash`].
+#[cfg(feature = "async")]
+pub async fn file_hash_async(path: &Path, algorithm: HashAlgorithm) -> io::Result<FileHash> {
+    use tokio::io::AsyncReadExt;
+
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hashing = Hashing::new(algorithm);
+    let mut buf = vec![0; 1024 * 1024];
+    loop {
+        match file.read(&mut buf).await? {
+            0 => return Ok(hashing.finish()),
+            n => {
+                if throttle::is_limited() {
+                    tokio::task::spawn_blocking(move || throttle::consume(n))
+                        .await
+                        .map_err(io::Error::other)?;
+                }
+                hashing.update(&buf[..n]);
+            }
+        }
+    }
+}
+
+/// A hash of `algorithm` fed chunk by chunk.
+enum Hashing {
+    Blake3(Box<blake3::Hasher>),
+    Sha256(Sha256),
+    Xxh3(Box<Xxh3>),
+}
+
+impl Hashing {
+    fn new(algorithm: HashAlgorithm) -> Self {
+        match algorithm {
+            HashAlgorithm::Blake3 => Self::Blake3(Box::default()),
+            HashAlgorithm::Sha256 => Self::Sha256(Sha256::new()),
+            HashAlgorithm::Xxh3 => Self::Xxh3(Box::default()),
+        }
+    }
+
+    fn update(&mut self, chunk: &[u8]) {
+        match self {
+            Self::Blake3(blake3) => {
+                blake3.update(chunk);
+            }
+            Self::Sha256(sha256) => sha256.update(chunk),
+            Self::Xxh3(xxh3) => xxh3.update(chunk),
+        }
+    }
+
+    fn finish(self) -> FileHash {
+        let (algorithm, digest) = match self {
+            Self::Blake3(blake3) => (HashAlgorithm::Blake3, blake3.finalize().as_bytes().to_vec()),
+            Self::Sha256(sha256) => (HashAlgorithm::Sha256, sha256.finalize().to_vec()),
+            Self::Xxh3(xxh3) => (HashAlgorithm::Xxh3, xxh3.digest128().to_be_bytes().to_vec()),
+        };
+        FileHash {
+            algorithm,
+            digest: encode_digest(&digest),
+        }
+    }
+}
+
+/// The digest recorded for the raw hash `bytes`: all of them in URL-safe
+/// base64.
+pub fn encode_digest(bytes: &[u8]) -> String {
+    Base64UrlUnpadded::encode_string(bytes)
+}
+
+/// Whether `s` has the form of a recorded digest, a short or full one in
+/// URL-safe base64, so it can name a file without leaving its directory.
+pub fn is_digest(s: &str) -> bool {
+    matches!(s.len(), SHORT_DIGEST_LEN | 43)
+        && s.bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Whether the files at `a` and `b` hold the same bytes, read side by side
+/// in chunks so neither is loaded whole.
+pub fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    let (mut a, mut b) = (File::open(a)?, File::open(b)?);
+    if a.metadata()?.len() != b.metadata()?.len() {
+        return Ok(false);
+    }
+    let mut buf_a = vec![0; 64 * 1024];
+    let mut buf_b = vec![0; 64 * 1024];
+    loop {
+        let n = a.read(&mut buf_a)?;
+        if n == 0 {
+            // same length, so `b` is at its end as well
+            return Ok(true);
+        }
+        b.read_exact(&mut buf_b[..n])?;
+        throttle::consume(2 * n);
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+fn read_chunks(mut file: impl Read, mut update: impl FnMut(&[u8])) -> io::Result<()> {
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buf)? {
+            0 => return Ok(()),
+            n => {
+                throttle::consume(n);
+                update(&buf[..n]);
+            }
+        }
+    }
+}
+
+/// Hashes the file mapped whole, which also lets BLAKE3 hash many chunks at
+/// once; under a read rate limit it is hashed 1 MiB at a time, so the pages
+/// are faulted in no faster than the limit. Empty files and files that
+/// cannot be mapped, such as pipes, are read buffered.
+fn read_mapped(file: File, update: &mut dyn FnMut(&[u8])) -> io::Result<()> {
+    match platform::Mmap::map(&file) {
+        Ok(map) if throttle::is_limited() => {
+            for chunk in map.chunks(1024 * 1024) {
+                throttle::consume(chunk.len());
+                update(chunk);
+            }
+            Ok(())
+        }
+        Ok(map) => {
+            update(&map);
+            Ok(())
+        }
+        Err(_) => read_chunks(file, update),
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+fn read_uring(file: File, update: &mut dyn FnMut(&[u8])) -> io::Result<()> {
+    read_chunks(file, update)
+}
+
+/// Reads the file in 1 MiB chunks with up to four of them queued at once,
+/// hashing each in order while the next are read. Kernels without io_uring,
+/// or where it is forbidden, get buffered reads.
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+fn read_uring(file: File, update: &mut dyn FnMut(&[u8])) -> io::Result<()> {
+    let mut ring = match io_uring::IoUring::new(URING_DEPTH as u32) {
+        Ok(ring) => ring,
+        Err(_) => return read_chunks(file, update),
+    };
+    let mut bufs = vec![vec![0u8; URING_CHUNK]; URING_DEPTH];
+    let mut in_flight = 0;
+    let result = read_queued(&mut ring, &mut bufs, &file, update, &mut in_flight);
+    // the kernel may still write into reads queued before a failure
+    while in_flight > 0 {
+        match ring.submit_and_wait(1) {
+            Ok(_) => in_flight -= ring.completion().count(),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(_) => {
+                std::mem::forget(bufs);
+                break;
+            }
+        }
+    }
+    result
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+const URING_DEPTH: usize = 4;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+const URING_CHUNK: usize = 1024 * 1024;
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+fn read_queued(
+    ring: &mut io_uring::IoUring,
+    bufs: &mut [Vec<u8>],
+    file: &File,
+    update: &mut dyn FnMut(&[u8]),
+    in_flight: &mut usize,
+) -> io::Result<()> {
+    use io_uring::{opcode, types};
+    use std::os::unix::{fs::FileExt, io::AsRawFd};
+
+    let len = file.metadata()?.len();
+    let chunks = len.div_ceil(URING_CHUNK as u64);
+    let fd = types::Fd(file.as_raw_fd());
+    let mut results = [None; URING_DEPTH];
+    let mut submitted = 0;
+    for chunk in 0..chunks {
+        while submitted < chunks && submitted - chunk < URING_DEPTH as u64 {
+            let slot = (submitted % URING_DEPTH as u64) as usize;
+            let read = opcode::Read::new(fd, bufs[slot].as_mut_ptr(), URING_CHUNK as u32)
+                .offset(submitted * URING_CHUNK as u64)
+                .build()
+                .user_data(slot as u64);
+            // SAFETY: the buffer is not touched again until the read completes
+            unsafe { ring.submission().push(&read) }.map_err(io::Error::other)?;
+            submitted += 1;
+            *in_flight += 1;
+        }
+        let slot = (chunk % URING_DEPTH as u64) as usize;
+        while results[slot].is_none() {
+            ring.submit_and_wait(1)?;
+            for completion in ring.completion() {
+                results[completion.user_data() as usize] = Some(completion.result());
+                *in_flight -= 1;
+            }
+        }
+        let read = results[slot].take().unwrap_or_default();
+        if read < 0 {
+            return Err(io::Error::from_raw_os_error(-read));
+        }
+        let offset = chunk * URING_CHUNK as u64;
+        let expected = (len - offset).min(URING_CHUNK as u64) as usize;
+        let read = (read as usize).min(expected);
+        let buf = &mut bufs[slot][..expected];
+        // short reads are rare on regular files; finish them synchronously
+        file.read_exact_at(&mut buf[read..], offset + read as u64)?;
+        throttle::consume(expected);
+        update(buf);
+    }
+    Ok(())
 }
 
 #[test]
 fn test_file_hash() {
-    let base64_hash = file_hash(Path::new(
-        "/storage/Videos/2023/2023-09-01-22-49-41-343.mp4",
-    ));
+    let base64_hash = file_hash(
+        Path::new("/storage/Videos/2023/2023-09-01-22-49-41-343.mp4"),
+        HashAlgorithm::Sha256,
+    );
     assert_eq!(
         "BrV-IyQTvSXPicvRzKjzjx00GvdnYorDD565BwgWzNs",
-        base64_hash.unwrap()
+        base64_hash.unwrap().digest
+    );
+}
+
+#[test]
+fn test_same_contents() {
+    let dir = std::env::temp_dir().join(format!("deduper-contents-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (a, b, c) = (dir.join("a"), dir.join("b"), dir.join("c"));
+    let contents = vec![7; 200 * 1024];
+    std::fs::write(&a, &contents).unwrap();
+    std::fs::write(&b, &contents).unwrap();
+    let mut changed = contents.clone();
+    changed[150 * 1024] = 8;
+    std::fs::write(&c, &changed).unwrap();
+    assert!(same_contents(&a, &b).unwrap());
+    assert!(!same_contents(&a, &c).unwrap());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_short_digest() {
+    let hash = FileHash {
+        algorithm: HashAlgorithm::Sha256,
+        digest: "BrV-IyQTvSXPicvRzKjzjx00GvdnYorDD565BwgWzNs".to_owned(),
+    };
+    assert_eq!(HashAlgorithm::Sha256.digest_len(), hash.digest.len());
+    assert_eq!("BrV-IyQTvSXPicvRzKjzjx", hash.short());
+    assert!(hash.matches("BrV-IyQTvSXPicvRzKjzjx"));
+    assert!(!hash.matches("BrV-IyQTvSXPicvRzKjzjy"));
+    assert!(is_digest(&hash.digest));
+    assert!(is_digest(hash.short()));
+    assert!(!is_digest("../../../../etc/passwd"));
+    assert!(!is_digest("BrV-IyQTvSXPicvRzKjzj/"));
+}
+
+#[test]
+fn test_partial_hash() {
+    let dir = std::env::temp_dir().join(format!("deduper-partial-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (a, b) = (dir.join("a"), dir.join("b"));
+    let contents = vec![7; 3 * PARTIAL_HASH_BYTES as usize];
+    let mut changed = contents.clone();
+    changed[PARTIAL_HASH_BYTES as usize + 1] = 8;
+    std::fs::write(&a, &contents).unwrap();
+    std::fs::write(&b, &changed).unwrap();
+    // the middle is not read
+    assert_eq!(
+        partial_hash(&a, HashAlgorithm::Sha256).unwrap(),
+        partial_hash(&b, HashAlgorithm::Sha256).unwrap()
+    );
+    assert_ne!(
+        file_hash(&a, HashAlgorithm::Sha256).unwrap(),
+        file_hash(&b, HashAlgorithm::Sha256).unwrap()
     );
+    std::fs::write(&b, &contents[1..]).unwrap();
+    assert_ne!(
+        partial_hash(&a, HashAlgorithm::Sha256).unwrap(),
+        partial_hash(&b, HashAlgorithm::Sha256).unwrap()
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_read_backends() {
+    let dir = std::env::temp_dir().join(format!("deduper-backends-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (large, empty) = (dir.join("large"), dir.join("empty"));
+    let contents = (0..3 * 1024 * 1024 + 7)
+        .map(|i| i as u8)
+        .collect::<Vec<_>>();
+    std::fs::write(&large, &contents).unwrap();
+    std::fs::write(&empty, []).unwrap();
+    for path in [&large, &empty] {
+        let buffered = file_hash(path, HashAlgorithm::Sha256).unwrap();
+        for backend in [ReadBackend::Mmap, ReadBackend::IoUring] {
+            assert_eq!(
+                buffered,
+                file_hash_with(path, HashAlgorithm::Sha256, backend).unwrap()
+            );
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
 }
@@ -0,0 +1,123 @@
+//! Escaping and form decoding for the pages `serve` and `gallery` render.
+
+use std::net::SocketAddr;
+
+/// Escapes `text` for use in HTML text and quoted attribute values.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Percent-encodes `text` for a query string; only unreserved characters
+/// are left as they are.
+pub fn encode_component(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Name-value pairs of a query string or an
+/// `application/x-www-form-urlencoded` body, in order. Invalid escapes are
+/// kept as they are.
+pub fn decode_form(input: &str) -> Vec<(String, String)> {
+    input
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode_component(name), decode_component(value))
+        })
+        .collect()
+}
+
+fn decode_component(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let hex = text
+                    .get(index + 1..index + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                if let Some(byte) = hex {
+                    decoded.push(byte);
+                    index += 3;
+                    continue;
+                }
+                decoded.push(b'%');
+            }
+            byte => decoded.push(byte),
+        }
+        index += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Whether a request sent with the `Host` header `host` is meant for a
+/// server listening on `listen`: the address itself, or `localhost` and
+/// `127.0.0.1` at its port. Pages of a site whose name was rebound to the
+/// server's address send their own name, so they are turned away.
+pub fn known_host(listen: SocketAddr, host: &str) -> bool {
+    let port = listen.port();
+    host == listen.to_string()
+        || host == format!("localhost:{}", port)
+        || host == format!("127.0.0.1:{}", port)
+}
+
+#[test]
+fn test_escape() {
+    assert_eq!(
+        "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;",
+        escape("<a href=\"x\">Tom & Jerry's</a>")
+    );
+}
+
+#[test]
+fn test_decode_form() {
+    let path = "/photos/2019 trip/ä & b+c%.jpg";
+    let form = format!("keep={}&resolution=delete&page=", encode_component(path));
+    assert_eq!(
+        vec![
+            ("keep".to_owned(), path.to_owned()),
+            ("resolution".to_owned(), "delete".to_owned()),
+            ("page".to_owned(), String::new()),
+        ],
+        decode_form(&form)
+    );
+    assert_eq!(
+        vec![("q".to_owned(), "a b%zz%".to_owned())],
+        decode_form("q=a+b%zz%")
+    );
+}
+
+#[test]
+fn test_known_host() {
+    let listen = "127.0.0.1:8080".parse().unwrap();
+    assert!(known_host(listen, "127.0.0.1:8080"));
+    assert!(known_host(listen, "localhost:8080"));
+    assert!(!known_host(listen, "localhost:8081"));
+    assert!(!known_host(listen, "evil.example:8080"));
+    assert!(!known_host(listen, "localhost"));
+    let listen = "192.168.1.5:8080".parse().unwrap();
+    assert!(known_host(listen, "192.168.1.5:8080"));
+    assert!(known_host(listen, "127.0.0.1:8080"));
+    assert!(!known_host(listen, "nas.evil.example:8080"));
+}
@@ -0,0 +1,327 @@
+//! Gitignore-style `.deduperignore` files, so thumbnail caches, NAS index
+//! folders such as `@eaDir` and trash folders are left out of walks.
+//!
+//! Each line is a pattern: `*` and `?` match within a name, `[...]` a
+//! class, `**` any number of directories, a trailing `/` only matches
+//! directories and a leading `!` takes a file back in. Patterns without a
+//! slash match a name at any depth, others the path relative to the
+//! directory of the ignore file. The last pattern that matches decides,
+//! and files deeper in the tree override those above them.
+
+use std::{
+    env, fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use regex::Regex;
+
+/// The name of the per-directory ignore files.
+pub const FILE_NAME: &str = ".deduperignore";
+
+/// The user's ignore file that applies to every source,
+/// `$XDG_CONFIG_HOME/deduper/ignore` or `~/.config/deduper/ignore`.
+pub fn default_global_file() -> Option<PathBuf> {
+    env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .map(|config| config.join("deduper/ignore"))
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    regex: Regex,
+    negated: bool,
+    dir_only: bool,
+}
+
+/// The patterns of one ignore file.
+#[derive(Debug, Default, Clone)]
+pub struct Rules(Vec<Rule>);
+
+impl Rules {
+    /// Reads the ignore file at `path`; lines that are no valid pattern are
+    /// skipped, as git does.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse(text: &str) -> Self {
+        Self(text.lines().filter_map(compile).collect())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether the last pattern matching `relative`, a path below the
+    /// directory the rules apply to, ignores it: `Some(false)` when a
+    /// negated pattern takes it back, `None` when no pattern matches.
+    pub fn matches(&self, relative: &Path, is_dir: bool) -> Option<bool> {
+        let relative = slash_path(relative);
+        self.0
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.regex.is_match(&relative))
+            .map(|rule| !rule.negated)
+    }
+}
+
+/// The rules in effect during a depth-first walk of `root`: the global
+/// ones and those of the ignore files of the directories entered so far.
+#[derive(Debug)]
+pub struct Stack {
+    root: PathBuf,
+    global: Rules,
+    dirs: Vec<(PathBuf, Rules)>,
+}
+
+impl Stack {
+    pub fn new(root: &Path, global: Rules) -> Self {
+        Self {
+            root: root.to_owned(),
+            global,
+            dirs: Vec::new(),
+        }
+    }
+
+    /// Whether the entry at `path` is left out. Entries must come in walk
+    /// order, a directory before what is in it; the ignore file of every
+    /// directory that is not left out is read on the way.
+    pub fn is_ignored(&mut self, path: &Path, is_dir: bool) -> bool {
+        while let Some((dir, _)) = self.dirs.last() {
+            if path.starts_with(dir) && path != dir {
+                break;
+            }
+            self.dirs.pop();
+        }
+        if !is_dir && path.file_name().is_some_and(|name| name == FILE_NAME) {
+            return true;
+        }
+        if path != self.root {
+            let ignored = self
+                .dirs
+                .iter()
+                .rev()
+                .find_map(|(dir, rules)| rules.matches(path.strip_prefix(dir).ok()?, is_dir))
+                .or_else(|| {
+                    let relative = path.strip_prefix(&self.root).ok()?;
+                    self.global.matches(relative, is_dir)
+                })
+                .unwrap_or(false);
+            if ignored {
+                return true;
+            }
+        }
+        if is_dir {
+            let rules = Rules::load(&path.join(FILE_NAME)).unwrap_or_default();
+            if !rules.is_empty() {
+                self.dirs.push((path.to_owned(), rules));
+            }
+        }
+        false
+    }
+}
+
+/// Whether a walk of `root` would leave out `path`, for files found some
+/// other way such as by a watcher. Reads the ignore files of `root` and of
+/// every directory between it and `path`.
+pub fn is_ignored(root: &Path, path: &Path, global: Rules) -> bool {
+    let Ok(relative) = path.strip_prefix(root) else {
+        return false;
+    };
+    let mut stack = Stack::new(root, global);
+    stack.is_ignored(root, true);
+    let mut names = relative.components().peekable();
+    let mut entry = root.to_owned();
+    while let Some(name) = names.next() {
+        entry.push(name);
+        let is_dir = names.peek().is_some() || entry.is_dir();
+        if stack.is_ignored(&entry, is_dir) {
+            return true;
+        }
+    }
+    false
+}
+
+/// The names of `path` joined with `/`, as patterns are written.
+fn slash_path(path: &Path) -> String {
+    path.components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// The rule of one line, `None` for blank lines and comments.
+fn compile(line: &str) -> Option<Rule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negated, pattern) = match line.strip_prefix('!') {
+        Some(pattern) => (true, pattern),
+        None => (false, line),
+    };
+    // `\#` and `\!` start patterns with those characters
+    let pattern = match pattern.strip_prefix('\\') {
+        Some(rest) if rest.starts_with(['#', '!']) => rest,
+        _ => pattern,
+    };
+    let (dir_only, pattern) = match pattern.strip_suffix('/') {
+        Some(pattern) => (true, pattern),
+        None => (false, pattern),
+    };
+    let anchored = pattern.contains('/');
+    let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+    if pattern.is_empty() {
+        return None;
+    }
+
+    let mut regex = String::from("(?s)^");
+    if !anchored {
+        regex.push_str("(?:.*/)?");
+    }
+    let segments = pattern.split('/').collect::<Vec<_>>();
+    for (i, segment) in segments.iter().enumerate() {
+        let last = i + 1 == segments.len();
+        if *segment == "**" {
+            regex.push_str(if last { ".*" } else { "(?:.*/)?" });
+            continue;
+        }
+        translate(segment, &mut regex);
+        if !last {
+            regex.push('/');
+        }
+    }
+    regex.push('$');
+    Some(Rule {
+        regex: Regex::new(&regex).ok()?,
+        negated,
+        dir_only,
+    })
+}
+
+/// Appends the regex of the glob `segment`, which matches within one name.
+fn translate(segment: &str, regex: &mut String) {
+    let chars = segment.chars().collect::<Vec<_>>();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' => {
+                while chars.get(i + 1) == Some(&'*') {
+                    i += 1;
+                }
+                regex.push_str("[^/]*");
+            }
+            '?' => regex.push_str("[^/]"),
+            '[' => match class_end(&chars, i) {
+                Some(end) => {
+                    regex.push('[');
+                    let mut class = &chars[i + 1..end];
+                    if let Some(('!' | '^', rest)) = class.split_first() {
+                        regex.push('^');
+                        class = rest;
+                    }
+                    for &c in class {
+                        // `&&`, `--` and `~~` are set operations in regex classes
+                        if matches!(c, '\\' | '[' | '&' | '~') {
+                            regex.push('\\');
+                        }
+                        regex.push(c);
+                    }
+                    regex.push(']');
+                    i = end;
+                }
+                None => regex.push_str(r"\["),
+            },
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                regex.push_str(&regex::escape(&chars[i].to_string()));
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+}
+
+/// The index of the `]` closing the class opened at `start`; a `]` right
+/// after the opening (or its negation) is part of the class.
+fn class_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    if matches!(chars.get(i), Some('!' | '^')) {
+        i += 1;
+    }
+    if chars.get(i) == Some(&']') {
+        i += 1;
+    }
+    (i..chars.len()).find(|&i| chars[i] == ']')
+}
+
+#[test]
+fn test_rules() {
+    let rules = Rules::parse(
+        "# Synology and desktop clutter
+@eaDir/
+.thumbnails
+*.tmp
+/Trash/
+raw/**/preview
+!keep.tmp
+\\#notes
+IMG_[0-9][!a-z].jpg
+",
+    );
+    let ignored = |path: &str, is_dir: bool| rules.matches(Path::new(path), is_dir);
+    assert_eq!(Some(true), ignored("2023/@eaDir", true));
+    // directory-only patterns leave files of that name alone
+    assert_eq!(None, ignored("2023/@eaDir", false));
+    assert_eq!(Some(true), ignored("a/b/.thumbnails", true));
+    assert_eq!(Some(true), ignored("a/b.tmp", false));
+    assert_eq!(Some(false), ignored("a/keep.tmp", false));
+    assert_eq!(Some(true), ignored("Trash", true));
+    // anchored to the directory of the ignore file
+    assert_eq!(None, ignored("a/Trash", true));
+    assert_eq!(Some(true), ignored("raw/preview", false));
+    assert_eq!(Some(true), ignored("raw/2023/06/preview", true));
+    assert_eq!(Some(true), ignored("#notes", false));
+    assert_eq!(Some(true), ignored("IMG_12.jpg", false));
+    assert_eq!(None, ignored("IMG_1a.jpg", false));
+    assert_eq!(None, ignored("photo.jpg", false));
+}
+
+#[test]
+fn test_stack() {
+    let dir = std::env::temp_dir().join(format!("deduper-ignore-{}", std::process::id()));
+    fs::create_dir_all(dir.join("2023/cache")).unwrap();
+    fs::write(dir.join(FILE_NAME), "cache/\n").unwrap();
+    fs::write(dir.join("2023").join(FILE_NAME), "*.png\n!cache/\n").unwrap();
+
+    let mut stack = Stack::new(&dir, Rules::parse("*.gif"));
+    assert!(!stack.is_ignored(&dir, true));
+    assert!(stack.is_ignored(&dir.join(FILE_NAME), false));
+    assert!(stack.is_ignored(&dir.join("a.gif"), false));
+    assert!(!stack.is_ignored(&dir.join("2023"), true));
+    assert!(stack.is_ignored(&dir.join("2023/a.png"), false));
+    // the deeper file takes the directory back in
+    assert!(!stack.is_ignored(&dir.join("2023/cache"), true));
+    assert!(stack.is_ignored(&dir.join("2023/cache/a.gif"), false));
+    // leaving 2023 drops its rules
+    assert!(!stack.is_ignored(&dir.join("b.png"), false));
+    assert!(stack.is_ignored(&dir.join("other/cache"), true));
+
+    // single paths are decided as the walk decides them
+    let ignored = |path: &str| is_ignored(&dir, &dir.join(path), Rules::parse("*.gif"));
+    assert!(ignored("other/cache/a.jpg"));
+    assert!(ignored("2023/a.png"));
+    assert!(!ignored("2023/cache/a.jpg"));
+    assert!(!ignored("2023/a.jpg"));
+    assert!(!is_ignored(
+        &dir,
+        Path::new("/elsewhere/a.png"),
+        Rules::default()
+    ));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,146 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, Read},
+    path::PathBuf,
+};
+
+use serde::Deserialize;
+
+use crate::hasher::{self, FileHash, HashAlgorithm};
+
+/// Output formats of other deduplication tools that can seed the database.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ImportFormat {
+    /// JSON written by `rmlint -o json`
+    Rmlint,
+    /// Plain output of fdupes or jdupes: one path per line, groups separated
+    /// by blank lines
+    Fdupes,
+}
+
+/// Files another tool found to be identical.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateSet {
+    pub paths: Vec<PathBuf>,
+    /// The tool's digest, when it was computed with an algorithm deduper
+    /// also hashes with
+    pub hash: Option<FileHash>,
+}
+
+impl ImportFormat {
+    pub fn parse(self, reader: impl BufRead) -> io::Result<Vec<DuplicateSet>> {
+        match self {
+            ImportFormat::Rmlint => parse_rmlint(reader),
+            ImportFormat::Fdupes => parse_fdupes(reader),
+        }
+    }
+}
+
+/// Header, lint entry or footer of an rmlint JSON document; the header
+/// names the checksum and the entries carry the rest.
+#[derive(Deserialize)]
+struct RmlintEntry {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    checksum_type: Option<String>,
+    digest: Option<String>,
+    path: Option<String>,
+}
+
+/// Duplicate files of an rmlint JSON document, grouped by digest. Other
+/// lint such as empty files or directories is ignored.
+pub fn parse_rmlint(reader: impl Read) -> io::Result<Vec<DuplicateSet>> {
+    let entries: Vec<RmlintEntry> = serde_json::from_reader(reader)?;
+    let algorithm = entries
+        .iter()
+        .find_map(|entry| entry.checksum_type.as_deref())
+        .and_then(|checksum| match checksum {
+            "sha256" => Some(HashAlgorithm::Sha256),
+            _ => None,
+        });
+    let mut sets = BTreeMap::<String, Vec<PathBuf>>::new();
+    for entry in entries {
+        if entry.kind.as_deref() != Some("duplicate_file") {
+            continue;
+        }
+        if let (Some(digest), Some(path)) = (entry.digest, entry.path) {
+            sets.entry(digest).or_default().push(PathBuf::from(path));
+        }
+    }
+    Ok(sets
+        .into_iter()
+        .map(|(digest, paths)| DuplicateSet {
+            hash: algorithm
+                .zip(decode_hex(&digest))
+                .map(|(algorithm, bytes)| FileHash {
+                    algorithm,
+                    digest: hasher::encode_digest(&bytes),
+                }),
+            paths,
+        })
+        .collect())
+}
+
+/// Groups of fdupes or jdupes output. The `N bytes each:` lines `-S` adds
+/// are skipped.
+pub fn parse_fdupes(reader: impl BufRead) -> io::Result<Vec<DuplicateSet>> {
+    let mut sets = Vec::new();
+    let mut paths = Vec::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            if paths.len() > 1 {
+                sets.push(DuplicateSet {
+                    paths: std::mem::take(&mut paths),
+                    hash: None,
+                });
+            }
+            paths.clear();
+        } else if !is_size_line(line) {
+            paths.push(PathBuf::from(line));
+        }
+    }
+    if paths.len() > 1 {
+        sets.push(DuplicateSet { paths, hash: None });
+    }
+    Ok(sets)
+}
+
+fn is_size_line(line: &str) -> bool {
+    line.strip_suffix(" bytes each:")
+        .or_else(|| line.strip_suffix(" byte each:"))
+        .is_some_and(|size| !size.is_empty() && size.bytes().all(|b| b.is_ascii_digit()))
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair)
+                .ok()
+                .filter(|pair| pair.len() == 2)?;
+            u8::from_str_radix(pair, 16).ok()
+        })
+        .collect()
+}
+
+#[test]
+fn test_parse_fdupes() {
+    let output = "12 bytes each:\n/a/one.jpg\n/b/one.jpg\n\n/a/two.mp4\n/b/two.mp4\n/c/two.mp4\n";
+    let sets = parse_fdupes(output.as_bytes()).unwrap();
+    assert_eq!(2, sets.len());
+    assert_eq!(
+        vec![PathBuf::from("/a/one.jpg"), PathBuf::from("/b/one.jpg")],
+        sets[0].paths
+    );
+    assert_eq!(3, sets[1].paths.len());
+    assert_eq!(None, sets[1].hash);
+}
+
+#[test]
+fn test_decode_hex() {
+    assert_eq!(Some(vec![0x00, 0xab, 0xff]), decode_hex("00abFF"));
+    assert_eq!(None, decode_hex("abc"));
+    assert_eq!(None, decode_hex("zz"));
+}
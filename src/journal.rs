@@ -0,0 +1,100 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use chrono::Utc;
+
+use crate::database::DB;
+
+/// Progress of a command over its sources, written to the database as files
+/// are processed so an interrupted run can be picked up where it stopped.
+#[derive(Debug)]
+pub struct Journal {
+    run_id: i64,
+    done: HashSet<PathBuf>,
+}
+
+impl Journal {
+    /// Starts a new run of `command`, or with `resume` continues the latest
+    /// unfinished run started with the same `parameters`.
+    pub fn open(db: &DB, command: &str, parameters: &str, resume: bool) -> rusqlite::Result<Self> {
+        let db = db.lock();
+        let unfinished = if resume {
+            db.find_unfinished_run(command, parameters)?
+        } else {
+            None
+        };
+        match unfinished {
+            Some(run_id) => Ok(Self {
+                run_id,
+                done: db.journaled_paths(run_id)?.into_iter().collect(),
+            }),
+            None => Ok(Self {
+                run_id: db.start_run(command, parameters, Utc::now().timestamp())?,
+                done: HashSet::new(),
+            }),
+        }
+    }
+
+    pub fn run_id(&self) -> i64 {
+        self.run_id
+    }
+
+    /// Number of paths an earlier attempt of this run already finished.
+    pub fn resumed(&self) -> usize {
+        self.done.len()
+    }
+
+    pub fn is_done(&self, path: &Path) -> bool {
+        self.done.contains(path)
+    }
+
+    pub fn record(&self, db: &DB, path: &Path) -> rusqlite::Result<()> {
+        db.lock().journal_path(self.run_id, path)
+    }
+
+    pub fn finish(self, db: &DB) -> rusqlite::Result<()> {
+        db.lock().finish_run(self.run_id, Utc::now().timestamp())
+    }
+}
+
+/// Identifies a run by the paths it was started with, so `--resume` only
+/// continues a run over the same sources.
+pub fn parameters<'a>(paths: impl IntoIterator<Item = &'a Path>) -> String {
+    paths
+        .into_iter()
+        .map(|path| path.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[test]
+fn test_journal() {
+    let path = std::env::temp_dir().join(format!("deduper-journal-{}.db", std::process::id()));
+    let db = DB::new(&path).unwrap();
+    let parameters = parameters([Path::new("/photos")]);
+    let journal = Journal::open(&db, "scan", &parameters, false).unwrap();
+    journal.record(&db, Path::new("/photos/a.jpg")).unwrap();
+
+    let resumed = Journal::open(&db, "scan", &parameters, true).unwrap();
+    assert_eq!(1, resumed.resumed());
+    assert!(resumed.is_done(Path::new("/photos/a.jpg")));
+    assert!(!resumed.is_done(Path::new("/photos/b.jpg")));
+    assert_eq!(
+        0,
+        Journal::open(&db, "organize", &parameters, true)
+            .unwrap()
+            .resumed()
+    );
+
+    resumed.finish(&db).unwrap();
+    assert_eq!(
+        0,
+        Journal::open(&db, "scan", &parameters, true)
+            .unwrap()
+            .resumed()
+    );
+    drop(db);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,258 @@
+use std::{fmt, path::PathBuf, str::FromStr};
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::{geo::Place, media::Media};
+
+pub const DEFAULT_LAYOUT: &str = "{category}/{year}";
+
+/// How finely the default layout buckets media by capture date, for years
+/// too full to browse.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Granularity {
+    /// `{category}/{year}`, as [`DEFAULT_LAYOUT`]
+    #[default]
+    Year,
+    /// `{category}/{year}/{month}`
+    Month,
+    /// `{category}/{year}/{month}/{day}`
+    Day,
+}
+
+impl Granularity {
+    pub fn layout(self) -> Layout {
+        match self {
+            Granularity::Year => DEFAULT_LAYOUT,
+            Granularity::Month => "{category}/{year}/{month}",
+            Granularity::Day => "{category}/{year}/{month}/{day}",
+        }
+        .parse()
+        .expect("valid layout")
+    }
+}
+
+/// Placeholder in a [`Layout`], written as `{name}`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Token {
+    Category,
+    Year,
+    Month,
+    Day,
+    /// Country code of the nearest place, needs a geocoder
+    Country,
+    /// Nearest populated place, needs a geocoder
+    City,
+    /// Camera manufacturer
+    Make,
+    /// Camera model
+    Model,
+    Lens,
+    /// Live photo or burst; files of neither skip the directory
+    Group,
+    /// App the file was saved from, such as `WhatsApp`; files of none skip
+    /// the directory
+    Source,
+    /// Resolution class of the frame, such as `4K` or `1080p`
+    Resolution,
+}
+
+impl Token {
+    const ALL: [(&'static str, Token); 12] = [
+        ("category", Token::Category),
+        ("year", Token::Year),
+        ("month", Token::Month),
+        ("day", Token::Day),
+        ("country", Token::Country),
+        ("city", Token::City),
+        ("make", Token::Make),
+        ("model", Token::Model),
+        ("lens", Token::Lens),
+        ("group", Token::Group),
+        ("source", Token::Source),
+        ("resolution", Token::Resolution),
+    ];
+
+    /// Whether the token is resolved by reverse geocoding.
+    pub fn is_geographic(self) -> bool {
+        matches!(self, Token::Country | Token::City)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Token(Token),
+}
+
+/// Template for the directories media is placed in below the destination,
+/// such as `{category}/{year}/{country}/{city}` or `{category}/{model}/{year}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    template: String,
+    parts: Vec<Part>,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        DEFAULT_LAYOUT.parse().unwrap()
+    }
+}
+
+impl FromStr for Layout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_owned()));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed {{ in layout {}", s))?
+                + start;
+            let name = &rest[start + 1..end];
+            let token = Token::ALL
+                .iter()
+                .find(|(token_name, _)| *token_name == name)
+                .map(|(_, token)| *token)
+                .ok_or_else(|| format!("unknown layout token {{{}}}", name))?;
+            parts.push(Part::Token(token));
+            rest = &rest[end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Literal(rest.to_owned()));
+        }
+        Ok(Self {
+            template: s.to_owned(),
+            parts,
+        })
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.template)
+    }
+}
+
+impl Layout {
+    pub fn uses(&self, predicate: impl Fn(Token) -> bool) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Token(token) if predicate(*token)))
+    }
+
+    /// Directories for `media` captured at `timestamp` near `place`. Values
+    /// missing from the media render as `Unknown`, except a missing group or
+    /// source, which renders as nothing.
+    pub fn render(
+        &self,
+        media: &Media,
+        timestamp: DateTime<FixedOffset>,
+        place: Option<&Place>,
+    ) -> PathBuf {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => rendered.push_str(literal),
+                Part::Token(token) => {
+                    let value = match token {
+                        Token::Category => Some(media.category.to_owned()),
+                        Token::Year => Some(timestamp.format("%Y").to_string()),
+                        Token::Month => Some(timestamp.format("%m").to_string()),
+                        Token::Day => Some(timestamp.format("%d").to_string()),
+                        Token::Country => place.map(|place| place.country.clone()),
+                        Token::City => place.map(|place| place.city.clone()),
+                        Token::Make => media.camera.make.clone(),
+                        Token::Model => media.camera.model.clone(),
+                        Token::Lens => media.camera.lens.clone(),
+                        Token::Group => Some(media.group.clone().unwrap_or_default()),
+                        Token::Source => Some(
+                            media
+                                .source
+                                .map(|source| source.name().to_owned())
+                                .unwrap_or_default(),
+                        ),
+                        Token::Resolution => media.format.resolution().map(str::to_owned),
+                    };
+                    rendered.push_str(&sanitize(value.as_deref().unwrap_or("Unknown")));
+                }
+            }
+        }
+        rendered
+            .split('/')
+            .filter(|component| !component.is_empty())
+            .collect()
+    }
+}
+
+/// Keeps a token value to a single path component.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | '\0' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[test]
+fn test_layout() {
+    let layout = "{category}/{year}/{country}/{city}"
+        .parse::<Layout>()
+        .unwrap();
+    assert!(layout.uses(Token::is_geographic));
+    assert!(!Layout::default().uses(Token::is_geographic));
+    assert!("{category}/{decade}".parse::<Layout>().is_err());
+    assert!("{category".parse::<Layout>().is_err());
+    assert_eq!(
+        "by-year/{year}",
+        "by-year/{year}".parse::<Layout>().unwrap().to_string()
+    );
+    assert_eq!(Layout::default(), Granularity::Year.layout());
+    assert_eq!(
+        "{category}/{year}/{month}/{day}",
+        Granularity::Day.layout().to_string()
+    );
+}
+
+#[test]
+fn test_render_group() {
+    let mut media = Media {
+        mime_type: "video/quicktime".parse().unwrap(),
+        group: Some("IMG_1234".to_owned()),
+        ..Media::test("IMG_1234.MOV", "BrV-IyQTvSXPicvRzKjzjx")
+    };
+    let layout = "{category}/{year}/{group}".parse::<Layout>().unwrap();
+    assert_eq!(
+        PathBuf::from("Photos/2023/IMG_1234"),
+        layout.render(&media, media.timestamp, None)
+    );
+    media.group = None;
+    assert_eq!(
+        PathBuf::from("Photos/2023"),
+        layout.render(&media, media.timestamp, None)
+    );
+}
+
+#[test]
+fn test_render_source() {
+    let mut media = Media {
+        timestamp_source: crate::media::TimestampSource::Filename,
+        source: Some(crate::extractor::MediaSource::WhatsApp),
+        ..Media::test("IMG-20230901-WA0012.jpg", "BrV-IyQTvSXPicvRzKjzjx")
+    };
+    let layout = "{category}/{source}/{year}".parse::<Layout>().unwrap();
+    assert_eq!(
+        PathBuf::from("Photos/WhatsApp/2023"),
+        layout.render(&media, media.timestamp, None)
+    );
+    media.source = None;
+    assert_eq!(
+        PathBuf::from("Photos/2023"),
+        layout.render(&media, media.timestamp, None)
+    );
+}
@@ -0,0 +1,59 @@
+//! Finds duplicate photos and videos, records them in an SQLite index and
+//! organizes them into a dated destination tree.
+//!
+//! The [`Scanner`] records files in a [`database::DB`], the [`Deduper`]
+//! resolves duplicate groups from it, and the [`Organizer`] places media
+//! into the destination tree.
+
+pub mod archive;
+pub mod container;
+#[cfg(unix)]
+pub mod control;
+pub mod csv;
+pub mod database;
+pub mod dedupe;
+pub mod duplicates;
+pub mod error;
+pub mod events;
+pub mod extractor;
+pub mod geo;
+pub mod group;
+pub mod hasher;
+pub mod html;
+pub mod ignore;
+pub mod import;
+pub mod journal;
+pub mod layout;
+pub mod linker;
+pub mod manifest;
+pub mod media;
+pub mod organizer;
+pub mod phash;
+pub mod photoslibrary;
+pub mod plan;
+pub mod platform;
+pub mod quarantine;
+pub mod rclone;
+#[cfg(feature = "s3")]
+pub mod s3;
+pub mod scan;
+pub mod scanner;
+pub mod sidecar;
+pub mod source;
+pub mod space;
+pub mod takeout;
+pub mod throttle;
+pub mod thumbnail;
+pub mod transcoder;
+pub mod trash;
+pub mod undo;
+pub mod videohash;
+pub mod volume;
+pub mod watcher;
+
+pub use dedupe::Deduper;
+pub use error::DeduperError;
+pub use events::EventSink;
+pub use organizer::Organizer;
+pub use scan::ScanBuilder;
+pub use scanner::Scanner;
@@ -0,0 +1,358 @@
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{
+    hasher::{self, FileHash},
+    platform,
+};
+
+/// Numbers the staging files of this process, so two threads placing at
+/// the same name never share one.
+static STAGED: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LinkStrategy {
+    #[default]
+    Symlink,
+    Hardlink,
+    Copy,
+    Move,
+    Reflink,
+}
+
+impl fmt::Display for LinkStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LinkStrategy::Symlink => "symlink",
+            LinkStrategy::Hardlink => "hardlink",
+            LinkStrategy::Copy => "copy",
+            LinkStrategy::Move => "move",
+            LinkStrategy::Reflink => "reflink",
+        })
+    }
+}
+
+/// Places `source` at `destination` using `strategy`. Never overwrites an
+/// existing destination; that case surfaces as `ErrorKind::AlreadyExists`.
+/// Where symlinks are not permitted (Windows without the privilege) the
+/// file is copied instead. Copies keep the permissions, owner and extended
+/// attributes of the source, see [`place_with`].
+///
+/// Copies, including those of moves across filesystems, and reflinks are
+/// staged in a hidden `.part` file next to `destination`, synced to disk
+/// and only then given its name, so an interrupted run never leaves a
+/// truncated file under a name that looks complete.
+pub fn place(strategy: LinkStrategy, source: &Path, destination: &Path) -> io::Result<()> {
+    place_with(strategy, source, destination, true, None)
+}
+
+/// [`place`], giving copies, including those of moves across filesystems
+/// and reflinks, the permissions, owner and extended attributes of the
+/// source only when `preserve` is set. Copies made without take the
+/// defaults of the process, like files it creates. With `expected`, a copy
+/// is hashed before it takes its name and discarded as
+/// `ErrorKind::InvalidData` when it does not match.
+pub fn place_with(
+    strategy: LinkStrategy,
+    source: &Path,
+    destination: &Path,
+    preserve: bool,
+    expected: Option<&FileHash>,
+) -> io::Result<()> {
+    match strategy {
+        LinkStrategy::Symlink => match platform::symlink_file(source, destination) {
+            Err(err) if platform::symlink_not_permitted(&err) => {
+                copy_new(source, destination, preserve, expected)
+            }
+            linked => linked,
+        },
+        LinkStrategy::Hardlink => fs::hard_link(source, destination),
+        LinkStrategy::Copy => copy_new(source, destination, preserve, expected),
+        LinkStrategy::Move => move_file(source, destination, preserve, expected),
+        LinkStrategy::Reflink => reflink(source, destination, preserve),
+    }
+}
+
+/// Points the symlink `link` at `source` instead. The new link is made
+/// next to it and renamed over it, so `link` is never missing.
+pub fn relink(source: &Path, link: &Path) -> io::Result<()> {
+    let mut temp_name = link.file_name().unwrap_or_default().to_owned();
+    temp_name.push(".relink");
+    let temp_path = link.with_file_name(temp_name);
+    platform::symlink_file(source, &temp_path)?;
+    if let Err(err) = fs::rename(&temp_path, link) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// Renames `from` to `to` unless something is at `to` already, which
+/// surfaces as `ErrorKind::AlreadyExists`. The name is refused atomically
+/// by `renameat2` on Linux, else by hard-linking before removing `from`;
+/// only a filesystem with neither, such as exFAT, is checked first and then
+/// renamed. The file is at one of the two names throughout.
+pub fn rename_new(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(renamed) = platform::rename_noreplace(from, to) {
+        return renamed;
+    }
+    match fs::hard_link(from, to) {
+        Ok(()) => return fs::remove_file(from),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => return Err(err),
+        Err(_) => {}
+    }
+    if fs::symlink_metadata(to).is_ok() {
+        return Err(exists(to));
+    }
+    fs::rename(from, to)
+}
+
+/// `name.ext` as `name.n.ext`, the name a file gets when `name.ext` is
+/// taken.
+pub fn numbered(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.file_stem().unwrap_or_default().to_owned();
+    name.push(format!(".{}", n));
+    if let Some(ext) = path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    path.with_file_name(name)
+}
+
+fn exists(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("{} exists", path.to_string_lossy()),
+    )
+}
+
+/// `.name.<pid>-<n>.part` next to `destination`.
+fn part_path(destination: &Path) -> PathBuf {
+    let mut name = std::ffi::OsString::from(".");
+    name.push(destination.file_name().unwrap_or_default());
+    name.push(format!(
+        ".{}-{}.part",
+        std::process::id(),
+        STAGED.fetch_add(1, Ordering::Relaxed)
+    ));
+    destination.with_file_name(name)
+}
+
+/// Stages a new file at a [`part_path`] with `write`, then gives it the
+/// name `destination`. The staging file is removed whatever happens.
+fn stage(destination: &Path, write: impl FnOnce(&Path, &File) -> io::Result<()>) -> io::Result<()> {
+    // spares copying a whole file only to find the name taken
+    if fs::symlink_metadata(destination).is_ok() {
+        return Err(exists(destination));
+    }
+    let part = part_path(destination);
+    let staged = create_new(&part)
+        .and_then(|file| {
+            write(&part, &file)?;
+            file.sync_all()
+        })
+        .and_then(|()| rename_new(&part, destination));
+    let _ = fs::remove_file(&part);
+    staged
+}
+
+fn copy_new(
+    source: &Path,
+    destination: &Path,
+    preserve: bool,
+    expected: Option<&FileHash>,
+) -> io::Result<()> {
+    let mut src = File::open(source)?;
+    stage(destination, |part, mut dst| {
+        io::copy(&mut src, &mut dst)?;
+        if preserve {
+            platform::copy_metadata(&src, dst)?;
+        }
+        let Some(expected) = expected else {
+            return Ok(());
+        };
+        if !hasher::file_hash(part, expected.algorithm)?.matches(&expected.digest) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "the copy of {} does not match its hash",
+                    source.to_string_lossy()
+                ),
+            ));
+        }
+        Ok(())
+    })
+}
+
+fn move_file(
+    source: &Path,
+    destination: &Path,
+    preserve: bool,
+    expected: Option<&FileHash>,
+) -> io::Result<()> {
+    // hard_link refuses to replace an existing destination, unlike rename
+    match fs::hard_link(source, destination) {
+        Ok(()) => fs::remove_file(source),
+        Err(err) if platform::is_cross_device(&err) => {
+            copy_new(source, destination, preserve, expected)?;
+            fs::remove_file(source)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(source: &Path, destination: &Path, preserve: bool) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let src = File::open(source)?;
+    stage(destination, |_, dst| {
+        // SAFETY: both descriptors are open for the duration of the call
+        let ret = unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        if preserve {
+            platform::copy_metadata(&src, dst)?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_source: &Path, _destination: &Path, _preserve: bool) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflink is only supported on Linux",
+    ))
+}
+
+fn create_new(path: &Path) -> io::Result<File> {
+    OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+#[test]
+fn test_place_never_overwrites() {
+    let dir = std::env::temp_dir().join(format!("deduper-linker-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("source.jpg");
+    let destination = dir.join("destination.jpg");
+    fs::write(&source, b"original").unwrap();
+    fs::write(&destination, b"existing").unwrap();
+
+    for strategy in [
+        LinkStrategy::Copy,
+        LinkStrategy::Move,
+        LinkStrategy::Hardlink,
+    ] {
+        let err = place(strategy, &source, &destination).unwrap_err();
+        assert_eq!(io::ErrorKind::AlreadyExists, err.kind());
+    }
+    assert_eq!(b"existing", &fs::read(&destination).unwrap()[..]);
+    assert!(source.exists());
+
+    fs::remove_file(&destination).unwrap();
+    place(LinkStrategy::Move, &source, &destination).unwrap();
+    assert!(!source.exists());
+    assert_eq!(b"original", &fs::read(&destination).unwrap()[..]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_rename_new() {
+    let dir = std::env::temp_dir().join(format!("deduper-rename-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let (a, b, c) = (dir.join("a.jpg"), dir.join("b.jpg"), dir.join("c.jpg"));
+    fs::write(&a, b"a").unwrap();
+    fs::write(&b, b"b").unwrap();
+
+    let err = rename_new(&a, &b).unwrap_err();
+    assert_eq!(io::ErrorKind::AlreadyExists, err.kind());
+    assert_eq!(b"b", &fs::read(&b).unwrap()[..]);
+    rename_new(&a, &c).unwrap();
+    assert!(!a.exists());
+    assert_eq!(b"a", &fs::read(&c).unwrap()[..]);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_copy_is_staged() {
+    let dir = std::env::temp_dir().join(format!("deduper-staged-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("source.jpg");
+    let destination = dir.join("destination.jpg");
+    fs::write(&source, b"original").unwrap();
+
+    let mut wrong = hasher::file_hash(&source, hasher::HashAlgorithm::default()).unwrap();
+    wrong.digest = "0".repeat(wrong.digest.len());
+    let err = place_with(
+        LinkStrategy::Copy,
+        &source,
+        &destination,
+        true,
+        Some(&wrong),
+    )
+    .unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    assert!(!destination.exists());
+
+    let hash = hasher::file_hash(&source, hasher::HashAlgorithm::default()).unwrap();
+    place_with(LinkStrategy::Copy, &source, &destination, true, Some(&hash)).unwrap();
+    assert_eq!(b"original", &fs::read(&destination).unwrap()[..]);
+    // no staging file is left behind
+    assert_eq!(2, fs::read_dir(&dir).unwrap().count());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_copy_preserves_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = std::env::temp_dir().join(format!("deduper-preserve-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("source.jpg");
+    fs::write(&source, b"original").unwrap();
+    // a mode no usual umask leaves, so only a copied one matches
+    fs::set_permissions(&source, fs::Permissions::from_mode(0o604)).unwrap();
+
+    let preserved = dir.join("preserved.jpg");
+    place(LinkStrategy::Copy, &source, &preserved).unwrap();
+    let mode = fs::metadata(&preserved).unwrap().permissions().mode();
+    assert_eq!(0o604, mode & 0o777);
+
+    let plain = dir.join("plain.jpg");
+    place_with(LinkStrategy::Copy, &source, &plain, false, None).unwrap();
+    let mode = fs::metadata(&plain).unwrap().permissions().mode();
+    assert_ne!(0o604, mode & 0o777);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_relink() {
+    let dir = std::env::temp_dir().join(format!("deduper-relink-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("moved.jpg");
+    let link = dir.join("link.jpg");
+    fs::write(&source, b"original").unwrap();
+    platform::symlink_file(&dir.join("gone.jpg"), &link).unwrap();
+    assert!(!link.exists());
+
+    relink(&source, &link).unwrap();
+    assert_eq!(source, fs::read_link(&link).unwrap());
+    assert_eq!(vec![link.clone(), source.clone()], {
+        let mut entries = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        entries.sort();
+        entries
+    });
+    fs::remove_dir_all(&dir).unwrap();
+}
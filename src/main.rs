@@ -1,112 +1,135 @@
-mod csv;
-mod extractor;
-mod hasher;
+mod commands;
 
-use std::{fs::create_dir_all, os::unix::fs::symlink, path::PathBuf};
+use std::process::ExitCode;
 
-use chrono::Datelike;
-use clap::Parser;
-use mime_guess::mime;
-use walkdir::WalkDir;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+use tracing::{error, info_span};
 
-use rayon::prelude::*;
+#[cfg(unix)]
+use commands::daemon;
+#[cfg(feature = "web")]
+use commands::serve;
+use commands::{
+    completions, conflicts, db, dedupe, duplicates, errors, export, gallery, history, import,
+    logging, organize, progress::OutputArgs, quarantine, relayout, repair, report, scan, transcode,
+    undo, verify, watch,
+};
 
-fn main() {
-    let cli = Cli::parse();
-    println!(
-        "sources: \n\t{}",
-        cli.sources
-            .iter()
-            .map(|s| s.to_string_lossy())
-            .collect::<Vec<_>>()
-            .join("\n\t")
-    );
-    println!("destination: {}", cli.destination.to_string_lossy());
-    cli.sources.par_iter().for_each(|source| {
-        for entry in WalkDir::new(source)
-            .into_iter()
-            .filter_map(|e| e.ok())
-            .filter(|e| e.metadata().ok().map(|e| e.is_file()).unwrap_or_default())
-        {
-            let mime_type = extractor::extract_mimetype(entry.path());
-
-            let (timestamp, category) = match mime_type.type_() {
-                mime::IMAGE => (extractor::extract_image_timestamp(entry.path()), "Photos"),
-                mime::VIDEO => (extractor::extract_video_timestamp(entry.path()), "Videos"),
-                other => {
-                    println!(
-                        "'{}' not supported: {}",
-                        other,
-                        entry.path().to_string_lossy()
-                    );
-                    continue;
-                }
-            };
-
-            let timestamp = match timestamp {
-                Some(timestamp) => timestamp,
-                None => {
-                    println!(
-                        "using filesystem timestamp for {}",
-                        entry.path().to_string_lossy()
-                    );
-                    match extractor::extract_filesystem_timestamp(entry.path()) {
-                        Some(timestamp) => timestamp,
-                        None => {
-                            println!(
-                                "failed to get timestamp for {}",
-                                entry.path().to_string_lossy()
-                            );
-                            continue;
-                        }
-                    }
-                }
-            };
-
-            let Some(hash) = hasher::file_hash(entry.path()) else {
-                println!(
-                    "failed to get file hash for {}",
-                    entry.path().to_string_lossy()
-                );
-                continue;
-            };
-
-            let ext = entry
-                .path()
-                .extension()
-                .and_then(|ext| ext.to_str())
-                .unwrap_or_default();
-
-            let dest_dir_path = cli
-                .destination
-                .join(category)
-                .join(timestamp.year().to_string());
-            let dest_path = dest_dir_path.join(format!(
-                "{}_{}.{}",
-                timestamp.format("%F_%X").to_string(),
-                hash,
-                ext
-            ));
-            if create_dir_all(&dest_dir_path).is_err() {
-                println!(
-                    "failed to create directory {} for {}",
-                    dest_dir_path.to_string_lossy(),
-                    entry.path().to_string_lossy(),
-                );
-            };
-            if let Err(_) = symlink(entry.path(), dest_path) {
-                println!("link already exists for {}", entry.path().to_string_lossy());
-                continue;
-            };
+fn main() -> ExitCode {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    logging::init(&cli.output);
+    // every event of the run is logged within the span of its command
+    let _command = info_span!(
+        "command",
+        name = matches.subcommand_name().unwrap_or_default()
+    )
+    .entered();
+    let summary = match &cli.command {
+        Command::Scan(args) => scan::run(args, &cli.output),
+        Command::Organize(args) => organize::run(args, &cli.output),
+        Command::Dedupe(args) => dedupe::run(args),
+        Command::Duplicates(args) => duplicates::run(args, &cli.output),
+        Command::Transcode(args) => transcode::run(args, &cli.output),
+        Command::Verify(args) => verify::run(args, &cli.output),
+        Command::Report(args) => report::run(args),
+        Command::History(args) => history::run(args),
+        Command::Repair(args) => repair::run(args, &cli.output),
+        Command::Relayout(args) => relayout::run(args, &cli.output),
+        Command::Undo(args) => undo::run(args),
+        Command::Quarantine(args) => quarantine::run(args),
+        Command::Export(args) => export::export(args),
+        Command::ImportCsv(args) => export::import(args),
+        Command::Import(args) => import::run(args, &cli.output),
+        Command::Db(args) => db::run(args),
+        Command::Gallery(args) => gallery::run(args, &cli.output),
+        Command::Errors(args) => errors::run(args, &cli.output),
+        Command::Conflicts(args) => conflicts::run(args),
+        Command::Watch(args) => watch::run(args),
+        Command::Completions(args) => completions::completions(args, Cli::command()),
+        Command::Man(args) => completions::man(args, Cli::command()),
+        #[cfg(unix)]
+        Command::Daemon(args) => daemon::run(args),
+        #[cfg(unix)]
+        Command::Ctl(args) => daemon::ctl(args),
+        #[cfg(feature = "web")]
+        Command::Serve(args) => serve::run(args),
+    };
+    if let Some(path) = &cli.output.summary_json {
+        if let Err(err) = summary.write(path) {
+            error!(
+                "failed to write summary to {}: {}",
+                path.to_string_lossy(),
+                err
+            );
         }
-    })
+    }
+    summary.status.into()
 }
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Cli {
-    #[arg(short, long, value_hint = clap::ValueHint::DirPath, num_args = 1.., required = true)]
-    sources: Vec<PathBuf>,
-    #[arg(short, long, value_hint = clap::ValueHint::DirPath, required = true)]
-    destination: PathBuf,
+    #[command(flatten)]
+    output: OutputArgs,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Record hash, timestamp and media type of every source file in the database
+    Scan(scan::ScanArgs),
+    /// Place media from the sources into a dated destination tree
+    Organize(organize::OrganizeArgs),
+    /// Mark the original of every group of identical files in the database
+    Dedupe(dedupe::DedupeArgs),
+    /// List identical files of the sources, hashing only files that share a size
+    Duplicates(duplicates::DuplicatesArgs),
+    /// Re-encode original videos recorded in the database to AV1
+    Transcode(transcode::TranscodeArgs),
+    /// Re-hash recorded files and report any that changed or disappeared
+    Verify(verify::VerifyArgs),
+    /// Print file and duplicate statistics from the database
+    Report(report::ReportArgs),
+    /// List past scans, or what happened to files since one
+    History(history::HistoryArgs),
+    /// Point dangling symlinks in the destination at where their sources moved
+    Repair(repair::RepairArgs),
+    /// Move placed files to where a new layout puts them, from the database
+    Relayout(relayout::RelayoutArgs),
+    /// Roll back what a dedupe, organize or transcode run did to files
+    Undo(undo::UndoArgs),
+    /// Delete the duplicates `dedupe --quarantine` moved aside once reviewed
+    Quarantine(quarantine::QuarantineArgs),
+    /// Write the files table as CSV, JSON or NDJSON
+    #[command(alias = "export-csv")]
+    Export(export::ExportArgs),
+    /// Load files from CSV written by export, replacing rows with the same path
+    ImportCsv(export::ImportCsvArgs),
+    /// Record the duplicates found by rmlint, fdupes or jdupes without hashing each file
+    Import(import::ImportArgs),
+    /// Maintain the database, e.g. merge in the files of another machine
+    Db(db::DbArgs),
+    /// Write a static HTML timeline of the placed media, by year and month
+    Gallery(gallery::GalleryArgs),
+    /// List the files scans failed on, or scan them again
+    Errors(errors::ErrorsArgs),
+    /// List files placed under another name because theirs held other contents
+    Conflicts(conflicts::ConflictsArgs),
+    /// Organize new media as it appears in the sources
+    Watch(watch::WatchArgs),
+    /// Print the completion script for bash, zsh, fish, elvish or PowerShell
+    Completions(completions::CompletionsArgs),
+    /// Write man pages of deduper and every subcommand
+    Man(completions::ManArgs),
+    /// Watch the sources in the background, controlled over a Unix socket
+    #[cfg(unix)]
+    Daemon(daemon::DaemonArgs),
+    /// Pause, resume, query or trigger a scan in a running daemon
+    #[cfg(unix)]
+    Ctl(daemon::CtlArgs),
+    /// Browse duplicate groups and statistics and record decisions in a browser
+    #[cfg(feature = "web")]
+    Serve(serve::ServeArgs),
 }
@@ -0,0 +1,98 @@
+//! Checksum manifests in the format `sha256sum` and `b3sum` write, so a
+//! copy of the destination can be checked with `sha256sum -c` or
+//! `b3sum -c` on a machine without deduper.
+
+use std::path::{Component, Path};
+
+use base64ct::{Base64UrlUnpadded, Encoding};
+
+use crate::{hasher::HashAlgorithm, platform};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ManifestAlgorithm {
+    /// SHA256SUMS, checked with `sha256sum -c`
+    Sha256,
+    /// BLAKE3SUMS, checked with `b3sum -c`
+    Blake3,
+}
+
+impl ManifestAlgorithm {
+    pub fn hash_algorithm(self) -> HashAlgorithm {
+        match self {
+            ManifestAlgorithm::Sha256 => HashAlgorithm::Sha256,
+            ManifestAlgorithm::Blake3 => HashAlgorithm::Blake3,
+        }
+    }
+
+    /// The name manifests of this algorithm go by.
+    pub fn file_name(self) -> &'static str {
+        match self {
+            ManifestAlgorithm::Sha256 => "SHA256SUMS",
+            ManifestAlgorithm::Blake3 => "BLAKE3SUMS",
+        }
+    }
+}
+
+/// The lowercase hex a checksum tool prints for a recorded digest. `None`
+/// for digests that are not a full 32-byte hash.
+pub fn hex_digest(digest: &str) -> Option<String> {
+    let bytes = Base64UrlUnpadded::decode_vec(digest).ok()?;
+    if bytes.len() != 32 {
+        return None;
+    }
+    Some(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// The manifest line of `path`, relative to the directory the manifest is
+/// checked from, with `/` between components. Like the checksum tools, a
+/// name with a backslash or newline is escaped and the line starts with a
+/// backslash.
+pub fn line(hex: &str, path: &Path) -> Vec<u8> {
+    let mut name = Vec::new();
+    for component in path.components() {
+        if let Component::Normal(component) = component {
+            if !name.is_empty() {
+                name.push(b'/');
+            }
+            name.extend_from_slice(&platform::os_bytes(component));
+        }
+    }
+    let escaped = name.contains(&b'\\') || name.contains(&b'\n');
+    let mut line = Vec::with_capacity(hex.len() + name.len() + 4);
+    if escaped {
+        line.push(b'\\');
+    }
+    line.extend_from_slice(hex.as_bytes());
+    line.extend_from_slice(b"  ");
+    for byte in name {
+        match byte {
+            b'\\' if escaped => line.extend_from_slice(b"\\\\"),
+            b'\n' => line.extend_from_slice(b"\\n"),
+            byte => line.push(byte),
+        }
+    }
+    line.push(b'\n');
+    line
+}
+
+#[test]
+fn test_line() {
+    let hex = "ab".repeat(32);
+    assert_eq!(
+        format!("{}  Photos/2023/a b.jpg\n", hex).into_bytes(),
+        line(&hex, Path::new("Photos/2023/a b.jpg"))
+    );
+    assert_eq!(
+        format!("\\{}  Photos/a\\\\b\\nc.jpg\n", hex).into_bytes(),
+        line(&hex, Path::new("Photos/a\\b\nc.jpg"))
+    );
+}
+
+#[test]
+fn test_hex_digest() {
+    let digest = crate::hasher::encode_digest(&[0xAB; 32]);
+    assert_eq!(Some("ab".repeat(32)), hex_digest(&digest));
+    // an xxh3 digest is too short for either tool
+    assert_eq!(None, hex_digest(&crate::hasher::encode_digest(&[1; 16])));
+    assert_eq!(None, hex_digest("not base64!"));
+}
@@ -0,0 +1,581 @@
+use std::{
+    fs::{self, Metadata},
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{DateTime, FixedOffset, TimeZone};
+use mime_guess::{mime, Mime};
+use walkdir::{DirEntry, WalkDir};
+
+use crate::{
+    database::File,
+    error::{DeduperError, Result},
+    extractor::{self, Camera, Format, MediaSource},
+    geo::Location,
+    group,
+    hasher::{self, FileHash, HashAlgorithm, ReadBackend},
+    ignore,
+    photoslibrary::PhotosLibrary,
+    platform, takeout,
+};
+
+/// Kinds of files, each placed in a top-level directory of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Category {
+    /// Images other than RAW files
+    Photos,
+    /// Camera RAW files
+    Raw,
+    Videos,
+    Audio,
+    /// PDFs, office documents and archives, dated by their mtime
+    Documents,
+}
+
+impl Category {
+    /// What is inspected unless told otherwise; documents are left alone
+    /// unless asked for.
+    pub const DEFAULT: [Category; 4] = [
+        Category::Photos,
+        Category::Raw,
+        Category::Videos,
+        Category::Audio,
+    ];
+
+    pub fn of(mime_type: &Mime) -> Option<Self> {
+        match mime_type.type_() {
+            mime::IMAGE if extractor::is_raw(mime_type) => Some(Category::Raw),
+            mime::IMAGE => Some(Category::Photos),
+            mime::VIDEO => Some(Category::Videos),
+            mime::AUDIO => Some(Category::Audio),
+            _ if extractor::is_document(mime_type) => Some(Category::Documents),
+            _ => None,
+        }
+    }
+
+    /// The top-level destination directory.
+    pub fn name(self) -> &'static str {
+        match self {
+            Category::Photos => "Photos",
+            Category::Raw => "Raw",
+            Category::Videos => "Videos",
+            Category::Audio => "Audio",
+            Category::Documents => "Documents",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampSource {
+    /// The database of an Apple Photos library, see [`crate::photoslibrary`]
+    Library,
+    /// The JSON file of a Google Takeout export, see [`takeout`]
+    Takeout,
+    /// XMP sidecar or embedded XMP packet
+    Xmp,
+    /// EXIF or container metadata
+    Metadata,
+    /// Date and time embedded in the file name
+    Filename,
+    /// Filesystem modification time
+    Filesystem,
+    /// The `files` row of an earlier scan
+    Recorded,
+}
+
+#[derive(Debug, Clone)]
+pub struct Media {
+    pub path: PathBuf,
+    pub mime_type: Mime,
+    /// Top-level destination directory, see [`Category::name`]
+    pub category: &'static str,
+    /// Capture time at the UTC offset it was recorded with, or the local
+    /// offset when the source has none
+    pub timestamp: DateTime<FixedOffset>,
+    pub timestamp_source: TimestampSource,
+    pub hash: FileHash,
+    /// Size in bytes when the file was hashed
+    pub size: u64,
+    /// Where the media was captured, from EXIF GPS or container metadata
+    pub location: Option<Location>,
+    pub camera: Camera,
+    /// The live photo or burst the file belongs to, see [`group::group_of`]
+    pub group: Option<String>,
+    /// The app the file was saved from, by its name
+    pub source: Option<MediaSource>,
+    /// Frame size, and length and codec of videos
+    pub format: Format,
+}
+
+impl Media {
+    /// The media a row of `files` describes, so it can be placed without
+    /// reading the file again. Only the names next to a video are looked at,
+    /// to date the motion half of a live photo with its still; the group is
+    /// left out, see [`group::group_of`].
+    pub fn recorded(file: &File) -> Result<Self> {
+        let path = file.path.clone();
+        let mime_type = file
+            .media_type
+            .parse::<Mime>()
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+        let described_type = match mime_type.type_() {
+            mime::VIDEO => group::live_photo_still(&path)
+                .map(|still| extractor::extract_mimetype(&still))
+                .unwrap_or_else(|| mime_type.clone()),
+            _ => mime_type.clone(),
+        };
+        let category = Category::of(&described_type)
+            .ok_or_else(|| DeduperError::UnsupportedMedia(mime_type.clone()))?;
+        let timestamp = FixedOffset::east_opt(file.utc_offset)
+            .and_then(|offset| offset.timestamp_opt(file.created_at, 0).single())
+            .ok_or(DeduperError::TimestampMissing)?;
+        Ok(Media {
+            mime_type,
+            category: category.name(),
+            timestamp,
+            timestamp_source: TimestampSource::Recorded,
+            hash: FileHash {
+                algorithm: file.hash_algorithm.parse().unwrap_or_default(),
+                digest: file.hash.clone(),
+            },
+            size: file.size,
+            location: file
+                .latitude
+                .zip(file.longitude)
+                .map(|(latitude, longitude)| Location {
+                    latitude,
+                    longitude,
+                }),
+            camera: Camera {
+                make: file.camera_make.clone(),
+                model: file.camera_model.clone(),
+                lens: file.lens_model.clone(),
+            },
+            group: None,
+            source: file
+                .source
+                .as_deref()
+                .and_then(|source| source.parse().ok()),
+            format: Format {
+                width: file.width,
+                height: file.height,
+                duration: file.duration,
+                codec: file.codec.clone(),
+            },
+            path,
+        })
+    }
+}
+
+#[cfg(test)]
+impl Media {
+    /// A JPEG photo at `path` with the digest `hash`, captured at
+    /// 2023-09-01T22:49:41+02:00 by its metadata, with nothing else known;
+    /// tests set what they need on top with struct update syntax.
+    pub(crate) fn test(path: impl Into<PathBuf>, hash: &str) -> Self {
+        Self {
+            path: path.into(),
+            mime_type: mime::IMAGE_JPEG,
+            category: Category::Photos.name(),
+            timestamp: DateTime::parse_from_rfc3339("2023-09-01T22:49:41+02:00").unwrap(),
+            timestamp_source: TimestampSource::Metadata,
+            hash: FileHash {
+                algorithm: HashAlgorithm::Blake3,
+                digest: hash.to_owned(),
+            },
+            size: 1,
+            location: None,
+            camera: Camera::default(),
+            group: None,
+            source: None,
+            format: Format::default(),
+        }
+    }
+}
+
+/// What [`Inspector`] reads from a file besides its type and hash.
+struct Described {
+    category: &'static str,
+    timestamp: DateTime<FixedOffset>,
+    timestamp_source: TimestampSource,
+    location: Option<Location>,
+    camera: Camera,
+}
+
+/// Extracts everything needed to place or record a media file: its type,
+/// capture time and content hash. The capture time comes from XMP, which
+/// holds corrections made in photo editors, then the name WhatsApp gave a
+/// file, then the EXIF or container metadata, then the file name, then the
+/// filesystem mtime. Only when all of them fail is the media reported as
+/// [`DeduperError::TimestampMissing`], which organizing handles by placing
+/// it with the unknown files. With
+/// [`Inspector::takeout`], the JSON file of a Google Takeout export comes
+/// before all of them, and the database of an Apple Photos library given
+/// with [`Inspector::photos_library`] before that.
+#[derive(Debug, Clone)]
+pub struct Inspector {
+    algorithm: HashAlgorithm,
+    read_backend: ReadBackend,
+    filename_patterns: Vec<String>,
+    categories: Vec<Category>,
+    takeout: bool,
+    photos_library: Arc<PhotosLibrary>,
+}
+
+impl Default for Inspector {
+    fn default() -> Self {
+        Self::new(HashAlgorithm::default())
+    }
+}
+
+impl Inspector {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        Self {
+            algorithm,
+            read_backend: ReadBackend::default(),
+            filename_patterns: extractor::FILENAME_PATTERNS
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect(),
+            categories: Category::DEFAULT.to_vec(),
+            takeout: false,
+            photos_library: Arc::default(),
+        }
+    }
+
+    /// The kinds of files inspected, [`Category::DEFAULT`] unless given;
+    /// others are reported as [`DeduperError::UnsupportedMedia`].
+    pub fn categories(mut self, categories: Vec<Category>) -> Self {
+        self.categories = categories;
+        self
+    }
+
+    /// chrono formats searched for in file names, replacing
+    /// [`extractor::FILENAME_PATTERNS`].
+    pub fn filename_patterns(mut self, patterns: Vec<String>) -> Self {
+        self.filename_patterns = patterns;
+        self
+    }
+
+    /// Whether the capture time and place Google Takeout exported next to
+    /// a file are read, see [`takeout`]. They are preferred over everything
+    /// in the file, which Google Photos often stripped.
+    pub fn takeout(mut self, takeout: bool) -> Self {
+        self.takeout = takeout;
+        self
+    }
+
+    /// Date and locate the originals of Apple Photos libraries by what
+    /// `library` read from their databases.
+    pub fn photos_library(mut self, library: Arc<PhotosLibrary>) -> Self {
+        self.photos_library = library;
+        self
+    }
+
+    /// How files are read to hash them.
+    pub fn read_backend(mut self, backend: ReadBackend) -> Self {
+        self.read_backend = backend;
+        self
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    /// The step of [`Inspector::inspect`] that failed with `err`, as
+    /// recorded with the failure: `mime` for a file of no kind inspected,
+    /// `metadata` for one that could not be dated, `hash` for one that
+    /// could not be read and `record` when storing what was read failed.
+    pub fn stage_of(err: &DeduperError) -> &'static str {
+        match err {
+            DeduperError::UnsupportedMedia(_) => "mime",
+            DeduperError::Exif(_) | DeduperError::TimestampMissing => "metadata",
+            #[cfg(feature = "ffmpeg")]
+            DeduperError::Ffmpeg(_) => "metadata",
+            DeduperError::Db(_) => "record",
+            _ => "hash",
+        }
+    }
+
+    /// The hash of the contents of `path` alone.
+    pub fn hash(&self, path: &Path) -> io::Result<FileHash> {
+        hasher::file_hash_with(path, self.algorithm, self.read_backend)
+    }
+
+    pub fn inspect(&self, path: &Path) -> Result<Media> {
+        self.inspect_with(path, None)
+    }
+
+    /// Like [`Inspector::inspect`] for a file whose hash is already known,
+    /// e.g. from another deduplication tool, so it is not read in full.
+    pub fn inspect_hashed(&self, path: &Path, hash: FileHash) -> Result<Media> {
+        self.inspect_with(path, Some(hash))
+    }
+
+    /// The motion half of a live photo is described by its still, so the
+    /// pair is placed side by side.
+    fn inspect_with(&self, path: &Path, hash: Option<FileHash>) -> Result<Media> {
+        let mime_type = extractor::extract_mimetype(path);
+        let still = match mime_type.type_() {
+            mime::VIDEO => group::live_photo_still(path),
+            _ => None,
+        };
+        let described = still.and_then(|still| {
+            self.describe(&still, &extractor::extract_mimetype(&still))
+                .ok()
+        });
+        let described = match described {
+            Some(described) => described,
+            None => self.describe(path, &mime_type)?,
+        };
+        let size = fs::metadata(path)?.len();
+        let hash = match hash {
+            Some(hash) => hash,
+            None => self.hash(path)?,
+        };
+
+        Ok(Media {
+            path: path.to_owned(),
+            group: group::group_of(path, &mime_type),
+            source: MediaSource::of(path),
+            format: extractor::extract_format(path, &mime_type),
+            mime_type,
+            category: described.category,
+            timestamp: described.timestamp,
+            timestamp_source: described.timestamp_source,
+            hash,
+            size,
+            location: described.location,
+            camera: described.camera,
+        })
+    }
+
+    fn describe(&self, path: &Path, mime_type: &Mime) -> Result<Described> {
+        let category = Category::of(mime_type)
+            .filter(|category| self.categories.contains(category))
+            .ok_or_else(|| DeduperError::UnsupportedMedia(mime_type.clone()))?;
+        let extract_metadata_timestamp: fn(&Path) -> _ = match category {
+            Category::Photos | Category::Raw => extractor::extract_image_timestamp,
+            Category::Videos => extractor::extract_video_timestamp,
+            Category::Audio => extractor::extract_audio_timestamp,
+            Category::Documents => {
+                return Ok(Described {
+                    category: category.name(),
+                    timestamp: extractor::extract_filesystem_timestamp(path)?,
+                    timestamp_source: TimestampSource::Filesystem,
+                    location: None,
+                    camera: Camera::default(),
+                });
+            }
+        };
+
+        let takeout = self.takeout.then(|| takeout::metadata(path)).flatten();
+        let asset = self.photos_library.asset(path);
+        let (timestamp, timestamp_source) = asset
+            .and_then(|asset| asset.timestamp)
+            .map(|timestamp| (timestamp, TimestampSource::Library))
+            .or_else(|| {
+                takeout
+                    .as_ref()
+                    .and_then(|takeout| takeout.timestamp)
+                    .map(|timestamp| (timestamp, TimestampSource::Takeout))
+            })
+            .ok_or(DeduperError::TimestampMissing)
+            .or_else(|_| {
+                extractor::extract_xmp_timestamp(path)
+                    .map(|timestamp| (timestamp, TimestampSource::Xmp))
+            })
+            // WhatsApp strips EXIF, and the container dates of its videos
+            // are when it re-encoded them
+            .or_else(|_| {
+                extractor::extract_whatsapp_timestamp(path)
+                    .map(|timestamp| (timestamp, TimestampSource::Filename))
+            })
+            .or_else(|_| {
+                extract_metadata_timestamp(path)
+                    .map(|timestamp| (timestamp, TimestampSource::Metadata))
+            })
+            .or_else(|_| {
+                extractor::extract_filename_timestamp(path, &self.filename_patterns)
+                    .map(|timestamp| (timestamp, TimestampSource::Filename))
+            })
+            .or_else(|_| {
+                extractor::extract_filesystem_timestamp(path)
+                    .map(|timestamp| (timestamp, TimestampSource::Filesystem))
+            })?;
+
+        let (location, camera) = match category {
+            Category::Videos => (
+                extractor::extract_video_location(path),
+                extractor::extract_video_camera(path),
+            ),
+            Category::Audio => (None, Camera::default()),
+            _ => (
+                extractor::extract_image_location(path),
+                extractor::extract_image_camera(path),
+            ),
+        };
+        let location = asset
+            .and_then(|asset| asset.location)
+            .or(takeout.and_then(|takeout| takeout.location))
+            .or(location);
+        Ok(Described {
+            category: category.name(),
+            timestamp,
+            timestamp_source,
+            location,
+            camera,
+        })
+    }
+}
+
+/// How sources are walked.
+#[derive(Debug, Default, Clone)]
+pub struct WalkOptions {
+    /// Descend into symlinked directories and yield symlinked files.
+    /// Symlink loops are reported as errors rather than followed.
+    pub follow_symlinks: bool,
+    /// Directories that are never entered, such as a destination tree that
+    /// lies inside a source.
+    pub exclude: Vec<PathBuf>,
+    /// Leave out what `.deduperignore` files in the sources exclude, and
+    /// what these rules exclude below every source; see [`ignore`].
+    pub ignore: Option<ignore::Rules>,
+}
+
+/// Every regular file below `sources`, in walk order. Entries that cannot be
+/// read, and symlink loops, are yielded as errors.
+pub fn walk_files<'a>(
+    sources: &'a [PathBuf],
+    options: &'a WalkOptions,
+) -> impl Iterator<Item = walkdir::Result<PathBuf>> + Send + 'a {
+    let excluded = options
+        .exclude
+        .iter()
+        .filter_map(|dir| Some((dir.clone(), fs::metadata(dir).ok()?)))
+        .collect::<Vec<_>>();
+    sources
+        .iter()
+        .flat_map(move |source| {
+            let excluded = excluded.clone();
+            let mut ignore = options
+                .ignore
+                .clone()
+                .map(|global| ignore::Stack::new(source, global));
+            WalkDir::new(source)
+                .follow_links(options.follow_symlinks)
+                .into_iter()
+                .filter_entry(move |entry| {
+                    !is_excluded(entry, &excluded)
+                        && !ignore.as_mut().is_some_and(|ignore| {
+                            ignore.is_ignored(entry.path(), entry.file_type().is_dir())
+                        })
+                })
+        })
+        .filter_map(|entry| match entry {
+            Ok(entry) if entry.file_type().is_file() => Some(Ok(entry.into_path())),
+            Ok(_) => None,
+            Err(err) => Some(Err(err)),
+        })
+}
+
+/// Matches excluded directories by path and by identity, so another
+/// spelling of the same directory is caught too.
+fn is_excluded(entry: &DirEntry, excluded: &[(PathBuf, Metadata)]) -> bool {
+    entry.file_type().is_dir()
+        && excluded.iter().any(|(dir, dir_metadata)| {
+            entry.path().starts_with(dir)
+                || entry
+                    .metadata()
+                    .is_ok_and(|metadata| platform::same_file(&metadata, dir_metadata))
+        })
+}
+
+#[cfg(unix)]
+#[test]
+fn test_walk_files() {
+    let dir = std::env::temp_dir().join(format!("deduper-walk-{}", std::process::id()));
+    fs::create_dir_all(dir.join("photos")).unwrap();
+    fs::create_dir_all(dir.join("organized")).unwrap();
+    fs::write(dir.join("photos/a.jpg"), b"a").unwrap();
+    fs::write(dir.join("organized/b.jpg"), b"b").unwrap();
+    std::os::unix::fs::symlink(&dir, dir.join("photos/loop")).unwrap();
+
+    let walk = |options: &WalkOptions| {
+        let (files, errors): (Vec<_>, Vec<_>) =
+            walk_files(std::slice::from_ref(&dir), options).partition(|entry| entry.is_ok());
+        (files.len(), errors.len())
+    };
+    assert_eq!((2, 0), walk(&WalkOptions::default()));
+    let options = WalkOptions {
+        follow_symlinks: true,
+        exclude: vec![dir.join("organized")],
+        ..WalkOptions::default()
+    };
+    assert_eq!((1, 1), walk(&options));
+    fs::write(dir.join(ignore::FILE_NAME), "organized/\n").unwrap();
+    let options = WalkOptions {
+        ignore: Some(ignore::Rules::default()),
+        ..WalkOptions::default()
+    };
+    assert_eq!((1, 0), walk(&options));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_documents() {
+    let path = std::env::temp_dir().join(format!("deduper-document-{}.pdf", std::process::id()));
+    fs::write(&path, b"%PDF-1.4").unwrap();
+    let inspector = Inspector::new(HashAlgorithm::Sha256);
+    let err = inspector.inspect(&path).unwrap_err();
+    assert!(matches!(err, DeduperError::UnsupportedMedia(_)));
+    assert_eq!("mime", Inspector::stage_of(&err));
+    let gone = path.with_file_name("deduper-gone.jpg");
+    assert_eq!(
+        "hash",
+        Inspector::stage_of(&inspector.inspect(&gone).unwrap_err())
+    );
+    let media = inspector
+        .categories(vec![Category::Documents])
+        .inspect(&path)
+        .unwrap();
+    fs::remove_file(&path).unwrap();
+    assert_eq!("Documents", media.category);
+    assert_eq!(TimestampSource::Filesystem, media.timestamp_source);
+}
+
+#[test]
+fn test_recorded() {
+    let file = File {
+        hash_algorithm: "sha256".to_owned(),
+        size: 1024,
+        created_at: 1693601381,
+        modified_at: 1693601381,
+        original: true,
+        utc_offset: 7200,
+        latitude: Some(48.85),
+        longitude: Some(2.35),
+        camera_make: Some("Apple".to_owned()),
+        camera_model: Some("iPhone 12".to_owned()),
+        ..File::test(
+            "/photos/IMG_1.JPG",
+            "BrV-IyQTvSXPicvRzKjzjxUy8mbJSaYjcHRXLd_ssbA",
+        )
+    };
+    let media = Media::recorded(&file).unwrap();
+    assert_eq!("Photos", media.category);
+    assert_eq!("2023-09-01T22:49:41+02:00", media.timestamp.to_rfc3339());
+    assert_eq!(HashAlgorithm::Sha256, media.hash.algorithm);
+    assert_eq!(
+        Some(2.35),
+        media.location.map(|location| location.longitude)
+    );
+
+    let unsupported = File {
+        media_type: "text/plain".to_owned(),
+        ..file
+    };
+    assert!(Media::recorded(&unsupported).is_err());
+}
@@ -0,0 +1,924 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, create_dir_all},
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use chrono::{DateTime, FixedOffset, Local};
+use filetime::FileTime;
+use mime_guess::Mime;
+
+use crate::{
+    error::Result,
+    events::{EventSink, Events},
+    geo::Geocoder,
+    hasher::{self, FileHash, SHORT_DIGEST_LEN},
+    layout::Layout,
+    linker::{self, numbered, LinkStrategy},
+    media::{Category, Media},
+    platform, rclone,
+    sidecar::{self, Sidecars},
+    space::{self, Budget},
+};
+
+/// Timezone capture times are shown in when bucketing and naming files.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Timezone {
+    /// The offset the file was captured at
+    #[default]
+    Capture,
+    /// The timezone of this machine
+    Local,
+    Fixed(FixedOffset),
+}
+
+impl Timezone {
+    pub fn convert(self, timestamp: DateTime<FixedOffset>) -> DateTime<FixedOffset> {
+        match self {
+            Timezone::Capture => timestamp,
+            Timezone::Local => timestamp.with_timezone(&Local).fixed_offset(),
+            Timezone::Fixed(offset) => timestamp.with_timezone(&offset),
+        }
+    }
+}
+
+impl FromStr for Timezone {
+    type Err = String;
+
+    /// `capture`, `local`, `utc` or an offset such as `+05:30`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "capture" => Ok(Timezone::Capture),
+            "local" => Ok(Timezone::Local),
+            "utc" => Ok(Timezone::Fixed(FixedOffset::east_opt(0).unwrap())),
+            _ => s.parse().map(Timezone::Fixed).map_err(|_| {
+                format!(
+                    "expected capture, local, utc or an offset like +05:30, got {}",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+/// Where [`Organizer::place`] put a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    /// The name the file went to, or that held it or its contents already
+    pub path: PathBuf,
+    /// Whether `path` held the file before, so nothing was placed
+    pub existed: bool,
+    /// The name the layout gave the file, when another file with other
+    /// contents held it and `path` is a numbered one instead
+    pub taken: Option<PathBuf>,
+}
+
+/// Where [`Organizer::relocate`] moved a placed file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    pub path: PathBuf,
+    /// Each sidecar moved along, from its old to its new name
+    pub sidecars: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Places media into a destination tree laid out by a [`Layout`],
+/// `<category>/<year>/` by default, named after the capture time and
+/// content hash.
+#[derive(Debug, Clone)]
+pub struct Organizer {
+    destination: PathBuf,
+    /// Roots of the categories placed elsewhere, by [`Category::name`]
+    category_destinations: BTreeMap<&'static str, PathBuf>,
+    strategy: LinkStrategy,
+    timezone: Timezone,
+    layout: Layout,
+    geocoder: Option<Arc<Geocoder>>,
+    unknown_dir: Option<PathBuf>,
+    sidecars: Sidecars,
+    set_mtime: bool,
+    preserve: bool,
+    /// Budgets of the destination roots whose filesystems lack room
+    budgets: Vec<(PathBuf, Arc<Budget>)>,
+    events: Events,
+}
+
+impl Organizer {
+    pub fn new(destination: impl Into<PathBuf>, strategy: LinkStrategy) -> Self {
+        Self {
+            destination: destination.into(),
+            category_destinations: BTreeMap::new(),
+            strategy,
+            timezone: Timezone::default(),
+            layout: Layout::default(),
+            geocoder: None,
+            unknown_dir: None,
+            sidecars: Sidecars::default(),
+            set_mtime: false,
+            preserve: true,
+            budgets: Vec::new(),
+            events: Events::default(),
+        }
+    }
+
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// Places media of `category` below `destination` rather than the
+    /// destination given to [`Organizer::new`], laid out the same way.
+    pub fn category_destination(
+        mut self,
+        category: Category,
+        destination: impl Into<PathBuf>,
+    ) -> Self {
+        self.category_destinations
+            .insert(category.name(), destination.into());
+        self
+    }
+
+    /// Resolves the `{country}` and `{city}` layout tokens; without one
+    /// they render as `Unknown`.
+    pub fn geocoder(mut self, geocoder: Arc<Geocoder>) -> Self {
+        self.geocoder = Some(geocoder);
+        self
+    }
+
+    /// Show capture times in `timezone` when picking the year and file name.
+    pub fn timezone(mut self, timezone: Timezone) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    /// Where media that no source could date goes, `<destination>/Unknown`
+    /// by default.
+    pub fn unknown_dir(mut self, unknown_dir: impl Into<PathBuf>) -> Self {
+        self.unknown_dir = Some(unknown_dir.into());
+        self
+    }
+
+    /// Whether `.thm`, `.aae`, `.srt` and `.xmp` companions are placed
+    /// along with their media.
+    pub fn sidecars(mut self, sidecars: Sidecars) -> Self {
+        self.sidecars = sidecars;
+        self
+    }
+
+    /// Set the modification and access times of copied, moved and
+    /// reflinked files to their capture time, for gallery apps that sort by
+    /// mtime. Links are left alone, as they share the times of the source.
+    pub fn set_mtime(mut self, set_mtime: bool) -> Self {
+        self.set_mtime = set_mtime;
+        self
+    }
+
+    /// Whether copies, moves across filesystems and reflinks keep the
+    /// permissions, owner and extended attributes of the source, as they do
+    /// by default. Copies to remotes never do.
+    pub fn preserve(mut self, preserve: bool) -> Self {
+        self.preserve = preserve;
+        self
+    }
+
+    /// Bytes copies below `root`, one of [`Organizer::destinations`], may
+    /// take altogether; files that no longer fit fail with
+    /// `ErrorKind::StorageFull`. Roots on one filesystem share a budget.
+    pub fn budget(mut self, root: impl Into<PathBuf>, budget: Arc<Budget>) -> Self {
+        self.budgets.push((root.into(), budget));
+        self
+    }
+
+    /// Report the files placed as links to `sink`.
+    pub fn events(mut self, sink: Arc<dyn EventSink>) -> Self {
+        self.events = Events::new(sink);
+        self
+    }
+
+    pub fn destination(&self) -> &Path {
+        &self.destination
+    }
+
+    /// The root media of `category`, a [`Category::name`], is placed below.
+    pub fn destination_of(&self, category: &str) -> &Path {
+        self.category_destinations
+            .get(category)
+            .unwrap_or(&self.destination)
+    }
+
+    /// Every root media is placed below, the destination first and those
+    /// of categories after it by category name.
+    pub fn destinations(&self) -> Vec<&Path> {
+        let mut destinations = vec![self.destination.as_path()];
+        for destination in self.category_destinations.values() {
+            if !destinations.contains(&destination.as_path()) {
+                destinations.push(destination);
+            }
+        }
+        destinations
+    }
+
+    /// The root of [`Organizer::destinations`] that `path` lies below, the
+    /// innermost when they nest.
+    pub fn root_of(&self, path: &Path) -> Option<&Path> {
+        self.destinations()
+            .into_iter()
+            .filter(|destination| path.starts_with(destination))
+            .max_by_key(|destination| destination.components().count())
+    }
+
+    pub fn strategy(&self) -> LinkStrategy {
+        self.strategy
+    }
+
+    pub fn destination_for(&self, media: &Media) -> PathBuf {
+        let ext = media
+            .path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default();
+        let timestamp = self.timezone.convert(media.timestamp);
+        let place = media
+            .location
+            .zip(self.geocoder.as_deref())
+            .and_then(|(location, geocoder)| geocoder.nearest(location));
+        self.destination_of(media.category)
+            .join(self.layout.render(media, timestamp, place))
+            .join(format!(
+                "{}_{}.{}",
+                timestamp.format("%F_%X"),
+                media.hash.short(),
+                ext
+            ))
+    }
+
+    /// The short content hash a placed file is named with; dated and
+    /// unknown names both end in `_<hash>`, or `_<hash>.<n>` when numbered.
+    pub fn hash_in_name(dest_path: &Path) -> Option<&str> {
+        let stem = dest_path.file_stem()?.to_str()?;
+        // the counter of a name that collided, see `numbered`
+        let stem = stem
+            .rsplit_once('.')
+            .filter(|(_, n)| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+            .map_or(stem, |(stem, _)| stem);
+        // the digest may contain `_` itself, so count from the end
+        let split = stem.len().checked_sub(SHORT_DIGEST_LEN + 1)?;
+        let hash = stem.get(split + 1..)?;
+        (stem.as_bytes()[split] == b'_'
+            && hash
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'))
+        .then_some(hash)
+    }
+
+    /// Places `media` and returns where it went. An existing destination is
+    /// never overwritten: when it already holds the same contents that is
+    /// where the file is, and when it holds other contents the file gets a
+    /// numbered name, see [`Organizer::place_at`].
+    pub fn place(&self, media: &Media) -> Result<Placement> {
+        let placement = self.place_at(&media.path, &media.hash, self.destination_for(media))?;
+        let dest_path = &placement.path;
+        // rclone keeps the mtime of the source, and cannot set another
+        if self.set_mtime
+            && !placement.existed
+            && rclone::remote_path(dest_path).is_none()
+            && matches!(
+                self.strategy,
+                LinkStrategy::Copy | LinkStrategy::Move | LinkStrategy::Reflink
+            )
+        {
+            let captured = FileTime::from_unix_time(
+                media.timestamp.timestamp(),
+                media.timestamp.timestamp_subsec_nanos(),
+            );
+            filetime::set_file_times(dest_path, captured, captured)?;
+        }
+        Ok(placement)
+    }
+
+    /// Media without a timestamp keeps its name, suffixed with the content
+    /// hash so different files of the same name do not collide.
+    pub fn unknown_destination_for(&self, path: &Path, hash: &FileHash) -> PathBuf {
+        let unknown_dir = self
+            .unknown_dir
+            .clone()
+            .unwrap_or_else(|| self.destination.join("Unknown"));
+        let mut name = path.file_stem().unwrap_or_default().to_owned();
+        name.push(format!("_{}", hash.short()));
+        if let Some(ext) = path.extension() {
+            name.push(".");
+            name.push(ext);
+        }
+        unknown_dir.join(name)
+    }
+
+    /// Places media that could not be dated into the unknown directory.
+    pub fn place_unknown(&self, path: &Path, hash: &FileHash) -> Result<Placement> {
+        self.place_at(path, hash, self.unknown_destination_for(path, hash))
+    }
+
+    /// Places `source` at `dest_path`, or at `name.1.ext`, `name.2.ext` and
+    /// so on when another file took the name: two files can share a
+    /// timestamp and a truncated digest, and anything else may have been put
+    /// in the destination by hand. A name already holding `source` or its
+    /// contents means the file was placed before, and is returned as
+    /// [`Placement::existed`].
+    fn place_at(&self, source: &Path, hash: &FileHash, dest_path: PathBuf) -> Result<Placement> {
+        // remotes make directories as files are copied into them
+        if let Some(dest_dir_path) = dest_path
+            .parent()
+            .filter(|_| rclone::remote_path(&dest_path).is_none())
+        {
+            create_dir_all(dest_dir_path)?;
+        }
+        let mut candidate = dest_path.clone();
+        let mut n = 0;
+        let existed = loop {
+            match self.put(source, &candidate, Some(hash)) {
+                Ok(()) => break false,
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                    if holds(&candidate, source, hash) {
+                        break true;
+                    }
+                }
+                Err(err) => return Err(err.into()),
+            }
+            n += 1;
+            candidate = numbered(&dest_path, n);
+        };
+        Ok(Placement {
+            taken: (n > 0).then_some(dest_path),
+            path: candidate,
+            existed,
+        })
+    }
+
+    /// Places `source` at `dest_path` with the strategy, through rclone
+    /// when `dest_path` is on a remote. Local copies are checked against
+    /// `hash` before they take the name.
+    fn put(&self, source: &Path, dest_path: &Path, hash: Option<&FileHash>) -> io::Result<()> {
+        match rclone::remote_path(dest_path) {
+            Some(remote) => rclone::place(self.strategy, source, remote),
+            None => {
+                let taken = self.take_space(source, dest_path)?;
+                let placed =
+                    linker::place_with(self.strategy, source, dest_path, self.preserve, hash);
+                match (&placed, taken) {
+                    (Ok(()), _) => self.placed(source, dest_path),
+                    (Err(_), Some((budget, size))) => budget.give_back(size),
+                    (Err(_), None) => {}
+                }
+                placed
+            }
+        }
+    }
+
+    /// Takes the size of `source` from the budget when placing it at
+    /// `dest_path` writes a new file, and fails once the budget is spent.
+    fn take_space(&self, source: &Path, dest_path: &Path) -> io::Result<Option<(&Budget, u64)>> {
+        let Some(budget) = self
+            .root_of(dest_path)
+            .and_then(|root| {
+                self.budgets
+                    .iter()
+                    .find(|(budget_root, _)| budget_root == root)
+            })
+            .map(|(_, budget)| budget)
+        else {
+            return Ok(None);
+        };
+        // a name that is taken is not written to
+        if fs::symlink_metadata(dest_path).is_ok()
+            || !space::writes(self.strategy, source, dest_path)
+        {
+            return Ok(None);
+        }
+        let size = fs::metadata(source)?.len();
+        if !budget.take(size) {
+            return Err(io::Error::new(
+                io::ErrorKind::StorageFull,
+                format!(
+                    "{} does not fit in the space left",
+                    source.to_string_lossy()
+                ),
+            ));
+        }
+        Ok(Some((budget, size)))
+    }
+
+    /// Whether `path` is a sidecar placed with its media rather than on
+    /// its own.
+    pub fn skips(&self, path: &Path) -> bool {
+        self.sidecars == Sidecars::Keep && sidecar::is_sidecar(path)
+    }
+
+    /// The sidecars of `source` and where they go once it is placed at
+    /// `dest_path`, none when they are ignored.
+    pub fn sidecar_destinations(
+        &self,
+        source: &Path,
+        mime_type: &Mime,
+        dest_path: &Path,
+    ) -> Vec<(PathBuf, PathBuf)> {
+        match self.sidecars {
+            Sidecars::Keep => sidecar::sidecars(source, mime_type, dest_path),
+            Sidecars::Ignore => Vec::new(),
+        }
+    }
+
+    /// Places the sidecars of `source` next to where it was placed and
+    /// returns how many were. Sidecars already there are left alone.
+    pub fn place_sidecars(
+        &self,
+        source: &Path,
+        mime_type: &Mime,
+        dest_path: &Path,
+    ) -> Result<Vec<(PathBuf, PathBuf)>> {
+        let mut placed = Vec::new();
+        for (sidecar, sidecar_dest) in self.sidecar_destinations(source, mime_type, dest_path) {
+            match self.put(&sidecar, &sidecar_dest, None) {
+                Ok(()) => placed.push((sidecar, sidecar_dest)),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(placed)
+    }
+
+    /// Where the layout puts `media` now, which was placed at `dest_path`,
+    /// or `None` when it is there already, numbered or not.
+    pub fn relocation_for(&self, media: &Media, dest_path: &Path) -> Option<PathBuf> {
+        let target = self.destination_for(media);
+        (unnumbered(dest_path) != target).then_some(target)
+    }
+
+    /// Moves what was placed at `dest_path` for `media`, with its sidecars,
+    /// to where the layout puts it now, or returns `None` when it is there
+    /// already. Each rename is atomic, and a name another file took gets a
+    /// number as with [`Organizer::place`]. Files on an rclone remote cannot
+    /// be renamed atomically, so they are refused.
+    pub fn relocate(&self, media: &Media, dest_path: &Path) -> Result<Option<Relocation>> {
+        let Some(target) = self.relocation_for(media, dest_path) else {
+            return Ok(None);
+        };
+        if rclone::remote_path(dest_path).is_some() || rclone::remote_path(&target).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "{} is on an rclone remote, which cannot be relaid out",
+                    dest_path.to_string_lossy()
+                ),
+            )
+            .into());
+        }
+        if let Some(dest_dir_path) = target.parent() {
+            create_dir_all(dest_dir_path)?;
+        }
+        let mut candidate = target.clone();
+        let mut n = 0;
+        loop {
+            match linker::rename_new(dest_path, &candidate) {
+                Ok(()) => break,
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err.into()),
+            }
+            n += 1;
+            candidate = numbered(&target, n);
+        }
+        let sidecars = self
+            .sidecar_destinations(&media.path, &media.mime_type, dest_path)
+            .into_iter()
+            .zip(self.sidecar_destinations(&media.path, &media.mime_type, &candidate));
+        let mut moved = Vec::new();
+        for ((_, old), (_, new)) in sidecars {
+            if fs::symlink_metadata(&old).is_err() {
+                continue;
+            }
+            match linker::rename_new(&old, &new) {
+                Ok(()) => moved.push((old, new)),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+        Ok(Some(Relocation {
+            path: candidate,
+            sidecars: moved,
+        }))
+    }
+
+    /// Removes `dir` and then its parents while they are empty, up to the
+    /// destination root it lies below, and returns how many were removed.
+    pub fn prune_empty_dirs(&self, dir: &Path) -> usize {
+        let Some(root) = self.root_of(dir) else {
+            return 0;
+        };
+        let mut removed = 0;
+        let mut dir = dir;
+        while dir.starts_with(root) && dir != root {
+            if fs::remove_dir(dir).is_err() {
+                break;
+            }
+            removed += 1;
+            match dir.parent() {
+                Some(parent) => dir = parent,
+                None => break,
+            }
+        }
+        removed
+    }
+
+    /// Places `source` at `dest_path` again when nothing is there or only
+    /// a symlink whose target is gone, and returns whether it did. On an
+    /// rclone remote, `source` is uploaded again when nothing is there.
+    pub fn repair(&self, source: &Path, dest_path: &Path) -> Result<bool> {
+        if let Some(remote) = rclone::remote_path(dest_path) {
+            if rclone::size(remote)?.is_some() {
+                return Ok(false);
+            }
+            fs::metadata(source)?;
+            rclone::place(self.strategy, source, remote)?;
+            return Ok(true);
+        }
+        if dest_path.exists() {
+            return Ok(false);
+        }
+        // a symlink to a missing source would dangle right away
+        fs::metadata(source)?;
+        if fs::symlink_metadata(dest_path).is_ok() {
+            fs::remove_file(dest_path)?;
+        }
+        if let Some(dest_dir_path) = dest_path.parent() {
+            create_dir_all(dest_dir_path)?;
+        }
+        linker::place_with(self.strategy, source, dest_path, self.preserve, None)?;
+        self.placed(source, dest_path);
+        Ok(true)
+    }
+
+    /// Reports `dest_path` when the strategy made it a link to `source`.
+    fn placed(&self, source: &Path, dest_path: &Path) {
+        if matches!(
+            self.strategy,
+            LinkStrategy::Symlink | LinkStrategy::Hardlink
+        ) {
+            self.events.linked(dest_path, source);
+        }
+    }
+}
+
+/// `name.n.ext` as `name.ext`, other names as they are.
+fn unnumbered(dest_path: &Path) -> PathBuf {
+    let stem = Path::new(dest_path.file_stem().unwrap_or_default());
+    let (Some(stem), Some(n)) = (stem.file_stem(), stem.extension()) else {
+        return dest_path.to_owned();
+    };
+    if n.is_empty()
+        || !n
+            .to_str()
+            .is_some_and(|n| n.bytes().all(|b| b.is_ascii_digit()))
+    {
+        return dest_path.to_owned();
+    }
+    let mut name = stem.to_owned();
+    if let Some(ext) = dest_path.extension() {
+        name.push(".");
+        name.push(ext);
+    }
+    dest_path.with_file_name(name)
+}
+
+/// Whether `existing` is `source` itself, directly or through a link, or
+/// a file with the contents `hash` was computed from.
+fn holds(existing: &Path, source: &Path, hash: &FileHash) -> bool {
+    if let Some(remote) = rclone::remote_path(existing) {
+        return rclone::file_hash(remote, hash.algorithm)
+            .is_ok_and(|existing| existing.digest == hash.digest);
+    }
+    let (Ok(existing_metadata), Ok(source_metadata)) =
+        (fs::metadata(existing), fs::metadata(source))
+    else {
+        // a dangling link, or a moved source whose copy is all that is left
+        return hasher::file_hash(existing, hash.algorithm)
+            .is_ok_and(|existing| existing.digest == hash.digest);
+    };
+    if platform::same_file(&existing_metadata, &source_metadata) {
+        return true;
+    }
+    existing_metadata.len() == source_metadata.len()
+        && hasher::file_hash(existing, hash.algorithm)
+            .is_ok_and(|existing| existing.digest == hash.digest)
+}
+
+#[test]
+fn test_timezone() {
+    let timestamp = DateTime::parse_from_rfc3339("2023-12-31T23:30:00+09:00").unwrap();
+    let convert = |timezone: &str| {
+        timezone
+            .parse::<Timezone>()
+            .unwrap()
+            .convert(timestamp)
+            .to_string()
+    };
+    assert_eq!("2023-12-31 23:30:00 +09:00", convert("capture"));
+    assert_eq!("2023-12-31 14:30:00 +00:00", convert("UTC"));
+    assert_eq!("2023-12-31 20:00:00 +05:30", convert("+05:30"));
+    assert!("nowhere".parse::<Timezone>().is_err());
+}
+
+#[test]
+fn test_unknown_destination_for() {
+    let hash = FileHash {
+        algorithm: crate::hasher::HashAlgorithm::Blake3,
+        digest: "abc".to_owned(),
+    };
+    let organizer = Organizer::new("/dest", LinkStrategy::Symlink);
+    assert_eq!(
+        Path::new("/dest/Unknown/clip_abc.mp4"),
+        organizer.unknown_destination_for(Path::new("/src/clip.mp4"), &hash)
+    );
+    let organizer = organizer.unknown_dir("/undated");
+    assert_eq!(
+        Path::new("/undated/README_abc"),
+        organizer.unknown_destination_for(Path::new("/src/README"), &hash)
+    );
+}
+
+#[test]
+fn test_repair() {
+    let dir = std::env::temp_dir().join(format!("deduper-repair-{}", std::process::id()));
+    let source = dir.join("a.jpg");
+    let dest_path = dir.join("dest/2023/a.jpg");
+    create_dir_all(&dir).unwrap();
+    fs::write(&source, b"a").unwrap();
+    let organizer = Organizer::new(dir.join("dest"), LinkStrategy::Copy);
+    assert!(organizer.repair(&source, &dest_path).unwrap());
+    assert_eq!(b"a".to_vec(), fs::read(&dest_path).unwrap());
+    assert!(!organizer.repair(&source, &dest_path).unwrap());
+    fs::remove_file(&dest_path).unwrap();
+    fs::remove_file(&source).unwrap();
+    assert!(organizer.repair(&source, &dest_path).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_linked_events() {
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Collect(Mutex<Vec<(PathBuf, PathBuf)>>);
+
+    impl EventSink for Collect {
+        fn linked(&self, path: &Path, target: &Path) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((path.to_owned(), target.to_owned()));
+        }
+    }
+
+    let dir = std::env::temp_dir().join(format!("deduper-linked-events-{}", std::process::id()));
+    let source = dir.join("a.jpg");
+    create_dir_all(&dir).unwrap();
+    fs::write(&source, b"a").unwrap();
+    let hash = hasher::file_hash(&source, hasher::HashAlgorithm::Blake3).unwrap();
+    let sink = Arc::new(Collect::default());
+    let linking = Organizer::new(dir.join("dest"), LinkStrategy::Symlink).events(sink.clone());
+    let placement = linking.place_unknown(&source, &hash).unwrap();
+    assert_eq!(
+        vec![(placement.path.clone(), source.clone())],
+        *sink.0.lock().unwrap()
+    );
+    // copies are no links
+    let copying = Organizer::new(dir.join("copies"), LinkStrategy::Copy).events(sink.clone());
+    copying.place_unknown(&source, &hash).unwrap();
+    assert_eq!(1, sink.0.lock().unwrap().len());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_hash_in_name() {
+    assert_eq!(
+        Some("ab_cdefghijklmnopqrstu"),
+        Organizer::hash_in_name(Path::new(
+            "/dest/2023/2023-05-01_12:00:00_ab_cdefghijklmnopqrstu.jpg"
+        ))
+    );
+    assert_eq!(
+        Some("abcdefghijklmnopqrstuv"),
+        Organizer::hash_in_name(Path::new(
+            "/dest/Unknown/IMG_1_abcdefghijklmnopqrstuv.2.png"
+        ))
+    );
+    assert_eq!(None, Organizer::hash_in_name(Path::new("/dest/notes.txt")));
+}
+
+#[test]
+fn test_place_numbers_collisions() {
+    let dir = std::env::temp_dir().join(format!("deduper-collide-{}", std::process::id()));
+    let source = dir.join("a.jpg");
+    create_dir_all(&dir).unwrap();
+    fs::write(&source, b"a").unwrap();
+    let hash = FileHash {
+        algorithm: hasher::HashAlgorithm::Blake3,
+        digest: "abcdefghijklmnopqrstuv".to_owned(),
+    };
+    let organizer = Organizer::new(dir.join("dest"), LinkStrategy::Hardlink);
+    let dest_path = organizer.unknown_destination_for(&source, &hash);
+    create_dir_all(dest_path.parent().unwrap()).unwrap();
+    fs::write(&dest_path, b"other").unwrap();
+
+    let placement = organizer.place_unknown(&source, &hash).unwrap();
+    let placed = numbered(&dest_path, 1);
+    assert_eq!(
+        Placement {
+            path: placed.clone(),
+            existed: false,
+            taken: Some(dest_path.clone()),
+        },
+        placement
+    );
+    assert_eq!(b"a".to_vec(), fs::read(&placed).unwrap());
+    assert_eq!(
+        Organizer::hash_in_name(&dest_path),
+        Organizer::hash_in_name(&placed)
+    );
+    // placed before under the numbered name
+    let placement = organizer.place_unknown(&source, &hash).unwrap();
+    assert_eq!(placed, placement.path);
+    assert!(placement.existed);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_place_sets_mtime() {
+    let dir = std::env::temp_dir().join(format!("deduper-mtime-{}", std::process::id()));
+    let source = dir.join("IMG_1234.JPG");
+    create_dir_all(&dir).unwrap();
+    fs::write(&source, b"a").unwrap();
+    // copies are checked against the hash
+    let hash = hasher::file_hash(&source, hasher::HashAlgorithm::Blake3).unwrap();
+    let media = Media::test(&source, &hash.digest);
+    let organizer = Organizer::new(dir.join("dest"), LinkStrategy::Copy).set_mtime(true);
+    let placed = organizer.place(&media).unwrap().path;
+    let modified = fs::metadata(&placed).unwrap().modified().unwrap();
+    assert_eq!(
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_693_601_381),
+        modified
+    );
+    // the source keeps its own
+    assert_ne!(modified, fs::metadata(&source).unwrap().modified().unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_place_conflicting_media() {
+    let dir = std::env::temp_dir().join(format!("deduper-conflict-{}", std::process::id()));
+    let media = |source: &str, contents: &[u8]| {
+        let path = dir.join(source).join("IMG_1234.JPG");
+        create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        fs::write(path.with_extension("xmp"), contents).unwrap();
+        // two cameras' shots of one second whose short digests collide
+        Media::test(path, "abcdefghijklmnopqrstuv")
+    };
+    let first = media("camera", b"a");
+    let second = media("phone", b"b");
+    let organizer = Organizer::new(dir.join("dest"), LinkStrategy::Hardlink);
+    let name = organizer.destination_for(&first);
+    assert_eq!(name, organizer.destination_for(&second));
+
+    let placement = organizer.place(&first).unwrap();
+    assert_eq!((name.clone(), None), (placement.path, placement.taken));
+    organizer
+        .place_sidecars(&first.path, &first.mime_type, &name)
+        .unwrap();
+    let placement = organizer.place(&second).unwrap();
+    let numbered = numbered(&name, 1);
+    assert_eq!(
+        Placement {
+            path: numbered.clone(),
+            existed: false,
+            taken: Some(name.clone()),
+        },
+        placement
+    );
+    organizer
+        .place_sidecars(&second.path, &second.mime_type, &placement.path)
+        .unwrap();
+
+    // a rerun finds each file under its own name, sidecars included
+    let placement = organizer.place(&second).unwrap();
+    assert_eq!(numbered, placement.path);
+    assert!(placement.existed);
+    assert_eq!(b"b".to_vec(), fs::read(&numbered).unwrap());
+    assert_eq!(
+        b"b".to_vec(),
+        fs::read(numbered.with_extension("xmp")).unwrap()
+    );
+    assert_eq!(b"a".to_vec(), fs::read(name.with_extension("xmp")).unwrap());
+    let placement = organizer.place(&first).unwrap();
+    assert_eq!((name, true), (placement.path, placement.existed));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_relocate() {
+    let dir = std::env::temp_dir().join(format!("deduper-relocate-{}", std::process::id()));
+    let source = dir.join("IMG_1234.JPG");
+    create_dir_all(&dir).unwrap();
+    fs::write(&source, b"a").unwrap();
+    fs::write(dir.join("IMG_1234.xmp"), b"<x/>").unwrap();
+    let hash = hasher::file_hash(&source, hasher::HashAlgorithm::Blake3).unwrap();
+    let media = Media::test(&source, &hash.digest);
+    let organizer = Organizer::new(dir.join("dest"), LinkStrategy::Copy);
+    let placed = organizer.place(&media).unwrap().path;
+    organizer
+        .place_sidecars(&source, &media.mime_type, &placed)
+        .unwrap();
+
+    let organizer = Organizer::new(dir.join("dest"), LinkStrategy::Copy)
+        .layout("{category}/{year}/{month}".parse().unwrap());
+    let relocation = organizer.relocate(&media, &placed).unwrap().unwrap();
+    let moved = relocation.path;
+    assert_eq!(dir.join("dest/Photos/2023/09"), moved.parent().unwrap());
+    assert_eq!(
+        vec![(placed.with_extension("xmp"), moved.with_extension("xmp"))],
+        relocation.sidecars
+    );
+    assert_eq!(placed.file_name(), moved.file_name());
+    assert!(moved.with_extension("xmp").is_file());
+    assert!(fs::symlink_metadata(&placed).is_err());
+    assert_eq!(None, organizer.relocate(&media, &moved).unwrap());
+    let remote = Path::new("rclone:nas:Library/IMG_1234.JPG");
+    let error = organizer.relocate(&media, remote).unwrap_err();
+    assert!(
+        matches!(&error, crate::error::DeduperError::Io(e) if e.kind() == io::ErrorKind::Unsupported)
+    );
+
+    // 2023 still holds 09
+    assert_eq!(0, organizer.prune_empty_dirs(placed.parent().unwrap()));
+    let organizer =
+        Organizer::new(dir.join("dest"), LinkStrategy::Copy).layout("{year}".parse().unwrap());
+    let flat = organizer.relocate(&media, &moved).unwrap().unwrap().path;
+    assert_eq!(dir.join("dest/2023"), flat.parent().unwrap());
+    // 09, 2023 and Photos
+    assert_eq!(3, organizer.prune_empty_dirs(moved.parent().unwrap()));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_category_destination() {
+    let media = Media {
+        mime_type: "video/mp4".parse().unwrap(),
+        category: "Videos",
+        ..Media::test("/src/clip.mp4", "abcdefghijkl")
+    };
+    let organizer = Organizer::new("/nas/photos", LinkStrategy::Copy)
+        .category_destination(Category::Videos, "/media/videos");
+    let dest_path = organizer.destination_for(&media);
+    assert!(dest_path.starts_with("/media/videos/Videos/2023"));
+    assert_eq!(
+        Some(Path::new("/media/videos")),
+        organizer.root_of(&dest_path)
+    );
+    let photo = Media {
+        category: "Photos",
+        ..media
+    };
+    assert!(organizer
+        .destination_for(&photo)
+        .starts_with("/nas/photos/Photos/2023"));
+    assert_eq!(
+        vec![Path::new("/nas/photos"), Path::new("/media/videos")],
+        organizer.destinations()
+    );
+    assert_eq!(None, organizer.root_of(Path::new("/elsewhere/a.jpg")));
+    // roots come in category order, whichever was set first
+    let organizer = organizer.category_destination(Category::Audio, "/media/audio");
+    assert_eq!(
+        vec![
+            Path::new("/nas/photos"),
+            Path::new("/media/audio"),
+            Path::new("/media/videos")
+        ],
+        organizer.destinations()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_utf8_name() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let hash = FileHash {
+        algorithm: crate::hasher::HashAlgorithm::Blake3,
+        digest: "abc".to_owned(),
+    };
+    let organizer = Organizer::new("/dest", LinkStrategy::Symlink);
+    assert_eq!(
+        Path::new(OsStr::from_bytes(b"/dest/Unknown/caf\xe9_abc.mp4")),
+        organizer.unknown_destination_for(Path::new(OsStr::from_bytes(b"/src/caf\xe9.mp4")), &hash)
+    );
+}
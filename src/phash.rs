@@ -0,0 +1,178 @@
+use std::{collections::HashMap, path::Path};
+
+use image::imageops::FilterType;
+
+/// Difference hash: the image is shrunk to 9x8 grayscale and every bit
+/// records whether a pixel is brighter than its right-hand neighbour, so
+/// resized or re-encoded copies land within a few bits of each other.
+pub fn image_phash(path: &Path) -> Option<u64> {
+    let pixels = image::open(path)
+        .ok()?
+        .resize_exact(9, 8, FilterType::Triangle)
+        .to_luma8();
+    Some(luma_dhash(pixels.as_raw()))
+}
+
+/// Difference hash of a 9x8 grayscale image given row by row, one byte
+/// per pixel.
+pub fn luma_dhash(pixels: &[u8]) -> u64 {
+    let mut hash = 0u64;
+    for row in pixels.chunks_exact(9).take(8) {
+        for pair in row.windows(2) {
+            hash = (hash << 1) | u64::from(pair[0] > pair[1]);
+        }
+    }
+    hash
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Groups items whose hashes are transitively within `distance` bits of each
+/// other. Singletons are dropped; each group keeps the input order.
+pub fn group_by_distance<T>(items: Vec<(u64, T)>, distance: u32) -> Vec<Vec<T>> {
+    let mut tree = BkTree::default();
+    for (index, (hash, _)) in items.iter().enumerate() {
+        tree.insert(*hash, index);
+    }
+    let links = items
+        .iter()
+        .enumerate()
+        .flat_map(|(index, (hash, _))| {
+            tree.find(*hash, distance)
+                .into_iter()
+                .map(move |other| (index, other))
+        })
+        .collect::<Vec<_>>();
+    group_linked(items.into_iter().map(|(_, item)| item).collect(), links)
+}
+
+/// Groups items transitively connected by `links` between their indices.
+/// Singletons are dropped; each group keeps the input order.
+pub(crate) fn group_linked<T>(
+    items: Vec<T>,
+    links: impl IntoIterator<Item = (usize, usize)>,
+) -> Vec<Vec<T>> {
+    let mut parents = (0..items.len()).collect::<Vec<_>>();
+    for (a, b) in links {
+        union(&mut parents, a, b);
+    }
+
+    let mut groups = HashMap::<usize, Vec<T>>::new();
+    let mut order = Vec::new();
+    for (index, item) in items.into_iter().enumerate() {
+        let root = find(&mut parents, index);
+        let group = groups.entry(root).or_default();
+        if group.is_empty() {
+            order.push(root);
+        }
+        group.push(item);
+    }
+    order
+        .into_iter()
+        .filter_map(|root| groups.remove(&root))
+        .filter(|group| group.len() > 1)
+        .collect()
+}
+
+fn find(parents: &mut [usize], mut index: usize) -> usize {
+    while parents[index] != index {
+        parents[index] = parents[parents[index]];
+        index = parents[index];
+    }
+    index
+}
+
+fn union(parents: &mut [usize], a: usize, b: usize) {
+    let (a, b) = (find(parents, a), find(parents, b));
+    if a != b {
+        parents[a.max(b)] = a.min(b);
+    }
+}
+
+/// Burkhard-Keller tree over Hamming distance, so lookups only visit
+/// subtrees that can contain a match instead of every stored hash.
+#[derive(Default)]
+struct BkTree {
+    nodes: Vec<BkNode>,
+}
+
+struct BkNode {
+    hash: u64,
+    index: usize,
+    children: HashMap<u32, usize>,
+}
+
+impl BkTree {
+    fn insert(&mut self, hash: u64, index: usize) {
+        let new_node = self.nodes.len();
+        self.nodes.push(BkNode {
+            hash,
+            index,
+            children: HashMap::new(),
+        });
+        if new_node == 0 {
+            return;
+        }
+        let mut current = 0;
+        loop {
+            let distance = hamming_distance(self.nodes[current].hash, hash);
+            match self.nodes[current].children.get(&distance) {
+                Some(&child) => current = child,
+                None => {
+                    self.nodes[current].children.insert(distance, new_node);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn find(&self, hash: u64, max_distance: u32) -> Vec<usize> {
+        let mut found = Vec::new();
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(current) = stack.pop() {
+            let node = &self.nodes[current];
+            let distance = hamming_distance(node.hash, hash);
+            if distance <= max_distance {
+                found.push(node.index);
+            }
+            let low = distance.saturating_sub(max_distance);
+            let high = distance + max_distance;
+            stack.extend(
+                node.children
+                    .iter()
+                    .filter(|(d, _)| (low..=high).contains(*d))
+                    .map(|(_, &child)| child),
+            );
+        }
+        found
+    }
+}
+
+#[test]
+fn test_group_by_distance() {
+    let items = vec![
+        (0b0000_0000, "a"),
+        (0xFFFF_FFFF_0000_0000, "far"),
+        (0b0000_0011, "b"),
+        (0b0000_1111, "c"),
+        (0xFFFF_FFFF_0000_0001, "near far"),
+        (0x0F0F_0F0F_0F0F_0F0F, "alone"),
+    ];
+    let groups = group_by_distance(items, 2);
+    assert_eq!(vec![vec!["a", "b", "c"], vec!["far", "near far"]], groups);
+}
+
+#[test]
+fn test_luma_dhash() {
+    // brightness falls to the right on the first row only
+    let mut pixels = [0u8; 72];
+    for (x, pixel) in pixels[..9].iter_mut().enumerate() {
+        *pixel = 255 - x as u8;
+    }
+    assert_eq!(0xFF00_0000_0000_0000, luma_dhash(&pixels));
+}
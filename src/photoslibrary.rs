@@ -0,0 +1,371 @@
+//! Apple Photos and iPhoto libraries. A `.photoslibrary` bundle keeps its
+//! originals under folders and names of its own, `originals/3/3F2A...jpg`
+//! since Photos 5 and `Masters/2019/09/01/20190901-070202/IMG_0001.JPG`
+//! before, and their capture time, place, favorite flag and albums in an
+//! SQLite database next to them, which is read here so the files need not
+//! be dated from their names or mtimes.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, FixedOffset, Local, TimeZone};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+
+use crate::{error::Result, geo::Location};
+
+/// The extension of a library bundle.
+pub const EXTENSION: &str = "photoslibrary";
+
+/// Core Data stores times as seconds since 2001-01-01 UTC.
+const CORE_DATA_EPOCH: i64 = 978_307_200;
+
+/// The database and originals directory of Photos 5 and later.
+const PHOTOS_DATABASE: &str = "database/Photos.sqlite";
+const PHOTOS_ORIGINALS: &str = "originals";
+
+/// The database and originals directory of iPhoto and Photos 1 to 4.
+const LEGACY_DATABASE: &str = "database/photos.db";
+const LEGACY_ORIGINALS: &str = "Masters";
+
+/// Photos writes this latitude and longitude where it knows no place.
+const NO_COORDINATE: f64 = -180.0;
+
+/// `ZKIND` of an album the user made, rather than a folder or a smart or
+/// shared album.
+const USER_ALBUM_KIND: i64 = 2;
+
+/// `albumSubclass` of a user album in the legacy database.
+const LEGACY_USER_ALBUM_SUBCLASS: i64 = 3;
+
+/// A photo or video of a library, as its database describes it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Asset {
+    /// The original in the bundle
+    pub path: PathBuf,
+    /// The name the file was imported under
+    pub original_name: Option<String>,
+    pub timestamp: Option<DateTime<FixedOffset>>,
+    pub location: Option<Location>,
+    pub favorite: bool,
+    /// The albums the user put it in, by title
+    pub albums: Vec<String>,
+}
+
+/// The assets of the libraries among the sources, by the path of their
+/// original.
+#[derive(Debug, Default, Clone)]
+pub struct PhotosLibrary {
+    assets: HashMap<PathBuf, Asset>,
+    /// Bundles without a database, whose files are dated like any other
+    pub unread: Vec<PathBuf>,
+}
+
+impl PhotosLibrary {
+    /// Reads the database of every library bundle among `sources`; other
+    /// sources are left out, and bundles without a database are listed in
+    /// `unread`. Assets in the trash are left out too.
+    pub fn open(sources: &[PathBuf]) -> Result<Self> {
+        let mut library = Self::default();
+        for bundle in sources.iter().filter(|source| is_library(source)) {
+            if !has_database(bundle) {
+                library.unread.push(bundle.clone());
+                continue;
+            }
+            for asset in read_assets(bundle)? {
+                library.assets.insert(asset.path.clone(), asset);
+            }
+        }
+        Ok(library)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.assets.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.assets.len()
+    }
+
+    /// The asset whose original is at `path`.
+    pub fn asset(&self, path: &Path) -> Option<&Asset> {
+        self.assets.get(path)
+    }
+}
+
+/// Whether the bundle holds the database of either version.
+fn has_database(bundle: &Path) -> bool {
+    bundle.join(PHOTOS_DATABASE).is_file() || bundle.join(LEGACY_DATABASE).is_file()
+}
+
+/// Whether `path` is a library bundle.
+pub fn is_library(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == EXTENSION) && path.is_dir()
+}
+
+/// What to walk of `source`: the originals directory of a library bundle,
+/// so its thumbnails, previews and edits are not taken for media, and
+/// anything else as it is. A bundle without a database is told apart by
+/// its directories.
+pub fn walked(source: &Path) -> PathBuf {
+    if !is_library(source) {
+        return source.to_owned();
+    }
+    let photos = match has_database(source) {
+        true => source.join(PHOTOS_DATABASE).is_file(),
+        false => source.join(PHOTOS_ORIGINALS).is_dir(),
+    };
+    match photos {
+        true => source.join(PHOTOS_ORIGINALS),
+        false => source.join(LEGACY_ORIGINALS),
+    }
+}
+
+/// The assets of the library `bundle`, from whichever database it has.
+pub fn read_assets(bundle: &Path) -> Result<Vec<Asset>> {
+    let database = bundle.join(PHOTOS_DATABASE);
+    if database.is_file() {
+        return read_photos_assets(&open_read_only(&database)?, &bundle.join(PHOTOS_ORIGINALS));
+    }
+    read_legacy_assets(
+        &open_read_only(&bundle.join(LEGACY_DATABASE))?,
+        &bundle.join(LEGACY_ORIGINALS),
+    )
+}
+
+/// Opens a library database without writing to it, even while Photos has
+/// it open.
+fn open_read_only(path: &Path) -> rusqlite::Result<Connection> {
+    Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+}
+
+/// The assets of a Photos 5 or later database, whose asset table is
+/// `ZGENERICASSET` in Photos 5 and `ZASSET` since.
+fn read_photos_assets(conn: &Connection, originals: &Path) -> Result<Vec<Asset>> {
+    let table = match has_table(conn, "ZASSET")? {
+        true => "ZASSET",
+        false => "ZGENERICASSET",
+    };
+    let albums = read_photos_albums(conn, table)?;
+    let mut stmt = conn.prepare(&format!(
+        "SELECT asset.Z_PK, asset.ZDIRECTORY, asset.ZFILENAME, asset.ZDATECREATED,
+            asset.ZFAVORITE, asset.ZLATITUDE, asset.ZLONGITUDE,
+            attributes.ZORIGINALFILENAME, attributes.ZTIMEZONEOFFSET
+        FROM {} asset
+        LEFT JOIN ZADDITIONALASSETATTRIBUTES attributes ON attributes.ZASSET = asset.Z_PK
+        WHERE asset.ZTRASHEDSTATE = 0 AND asset.ZFILENAME IS NOT NULL",
+        table
+    ))?;
+    let assets = stmt.query_map(params![], |row| {
+        let id = row.get::<_, i64>(0)?;
+        let directory = row.get::<_, Option<String>>(1)?.unwrap_or_default();
+        Ok(Asset {
+            path: originals.join(directory).join(row.get::<_, String>(2)?),
+            original_name: row.get(7)?,
+            timestamp: core_data_time(row.get(3)?, row.get(8)?),
+            location: location(row.get(5)?, row.get(6)?),
+            favorite: row.get::<_, Option<bool>>(4)?.unwrap_or_default(),
+            albums: albums.get(&id).cloned().unwrap_or_default(),
+        })
+    })?;
+    Ok(assets.collect::<rusqlite::Result<_>>()?)
+}
+
+/// The titles of the user albums of each asset. Core Data names the table
+/// joining albums and assets after the entity numbers of the library's
+/// version, e.g. `Z_26ASSETS` with the columns `Z_26ALBUMS` and
+/// `Z_34ASSETS`, so it is looked up by its shape.
+fn read_photos_albums(conn: &Connection, table: &str) -> Result<HashMap<i64, Vec<String>>> {
+    let mut albums: HashMap<i64, Vec<String>> = HashMap::new();
+    let Some((join_table, album_column, asset_column)) = album_join_table(conn)? else {
+        return Ok(albums);
+    };
+    let mut stmt = conn.prepare(&format!(
+        "SELECT asset.Z_PK, album.ZTITLE FROM {join} link
+        JOIN ZGENERICALBUM album ON album.Z_PK = link.{album_column}
+        JOIN {table} asset ON asset.Z_PK = link.{asset_column}
+        WHERE album.ZKIND = ?1 AND album.ZTRASHEDSTATE = 0 AND album.ZTITLE IS NOT NULL
+        ORDER BY album.ZTITLE",
+        join = join_table,
+    ))?;
+    let rows = stmt.query_map(params![USER_ALBUM_KIND], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+    })?;
+    for row in rows {
+        let (id, title) = row?;
+        albums.entry(id).or_default().push(title);
+    }
+    Ok(albums)
+}
+
+/// The table joining albums and assets, with its album and asset columns.
+fn album_join_table(conn: &Connection) -> rusqlite::Result<Option<(String, String, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name GLOB 'Z_[0-9]*ASSETS'",
+    )?;
+    let tables = stmt
+        .query_map(params![], |row| row.get::<_, String>(0))?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for table in tables {
+        let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+        let columns = stmt
+            .query_map(params![], |row| row.get::<_, String>(1))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        let album = columns.iter().find(|column| column.ends_with("ALBUMS"));
+        let asset = columns.iter().find(|column| column.ends_with("ASSETS"));
+        if let (Some(album), Some(asset)) = (album, asset) {
+            return Ok(Some((table, album.clone(), asset.clone())));
+        }
+    }
+    Ok(None)
+}
+
+/// The assets of an iPhoto or Photos 1 to 4 database: its versions, each
+/// with the master file it was made from.
+fn read_legacy_assets(conn: &Connection, masters: &Path) -> Result<Vec<Asset>> {
+    let mut albums: HashMap<i64, Vec<String>> = HashMap::new();
+    let mut stmt = conn.prepare(
+        "SELECT link.versionId, album.name FROM RKAlbumVersion link
+        JOIN RKAlbum album ON album.modelId = link.albumId
+        WHERE album.albumSubclass = ?1 AND album.isInTrash = 0 AND album.name IS NOT NULL
+        ORDER BY album.name",
+    )?;
+    let rows = stmt.query_map(params![LEGACY_USER_ALBUM_SUBCLASS], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+    })?;
+    for row in rows {
+        let (id, name) = row?;
+        albums.entry(id).or_default().push(name);
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT version.modelId, master.imagePath, master.originalFileName,
+            version.imageDate, version.imageTimeZoneOffsetSeconds, version.isFavorite,
+            version.latitude, version.longitude
+        FROM RKVersion version
+        JOIN RKMaster master ON master.uuid = version.masterUuid
+        WHERE version.isInTrash = 0 AND master.imagePath IS NOT NULL",
+    )?;
+    let rows = stmt.query_map(params![], |row| {
+        let id = row.get::<_, i64>(0)?;
+        Ok(Asset {
+            path: masters.join(row.get::<_, String>(1)?),
+            original_name: row.get(2)?,
+            timestamp: core_data_time(row.get(3)?, row.get(4)?),
+            location: location(row.get(6)?, row.get(7)?),
+            favorite: row.get::<_, Option<bool>>(5)?.unwrap_or_default(),
+            albums: albums.remove(&id).unwrap_or_default(),
+        })
+    })?;
+    // a master with several versions is one file
+    let mut assets: HashMap<PathBuf, Asset> = HashMap::new();
+    for asset in rows {
+        let asset = asset?;
+        match assets.get_mut(&asset.path) {
+            Some(known) => {
+                known.favorite |= asset.favorite;
+                known.albums.extend(asset.albums);
+                known.albums.sort();
+                known.albums.dedup();
+            }
+            None => {
+                assets.insert(asset.path.clone(), asset);
+            }
+        }
+    }
+    Ok(assets.into_values().collect())
+}
+
+fn has_table(conn: &Connection, name: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        params![name],
+        |_| Ok(()),
+    )
+    .optional()
+    .map(|found| found.is_some())
+}
+
+/// A Core Data time at the UTC offset recorded with it, or at the local
+/// offset without one.
+fn core_data_time(seconds: Option<f64>, offset: Option<i32>) -> Option<DateTime<FixedOffset>> {
+    let timestamp = CORE_DATA_EPOCH + seconds? as i64;
+    match offset.and_then(FixedOffset::east_opt) {
+        Some(offset) => offset.timestamp_opt(timestamp, 0).single(),
+        None => Local
+            .timestamp_opt(timestamp, 0)
+            .single()
+            .map(|timestamp| timestamp.fixed_offset()),
+    }
+}
+
+fn location(latitude: Option<f64>, longitude: Option<f64>) -> Option<Location> {
+    let (latitude, longitude) = latitude.zip(longitude)?;
+    if latitude == NO_COORDINATE || longitude == NO_COORDINATE {
+        return None;
+    }
+    Location::new(latitude, longitude)
+}
+
+#[test]
+fn test_read_assets() {
+    let bundle = std::env::temp_dir().join(format!(
+        "deduper-library-{}.{}",
+        std::process::id(),
+        EXTENSION
+    ));
+    std::fs::create_dir_all(bundle.join("database")).unwrap();
+    let conn = Connection::open(bundle.join(PHOTOS_DATABASE)).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE ZASSET (Z_PK INTEGER PRIMARY KEY, ZDIRECTORY TEXT, ZFILENAME TEXT,
+            ZDATECREATED REAL, ZFAVORITE INTEGER, ZLATITUDE REAL, ZLONGITUDE REAL,
+            ZTRASHEDSTATE INTEGER);
+        CREATE TABLE ZADDITIONALASSETATTRIBUTES (Z_PK INTEGER PRIMARY KEY, ZASSET INTEGER,
+            ZORIGINALFILENAME TEXT, ZTIMEZONEOFFSET INTEGER);
+        CREATE TABLE ZGENERICALBUM (Z_PK INTEGER PRIMARY KEY, ZTITLE TEXT, ZKIND INTEGER,
+            ZTRASHEDSTATE INTEGER);
+        CREATE TABLE Z_28ASSETS (Z_28ALBUMS INTEGER, Z_3ASSETS INTEGER);
+        INSERT INTO ZASSET VALUES (1, 'A', 'A1B2.jpeg', 715293781, 1, 48.85, 2.35, 0);
+        INSERT INTO ZASSET VALUES (2, 'B', 'B3C4.mov', 715293781, 0, -180.0, -180.0, 0);
+        INSERT INTO ZASSET VALUES (3, 'C', 'C5D6.jpeg', 715293781, 0, -180.0, -180.0, 1);
+        INSERT INTO ZADDITIONALASSETATTRIBUTES VALUES (1, 1, 'IMG_0001.JPG', 7200);
+        INSERT INTO ZGENERICALBUM VALUES (1, 'Paris', 2, 0);
+        INSERT INTO ZGENERICALBUM VALUES (2, 'Recents', 1505, 0);
+        INSERT INTO Z_28ASSETS VALUES (1, 1);
+        INSERT INTO Z_28ASSETS VALUES (2, 1);",
+    )
+    .unwrap();
+    drop(conn);
+
+    assert!(is_library(&bundle));
+    assert_eq!(bundle.join(PHOTOS_ORIGINALS), walked(&bundle));
+    let library = PhotosLibrary::open(std::slice::from_ref(&bundle)).unwrap();
+    assert_eq!(2, library.len());
+    let asset = library
+        .asset(&bundle.join("originals/A/A1B2.jpeg"))
+        .unwrap();
+    assert_eq!(Some("IMG_0001.JPG"), asset.original_name.as_deref());
+    assert_eq!(
+        Some("2023-09-01T22:43:01+02:00".to_owned()),
+        asset.timestamp.map(|timestamp| timestamp.to_rfc3339())
+    );
+    assert_eq!(Location::new(48.85, 2.35), asset.location);
+    assert!(asset.favorite);
+    assert_eq!(vec!["Paris".to_owned()], asset.albums);
+    let video = library.asset(&bundle.join("originals/B/B3C4.mov")).unwrap();
+    assert_eq!(None, video.location);
+    assert!(video.albums.is_empty());
+    std::fs::remove_dir_all(&bundle).unwrap();
+
+    // a bundle whose database is gone is walked, but not read
+    std::fs::create_dir_all(bundle.join(PHOTOS_ORIGINALS)).unwrap();
+    let library = PhotosLibrary::open(std::slice::from_ref(&bundle)).unwrap();
+    assert!(library.is_empty());
+    assert_eq!(vec![bundle.clone()], library.unread);
+    assert_eq!(bundle.join(PHOTOS_ORIGINALS), walked(&bundle));
+    std::fs::remove_dir_all(&bundle).unwrap();
+}
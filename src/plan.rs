@@ -0,0 +1,106 @@
+use std::{
+    collections::HashSet,
+    fs::File,
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PlannedAction {
+    Link {
+        source: PathBuf,
+        destination: PathBuf,
+    },
+    SkipDuplicate {
+        source: PathBuf,
+        destination: PathBuf,
+    },
+}
+
+/// Collects what a run would do without touching the filesystem.
+#[derive(Default)]
+pub struct DryRun {
+    claimed: Mutex<HashSet<PathBuf>>,
+    actions: Mutex<Vec<PlannedAction>>,
+}
+
+impl DryRun {
+    /// Records the placement of `source` at `destination`. A destination that
+    /// already exists, or was claimed earlier in this run, is reported as a
+    /// duplicate, mirroring how a real run refuses to overwrite links.
+    pub fn record(&self, source: &Path, destination: PathBuf) {
+        let fresh =
+            !destination.exists() && self.claimed.lock().unwrap().insert(destination.clone());
+        let source = source.to_owned();
+        let action = if fresh {
+            PlannedAction::Link {
+                source,
+                destination,
+            }
+        } else {
+            PlannedAction::SkipDuplicate {
+                source,
+                destination,
+            }
+        };
+        self.actions.lock().unwrap().push(action);
+    }
+
+    pub fn into_actions(self) -> Vec<PlannedAction> {
+        let mut actions = self.actions.into_inner().unwrap();
+        actions.sort_by(|a, b| a.source().cmp(b.source()));
+        actions
+    }
+}
+
+impl PlannedAction {
+    pub fn source(&self) -> &Path {
+        match self {
+            PlannedAction::Link { source, .. } | PlannedAction::SkipDuplicate { source, .. } => {
+                source
+            }
+        }
+    }
+}
+
+pub fn print_actions(actions: &[PlannedAction]) {
+    let mut links = 0;
+    for action in actions {
+        match action {
+            PlannedAction::Link {
+                source,
+                destination,
+            } => {
+                links += 1;
+                println!(
+                    "would link {} -> {}",
+                    destination.to_string_lossy(),
+                    source.to_string_lossy()
+                );
+            }
+            PlannedAction::SkipDuplicate {
+                source,
+                destination,
+            } => println!(
+                "would skip duplicate {} ({} already taken)",
+                source.to_string_lossy(),
+                destination.to_string_lossy()
+            ),
+        }
+    }
+    println!(
+        "dry run: {} to link, {} duplicates skipped",
+        links,
+        actions.len() - links
+    );
+}
+
+pub fn write_json(actions: &[PlannedAction], path: &Path) -> std::io::Result<()> {
+    let writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(writer, actions)?;
+    Ok(())
+}
@@ -0,0 +1,538 @@
+use std::{
+    borrow::Cow,
+    ffi::OsStr,
+    fs::{File, Metadata},
+    io,
+    ops::Deref,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Creates a symlink to the file `source`. Windows only allows this with
+/// Developer Mode or the symlink privilege; see [`symlink_not_permitted`].
+#[cfg(unix)]
+pub fn symlink_file(source: &Path, destination: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(source, destination)
+}
+
+#[cfg(windows)]
+pub fn symlink_file(source: &Path, destination: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(source, destination)
+}
+
+/// Whether a failed symlink was refused for lack of privilege, in which case
+/// callers fall back to copying. Junctions are no alternative since they
+/// only point at directories.
+#[cfg(unix)]
+pub fn symlink_not_permitted(_err: &io::Error) -> bool {
+    false
+}
+
+#[cfg(windows)]
+pub fn symlink_not_permitted(err: &io::Error) -> bool {
+    const ERROR_PRIVILEGE_NOT_HELD: i32 = 1314;
+    err.raw_os_error() == Some(ERROR_PRIVILEGE_NOT_HELD)
+}
+
+/// Whether a hard link or rename failed because the paths are on different
+/// filesystems.
+#[cfg(unix)]
+pub fn is_cross_device(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::EXDEV)
+}
+
+#[cfg(windows)]
+pub fn is_cross_device(err: &io::Error) -> bool {
+    const ERROR_NOT_SAME_DEVICE: i32 = 17;
+    err.raw_os_error() == Some(ERROR_NOT_SAME_DEVICE)
+}
+
+/// Whether both metadata describe the same file, i.e. hard links of each
+/// other. Stable Rust exposes no file index on Windows, so there this is
+/// always false and already linked duplicates are simply linked again.
+#[cfg(unix)]
+pub fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    (a.dev(), a.ino()) == (b.dev(), b.ino())
+}
+
+#[cfg(windows)]
+pub fn same_file(_a: &Metadata, _b: &Metadata) -> bool {
+    false
+}
+
+/// The device and inode of a file, which all its hard links share.
+/// Stable Rust exposes neither on Windows, so there it is `None`.
+#[cfg(unix)]
+pub fn file_id(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(windows)]
+pub fn file_id(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// How many hard links the file has; 1 on Windows.
+#[cfg(unix)]
+pub fn link_count(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.nlink()
+}
+
+#[cfg(windows)]
+pub fn link_count(_metadata: &Metadata) -> u64 {
+    1
+}
+
+/// Gives the copy `destination` the mode bits, owner and extended
+/// attributes of `source`, which also carry POSIX ACLs and SELinux labels.
+/// The owner is only set when running as root, the only user allowed to
+/// give files away; attributes the destination filesystem does not support
+/// are dropped.
+#[cfg(unix)]
+pub fn copy_metadata(source: &File, destination: &File) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = source.metadata()?;
+    // SAFETY: geteuid cannot fail
+    if unsafe { libc::geteuid() } == 0 {
+        std::os::unix::fs::fchown(destination, Some(metadata.uid()), Some(metadata.gid()))?;
+    }
+    // after the owner, as changing it clears the setuid and setgid bits
+    destination.set_permissions(metadata.permissions())?;
+    #[cfg(target_os = "linux")]
+    copy_xattrs(source, destination)?;
+    Ok(())
+}
+
+/// Only the read-only flag makes up the permissions of a Windows file.
+#[cfg(windows)]
+pub fn copy_metadata(source: &File, destination: &File) -> io::Result<()> {
+    destination.set_permissions(source.metadata()?.permissions())
+}
+
+#[cfg(target_os = "linux")]
+fn copy_xattrs(source: &File, destination: &File) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    let (src, dst) = (source.as_raw_fd(), destination.as_raw_fd());
+    // SAFETY: a null buffer of size 0 asks for the size of the list
+    let names = xattr_buffer(|buf, size| unsafe { libc::flistxattr(src, buf, size) });
+    let names = match names {
+        Ok(names) => names,
+        Err(err) if err.raw_os_error() == Some(libc::ENOTSUP) => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    for name in names.split(|&b| b == 0).filter(|name| !name.is_empty()) {
+        let name = std::ffi::CString::new(name).expect("names are split at NUL");
+        // SAFETY: as above, with the NUL-terminated name of one attribute
+        let value = xattr_buffer(|buf, size| unsafe {
+            libc::fgetxattr(src, name.as_ptr(), buf.cast(), size)
+        });
+        let value = match value {
+            Ok(value) => value,
+            // removed since it was listed
+            Err(err) if err.raw_os_error() == Some(libc::ENODATA) => continue,
+            Err(err) => return Err(err),
+        };
+        // SAFETY: the value is valid for its length
+        let set =
+            unsafe { libc::fsetxattr(dst, name.as_ptr(), value.as_ptr().cast(), value.len(), 0) };
+        if set != 0 {
+            let err = io::Error::last_os_error();
+            // e.g. security.* without the privilege, or user.* on tmpfs
+            if !matches!(err.raw_os_error(), Some(libc::ENOTSUP | libc::EPERM)) {
+                return Err(err);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reads a list or value of extended attributes with `read`, which is
+/// asked for the size first and retried if it grew in between.
+#[cfg(target_os = "linux")]
+fn xattr_buffer(
+    read: impl Fn(*mut libc::c_char, libc::size_t) -> libc::ssize_t,
+) -> io::Result<Vec<u8>> {
+    loop {
+        let size = read(std::ptr::null_mut(), 0);
+        if size < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut buf = vec![0u8; size as usize];
+        let read = read(buf.as_mut_ptr().cast(), buf.len());
+        if read >= 0 {
+            buf.truncate(read as usize);
+            return Ok(buf);
+        }
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(libc::ERANGE) {
+            return Err(err);
+        }
+    }
+}
+
+/// Renames `from` to `to` in one step that fails with
+/// `ErrorKind::AlreadyExists` when something is at `to`, or `None` where
+/// the kernel or filesystem cannot refuse a name, such as before Linux 3.15.
+#[cfg(target_os = "linux")]
+pub fn rename_noreplace(from: &Path, to: &Path) -> Option<io::Result<()>> {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let from = CString::new(from.as_os_str().as_bytes()).ok()?;
+    let to = CString::new(to.as_os_str().as_bytes()).ok()?;
+    // SAFETY: both paths are NUL-terminated and outlive the call
+    let renamed = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            from.as_ptr(),
+            libc::AT_FDCWD,
+            to.as_ptr(),
+            libc::RENAME_NOREPLACE,
+        )
+    };
+    if renamed == 0 {
+        return Some(Ok(()));
+    }
+    let err = io::Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EINVAL | libc::ENOSYS) => None,
+        _ => Some(Err(err)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn rename_noreplace(_from: &Path, _to: &Path) -> Option<io::Result<()>> {
+    None
+}
+
+/// Raw bytes of a path component; lossily converted UTF-8 off Unix.
+#[cfg(unix)]
+pub fn os_bytes(s: &OsStr) -> Cow<'_, [u8]> {
+    use std::os::unix::ffi::OsStrExt;
+
+    Cow::Borrowed(s.as_bytes())
+}
+
+#[cfg(windows)]
+pub fn os_bytes(s: &OsStr) -> Cow<'_, [u8]> {
+    match s.to_string_lossy() {
+        Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+    }
+}
+
+/// The path of the raw bytes [`os_bytes`] gave; lossily converted UTF-8
+/// off Unix.
+#[cfg(unix)]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+
+    PathBuf::from(OsStr::from_bytes(bytes))
+}
+
+#[cfg(windows)]
+pub fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// The raw bytes of `path` percent-encoded, leaving unreserved ASCII and
+/// `/` as they are, so a name that is not UTF-8 survives text formats and
+/// URLs. [`decode_path`] reverses it.
+pub fn encode_path(path: &Path) -> String {
+    let bytes = os_bytes(path.as_os_str());
+    let mut encoded = String::with_capacity(bytes.len());
+    for &byte in bytes.iter() {
+        if byte.is_ascii_alphanumeric() || b"/-_.~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// The path [`encode_path`] encoded, or `None` for an invalid escape.
+pub fn decode_path(encoded: &str) -> Option<PathBuf> {
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut rest = encoded.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    Some(path_from_bytes(&bytes))
+}
+
+/// Like [`std::fs::canonicalize`], but without the `\\?\` prefix Windows
+/// gives canonical paths wherever the path works without it, so they can be
+/// compared with paths given on the command line and opened by other
+/// programs. Longer paths keep the prefix, which is what lets them be
+/// opened at all.
+pub fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+    let canonical = std::fs::canonicalize(path)?;
+    if !cfg!(windows) {
+        return Ok(canonical);
+    }
+    Ok(canonical
+        .to_str()
+        .and_then(strip_verbatim)
+        .map(PathBuf::from)
+        .unwrap_or(canonical))
+}
+
+/// `\\?\C:\a` as `C:\a` and `\\?\UNC\server\share` as `\\server\share`,
+/// unless the result is too long for `MAX_PATH`.
+fn strip_verbatim(path: &str) -> Option<String> {
+    const MAX_PATH: usize = 260;
+    let rest = path.strip_prefix(r"\\?\")?;
+    let stripped = match rest.strip_prefix(r"UNC\") {
+        Some(share) => format!(r"\\{}", share),
+        None => {
+            let drive = rest.as_bytes();
+            let is_drive = drive.len() >= 3
+                && drive[0].is_ascii_alphabetic()
+                && drive[1] == b':'
+                && drive[2] == b'\\';
+            if !is_drive {
+                return None;
+            }
+            rest.to_owned()
+        }
+    };
+    // the limit counts the terminating NUL
+    (stripped.len() < MAX_PATH).then_some(stripped)
+}
+
+/// Runs the child of `command` at niceness `nice` and, on Linux, in the idle
+/// I/O class when `idle_io` is set, like `nice -n` and `ionice -c 3`.
+#[cfg(unix)]
+pub fn lower_priority(command: &mut Command, nice: Option<i32>, idle_io: bool) {
+    use std::os::unix::process::CommandExt;
+
+    if nice.is_none() && !idle_io {
+        return;
+    }
+    // SAFETY: only async-signal-safe system calls run between fork and exec
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(nice) = nice {
+                if libc::setpriority(libc::PRIO_PROCESS, 0, nice) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if idle_io {
+                // best effort, like ionice on kernels without the idle class
+                let _ = self::idle_io();
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Windows has no niceness; any `nice` above zero starts the child in the
+/// below-normal priority class. I/O priority is left alone.
+#[cfg(windows)]
+pub fn lower_priority(command: &mut Command, nice: Option<i32>, _idle_io: bool) {
+    use std::os::windows::process::CommandExt;
+
+    const BELOW_NORMAL_PRIORITY_CLASS: u32 = 0x4000;
+    if nice.is_some_and(|nice| nice > 0) {
+        command.creation_flags(BELOW_NORMAL_PRIORITY_CLASS);
+    }
+}
+
+/// Moves the calling thread, and the threads and processes it starts from
+/// now on, to the idle I/O class, as `ionice -c 3` does.
+#[cfg(target_os = "linux")]
+pub fn idle_io() -> io::Result<()> {
+    const IOPRIO_WHO_PROCESS: libc::c_long = 1;
+    const IOPRIO_CLASS_IDLE: libc::c_long = 3 << 13;
+    // SAFETY: only changes the I/O priority of the calling thread
+    let result = unsafe {
+        libc::syscall(
+            libc::SYS_ioprio_set,
+            IOPRIO_WHO_PROCESS,
+            0,
+            IOPRIO_CLASS_IDLE,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn idle_io() -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Bytes an unprivileged user may still write to the filesystem holding
+/// `path`, which has to exist.
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> io::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: the path is NUL-terminated and the buffer fits a statvfs
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: statvfs filled it in
+    let stat = unsafe { stat.assume_init() };
+    #[allow(clippy::useless_conversion)] // the field types differ by platform
+    Ok(u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize)))
+}
+
+/// Stable Rust has no free space query on Windows; callers skip the check.
+#[cfg(windows)]
+pub fn available_space(_path: &Path) -> io::Result<u64> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// The one minute load average, where the system reports one.
+#[cfg(unix)]
+pub fn load_average() -> Option<f64> {
+    let mut load = [0.0; 1];
+    // SAFETY: the buffer holds the one sample asked for
+    (unsafe { libc::getloadavg(load.as_mut_ptr(), 1) } == 1).then_some(load[0])
+}
+
+#[cfg(windows)]
+pub fn load_average() -> Option<f64> {
+    None
+}
+
+/// A read-only memory map of a whole, non-empty file. Reading it faults the
+/// pages in straight from the page cache, sparing the copy into a buffer;
+/// the file must not shrink while mapped, or reading past its new end
+/// raises SIGBUS.
+#[cfg(unix)]
+pub struct Mmap {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+#[cfg(unix)]
+impl Mmap {
+    pub fn map(file: &File) -> io::Result<Self> {
+        use std::os::unix::io::AsRawFd;
+
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::from(io::ErrorKind::Unsupported))?;
+        if len == 0 {
+            // zero-length maps are refused
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        // SAFETY: a fresh shared read-only mapping of an open file
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the range was just mapped; the advice is only a hint
+        unsafe { libc::madvise(ptr, len, libc::MADV_SEQUENTIAL) };
+        Ok(Self { ptr, len })
+    }
+}
+
+#[cfg(unix)]
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the mapping lives as long as `self` and is never written
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        // SAFETY: unmaps exactly the range mapped in `map`
+        unsafe { libc::munmap(self.ptr, self.len) };
+    }
+}
+
+/// Memory maps are only made on Unix; elsewhere callers read the file.
+#[cfg(windows)]
+pub struct Mmap(Vec<u8>);
+
+#[cfg(windows)]
+impl Mmap {
+    pub fn map(_file: &File) -> io::Result<Self> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+}
+
+#[cfg(windows)]
+impl Deref for Mmap {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[test]
+fn test_encode_path() {
+    assert_eq!(
+        "/photos/a%20b%25.jpg",
+        encode_path(Path::new("/photos/a b%.jpg"))
+    );
+    assert_eq!(
+        "/photos/2019%20trip/%C3%A4%20%23%201.jpg",
+        encode_path(Path::new("/photos/2019 trip/ä # 1.jpg"))
+    );
+    assert_eq!(
+        Some(PathBuf::from("/photos/a b%.jpg")),
+        decode_path("/photos/a%20b%25.jpg")
+    );
+    assert_eq!(None, decode_path("/photos/a%2"));
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+
+        let latin1 = Path::new(OsStr::from_bytes(b"/photos/caf\xe9.jpg"));
+        assert_eq!("/photos/caf%E9.jpg", encode_path(latin1));
+        assert_eq!(Some(latin1.to_owned()), decode_path(&encode_path(latin1)));
+    }
+}
+
+#[test]
+fn test_strip_verbatim() {
+    assert_eq!(
+        Some(r"C:\photos\a.jpg".to_owned()),
+        strip_verbatim(r"\\?\C:\photos\a.jpg")
+    );
+    assert_eq!(
+        Some(r"\\nas\photos\a.jpg".to_owned()),
+        strip_verbatim(r"\\?\UNC\nas\photos\a.jpg")
+    );
+    assert_eq!(None, strip_verbatim(r"\\?\Volume{1234}\a.jpg"));
+    assert_eq!(None, strip_verbatim(r"C:\photos\a.jpg"));
+    let long = format!(r"\\?\C:\{}", "a".repeat(300));
+    assert_eq!(None, strip_verbatim(&long));
+}
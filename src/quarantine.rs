@@ -0,0 +1,160 @@
+//! A quarantine for duplicates: instead of being deleted, they are moved
+//! into a folder of the day below the quarantine directory, at their
+//! absolute path, so they can be reviewed and put back before the folders
+//! older than a review period are purged.
+
+use std::{
+    fs, io,
+    path::{Component, Path, PathBuf},
+};
+
+use chrono::{Local, NaiveDate};
+use walkdir::WalkDir;
+
+use crate::{
+    linker::{self, LinkStrategy},
+    platform,
+};
+
+/// How the folders of the days are named.
+const DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// Moves `path` into today's folder of `quarantine_dir`, e.g.
+/// `/photos/2023/a.jpg` to `DIR/2024-06-01/photos/2023/a.jpg`, and returns
+/// the new path. A name already taken gets a number, `a.1.jpg`.
+pub fn quarantine(path: &Path, quarantine_dir: &Path) -> io::Result<PathBuf> {
+    let path = platform::canonicalize(path)?;
+    let relative = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name),
+            _ => None,
+        })
+        .collect::<PathBuf>();
+    let destination = quarantine_dir
+        .join(Local::now().format(DATE_FORMAT).to_string())
+        .join(relative);
+    if let Some(dir) = destination.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    for n in 0.. {
+        let candidate = match n {
+            0 => destination.clone(),
+            n => linker::numbered(&destination, n),
+        };
+        match linker::place(LinkStrategy::Move, &path, &candidate) {
+            Ok(()) => return Ok(candidate),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    unreachable!()
+}
+
+/// Moves `quarantined`, a file [`quarantine`] moved, back to `path`. An
+/// existing `path` is never overwritten.
+pub fn restore(quarantined: &Path, path: &Path) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    linker::place(LinkStrategy::Move, quarantined, path)
+}
+
+/// The folders of the days before `before` in `quarantine_dir`, oldest
+/// first. Entries not named like a date are no folders of the quarantine
+/// and left out.
+pub fn expired(quarantine_dir: &Path, before: NaiveDate) -> io::Result<Vec<(NaiveDate, PathBuf)>> {
+    let mut folders = Vec::new();
+    for entry in fs::read_dir(quarantine_dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(date) = name
+            .to_str()
+            .and_then(|name| NaiveDate::parse_from_str(name, DATE_FORMAT).ok())
+        else {
+            continue;
+        };
+        if date < before && entry.file_type()?.is_dir() {
+            folders.push((date, entry.path()));
+        }
+    }
+    folders.sort();
+    Ok(folders)
+}
+
+/// The number of files below `folder` and their bytes.
+pub fn usage(folder: &Path) -> (u64, u64) {
+    WalkDir::new(folder)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .fold((0, 0), |(files, bytes), entry| {
+            let size = entry.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            (files + 1, bytes + size)
+        })
+}
+
+/// Deletes `folder` with everything in it and returns its [`usage`].
+pub fn purge(folder: &Path) -> io::Result<(u64, u64)> {
+    let usage = usage(folder);
+    fs::remove_dir_all(folder)?;
+    Ok(usage)
+}
+
+#[test]
+fn test_quarantine() {
+    let dir = std::env::temp_dir().join(format!("deduper-quarantine-{}", std::process::id()));
+    let quarantine_dir = dir.join("quarantine");
+    let path = dir.join("photos/a.jpg");
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let mut quarantined = Vec::new();
+    for _ in 0..2 {
+        fs::write(&path, b"data").unwrap();
+        quarantined.push(quarantine(&path, &quarantine_dir).unwrap());
+    }
+    assert!(!path.exists());
+    let today = Local::now().date_naive();
+    assert!(quarantined[0].starts_with(quarantine_dir.join(today.format(DATE_FORMAT).to_string())));
+    assert!(quarantined[0].ends_with("photos/a.jpg"));
+    assert!(quarantined[1].ends_with("photos/a.1.jpg"));
+
+    restore(&quarantined[1], &path).unwrap();
+    assert!(path.exists());
+    // the name is taken now
+    assert!(restore(&quarantined[0], &path).is_err());
+
+    fs::create_dir_all(quarantine_dir.join("notes")).unwrap();
+    assert!(expired(&quarantine_dir, today).unwrap().is_empty());
+    let folders = expired(&quarantine_dir, today.succ_opt().unwrap()).unwrap();
+    assert_eq!(1, folders.len());
+    assert_eq!((1, 4), purge(&folders[0].1).unwrap());
+    assert!(quarantine_dir.join("notes").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[test]
+fn test_quarantine_non_utf8_name() {
+    use std::{ffi::OsStr, os::unix::ffi::OsStrExt};
+
+    let dir =
+        std::env::temp_dir().join(format!("deduper-quarantine-latin1-{}", std::process::id()));
+    let path = dir.join(OsStr::from_bytes(b"caf\xe9.jpg"));
+    fs::create_dir_all(&dir).unwrap();
+    let mut quarantined = Vec::new();
+    for _ in 0..2 {
+        fs::write(&path, b"data").unwrap();
+        quarantined.push(quarantine(&path, &dir.join("quarantine")).unwrap());
+    }
+    assert_eq!(
+        Some(OsStr::from_bytes(b"caf\xe9.jpg")),
+        quarantined[0].file_name()
+    );
+    assert_eq!(
+        Some(OsStr::from_bytes(b"caf\xe9.1.jpg")),
+        quarantined[1].file_name()
+    );
+    restore(&quarantined[1], &path).unwrap();
+    assert!(path.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
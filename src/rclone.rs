@@ -0,0 +1,284 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdout, Command, Output, Stdio},
+    thread::{self, JoinHandle},
+};
+
+use chrono::DateTime;
+use serde::Deserialize;
+
+use crate::{
+    hasher::{self, FileHash, HashAlgorithm},
+    linker::LinkStrategy,
+    source::{Entry, Source, RCLONE_SCHEME},
+};
+
+/// Exit codes of rclone for a directory and a file that do not exist.
+const NOT_FOUND: [i32; 2] = [3, 4];
+
+/// The rclone path of `path`, `gdrive:Photos/a.jpg` for
+/// `rclone:gdrive:Photos/a.jpg`, or `None` for a local path.
+pub fn remote_path(path: &Path) -> Option<&str> {
+    path.to_str()?.strip_prefix(RCLONE_SCHEME)
+}
+
+/// `path` below the remote path `dir`, without the `/` that would make it
+/// absolute after a bare remote such as `gdrive:`.
+fn join(dir: &str, path: &str) -> String {
+    if dir.is_empty() || dir.ends_with([':', '/']) {
+        format!("{}{}", dir, path)
+    } else {
+        format!("{}/{}", dir, path)
+    }
+}
+
+/// A file as `rclone lsjson` lists it.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Listed {
+    path: String,
+    /// -1 where the remote does not know, as for Google Docs
+    size: i64,
+    mod_time: String,
+    #[serde(default)]
+    is_dir: bool,
+}
+
+/// The files below a path of any remote rclone is configured for, listed
+/// with `rclone lsjson` and read with `rclone cat`. rclone has to be on
+/// the `PATH`.
+pub struct RcloneSource {
+    /// The rclone path, e.g. `gdrive:Photos`
+    remote: String,
+}
+
+impl RcloneSource {
+    pub fn new(remote: &str) -> Self {
+        Self {
+            remote: remote.to_owned(),
+        }
+    }
+}
+
+impl Source for RcloneSource {
+    fn list(&self) -> io::Result<Vec<Entry>> {
+        let output = run(Command::new("rclone")
+            .args(["lsjson", "--recursive", "--files-only", "--no-mimetype"])
+            .arg(&self.remote))?;
+        Ok(parse_list(&output.stdout)?
+            .into_iter()
+            .map(|listed| Entry {
+                path: PathBuf::from(format!(
+                    "{}{}",
+                    RCLONE_SCHEME,
+                    join(&self.remote, &listed.path)
+                )),
+                size: listed.size as u64,
+                modified_at: modified_at(&listed.mod_time),
+            })
+            .collect())
+    }
+
+    fn open(&self, entry: &Entry) -> io::Result<Box<dyn Read + Send>> {
+        let remote = remote_path(&entry.path).ok_or_else(|| not_remote(&entry.path))?;
+        Ok(Box::new(Cat::spawn(remote)?))
+    }
+}
+
+/// The files of a `lsjson` listing, leaving out directories and files of
+/// unknown size.
+fn parse_list(json: &[u8]) -> io::Result<Vec<Listed>> {
+    let listed: Vec<Listed> = serde_json::from_slice(json)?;
+    Ok(listed
+        .into_iter()
+        .filter(|listed| !listed.is_dir && listed.size >= 0)
+        .collect())
+}
+
+/// Unix seconds of an RFC 3339 `ModTime`; 0 for one rclone could not read.
+fn modified_at(mod_time: &str) -> i64 {
+    DateTime::parse_from_rfc3339(mod_time).map_or(0, |time| time.timestamp())
+}
+
+/// The output of `rclone cat`, checked for a failure once it ends. What it
+/// prints to stderr is read on a thread of its own, so rclone cannot block
+/// on a full stderr pipe while stdout is read.
+struct Cat {
+    child: Child,
+    stdout: ChildStdout,
+    stderr: Option<JoinHandle<Vec<u8>>>,
+    remote: String,
+}
+
+impl Cat {
+    fn spawn(remote: &str) -> io::Result<Self> {
+        let mut child = Command::new("rclone")
+            .arg("cat")
+            .arg(remote)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(missing_rclone)?;
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let mut pipe = child.stderr.take().expect("stderr is piped");
+        let stderr = thread::spawn(move || {
+            let mut stderr = Vec::new();
+            let _ = pipe.read_to_end(&mut stderr);
+            stderr
+        });
+        Ok(Self {
+            child,
+            stdout,
+            stderr: Some(stderr),
+            remote: remote.to_owned(),
+        })
+    }
+}
+
+impl Read for Cat {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stdout.read(buf)?;
+        if n == 0 && !buf.is_empty() {
+            let status = self.child.wait()?;
+            if !status.success() {
+                let stderr = self
+                    .stderr
+                    .take()
+                    .and_then(|stderr| stderr.join().ok())
+                    .unwrap_or_default();
+                return Err(failed(&format!("cat {}", self.remote), &stderr));
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for Cat {
+    fn drop(&mut self) {
+        // a reader dropped early leaves rclone streaming to nobody
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        if let Some(stderr) = self.stderr.take() {
+            let _ = stderr.join();
+        }
+    }
+}
+
+/// Size of the file at the rclone path `remote`, `None` when there is none.
+pub fn size(remote: &str) -> io::Result<Option<u64>> {
+    let output = Command::new("rclone")
+        .args(["lsjson", "--stat", "--no-mimetype", "--no-modtime"])
+        .arg(remote)
+        .output()
+        .map_err(missing_rclone)?;
+    if output
+        .status
+        .code()
+        .is_some_and(|code| NOT_FOUND.contains(&code))
+    {
+        return Ok(None);
+    }
+    if !output.status.success() {
+        return Err(failed(&format!("lsjson {}", remote), &output.stderr));
+    }
+    let listed: Listed = serde_json::from_slice(&output.stdout)?;
+    Ok((!listed.is_dir).then_some(listed.size.max(0) as u64))
+}
+
+/// Hash of the file at the rclone path `remote`, streamed with `rclone cat`.
+pub fn file_hash(remote: &str, algorithm: HashAlgorithm) -> io::Result<FileHash> {
+    hasher::reader_hash(Cat::spawn(remote)?, algorithm)
+}
+
+/// Places `source` at the rclone path `destination` with `rclone copyto`,
+/// deleting `source` afterwards when moving. Only copies and moves reach a
+/// remote. As with [`crate::linker::place`] an existing destination is
+/// never overwritten and surfaces as `ErrorKind::AlreadyExists`, though
+/// rclone cannot rule out another process taking the name in between.
+pub fn place(strategy: LinkStrategy, source: &Path, destination: &str) -> io::Result<()> {
+    if !matches!(strategy, LinkStrategy::Copy | LinkStrategy::Move) {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("files can only be copied or moved to {}", destination),
+        ));
+    }
+    if size(destination)?.is_some() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists", destination),
+        ));
+    }
+    run(Command::new("rclone")
+        .arg("copyto")
+        .arg(source)
+        .arg(destination))?;
+    if strategy == LinkStrategy::Move {
+        fs::remove_file(source)?;
+    }
+    Ok(())
+}
+
+/// Runs rclone, turning a failure into an error with what it printed.
+fn run(command: &mut Command) -> io::Result<Output> {
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .map_err(missing_rclone)?;
+    if !output.status.success() {
+        let args = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" ");
+        return Err(failed(&args, &output.stderr));
+    }
+    Ok(output)
+}
+
+fn failed(args: &str, stderr: &[u8]) -> io::Error {
+    let stderr = String::from_utf8_lossy(stderr);
+    io::Error::other(format!(
+        "rclone {} failed: {}",
+        args,
+        stderr.lines().last().unwrap_or_default().trim()
+    ))
+}
+
+fn missing_rclone(err: io::Error) -> io::Error {
+    if err.kind() == io::ErrorKind::NotFound {
+        return io::Error::new(io::ErrorKind::NotFound, "rclone is not on the PATH");
+    }
+    err
+}
+
+fn not_remote(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("{} is not an rclone path", path.to_string_lossy()),
+    )
+}
+
+#[test]
+fn test_parse_list() {
+    let json = br#"[
+        {"Path":"2023","Name":"2023","Size":-1,"ModTime":"2023-09-01T10:00:00Z","IsDir":true},
+        {"Path":"2023/IMG_0001.JPG","Name":"IMG_0001.JPG","Size":2048,"ModTime":"2023-09-01T12:30:00.123456789+02:00","IsDir":false},
+        {"Path":"Notes","Name":"Notes","Size":-1,"ModTime":"2023-09-02T00:00:00Z","IsDir":false}
+    ]"#;
+    let listed = parse_list(json).unwrap();
+    assert_eq!(1, listed.len());
+    assert_eq!("2023/IMG_0001.JPG", listed[0].path);
+    assert_eq!(2048, listed[0].size);
+    assert_eq!(1693564200, modified_at(&listed[0].mod_time));
+
+    assert_eq!("gdrive:Photos/a.jpg", join("gdrive:Photos", "a.jpg"));
+    assert_eq!("gdrive:a.jpg", join("gdrive:", "a.jpg"));
+    assert_eq!(
+        Some("gdrive:Photos/a.jpg"),
+        remote_path(Path::new("rclone:gdrive:Photos/a.jpg"))
+    );
+    assert_eq!(None, remote_path(Path::new("/photos/a.jpg")));
+}
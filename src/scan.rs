@@ -0,0 +1,858 @@
+//! Whole scans of a set of sources, for `deduper scan` and for programs
+//! that embed deduper, such as GUIs, which want progress reported to them
+//! rather than drawn on a terminal.
+
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use chrono::Utc;
+use rayon::prelude::*;
+
+use crate::{
+    database::{FileError, ScanCounts, DB},
+    error::{DeduperError, Result},
+    events::{EventSink, Events},
+    hasher::HashAlgorithm,
+    journal::{self, Journal},
+    media::{walk_files, Inspector, WalkOptions},
+    photoslibrary::{self, PhotosLibrary},
+    scanner::{self, ScanOutcome, Scanner},
+    source::{self, Entry, Source},
+    thumbnail::ThumbnailCache,
+    transcoder::FfmpegTools,
+    volume::Volumes,
+};
+
+/// What became of one file of a [`ScanBuilder`] run, passed to its
+/// progress callback.
+pub struct ScanProgress<'a> {
+    pub path: &'a Path,
+    pub outcome: FileOutcome<'a>,
+    /// Files finished so far, this one included
+    pub done: usize,
+    /// Files found below the sources before hashing started
+    pub total: usize,
+}
+
+/// What became of a file; see [`ScanProgress`].
+pub enum FileOutcome<'a> {
+    /// Recorded, or left as recorded as it is unchanged
+    Scanned(&'a ScanOutcome),
+    /// Could not be read or inspected
+    Failed(&'a DeduperError),
+    /// Finished by the interrupted scan this one resumes
+    Resumed,
+    /// Failed in an earlier scan and unchanged since, so not tried again
+    FailedBefore,
+}
+
+type ProgressCallback = Arc<dyn Fn(&ScanProgress) + Send + Sync>;
+
+/// Records every file below a set of sources, as `deduper scan` does:
+///
+/// ```no_run
+/// # use deduper::{database::DB, hasher::HashAlgorithm, ScanBuilder};
+/// # fn main() -> deduper::error::Result<()> {
+/// let db = DB::new("deduper.db".as_ref())?;
+/// let counts = ScanBuilder::new()
+///     .sources(["/photos"])
+///     .hash(HashAlgorithm::Blake3)
+///     .threads(8)
+///     .on_progress(|progress| println!("{}/{}", progress.done, progress.total))
+///     .run(&db)?;
+/// # Ok(())
+/// # }
+/// ```
+///
+/// Unchanged files are not hashed again, files recorded below the sources
+/// that are gone are forgotten, and the run is logged in the scan history.
+/// Files that fail are recorded in the errors table and, while unchanged,
+/// not tried again by later runs. Sources may be remote, see
+/// [`source::open_remote`], and the originals of Apple Photos libraries
+/// among them are recorded with their albums.
+#[derive(Clone, Default)]
+pub struct ScanBuilder {
+    sources: Vec<PathBuf>,
+    inspector: Inspector,
+    threads: usize,
+    read_concurrency: Option<usize>,
+    walk: WalkOptions,
+    force_rehash: bool,
+    refresh_metadata: bool,
+    resume: bool,
+    label: Option<String>,
+    library: Option<Arc<PhotosLibrary>>,
+    video_fingerprints: Option<FfmpegTools>,
+    thumbnails: Option<ThumbnailCache>,
+    on_progress: Option<ProgressCallback>,
+    sink: Option<Arc<dyn EventSink>>,
+}
+
+impl ScanBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Directories to scan, or locations of remote sources.
+    pub fn sources<P: Into<PathBuf>>(mut self, sources: impl IntoIterator<Item = P>) -> Self {
+        self.sources = sources.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Hash with `algorithm`, BLAKE3 by default. Shorthand for
+    /// [`ScanBuilder::inspector`] with a default [`Inspector`].
+    pub fn hash(mut self, algorithm: HashAlgorithm) -> Self {
+        self.inspector = Inspector::new(algorithm);
+        self
+    }
+
+    /// Hash and extract metadata with `inspector`.
+    pub fn inspector(mut self, inspector: Inspector) -> Self {
+        self.inspector = inspector;
+        self
+    }
+
+    /// Number of worker threads hashing and extracting (0, the default, is
+    /// one per CPU).
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Read up to `files` local files at once through tokio, for SMB and NFS
+    /// mounts where every read waits on the network; tens of files hide the
+    /// latency far better than one per thread. The threads still extract
+    /// metadata and record files as they are hashed. Needs a build with
+    /// the `async` feature; without it the run fails with
+    /// `ErrorKind::Unsupported`.
+    pub fn read_concurrency(mut self, files: usize) -> Self {
+        self.read_concurrency = Some(files.max(1));
+        self
+    }
+
+    /// How the sources are walked.
+    pub fn walk(mut self, walk: WalkOptions) -> Self {
+        self.walk = walk;
+        self
+    }
+
+    /// See [`Scanner::force_rehash`].
+    pub fn force_rehash(mut self, force_rehash: bool) -> Self {
+        self.force_rehash = force_rehash;
+        self
+    }
+
+    /// See [`Scanner::refresh_metadata`].
+    pub fn refresh_metadata(mut self, refresh_metadata: bool) -> Self {
+        self.refresh_metadata = refresh_metadata;
+        self
+    }
+
+    /// Continue the last interrupted scan of the same sources, skipping the
+    /// files it already finished.
+    pub fn resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+
+    /// See [`Scanner::label`].
+    pub fn label(mut self, label: Option<String>) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// The Apple Photos libraries among the sources, read from them when
+    /// the run starts without it.
+    pub fn photos_library(mut self, library: Arc<PhotosLibrary>) -> Self {
+        self.library = Some(library);
+        self
+    }
+
+    /// See [`Scanner::video_fingerprints`].
+    pub fn video_fingerprints(mut self, tools: FfmpegTools) -> Self {
+        self.video_fingerprints = Some(tools);
+        self
+    }
+
+    /// See [`Scanner::thumbnails`].
+    pub fn thumbnails(mut self, thumbnails: ThumbnailCache) -> Self {
+        self.thumbnails = Some(thumbnails);
+        self
+    }
+
+    /// Call `callback` after every file, from the worker thread that
+    /// scanned it.
+    pub fn on_progress(mut self, callback: impl Fn(&ScanProgress) + Send + Sync + 'static) -> Self {
+        self.on_progress = Some(Arc::new(callback));
+        self
+    }
+
+    /// Report files as they are discovered, hashed or fail to `sink`.
+    pub fn events(mut self, sink: Arc<dyn EventSink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    /// Scans the sources into `db` and returns what became of their files.
+    /// Files that cannot be read or inspected, including those that are no
+    /// media, are counted as failed and passed on to the callback; only
+    /// failures of the database, and of listing a remote source, end the
+    /// run.
+    pub fn run(&self, db: &DB) -> Result<ScanCounts> {
+        #[cfg(not(feature = "async"))]
+        if self.read_concurrency.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "reading files concurrently needs a build with the async feature",
+            )
+            .into());
+        }
+        let library = match &self.library {
+            Some(library) => library.clone(),
+            None => Arc::new(PhotosLibrary::open(&self.sources)?),
+        };
+        let remote = list_remote(&self.sources)?;
+        let parameters = journal::parameters(self.sources.iter().map(PathBuf::as_path));
+        let journal = Journal::open(db, "scan", &parameters, self.resume)?;
+        let known_errors = db
+            .lock()
+            .errors()?
+            .into_iter()
+            .filter(FileError::is_scan_failure)
+            .map(|known| (known.path, (known.size, known.modified_at)))
+            .collect();
+        let sources = self
+            .sources
+            .iter()
+            .map(|source| source.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        let scan_id = db.lock().start_scan(&sources, Utc::now().timestamp())?;
+        let mut scanner = Scanner::new(self.inspector.clone().photos_library(library.clone()))
+            .force_rehash(self.force_rehash)
+            .refresh_metadata(self.refresh_metadata)
+            .log_events(scan_id)
+            .volumes(Volumes::detect())
+            .label(self.label.clone());
+        if let Some(tools) = &self.video_fingerprints {
+            scanner = scanner.video_fingerprints(tools.clone());
+        }
+        if let Some(thumbnails) = &self.thumbnails {
+            scanner = scanner.thumbnails(thumbnails.clone());
+        }
+        let (scanner, events) = match &self.sink {
+            Some(sink) => (scanner.events(sink.clone()), Events::new(sink.clone())),
+            None => (scanner, Events::default()),
+        };
+        // only the originals of a library are media
+        let walked = self
+            .sources
+            .iter()
+            .filter(|source| !source::is_remote(source))
+            .map(|source| photoslibrary::walked(source))
+            .collect::<Vec<_>>();
+        let total = walk_files(&walked, &self.walk).count()
+            + remote
+                .iter()
+                .map(|(_, entries)| entries.len())
+                .sum::<usize>();
+        let listed = remote
+            .iter()
+            .flat_map(|(_, entries)| entries.iter().map(|entry| entry.path.clone()))
+            .collect::<HashSet<_>>();
+        let state = ScanState {
+            builder: self,
+            scanner,
+            events,
+            db,
+            journal,
+            library,
+            known_errors,
+            total,
+            done: AtomicUsize::new(0),
+            recorded: AtomicU64::new(0),
+            unchanged: AtomicU64::new(0),
+            failed: AtomicU64::new(0),
+            aborted: Mutex::new(None),
+        };
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(self.threads)
+            .build()
+            .map_err(io::Error::other)?;
+        let walk = walk_files(&walked, &self.walk);
+        match self.read_concurrency {
+            #[cfg(feature = "async")]
+            Some(files) => state.scan_async(walk, files, &pool)?,
+            _ => pool.install(|| walk.par_bridge().for_each(|entry| state.scan(entry))),
+        }
+        pool.install(|| {
+            remote
+                .into_iter()
+                .flat_map(|(source, entries)| {
+                    entries
+                        .into_iter()
+                        .map(move |entry| (source.clone(), entry))
+                })
+                .par_bridge()
+                .for_each(|(source, entry)| state.scan_remote(source.as_ref(), entry));
+        });
+        let ScanState {
+            scanner,
+            journal,
+            recorded,
+            unchanged,
+            failed,
+            aborted,
+            ..
+        } = state;
+        if let Some(err) = aborted.into_inner().unwrap() {
+            return Err(err);
+        }
+        let deleted = scanner.forget_deleted(&self.sources, Some(&listed), db)?;
+        db.flush()?;
+        journal.finish(db)?;
+        let counts = ScanCounts {
+            recorded: recorded.into_inner(),
+            unchanged: unchanged.into_inner(),
+            failed: failed.into_inner(),
+            deleted,
+        };
+        db.lock()
+            .finish_scan(scan_id, Utc::now().timestamp(), &counts)?;
+        Ok(counts)
+    }
+}
+
+/// A remote source and the files it lists.
+type Listing = (Arc<dyn Source>, Vec<Entry>);
+
+/// Lists the remote sources among `sources`.
+fn list_remote(sources: &[PathBuf]) -> Result<Vec<Listing>> {
+    let mut listed = Vec::new();
+    for location in sources {
+        let Some(source) = source::open_remote(location) else {
+            continue;
+        };
+        let listing = source.and_then(|source| {
+            let entries = source.list()?;
+            Ok((Arc::from(source), entries))
+        });
+        match listing {
+            Ok(listing) => listed.push(listing),
+            Err(err) => {
+                let message = format!("failed to list {}: {}", location.to_string_lossy(), err);
+                return Err(io::Error::new(err.kind(), message).into());
+            }
+        }
+    }
+    Ok(listed)
+}
+
+/// Panics of a read carry on in the caller, as they do under rayon.
+#[cfg(feature = "async")]
+fn resume_panic(result: std::result::Result<(), tokio::task::JoinError>) {
+    if let Err(err) = result {
+        if let Ok(panic) = err.try_into_panic() {
+            std::panic::resume_unwind(panic);
+        }
+    }
+}
+
+/// What every file of a run is recorded with and counted in.
+struct ScanState<'a> {
+    builder: &'a ScanBuilder,
+    scanner: Scanner,
+    events: Events,
+    db: &'a DB,
+    journal: Journal,
+    library: Arc<PhotosLibrary>,
+    /// Size and mtime of the files that failed in earlier scans
+    known_errors: HashMap<PathBuf, (Option<u64>, Option<i64>)>,
+    total: usize,
+    done: AtomicUsize,
+    recorded: AtomicU64,
+    unchanged: AtomicU64,
+    failed: AtomicU64,
+    /// The failure of the database that ends the run, after which the
+    /// remaining files are passed over
+    aborted: Mutex<Option<DeduperError>>,
+}
+
+impl ScanState<'_> {
+    /// Records the walked `entry` unless it is skipped.
+    fn scan(&self, entry: walkdir::Result<PathBuf>) {
+        if let Some(path) = self.admit(entry) {
+            let outcome = self.scanner.scan_file(&path, self.db);
+            self.recorded(&path, outcome, || scanner::stat(&path));
+        }
+    }
+
+    /// The path of the walked `entry` when it is to be scanned; walk
+    /// errors and skipped files are reported instead.
+    fn admit(&self, entry: walkdir::Result<PathBuf>) -> Option<PathBuf> {
+        if self.is_aborted() {
+            return None;
+        }
+        let path = match entry {
+            Ok(path) => path,
+            Err(err) => {
+                let path = err.path().map(Path::to_owned).unwrap_or_default();
+                let err = io::Error::from(err).into();
+                self.events.error(&path, &err);
+                self.failed.fetch_add(1, Ordering::Relaxed);
+                self.report(&path, FileOutcome::Failed(&err));
+                return None;
+            }
+        };
+        self.events.discovered(&path);
+        if let Some(skipped) = self.skipped(&path, || scanner::stat(&path)) {
+            self.report(&path, skipped);
+            return None;
+        }
+        Some(path)
+    }
+
+    /// Scans the walked `entries` with up to `files` of them read at once
+    /// on a tokio runtime. The walk runs on a thread of its own and hands
+    /// files that need hashing to the runtime; those hashed, and the rest,
+    /// are inspected and recorded on `pool`.
+    #[cfg(feature = "async")]
+    fn scan_async(
+        &self,
+        entries: impl Iterator<Item = walkdir::Result<PathBuf>> + Send,
+        files: usize,
+        pool: &rayon::ThreadPool,
+    ) -> io::Result<()> {
+        use std::{sync::mpsc, thread};
+        use tokio::{sync::Semaphore, task::JoinSet};
+
+        use crate::hasher::{self, FileHash};
+
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(pool.current_num_threads())
+            .max_blocking_threads(files)
+            .build()?;
+        let algorithm = self.builder.inspector.algorithm();
+        // files to read, and files with their hash, if they were read, to
+        // inspect
+        let (read, mut to_read) = tokio::sync::mpsc::channel::<PathBuf>(files);
+        let (inspect, to_inspect) = mpsc::channel::<(PathBuf, Option<io::Result<FileHash>>)>();
+        thread::scope(|scope| {
+            let walked = inspect.clone();
+            scope.spawn(move || {
+                for entry in entries {
+                    let Some(path) = self.admit(entry) else {
+                        continue;
+                    };
+                    let sent = match self.scanner.needs_hash(&path, self.db) {
+                        true => read.blocking_send(path).is_ok(),
+                        false => walked.send((path, None)).is_ok(),
+                    };
+                    if !sent {
+                        break;
+                    }
+                }
+            });
+            scope.spawn(move || {
+                runtime.block_on(async {
+                    let slots = Arc::new(Semaphore::new(files));
+                    let mut reads = JoinSet::new();
+                    while let Some(path) = to_read.recv().await {
+                        let slot = slots
+                            .clone()
+                            .acquire_owned()
+                            .await
+                            .expect("the semaphore is never closed");
+                        let inspect = inspect.clone();
+                        reads.spawn(async move {
+                            let hash = hasher::file_hash_async(&path, algorithm).await;
+                            drop(slot);
+                            let _ = inspect.send((path, Some(hash)));
+                        });
+                        while let Some(result) = reads.try_join_next() {
+                            resume_panic(result);
+                        }
+                    }
+                    while let Some(result) = reads.join_next().await {
+                        resume_panic(result);
+                    }
+                });
+            });
+            pool.install(|| {
+                to_inspect
+                    .into_iter()
+                    .par_bridge()
+                    .for_each(|(path, hash)| {
+                        if self.is_aborted() {
+                            return;
+                        }
+                        let outcome = match hash {
+                            None => self.scanner.scan_file(&path, self.db),
+                            Some(Ok(hash)) => self.scanner.import_file(&path, hash, self.db),
+                            Some(Err(err)) => Err(err.into()),
+                        };
+                        self.recorded(&path, outcome, || scanner::stat(&path));
+                    });
+            });
+        });
+        Ok(())
+    }
+
+    /// Records `entry` of a remote `source` unless it is skipped.
+    fn scan_remote(&self, source: &dyn Source, entry: Entry) {
+        if self.is_aborted() {
+            return;
+        }
+        self.events.discovered(&entry.path);
+        let stat = || Some((entry.size, entry.modified_at));
+        if let Some(skipped) = self.skipped(&entry.path, stat) {
+            return self.report(&entry.path, skipped);
+        }
+        let outcome = self.scanner.scan_entry(source, &entry, self.db);
+        self.recorded(&entry.path, outcome, stat);
+    }
+
+    /// Why `path` is not scanned, if it is not: an interrupted scan this
+    /// one resumes finished it, or it failed in an earlier scan and, going
+    /// by the size and mtime `stat` gives, has not changed since, so it
+    /// would fail again.
+    fn skipped(
+        &self,
+        path: &Path,
+        stat: impl FnOnce() -> Option<(u64, i64)>,
+    ) -> Option<FileOutcome<'static>> {
+        if self.journal.is_done(path) {
+            return Some(FileOutcome::Resumed);
+        }
+        let &(Some(size), Some(modified_at)) = self.known_errors.get(path)? else {
+            return None;
+        };
+        (stat() == Some((size, modified_at))).then_some(FileOutcome::FailedBefore)
+    }
+
+    /// Counts, journals and reports what became of `path`, recording a
+    /// failure with the size and mtime `stat` gives and forgetting an
+    /// earlier one once it is recorded.
+    fn recorded(
+        &self,
+        path: &Path,
+        outcome: Result<ScanOutcome>,
+        stat: impl FnOnce() -> Option<(u64, i64)>,
+    ) {
+        let kept = match &outcome {
+            Ok(ScanOutcome::Recorded(_)) => {
+                self.recorded.fetch_add(1, Ordering::Relaxed);
+                self.keep(path)
+            }
+            Ok(ScanOutcome::Unchanged(_)) => {
+                self.unchanged.fetch_add(1, Ordering::Relaxed);
+                self.keep(path)
+            }
+            Err(err) => {
+                self.failed.fetch_add(1, Ordering::Relaxed);
+                self.events.error(path, err);
+                self.record_failure(path, err, stat())
+            }
+        };
+        if let Err(err) = kept {
+            self.abort(err.into());
+        }
+        let outcome = match &outcome {
+            Ok(outcome) => FileOutcome::Scanned(outcome),
+            Err(err) => FileOutcome::Failed(err),
+        };
+        self.report(path, outcome);
+    }
+
+    /// Forgets that the recorded `path` failed before, records the album
+    /// and favorite of a Photos asset and journals it.
+    fn keep(&self, path: &Path) -> rusqlite::Result<()> {
+        if self.known_errors.contains_key(path) {
+            self.db.lock().forget_error(path)?;
+        }
+        if let Some(asset) = self.library.asset(path) {
+            self.db.lock().record_library_asset(path, &asset.into())?;
+        }
+        self.journal.record(self.db, path)
+    }
+
+    /// Records that `path` failed with `err`, unless it is no media to
+    /// begin with.
+    fn record_failure(
+        &self,
+        path: &Path,
+        err: &DeduperError,
+        stat: Option<(u64, i64)>,
+    ) -> rusqlite::Result<()> {
+        if matches!(err, DeduperError::UnsupportedMedia(_)) {
+            return Ok(());
+        }
+        self.db.lock().record_error(&FileError {
+            path: path.to_owned(),
+            stage: Inspector::stage_of(err).to_owned(),
+            kind: err.kind().to_owned(),
+            message: err.to_string(),
+            size: stat.map(|(size, _)| size),
+            modified_at: stat.map(|(_, modified_at)| modified_at),
+            attempts: 1,
+            at: Utc::now().timestamp(),
+            label: self.builder.label.clone(),
+        })
+    }
+
+    fn report(&self, path: &Path, outcome: FileOutcome) {
+        let done = self.done.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(callback) = &self.builder.on_progress {
+            callback(&ScanProgress {
+                path,
+                outcome,
+                done,
+                total: self.total,
+            });
+        }
+    }
+
+    fn abort(&self, err: DeduperError) {
+        self.aborted.lock().unwrap().get_or_insert(err);
+    }
+
+    fn is_aborted(&self) -> bool {
+        self.aborted.lock().unwrap().is_some()
+    }
+}
+
+#[test]
+fn test_scan_builder() {
+    use std::{fs, sync::Mutex};
+
+    let dir = std::env::temp_dir().join(format!("deduper-scan-builder-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    image::RgbImage::new(8, 8).save(dir.join("a.png")).unwrap();
+    fs::write(dir.join("notes.txt"), "not media").unwrap();
+    let db_path = dir.with_extension("db");
+    let db = DB::new(&db_path).unwrap();
+
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let scan = ScanBuilder::new()
+        .sources([&dir])
+        .hash(HashAlgorithm::Blake3)
+        .threads(2)
+        .on_progress({
+            let seen = seen.clone();
+            move |progress| {
+                seen.lock().unwrap().push((
+                    progress.path.file_name().unwrap().to_owned(),
+                    matches!(progress.outcome, FileOutcome::Scanned(_)),
+                    progress.total,
+                ))
+            }
+        });
+    let counts = scan.run(&db).unwrap();
+    assert_eq!(
+        (1, 0, 1),
+        (counts.recorded, counts.unchanged, counts.failed)
+    );
+    let mut seen = seen.lock().unwrap().clone();
+    seen.sort();
+    assert_eq!(
+        vec![("a.png".into(), true, 2), ("notes.txt".into(), false, 2)],
+        seen
+    );
+    assert_eq!(1, scan.run(&db).unwrap().unchanged);
+    assert_eq!(2, db.lock().scans().unwrap().len());
+
+    // a row from before the frame size was recorded gets it
+    let path = dir.join("a.png");
+    let row = db.read().find_file(&path).unwrap().unwrap();
+    db.lock()
+        .upsert_file(&crate::database::File {
+            width: None,
+            height: None,
+            ..row.clone()
+        })
+        .unwrap();
+    let counts = scan.clone().refresh_metadata(true).run(&db).unwrap();
+    assert_eq!(1, counts.unchanged);
+    let refreshed = db.read().find_file(&path).unwrap().unwrap();
+    assert_eq!((Some(8), Some(8)), (refreshed.width, refreshed.height));
+    assert_eq!(row.hash, refreshed.hash);
+    drop(db);
+    fs::remove_file(&db_path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_scan_events() {
+    use std::{fs, sync::Mutex};
+
+    #[derive(Default)]
+    struct Collect(Mutex<Vec<String>>);
+
+    impl EventSink for Collect {
+        fn discovered(&self, path: &Path) {
+            let name = path.file_name().unwrap().to_string_lossy();
+            self.0.lock().unwrap().push(format!("discovered {}", name));
+        }
+
+        fn hashed(&self, media: &crate::media::Media) {
+            let name = media.path.file_name().unwrap().to_string_lossy();
+            self.0.lock().unwrap().push(format!("hashed {}", name));
+        }
+
+        fn error(&self, path: &Path, _err: &DeduperError) {
+            let name = path.file_name().unwrap().to_string_lossy();
+            self.0.lock().unwrap().push(format!("error {}", name));
+        }
+    }
+
+    let dir = std::env::temp_dir().join(format!("deduper-scan-events-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    image::RgbImage::new(8, 8).save(dir.join("a.png")).unwrap();
+    fs::write(dir.join("notes.txt"), "not media").unwrap();
+    let db_path = dir.with_extension("db");
+    let db = DB::new(&db_path).unwrap();
+
+    let sink = Arc::new(Collect::default());
+    let scan = ScanBuilder::new().sources([&dir]).events(sink.clone());
+    scan.run(&db).unwrap();
+    let mut events = sink.0.lock().unwrap().drain(..).collect::<Vec<_>>();
+    events.sort();
+    assert_eq!(
+        vec![
+            "discovered a.png",
+            "discovered notes.txt",
+            "error notes.txt",
+            "hashed a.png"
+        ],
+        events
+    );
+    // unchanged files are not hashed again
+    scan.run(&db).unwrap();
+    assert!(!sink
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|event| event.starts_with("hashed")));
+    drop(db);
+    fs::remove_file(&db_path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_scan_skips() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("deduper-scan-skips-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let [a, b] = ["a.png", "b.png"].map(|name| dir.join(name));
+    image::RgbImage::new(8, 8).save(&a).unwrap();
+    image::RgbImage::new(4, 4).save(&b).unwrap();
+    let db_path = dir.with_extension("db");
+    let db = DB::new(&db_path).unwrap();
+
+    // an interrupted scan finished a.png, and b.png failed before
+    let parameters = journal::parameters([dir.as_path()]);
+    let interrupted = Journal::open(&db, "scan", &parameters, false).unwrap();
+    interrupted.record(&db, &a).unwrap();
+    let stat = scanner::stat(&b);
+    db.lock()
+        .record_error(&FileError {
+            path: b.clone(),
+            stage: "hash".to_owned(),
+            kind: "io".to_owned(),
+            message: "unreadable".to_owned(),
+            size: stat.map(|(size, _)| size),
+            modified_at: stat.map(|(_, modified_at)| modified_at),
+            attempts: 1,
+            at: 0,
+            label: None,
+        })
+        .unwrap();
+
+    let outcomes = Arc::new(Mutex::new(Vec::new()));
+    let scan = ScanBuilder::new()
+        .sources([&dir])
+        .label(Some("backup".to_owned()))
+        .on_progress({
+            let outcomes = outcomes.clone();
+            move |progress| {
+                let outcome = match progress.outcome {
+                    FileOutcome::Scanned(_) => "scanned",
+                    FileOutcome::Failed(_) => "failed",
+                    FileOutcome::Resumed => "resumed",
+                    FileOutcome::FailedBefore => "failed before",
+                };
+                outcomes
+                    .lock()
+                    .unwrap()
+                    .push((progress.path.to_owned(), outcome));
+            }
+        });
+    scan.clone().resume(true).run(&db).unwrap();
+    let mut seen = outcomes.lock().unwrap().drain(..).collect::<Vec<_>>();
+    seen.sort();
+    assert_eq!(
+        vec![(a.clone(), "resumed"), (b.clone(), "failed before")],
+        seen
+    );
+
+    // the journal is finished, so a.png is scanned into the index, and the
+    // failure of b.png is forgotten once it changes and is recorded
+    image::RgbImage::new(2, 2).save(&b).unwrap();
+    let counts = scan.resume(true).run(&db).unwrap();
+    assert_eq!(2, counts.recorded);
+    assert!(db.lock().errors().unwrap().is_empty());
+    let row = db.read().find_file(&a).unwrap().unwrap();
+    assert_eq!(Some("backup".to_owned()), row.label);
+    drop(db);
+    fs::remove_file(&db_path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_scan_async() {
+    use std::fs;
+
+    let dir = std::env::temp_dir().join(format!("deduper-scan-async-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for i in 0..8u8 {
+        image::RgbImage::from_pixel(8, 8, image::Rgb([i, 0, 0]))
+            .save(dir.join(format!("{}.png", i)))
+            .unwrap();
+    }
+    fs::write(dir.join("notes.txt"), "not media").unwrap();
+    let db_path = dir.with_extension("db");
+    let db = DB::new(&db_path).unwrap();
+
+    let scan = ScanBuilder::new()
+        .sources([&dir])
+        .threads(2)
+        .read_concurrency(3);
+    let counts = scan.run(&db).unwrap();
+    assert_eq!(
+        (8, 0, 1),
+        (counts.recorded, counts.unchanged, counts.failed)
+    );
+    // hashed as a blocking scan hashes them
+    let path = dir.join("5.png");
+    let row = db.read().find_file(&path).unwrap().unwrap();
+    assert_eq!(
+        crate::hasher::file_hash(&path, HashAlgorithm::Blake3)
+            .unwrap()
+            .digest,
+        row.hash
+    );
+    assert_eq!(8, scan.run(&db).unwrap().unchanged);
+    drop(db);
+    fs::remove_file(&db_path).unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+}
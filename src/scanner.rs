@@ -0,0 +1,501 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::UNIX_EPOCH,
+};
+
+use mime_guess::mime;
+
+use crate::{
+    database::{self, FileEvent, DB},
+    error::Result,
+    events::{EventSink, Events},
+    hasher::FileHash,
+    media::{Inspector, Media},
+    phash, platform,
+    source::{self, Download, Entry, Source},
+    thumbnail::ThumbnailCache,
+    transcoder::FfmpegTools,
+    videohash,
+    volume::Volumes,
+};
+
+/// The mtime in unix seconds, as recorded in `modified_at`.
+pub fn modified_at(metadata: &fs::Metadata) -> i64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|sys_time| sys_time.duration_since(UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// Size and mtime of the local file at `path`, as a failure to scan it is
+/// recorded with.
+pub fn stat(path: &Path) -> Option<(u64, i64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.len(), modified_at(&metadata)))
+}
+
+pub enum ScanOutcome {
+    Recorded(Media),
+    /// Size, mtime and hash algorithm match the recorded row, so the file
+    /// was not hashed again.
+    Unchanged(database::File),
+}
+
+/// The hash of each hard-linked file hashed by a scanner, by device, inode,
+/// size and mtime, so a file rewritten in place or a reused inode is hashed
+/// again by a long-lived scanner; a link being hashed holds its slot so
+/// other links wait for it.
+type Links = Arc<Mutex<HashMap<(u64, u64, u64, i64), Arc<Mutex<Option<FileHash>>>>>>;
+
+/// Records media files in the database.
+#[derive(Debug, Default, Clone)]
+pub struct Scanner {
+    inspector: Inspector,
+    force_rehash: bool,
+    refresh_metadata: bool,
+    scan_id: Option<i64>,
+    video_fingerprints: Option<FfmpegTools>,
+    thumbnails: Option<ThumbnailCache>,
+    volumes: Volumes,
+    label: Option<String>,
+    events: Events,
+    links: Links,
+}
+
+impl Scanner {
+    pub fn new(inspector: Inspector) -> Self {
+        Self {
+            inspector,
+            force_rehash: false,
+            refresh_metadata: false,
+            scan_id: None,
+            video_fingerprints: None,
+            thumbnails: None,
+            volumes: Volumes::default(),
+            label: None,
+            events: Events::default(),
+            links: Links::default(),
+        }
+    }
+
+    /// Hash every file even when it looks unchanged since the last scan.
+    pub fn force_rehash(mut self, force_rehash: bool) -> Self {
+        self.force_rehash = force_rehash;
+        self
+    }
+
+    /// Read the metadata of files that look unchanged again, keeping their
+    /// recorded hash, so rows from before a column was recorded, or from
+    /// an older extractor, get it.
+    pub fn refresh_metadata(mut self, refresh_metadata: bool) -> Self {
+        self.refresh_metadata = refresh_metadata;
+        self
+    }
+
+    /// Log what becomes of each file to `file_events` as part of scan
+    /// `scan_id`.
+    pub fn log_events(mut self, scan_id: i64) -> Self {
+        self.scan_id = Some(scan_id);
+        self
+    }
+
+    /// Fingerprint the frames of videos with `tools` so re-encoded and
+    /// trimmed copies can be found, see [`videohash`]. Videos scanned
+    /// before are fingerprinted too when they have no fingerprint yet.
+    pub fn video_fingerprints(mut self, tools: FfmpegTools) -> Self {
+        self.video_fingerprints = Some(tools);
+        self
+    }
+
+    /// Store a thumbnail of every image, and of every video when the cache
+    /// has ffmpeg, in `thumbnails`. Files scanned before get one too when
+    /// it is missing.
+    pub fn thumbnails(mut self, thumbnails: ThumbnailCache) -> Self {
+        self.thumbnails = Some(thumbnails);
+        self
+    }
+
+    /// Record the volume of every file from the mount table `volumes`, so
+    /// files on removable drives can be found again when mounted elsewhere.
+    pub fn volumes(mut self, volumes: Volumes) -> Self {
+        self.volumes = volumes;
+        self
+    }
+
+    /// Record files as those of the read-only index `label`, such as an old
+    /// backup drive, see [`database::File::label`]. A file recorded under
+    /// another label, or none, is recorded again.
+    pub fn label(mut self, label: Option<String>) -> Self {
+        self.label = label;
+        self
+    }
+
+    /// Report every file hashed to `sink`.
+    pub fn events(mut self, sink: Arc<dyn EventSink>) -> Self {
+        self.events = Events::new(sink);
+        self
+    }
+
+    pub fn scan_file(&self, path: &Path, db: &DB) -> Result<ScanOutcome> {
+        self.scan(path, None, db)
+    }
+
+    /// Records a file whose hash another tool already computed, extracting
+    /// everything else as [`Scanner::scan_file`] does.
+    pub fn import_file(&self, path: &Path, hash: FileHash, db: &DB) -> Result<ScanOutcome> {
+        self.scan(path, Some(hash), db)
+    }
+
+    /// Records `entry` of the remote `source`, streamed into a temporary
+    /// file to be hashed and inspected and recorded under the path of the
+    /// entry. Like local files, entries are not read again while their size
+    /// and mtime match the row.
+    pub fn scan_entry(&self, source: &dyn Source, entry: &Entry, db: &DB) -> Result<ScanOutcome> {
+        let mut previous_hash = None;
+        if let Some(known) = db.read().find_file(&entry.path)? {
+            if self.is_current(&known, entry.size, entry.modified_at) {
+                return Ok(ScanOutcome::Unchanged(known));
+            }
+            previous_hash = Some(known.hash);
+        }
+        let download = Download::fetch(source, entry)?;
+        let path = download.path();
+        let mut media = self.inspector.inspect(path)?;
+        let phash = match media.mime_type.type_() {
+            mime::IMAGE => phash::image_phash(path),
+            _ => None,
+        };
+        media.path = entry.path.clone();
+        let file = file_row(&media, entry.modified_at, phash);
+        self.record(path, file, previous_hash, db)?;
+        self.events.hashed(&media);
+        Ok(ScanOutcome::Recorded(media))
+    }
+
+    /// Whether [`Scanner::scan_file`] would read `path` whole to hash it:
+    /// it is not recorded as it is, nor has hard links, whose hash is shared.
+    /// A file that cannot be looked at is left to `scan_file` to fail on.
+    pub fn needs_hash(&self, path: &Path, db: &DB) -> bool {
+        let Ok(metadata) = fs::metadata(path) else {
+            return false;
+        };
+        if platform::file_id(&metadata).is_some() && platform::link_count(&metadata) > 1 {
+            return false;
+        }
+        match db.read().find_file(path) {
+            Ok(Some(known)) => !self.is_current(&known, metadata.len(), modified_at(&metadata)),
+            Ok(None) => true,
+            Err(_) => false,
+        }
+    }
+
+    /// Whether the row `known` still describes a file of `size` and
+    /// `modified_at` as this scanner would record it, so it is not hashed
+    /// again.
+    fn is_current(&self, known: &database::File, size: u64, modified_at: i64) -> bool {
+        !self.force_rehash
+            && known.size == size
+            && known.modified_at == modified_at
+            && known.hash_algorithm == self.inspector.algorithm().name()
+            // rows from before full digests were stored hold a short one
+            && known.hash.len() == self.inspector.algorithm().digest_len()
+            && known.label == self.label
+    }
+
+    fn scan(&self, path: &Path, hash: Option<FileHash>, db: &DB) -> Result<ScanOutcome> {
+        let metadata = fs::metadata(path)?;
+        let modified_at = modified_at(&metadata);
+        let file_id = platform::file_id(&metadata);
+
+        let mut previous_hash = None;
+        if let Some(mut known) = db.read().find_file(path)? {
+            if self.is_current(&known, metadata.len(), modified_at) {
+                if known.media_type.starts_with("video/") {
+                    self.fingerprint_video(path, &known.hash, db)?;
+                }
+                self.thumbnail(path, &known.hash, &known.media_type)?;
+                if let Some(id) =
+                    file_id.filter(|&id| (known.dev, known.inode) != (Some(id.0), Some(id.1)))
+                {
+                    db.lock().set_file_id(path, id)?;
+                    (known.dev, known.inode) = (Some(id.0), Some(id.1));
+                }
+                if self.refresh_metadata {
+                    known = self.refresh(path, known, db)?;
+                }
+                return Ok(ScanOutcome::Unchanged(known));
+            }
+            previous_hash = Some(known.hash);
+        }
+
+        let hash = match (hash, file_id) {
+            (None, Some(id)) if platform::link_count(&metadata) > 1 => {
+                Some(self.linked_hash(path, id, &metadata, db)?)
+            }
+            (hash, _) => hash,
+        };
+        let media = match hash {
+            Some(hash) => self.inspector.inspect_hashed(path, hash)?,
+            None => self.inspector.inspect(path)?,
+        };
+        let phash = match media.mime_type.type_() {
+            mime::IMAGE => phash::image_phash(path),
+            _ => None,
+        };
+        let location = self.volumes.locate(path);
+        let file = database::File {
+            volume: location.as_ref().map(|location| location.volume.clone()),
+            volume_path: location.map(|location| location.path),
+            dev: file_id.map(|id| id.0),
+            inode: file_id.map(|id| id.1),
+            ..file_row(&media, modified_at, phash)
+        };
+        self.record(path, file, previous_hash, db)?;
+        self.events.hashed(&media);
+        Ok(ScanOutcome::Recorded(media))
+    }
+
+    /// Reads the metadata of `path` again and records it in place of that
+    /// of `known`, leaving everything that is not read from the file.
+    fn refresh(&self, path: &Path, known: database::File, db: &DB) -> Result<database::File> {
+        let hash = FileHash {
+            algorithm: self.inspector.algorithm(),
+            digest: known.hash.clone(),
+        };
+        let media = self.inspector.inspect_hashed(path, hash)?;
+        let phash = match media.mime_type.type_() {
+            mime::IMAGE => phash::image_phash(path),
+            _ => None,
+        };
+        let file = database::File {
+            host: known.host,
+            volume: known.volume,
+            volume_path: known.volume_path,
+            dev: known.dev,
+            inode: known.inode,
+            label: known.label,
+            original: known.original,
+            optimized: known.optimized,
+            ..file_row(&media, known.modified_at, phash)
+        };
+        db.lock().upsert_file(&file)?;
+        Ok(file)
+    }
+
+    /// Stores `file`, read from `path`, with its event, fingerprint and
+    /// thumbnail.
+    fn record(
+        &self,
+        path: &Path,
+        file: database::File,
+        previous_hash: Option<String>,
+        db: &DB,
+    ) -> Result<()> {
+        let file = database::File {
+            label: self.label.clone(),
+            ..file
+        };
+        db.lock().upsert_file(&file)?;
+        if let Some(scan_id) = self.scan_id {
+            log_recorded(scan_id, &file, previous_hash, db)?;
+        }
+        if file.media_type.starts_with("video/") {
+            self.fingerprint_video(path, &file.hash, db)?;
+        }
+        self.thumbnail(path, &file.hash, &file.media_type)
+    }
+
+    /// The hash of `path`, which has hard links, from another link with
+    /// device and inode `id` hashed in this scan or recorded before, so the
+    /// contents are read once for all links.
+    fn linked_hash(
+        &self,
+        path: &Path,
+        id: (u64, u64),
+        metadata: &fs::Metadata,
+        db: &DB,
+    ) -> Result<FileHash> {
+        let key = (id.0, id.1, metadata.len(), modified_at(metadata));
+        let slot = self.links.lock().unwrap().entry(key).or_default().clone();
+        let mut slot = slot.lock().unwrap();
+        if let Some(hash) = slot.as_ref() {
+            return Ok(hash.clone());
+        }
+        let algorithm = self.inspector.algorithm();
+        let recorded = db.read().find_hard_link(id, path)?.filter(|link| {
+            !self.force_rehash
+                && link.size == metadata.len()
+                && link.modified_at == modified_at(metadata)
+                && link.hash_algorithm == algorithm.name()
+                && link.hash.len() == algorithm.digest_len()
+        });
+        let hash = match recorded {
+            Some(link) => FileHash {
+                algorithm,
+                digest: link.hash,
+            },
+            None => self.inspector.hash(path)?,
+        };
+        *slot = Some(hash.clone());
+        Ok(hash)
+    }
+
+    /// Records the fingerprint of the video `hash` unless it has one; a
+    /// video ffmpeg cannot decode is tried again by the next scan.
+    fn fingerprint_video(&self, path: &Path, hash: &str, db: &DB) -> Result<()> {
+        let Some(tools) = &self.video_fingerprints else {
+            return Ok(());
+        };
+        if db.read().has_video_fingerprint(hash)? {
+            return Ok(());
+        }
+        if let Some(frames) = videohash::fingerprint(tools, path) {
+            db.lock().set_video_fingerprint(hash, &frames)?;
+        }
+        Ok(())
+    }
+
+    fn thumbnail(&self, path: &Path, hash: &str, media_type: &str) -> Result<()> {
+        if let Some(thumbnails) = &self.thumbnails {
+            thumbnails.generate(path, hash, media_type)?;
+        }
+        Ok(())
+    }
+
+    /// Forgets recorded files below `sources` that no longer exist and
+    /// returns how many there were. Sources that are missing themselves,
+    /// like an unmounted drive, are left alone. Files of remote sources are
+    /// gone when `listed`, every entry the remote sources listed, lacks
+    /// them, and are left alone without it.
+    pub fn forget_deleted(
+        &self,
+        sources: &[PathBuf],
+        listed: Option<&HashSet<PathBuf>>,
+        db: &DB,
+    ) -> Result<u64> {
+        let sources = sources
+            .iter()
+            .filter(|source| match source::is_remote(source) {
+                true => listed.is_some(),
+                false => source.exists(),
+            })
+            .collect::<Vec<_>>();
+        let db = db.lock();
+        let mut deleted = 0;
+        for file in db.all_files()? {
+            let path = file.path.as_path();
+            let gone = match listed.filter(|_| source::is_remote(path)) {
+                Some(listed) => !listed.contains(path),
+                None => is_gone(path),
+            };
+            if !sources.iter().any(|source| path.starts_with(source)) || !gone {
+                continue;
+            }
+            db.delete_file(&file.path)?;
+            if let Some(scan_id) = self.scan_id {
+                db.log_event(
+                    Some(scan_id),
+                    &file.path,
+                    FileEvent::Deleted,
+                    Some(&file.hash),
+                    None,
+                )?;
+            }
+            deleted += 1;
+        }
+        Ok(deleted)
+    }
+}
+
+/// The row of `media` as scanned from a file of no particular volume.
+fn file_row(media: &Media, modified_at: i64, phash: Option<u64>) -> database::File {
+    database::File {
+        path: media.path.clone(),
+        hash: media.hash.digest.clone(),
+        hash_algorithm: media.hash.algorithm.to_string(),
+        size: media.size,
+        media_type: media.mime_type.to_string(),
+        created_at: media.timestamp.timestamp(),
+        utc_offset: media.timestamp.offset().local_minus_utc(),
+        latitude: media.location.map(|location| location.latitude),
+        longitude: media.location.map(|location| location.longitude),
+        camera_make: media.camera.make.clone(),
+        camera_model: media.camera.model.clone(),
+        lens_model: media.camera.lens.clone(),
+        host: None,
+        volume: None,
+        volume_path: None,
+        dev: None,
+        inode: None,
+        source: media.source.map(|source| source.name().to_owned()),
+        width: media.format.width,
+        height: media.format.height,
+        duration: media.format.duration,
+        codec: media.format.codec.clone(),
+        label: None,
+        modified_at,
+        original: false,
+        optimized: database::Optimized::No,
+        phash,
+    }
+}
+
+/// Logs how `file` came to be recorded: rehashed when its path was known
+/// before, moved when the same contents are recorded at a path that is
+/// gone, whose row is dropped, and added otherwise.
+fn log_recorded(
+    scan_id: i64,
+    file: &database::File,
+    previous_hash: Option<String>,
+    db: &DB,
+) -> rusqlite::Result<()> {
+    let db = db.lock();
+    if let Some(previous_hash) = previous_hash {
+        let detail = Some(previous_hash.as_str()).filter(|&hash| hash != file.hash);
+        return db.log_event(
+            Some(scan_id),
+            &file.path,
+            FileEvent::Rehashed,
+            Some(&file.hash),
+            detail,
+        );
+    }
+    let moved_from = db
+        .find_files_by_hash(&file.hash)?
+        .into_iter()
+        .find(|known| known.path != file.path && is_gone(&known.path));
+    match moved_from {
+        Some(known) => {
+            db.delete_file(&known.path)?;
+            db.log_event(
+                Some(scan_id),
+                &file.path,
+                FileEvent::Moved,
+                Some(&file.hash),
+                Some(&known.path.to_string_lossy()),
+            )
+        }
+        None => db.log_event(
+            Some(scan_id),
+            &file.path,
+            FileEvent::Added,
+            Some(&file.hash),
+            None,
+        ),
+    }
+}
+
+/// Whether nothing is at `path` any more; unreadable paths, and those of
+/// remote sources, are not gone.
+fn is_gone(path: &Path) -> bool {
+    if source::is_remote(path) {
+        return false;
+    }
+    matches!(fs::symlink_metadata(path), Err(err) if err.kind() == ErrorKind::NotFound)
+}
@@ -0,0 +1,140 @@
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use mime_guess::{mime, Mime};
+
+/// Whether the companion files of media are placed along with it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Sidecars {
+    /// Place them next to their media, named after it
+    #[default]
+    Keep,
+    /// Leave them in the sources
+    Ignore,
+}
+
+/// Sidecar extensions and the media type they belong to: Canon video
+/// thumbnails, iPhone photo edits, drone telemetry subtitles, and XMP for
+/// either.
+const SIDECARS: [(&str, Option<mime::Name>); 4] = [
+    ("thm", Some(mime::VIDEO)),
+    ("aae", Some(mime::IMAGE)),
+    ("srt", Some(mime::VIDEO)),
+    ("xmp", None),
+];
+
+/// Whether `path` is named like a sidecar.
+pub fn is_sidecar(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| {
+            SIDECARS
+                .iter()
+                .any(|(sidecar, _)| ext.eq_ignore_ascii_case(sidecar))
+        })
+}
+
+/// The sidecars of the media at `path`, each with where it goes when the
+/// media is placed at `dest_path`. `IMG_0001.THM` next to `IMG_0001.MOV`
+/// takes the name of the placed movie with its own extension, and
+/// `IMG_0001.CR2.xmp` the full name of the placed raw file with `.xmp`
+/// appended.
+pub fn sidecars(path: &Path, mime_type: &Mime, dest_path: &Path) -> Vec<(PathBuf, PathBuf)> {
+    let mut found = Vec::new();
+    for (ext, media_type) in SIDECARS {
+        if media_type.is_some_and(|media_type| media_type != mime_type.type_()) {
+            continue;
+        }
+        for ext in [ext.to_owned(), ext.to_uppercase()] {
+            let sidecar = path.with_extension(&ext);
+            if sidecar.is_file() {
+                found.push((sidecar, dest_path.with_extension(&ext)));
+                break;
+            }
+        }
+        if ext == "xmp" {
+            for ext in [ext.to_owned(), ext.to_uppercase()] {
+                let sidecar = appended(path, &ext);
+                if sidecar.is_file() {
+                    found.push((sidecar, appended(dest_path, &ext)));
+                    break;
+                }
+            }
+        }
+    }
+    found
+}
+
+/// Media files `sidecar` may belong to: files next to it named like it
+/// without its extension, or named like it without `.xmp` and keeping
+/// their own.
+pub fn media_for(sidecar: &Path) -> Vec<PathBuf> {
+    let (Some(dir), Some(stem)) = (sidecar.parent(), sidecar.file_stem()) else {
+        return Vec::new();
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            !is_sidecar(path)
+                && (path.file_stem() == Some(stem) || path.file_name() == Some(stem))
+                && path.is_file()
+        })
+        .collect()
+}
+
+/// `path` with `.ext` appended to its file name.
+fn appended(path: &Path, ext: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(".");
+    name.push(ext);
+    PathBuf::from(name)
+}
+
+#[test]
+fn test_sidecars() {
+    let dir = std::env::temp_dir().join(format!("deduper-sidecars-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for name in [
+        "MVI_0001.MOV",
+        "MVI_0001.THM",
+        "MVI_0001.AAE",
+        "IMG_0002.CR2",
+        "IMG_0002.CR2.xmp",
+    ] {
+        fs::write(dir.join(name), name).unwrap();
+    }
+    let video = "video/quicktime".parse().unwrap();
+    assert_eq!(
+        vec![(dir.join("MVI_0001.THM"), PathBuf::from("/d/a_hash.THM"))],
+        sidecars(
+            &dir.join("MVI_0001.MOV"),
+            &video,
+            Path::new("/d/a_hash.MOV")
+        )
+    );
+    let raw = "image/x-canon-cr2".parse().unwrap();
+    assert_eq!(
+        vec![(
+            dir.join("IMG_0002.CR2.xmp"),
+            PathBuf::from("/d/b_hash.CR2.xmp")
+        )],
+        sidecars(&dir.join("IMG_0002.CR2"), &raw, Path::new("/d/b_hash.CR2"))
+    );
+    assert!(is_sidecar(&dir.join("MVI_0001.THM")));
+    assert!(!is_sidecar(&dir.join("MVI_0001.MOV")));
+    assert_eq!(
+        vec![dir.join("MVI_0001.MOV")],
+        media_for(&dir.join("MVI_0001.THM"))
+    );
+    assert_eq!(
+        vec![dir.join("IMG_0002.CR2")],
+        media_for(&dir.join("IMG_0002.CR2.xmp"))
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
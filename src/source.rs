@@ -0,0 +1,140 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::throttle;
+
+/// Scheme of the sources [`open_remote`] reads from an S3 bucket.
+pub const S3_SCHEME: &str = "s3://";
+
+/// Prefix of the sources and destinations reached through rclone, as in
+/// `rclone:gdrive:Photos` for the remote path `gdrive:Photos`.
+pub const RCLONE_SCHEME: &str = "rclone:";
+
+/// A file listed by a [`Source`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The path the file is recorded under: a local path, or a URL such as
+    /// `s3://bucket/photos/IMG_0001.JPG`
+    pub path: PathBuf,
+    pub size: u64,
+    /// Modification time in unix seconds, as recorded in `modified_at`
+    pub modified_at: i64,
+}
+
+/// A remote store scanned files are listed and read from; local sources
+/// are walked with [`crate::media::walk_files`].
+pub trait Source: Send + Sync {
+    /// Every file below the source.
+    fn list(&self) -> io::Result<Vec<Entry>>;
+
+    /// Streams the contents of `entry`.
+    fn open(&self, entry: &Entry) -> io::Result<Box<dyn Read + Send>>;
+}
+
+/// Whether `path` was recorded from a remote [`Source`] rather than the
+/// local filesystem, so it being missing locally says nothing.
+pub fn is_remote(path: &Path) -> bool {
+    path.as_os_str()
+        .to_str()
+        .is_some_and(|path| path.starts_with(S3_SCHEME) || path.starts_with(RCLONE_SCHEME))
+}
+
+/// The remote source `location` names, or `None` for a local directory.
+/// Fails when the source cannot be set up, e.g. without credentials.
+pub fn open_remote(location: &Path) -> Option<io::Result<Box<dyn Source>>> {
+    let url = location.to_str().filter(|_| is_remote(location))?;
+    if let Some(remote) = url.strip_prefix(RCLONE_SCHEME) {
+        return Some(Ok(Box::new(crate::rclone::RcloneSource::new(remote))));
+    }
+    #[cfg(feature = "s3")]
+    return Some(crate::s3::S3Source::from_env(url).map(|source| Box::new(source) as _));
+    #[cfg(not(feature = "s3"))]
+    Some(Err(io::Error::other(format!(
+        "scanning {} needs a build with the s3 feature",
+        url
+    ))))
+}
+
+/// The contents of a remote entry saved to a file of the same name in a
+/// directory of its own, which is removed when this is dropped. The name
+/// is kept so files are dated and grouped by it as local ones are.
+pub struct Download {
+    dir: PathBuf,
+    path: PathBuf,
+}
+
+impl Download {
+    /// Streams `entry` of `source` to a temporary file.
+    pub fn fetch(source: &dyn Source, entry: &Entry) -> io::Result<Self> {
+        static DOWNLOADS: AtomicU64 = AtomicU64::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "deduper-download-{}-{}",
+            std::process::id(),
+            DOWNLOADS.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::create_dir_all(&dir)?;
+        let download = Self {
+            path: dir.join(entry.path.file_name().unwrap_or("download".as_ref())),
+            dir,
+        };
+        let mut reader = source.open(entry)?;
+        let mut file = File::create(&download.path)?;
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            match reader.read(&mut buf)? {
+                0 => break,
+                n => {
+                    throttle::consume(n);
+                    io::Write::write_all(&mut file, &buf[..n])?;
+                }
+            }
+        }
+        Ok(download)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Download {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[test]
+fn test_download() {
+    struct Bytes;
+
+    impl Source for Bytes {
+        fn list(&self) -> io::Result<Vec<Entry>> {
+            Ok(vec![Entry {
+                path: PathBuf::from("s3://photos/2023/IMG_0001.JPG"),
+                size: 5,
+                modified_at: 0,
+            }])
+        }
+
+        fn open(&self, _entry: &Entry) -> io::Result<Box<dyn Read + Send>> {
+            Ok(Box::new(&b"image"[..]))
+        }
+    }
+
+    let entries = Bytes.list().unwrap();
+    let download = Download::fetch(&Bytes, &entries[0]).unwrap();
+    assert_eq!(Some("IMG_0001.JPG".as_ref()), download.path().file_name());
+    assert_eq!(b"image", fs::read(download.path()).unwrap().as_slice());
+    let downloaded = download.path().to_owned();
+    drop(download);
+    assert!(!downloaded.exists());
+
+    assert!(is_remote(&entries[0].path));
+    assert!(is_remote(Path::new("rclone:gdrive:Photos/IMG_0001.JPG")));
+    assert!(!is_remote(&std::env::temp_dir()));
+    assert!(open_remote(&std::env::temp_dir()).is_none());
+}
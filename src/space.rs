@@ -0,0 +1,114 @@
+//! Free space checks, so batches that write to a disk refuse to start, or
+//! leave files out, rather than fail halfway through once it is full.
+
+use std::{
+    fs, io,
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::{linker::LinkStrategy, platform};
+
+/// Room left free on top of what a batch needs, for directories, the
+/// database and whatever else writes to the disk meanwhile.
+pub const RESERVE: u64 = 64 * 1024 * 1024;
+
+/// Bytes that may be written to the filesystem holding `path`, or its
+/// nearest ancestor that exists, as a destination may not yet.
+pub fn available(path: &Path) -> io::Result<u64> {
+    platform::available_space(existing_ancestor(path)?)
+}
+
+/// Whether `a` and `b`, or their nearest existing ancestors, are on the
+/// same filesystem. Where that is unknown, on Windows, they are taken to be
+/// on different ones.
+pub fn same_filesystem(a: &Path, b: &Path) -> bool {
+    let device = |path: &Path| {
+        let metadata = fs::metadata(existing_ancestor(path).ok()?).ok()?;
+        platform::file_id(&metadata).map(|(dev, _)| dev)
+    };
+    matches!((device(a), device(b)), (Some(a), Some(b)) if a == b)
+}
+
+/// Whether placing `source` at `destination` with `strategy` takes space
+/// there: copies always do, moves only across filesystems, while links and
+/// reflinks share the blocks of the source.
+pub fn writes(strategy: LinkStrategy, source: &Path, destination: &Path) -> bool {
+    match strategy {
+        LinkStrategy::Copy => true,
+        LinkStrategy::Move => !same_filesystem(source, destination),
+        LinkStrategy::Symlink | LinkStrategy::Hardlink | LinkStrategy::Reflink => false,
+    }
+}
+
+fn existing_ancestor(path: &Path) -> io::Result<&Path> {
+    path.ancestors()
+        .find(|ancestor| {
+            // a relative path runs out at "", which is the working directory
+            ancestor.as_os_str().is_empty() || fs::symlink_metadata(ancestor).is_ok()
+        })
+        .map(|ancestor| {
+            if ancestor.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                ancestor
+            }
+        })
+        .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+}
+
+/// The bytes a batch trimmed to the free space may still write. Threads
+/// take the size of a file before writing it and give it back when that
+/// failed or, for replacements, once the old file is gone.
+#[derive(Debug)]
+pub struct Budget {
+    left: AtomicU64,
+}
+
+impl Budget {
+    pub fn new(bytes: u64) -> Self {
+        Self {
+            left: AtomicU64::new(bytes),
+        }
+    }
+
+    /// Takes `bytes` from the budget, unless fewer are left.
+    pub fn take(&self, bytes: u64) -> bool {
+        self.left
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |left| {
+                left.checked_sub(bytes)
+            })
+            .is_ok()
+    }
+
+    pub fn give_back(&self, bytes: u64) {
+        self.left.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    pub fn left(&self) -> u64 {
+        self.left.load(Ordering::SeqCst)
+    }
+}
+
+#[test]
+fn test_budget() {
+    let budget = Budget::new(10);
+    assert!(budget.take(6));
+    assert!(!budget.take(6));
+    assert!(budget.take(4));
+    budget.give_back(6);
+    assert_eq!(6, budget.left());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_available() {
+    let dir = std::env::temp_dir();
+    assert!(available(&dir).unwrap() > 0);
+    // the destination may not exist yet
+    let missing = dir.join(format!("deduper-space-{}/Photos/2023", std::process::id()));
+    assert_eq!(available(&dir).is_ok(), available(&missing).is_ok());
+    assert!(same_filesystem(&dir, &missing));
+    assert!(!writes(LinkStrategy::Move, &dir, &missing));
+    assert!(writes(LinkStrategy::Copy, &dir, &missing));
+}
@@ -0,0 +1,174 @@
+//! Metadata of Google Takeout exports. Google Photos strips or never had
+//! the capture time and place of many files, and writes them to a JSON
+//! file next to each one instead, e.g. `IMG_0001.JPG.json` with
+//! `photoTakenTime` and `geoData`.
+
+use std::{
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::{DateTime, FixedOffset, Local, TimeZone};
+use serde::Deserialize;
+
+use crate::geo::Location;
+
+/// Takeout cuts the names of its JSON files to this many bytes, `.json`
+/// included.
+const MAX_NAME_LEN: usize = 51;
+
+/// Newer exports name the JSON `IMG_0001.JPG.supplemental-metadata.json`.
+const SUPPLEMENTAL: &str = ".supplemental-metadata";
+
+/// The suffix Google Photos gives an edited copy, which shares the JSON of
+/// its original.
+const EDITED: &str = "-edited";
+
+/// What a Takeout JSON file says about its media.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TakeoutMetadata {
+    /// Capture time at the local offset; Takeout only has the instant
+    pub timestamp: Option<DateTime<FixedOffset>>,
+    pub location: Option<Location>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TakeoutJson {
+    photo_taken_time: Option<TakeoutTime>,
+    geo_data: Option<GeoData>,
+    geo_data_exif: Option<GeoData>,
+}
+
+#[derive(Deserialize)]
+struct TakeoutTime {
+    /// Seconds since the epoch, as a string
+    timestamp: String,
+}
+
+#[derive(Deserialize)]
+struct GeoData {
+    latitude: f64,
+    longitude: f64,
+}
+
+impl GeoData {
+    /// Takeout writes zeros where it knows no place.
+    fn location(&self) -> Option<Location> {
+        if self.latitude == 0.0 && self.longitude == 0.0 {
+            return None;
+        }
+        Location::new(self.latitude, self.longitude)
+    }
+}
+
+/// The metadata Takeout exported for the media at `path`, if a JSON file
+/// for it is next to it.
+pub fn metadata(path: &Path) -> Option<TakeoutMetadata> {
+    let json = fs::read(metadata_path(path)?).ok()?;
+    let json: TakeoutJson = serde_json::from_slice(&json).ok()?;
+    let timestamp = json
+        .photo_taken_time
+        .and_then(|time| time.timestamp.parse::<i64>().ok())
+        .and_then(|seconds| Local.timestamp_opt(seconds, 0).single())
+        .map(|timestamp| timestamp.fixed_offset());
+    let location = json
+        .geo_data
+        .and_then(|geo_data| geo_data.location())
+        .or_else(|| json.geo_data_exif.and_then(|geo_data| geo_data.location()));
+    Some(TakeoutMetadata {
+        timestamp,
+        location,
+    })
+}
+
+/// The JSON file Takeout wrote for the media at `path`. Besides
+/// `IMG_0001.JPG.json` and `IMG_0001.JPG.supplemental-metadata.json`, an
+/// export names it `IMG_0001.JPG(1).json` or
+/// `IMG_0001.JPG.supplemental-metadata(1).json` for `IMG_0001(1).JPG`, the
+/// second file of that name in an album, shares it between
+/// `IMG_0001-edited.JPG` and its original, and cuts long names short.
+pub fn metadata_path(path: &Path) -> Option<PathBuf> {
+    let dir = path.parent()?;
+    let name = path.file_name()?.to_str()?;
+    // names without `.json`, each with the number Takeout puts last
+    let mut names = vec![(name.to_owned(), String::new())];
+    if let Some((stem, ext)) = name.rsplit_once('.') {
+        if let Some(original) = stem.strip_suffix(EDITED) {
+            names.push((format!("{}.{}", original, ext), String::new()));
+        }
+        if let Some((stem, number)) = numbered(stem) {
+            names.push((format!("{}.{}", stem, ext), format!("({})", number)));
+        }
+    }
+    names
+        .iter()
+        .flat_map(|(name, number)| {
+            [
+                cut(name, number),
+                cut(&format!("{}{}", name, SUPPLEMENTAL), number),
+            ]
+        })
+        .map(|json| dir.join(json))
+        .find(|json| json.is_file())
+}
+
+/// `IMG_0001(1)` as `IMG_0001` and `1`.
+fn numbered(stem: &str) -> Option<(&str, &str)> {
+    let (stem, number) = stem.strip_suffix(')')?.rsplit_once('(')?;
+    (!number.is_empty() && number.bytes().all(|b| b.is_ascii_digit())).then_some((stem, number))
+}
+
+/// The JSON file name of `name` and `number`, with `name` cut short the
+/// way Takeout does so the whole fits in [`MAX_NAME_LEN`] bytes.
+fn cut(name: &str, number: &str) -> OsString {
+    let mut len = MAX_NAME_LEN
+        .saturating_sub(".json".len() + number.len())
+        .min(name.len());
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    OsString::from(format!("{}{}.json", &name[..len], number))
+}
+
+#[test]
+fn test_metadata() {
+    let dir = std::env::temp_dir().join(format!("deduper-takeout-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let json = r#"{
+        "title": "IMG_0001.JPG",
+        "photoTakenTime": {"timestamp": "1693601381", "formatted": "Sep 1, 2023"},
+        "geoData": {"latitude": 0.0, "longitude": 0.0, "altitude": 0.0},
+        "geoDataExif": {"latitude": 48.85, "longitude": 2.35, "altitude": 35.0}
+    }"#;
+    fs::write(dir.join("IMG_0001.JPG.json"), json).unwrap();
+    fs::write(dir.join("IMG_0002.JPG.supplemental-metadata(1).json"), json).unwrap();
+    let long = "Screenshot_20230901-224941_Samsung Internet.jpg";
+    fs::write(
+        dir.join("Screenshot_20230901-224941_Samsung Internet.jp.json"),
+        json,
+    )
+    .unwrap();
+
+    let metadata = metadata(&dir.join("IMG_0001.JPG")).unwrap();
+    assert_eq!(
+        Some(1693601381),
+        metadata.timestamp.map(|time| time.timestamp())
+    );
+    assert_eq!(Location::new(48.85, 2.35), metadata.location);
+    assert_eq!(
+        Some(dir.join("IMG_0001.JPG.json")),
+        metadata_path(&dir.join("IMG_0001-edited.JPG"))
+    );
+    assert_eq!(
+        Some(dir.join("IMG_0002.JPG.supplemental-metadata(1).json")),
+        metadata_path(&dir.join("IMG_0002(1).JPG"))
+    );
+    assert_eq!(
+        Some(dir.join("Screenshot_20230901-224941_Samsung Internet.jp.json")),
+        metadata_path(&dir.join(long))
+    );
+    assert_eq!(None, metadata_path(&dir.join("IMG_0003.JPG")));
+    fs::remove_dir_all(&dir).unwrap();
+}
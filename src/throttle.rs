@@ -0,0 +1,85 @@
+//! Limits on how hard reading files for hashing may load the disks, so a
+//! scan of a NAS on spinning disks leaves it usable for everything else.
+
+use std::{
+    io,
+    sync::{Mutex, OnceLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::platform;
+
+/// Reads of all threads share one budget of bytes per second.
+#[derive(Debug)]
+struct ReadRate {
+    bytes_per_second: u64,
+    /// When the reads granted so far have used up their share of time
+    next: Mutex<Instant>,
+}
+
+impl ReadRate {
+    fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second: bytes_per_second.max(1),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Sleeps until the reads granted before `bytes` have had their share
+    /// of time.
+    fn consume(&self, bytes: usize) {
+        let share = Duration::from_secs_f64(bytes as f64 / self.bytes_per_second as f64);
+        let start = {
+            let mut next = self.next.lock().unwrap();
+            // time nobody read in is not saved up for a burst later
+            let start = (*next).max(Instant::now());
+            *next = start + share;
+            start
+        };
+        let wait = start.saturating_duration_since(Instant::now());
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
+
+static READ_RATE: OnceLock<ReadRate> = OnceLock::new();
+
+/// Caps reads for hashing in this process at `bytes_per_second` from now
+/// on, shared by all threads. Only the first limit set takes effect.
+pub fn limit_read_rate(bytes_per_second: u64) {
+    let _ = READ_RATE.set(ReadRate::new(bytes_per_second));
+}
+
+/// Whether a read rate is set, in which case reads should be made in
+/// pieces rather than all at once.
+pub fn is_limited() -> bool {
+    READ_RATE.get().is_some()
+}
+
+/// Accounts for `bytes` about to be read or just read, sleeping while the
+/// reads before them exceed the rate. Returns at once without a limit.
+pub fn consume(bytes: usize) {
+    if let Some(rate) = READ_RATE.get() {
+        rate.consume(bytes);
+    }
+}
+
+/// Puts this process, and the threads and processes it starts from now on,
+/// in the idle I/O class like `ionice -c 3`: its reads and writes only
+/// reach the disk when nothing else wants it. Linux only.
+pub fn idle_io() -> io::Result<()> {
+    platform::idle_io()
+}
+
+#[test]
+fn test_read_rate() {
+    let rate = ReadRate::new(1000);
+    let started = Instant::now();
+    for _ in 0..3 {
+        rate.consume(100);
+    }
+    // the first read goes at once, each later one waits its predecessor's share
+    assert!(started.elapsed() >= Duration::from_millis(200));
+}
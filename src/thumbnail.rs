@@ -0,0 +1,202 @@
+//! JPEG thumbnails of images and video keyframes, cached by the hash of
+//! the file so copies share one.
+
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use image::codecs::jpeg::JpegEncoder;
+
+use crate::{hasher, transcoder::FfmpegTools};
+
+/// Longest side of a thumbnail in pixels.
+pub const SIZE: u32 = 512;
+
+const QUALITY: u8 = 80;
+
+/// Keyframes of a video the most representative one is picked from, so a
+/// fade in does not leave a black thumbnail.
+const KEYFRAMES: u32 = 10;
+
+/// The user's thumbnail cache, `$XDG_CACHE_HOME/deduper/thumbnails` or
+/// `~/.cache/deduper/thumbnails`.
+pub fn default_cache_dir() -> Option<PathBuf> {
+    env::var_os("XDG_CACHE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .map(|cache| cache.join("deduper/thumbnails"))
+}
+
+/// A directory of thumbnails named after the hashes of the files they
+/// show, spread over subdirectories by the first two characters.
+#[derive(Debug, Clone)]
+pub struct ThumbnailCache {
+    dir: PathBuf,
+    ffmpeg: Option<FfmpegTools>,
+}
+
+impl ThumbnailCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            ffmpeg: None,
+        }
+    }
+
+    /// Generate thumbnails of videos from their keyframes with `tools`;
+    /// without them only images get one.
+    pub fn videos(mut self, tools: FfmpegTools) -> Self {
+        self.ffmpeg = Some(tools);
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Where the thumbnail of `hash` is stored, whether it exists or not.
+    pub fn path(&self, hash: &str) -> PathBuf {
+        let shard = hash.get(..2).unwrap_or("00");
+        self.dir.join(shard).join(format!("{}.jpg", hash))
+    }
+
+    /// The stored thumbnail of `hash`, if there is one. Only recorded
+    /// digests name thumbnails, see [`hasher::is_digest`].
+    pub fn get(&self, hash: &str) -> Option<PathBuf> {
+        if !hasher::is_digest(hash) {
+            return None;
+        }
+        let path = self.path(hash);
+        path.is_file().then_some(path)
+    }
+
+    /// The thumbnail of the file at `path` with `hash`, generated unless it
+    /// is stored already. `None` for files that are neither images nor
+    /// videos, or that cannot be decoded; they are tried again next time.
+    pub fn generate(
+        &self,
+        path: &Path,
+        hash: &str,
+        media_type: &str,
+    ) -> io::Result<Option<PathBuf>> {
+        if let Some(thumbnail) = self.get(hash) {
+            return Ok(Some(thumbnail));
+        }
+        let jpeg = if media_type.starts_with("image/") {
+            image_thumbnail(path)
+        } else if media_type.starts_with("video/") {
+            self.ffmpeg
+                .as_ref()
+                .and_then(|tools| video_thumbnail(tools, path))
+        } else {
+            None
+        };
+        let Some(jpeg) = jpeg else {
+            return Ok(None);
+        };
+        let thumbnail = self.path(hash);
+        store(&thumbnail, &jpeg)?;
+        Ok(Some(thumbnail))
+    }
+}
+
+fn image_thumbnail(path: &Path) -> Option<Vec<u8>> {
+    let image = image::open(path).ok()?.thumbnail(SIZE, SIZE);
+    let mut jpeg = Vec::new();
+    image
+        .to_rgb8()
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut jpeg, QUALITY))
+        .ok()?;
+    Some(jpeg)
+}
+
+/// The most representative of the first keyframes, decoding nothing else.
+fn video_thumbnail(tools: &FfmpegTools, path: &Path) -> Option<Vec<u8>> {
+    let result = Command::new(&tools.ffmpeg)
+        .args([
+            "-hide_banner",
+            "-nostdin",
+            "-v",
+            "error",
+            "-skip_frame",
+            "nokey",
+            "-i",
+        ])
+        .arg(path)
+        .args([
+            "-an",
+            "-vf",
+            &format!(
+                "thumbnail={},scale={size}:{size}:force_original_aspect_ratio=decrease",
+                KEYFRAMES,
+                size = SIZE
+            ),
+            "-frames:v",
+            "1",
+            "-q:v",
+            "4",
+            "-f",
+            "image2pipe",
+            "-c:v",
+            "mjpeg",
+            "-",
+        ])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|result| result.status.success())?;
+    (!result.stdout.is_empty()).then_some(result.stdout)
+}
+
+/// Writes `jpeg` next to `thumbnail` first and renames it into place, so
+/// readers and concurrent writers of copies never see half a file.
+fn store(thumbnail: &Path, jpeg: &[u8]) -> io::Result<()> {
+    static WRITES: AtomicUsize = AtomicUsize::new(0);
+    if let Some(dir) = thumbnail.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let temp_path = thumbnail.with_extension(format!(
+        "{}-{}.part",
+        std::process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&temp_path, jpeg)?;
+    if let Err(err) = fs::rename(&temp_path, thumbnail) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(err);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_thumbnail_cache() {
+    let dir = std::env::temp_dir().join(format!("deduper-thumbnails-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let cache = ThumbnailCache::new(&dir);
+    let hash = "ab12cdefghijklmnopqrst";
+    assert_eq!(dir.join("ab/ab12cdefghijklmnopqrst.jpg"), cache.path(hash));
+    assert_eq!(None, cache.get(hash));
+
+    // neither an image nor a video
+    let text = dir.join("notes.txt");
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(&text, "not media").unwrap();
+    assert_eq!(None, cache.generate(&text, hash, "text/plain").unwrap());
+    // videos need ffmpeg
+    assert_eq!(None, cache.generate(&text, hash, "video/mp4").unwrap());
+
+    store(&cache.path(hash), b"jpeg").unwrap();
+    assert_eq!(Some(cache.path(hash)), cache.get(hash));
+    // stored thumbnails are not generated again
+    assert_eq!(
+        Some(cache.path(hash)),
+        cache.generate(&text, hash, "image/jpeg").unwrap()
+    );
+    assert_eq!(1, fs::read_dir(dir.join("ab")).unwrap().count());
+    fs::remove_dir_all(&dir).unwrap();
+}
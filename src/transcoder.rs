@@ -0,0 +1,844 @@
+use std::{
+    collections::HashMap,
+    env,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use image::codecs::{avif::AvifEncoder, jpeg::JpegEncoder, webp::WebPEncoder};
+use serde::{Deserialize, Serialize};
+
+use crate::{container, platform};
+
+const LOAD_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How far the length of a transcode may be off, at least: encoders pad or
+/// trim the last frames and audio packets.
+const DURATION_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// ravif speed, 1 (slowest, smallest) to 10.
+const AVIF_SPEED: u8 = 6;
+
+/// Container tags that date and place a recording and name its camera,
+/// which an encode must not lose.
+const CAPTURE_TAGS: [&str; 8] = [
+    "creation_time",
+    "com.apple.quicktime.creationdate",
+    "location",
+    "com.apple.quicktime.location.ISO6709",
+    "com.apple.quicktime.make",
+    "com.apple.quicktime.model",
+    "com.android.manufacturer",
+    "com.android.model",
+];
+
+/// Names of the built-in [`TranscodeProfile`]s.
+pub const PRESETS: [&str; 3] = ["archive-av1", "compat-h264", "hevc-hw"];
+
+/// What happens to the audio streams of a transcoded video.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioHandling {
+    /// Keep the audio as it is
+    Copy,
+    Aac {
+        bitrate_kbps: u32,
+    },
+    Opus {
+        bitrate_kbps: u32,
+    },
+    /// Drop the audio streams
+    Strip,
+}
+
+/// How videos are re-encoded by ffmpeg. The built-in [`PRESETS`] are
+/// available by name and profiles can be loaded from JSON files with the
+/// same fields.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TranscodeProfile {
+    /// ffmpeg video encoder, e.g. `libsvtav1`, `libx264` or `hevc_qsv`
+    pub codec: String,
+    /// Constant rate factor, for encoders that take `-crf`
+    pub crf: Option<u8>,
+    /// Encoder speed preset
+    pub preset: Option<String>,
+    pub audio: AudioHandling,
+    /// Extension of the output container, e.g. `mp4`; the source's is kept
+    /// when unset
+    pub container: Option<String>,
+    /// Further ffmpeg output options, such as the quality flag of hardware
+    /// encoders
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+}
+
+impl Default for TranscodeProfile {
+    fn default() -> Self {
+        Self::preset("archive-av1").unwrap()
+    }
+}
+
+impl TranscodeProfile {
+    /// A built-in profile by name:
+    /// - `archive-av1`: SVT-AV1 at CRF 35, the smallest files, audio kept
+    /// - `compat-h264`: x264 in MP4 with AAC audio, plays everywhere
+    /// - `hevc-hw`: HEVC on Intel Quick Sync, fast on NAS CPUs
+    pub fn preset(name: &str) -> Option<Self> {
+        let profile = match name {
+            "archive-av1" => Self {
+                codec: "libsvtav1".to_owned(),
+                crf: Some(35),
+                preset: Some("8".to_owned()),
+                audio: AudioHandling::Copy,
+                container: None,
+                extra_args: Vec::new(),
+            },
+            "compat-h264" => Self {
+                codec: "libx264".to_owned(),
+                crf: Some(23),
+                preset: Some("medium".to_owned()),
+                audio: AudioHandling::Aac { bitrate_kbps: 160 },
+                container: Some("mp4".to_owned()),
+                extra_args: ["-pix_fmt", "yuv420p", "-movflags", "+faststart"]
+                    .map(str::to_owned)
+                    .to_vec(),
+            },
+            "hevc-hw" => Self {
+                codec: "hevc_qsv".to_owned(),
+                crf: None,
+                preset: Some("medium".to_owned()),
+                audio: AudioHandling::Copy,
+                container: None,
+                extra_args: ["-global_quality", "25", "-tag:v", "hvc1"]
+                    .map(str::to_owned)
+                    .to_vec(),
+            },
+            _ => return None,
+        };
+        Some(profile)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    /// ffmpeg output options encoding with this profile.
+    pub fn ffmpeg_args(&self) -> Vec<String> {
+        let mut args = vec!["-c:v".to_owned(), self.codec.clone()];
+        if let Some(crf) = self.crf {
+            args.extend(["-crf".to_owned(), crf.to_string()]);
+        }
+        if let Some(preset) = &self.preset {
+            args.extend(["-preset".to_owned(), preset.clone()]);
+        }
+        match self.audio {
+            AudioHandling::Copy => args.extend(["-c:a", "copy"].map(str::to_owned)),
+            AudioHandling::Aac { bitrate_kbps } => {
+                args.extend(["-c:a".to_owned(), "aac".to_owned()]);
+                args.extend(["-b:a".to_owned(), format!("{}k", bitrate_kbps)]);
+            }
+            AudioHandling::Opus { bitrate_kbps } => {
+                args.extend(["-c:a".to_owned(), "libopus".to_owned()]);
+                args.extend(["-b:a".to_owned(), format!("{}k", bitrate_kbps)]);
+            }
+            AudioHandling::Strip => args.push("-an".to_owned()),
+        }
+        args.extend(self.extra_args.iter().cloned());
+        args
+    }
+}
+
+/// Blocks while the one minute load average is above `max_load`. Returns
+/// at once where the load average is unknown.
+pub fn wait_for_load(max_load: f64) {
+    while platform::load_average().is_some_and(|load| load > max_load) {
+        thread::sleep(LOAD_CHECK_INTERVAL);
+    }
+}
+
+/// Resources one encode may take, so batch transcodes leave the machine
+/// usable.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Limits {
+    /// Threads ffmpeg encodes with
+    pub threads: Option<u32>,
+    /// Niceness of the ffmpeg process
+    pub nice: Option<i32>,
+    /// Only touch the disk when nothing else does (Linux)
+    pub idle_io: bool,
+}
+
+/// Where an encode is, from ffmpeg's `-progress` reports.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EncodeProgress {
+    /// Length of the input, when ffprobe could tell
+    pub duration: Option<Duration>,
+    /// Media time encoded so far
+    pub encoded: Duration,
+    pub fps: Option<f64>,
+    /// Encoding speed relative to playback
+    pub speed: Option<f64>,
+}
+
+impl EncodeProgress {
+    /// Takes one `key=value` line of a progress report. Returns true once
+    /// the line ending a report was read.
+    pub fn update(&mut self, line: &str) -> bool {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            return false;
+        };
+        match key {
+            // despite the name, out_time_ms is in microseconds as well
+            "out_time_us" | "out_time_ms" => {
+                if let Ok(us) = value.parse::<u64>() {
+                    self.encoded = Duration::from_micros(us);
+                }
+            }
+            "fps" => self.fps = value.parse().ok(),
+            "speed" => self.speed = value.trim_end_matches('x').trim().parse().ok(),
+            "progress" => return true,
+            _ => {}
+        }
+        false
+    }
+
+    /// Share of the input encoded, 0 to 1.
+    pub fn fraction(&self) -> Option<f64> {
+        let duration = self.duration.filter(|duration| !duration.is_zero())?;
+        Some((self.encoded.as_secs_f64() / duration.as_secs_f64()).min(1.0))
+    }
+
+    /// Time left at the current speed.
+    pub fn eta(&self) -> Option<Duration> {
+        let remaining = self.duration?.saturating_sub(self.encoded);
+        let speed = self.speed.filter(|speed| *speed > 0.0)?;
+        Some(remaining.div_f64(speed))
+    }
+}
+
+/// The ffmpeg and ffprobe executables videos are probed and encoded with.
+/// The default runs whichever are on the `PATH`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FfmpegTools {
+    pub ffmpeg: PathBuf,
+    pub ffprobe: PathBuf,
+}
+
+impl Default for FfmpegTools {
+    fn default() -> Self {
+        Self {
+            ffmpeg: PathBuf::from("ffmpeg"),
+            ffprobe: PathBuf::from("ffprobe"),
+        }
+    }
+}
+
+impl FfmpegTools {
+    /// ffmpeg at `path`, an executable or the directory holding it, or else
+    /// found on the `PATH` or in the usual install locations. ffprobe is
+    /// looked for next to it first.
+    pub fn locate(path: Option<&Path>) -> io::Result<Self> {
+        let ffmpeg = match path {
+            Some(path) if path.is_dir() => path.join(executable_name("ffmpeg")),
+            Some(path) => path.to_owned(),
+            None => find_executable("ffmpeg").ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "ffmpeg is not on the PATH; install it or pass --ffmpeg-path",
+                )
+            })?,
+        };
+        if !ffmpeg.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no ffmpeg at {}", ffmpeg.to_string_lossy()),
+            ));
+        }
+        let ffprobe = Some(ffmpeg.with_file_name(executable_name("ffprobe")))
+            .filter(|ffprobe| ffprobe.is_file())
+            .or_else(|| find_executable("ffprobe"))
+            .unwrap_or_else(|| PathBuf::from("ffprobe"));
+        Ok(Self { ffmpeg, ffprobe })
+    }
+}
+
+/// Where package managers put ffmpeg that may be missing from the `PATH` of
+/// cron jobs and services.
+#[cfg(unix)]
+const INSTALL_DIRS: [&str; 4] = [
+    "/usr/local/bin",
+    "/opt/homebrew/bin",
+    "/usr/bin",
+    "/snap/bin",
+];
+
+#[cfg(windows)]
+const INSTALL_DIRS: [&str; 0] = [];
+
+fn executable_name(name: &str) -> String {
+    format!("{}{}", name, env::consts::EXE_SUFFIX)
+}
+
+fn find_executable(name: &str) -> Option<PathBuf> {
+    let name = executable_name(name);
+    env::var_os("PATH")
+        .map(|paths| env::split_paths(&paths).collect::<Vec<_>>())
+        .unwrap_or_default()
+        .into_iter()
+        .chain(INSTALL_DIRS.iter().map(PathBuf::from))
+        .map(|dir| dir.join(&name))
+        .find(|path| path.is_file())
+}
+
+/// Length of a media file as reported by ffprobe.
+pub fn probe_duration(tools: &FfmpegTools, input: &Path) -> Option<Duration> {
+    let result = Command::new(&tools.ffprobe)
+        .args([
+            "-v",
+            "error",
+            "-show_entries",
+            "format=duration",
+            "-of",
+            "csv=p=0",
+        ])
+        .arg(input)
+        .output()
+        .ok()?;
+    let seconds = String::from_utf8_lossy(&result.stdout)
+        .trim()
+        .parse::<f64>()
+        .ok()?;
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// Codec and bitrate of the first video stream of a file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoInfo {
+    /// ffprobe's codec name, e.g. h264, hevc or av1
+    pub codec: String,
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: Option<f64>,
+    /// Bits per second of the stream, or of the whole file when the
+    /// container does not record it per stream
+    pub bit_rate: Option<u64>,
+}
+
+impl VideoInfo {
+    /// Bits spent on each pixel of each frame, which unlike the bitrate
+    /// compares across resolutions and frame rates.
+    pub fn bits_per_pixel(&self) -> Option<f64> {
+        let pixels = f64::from(self.width) * f64::from(self.height) * self.frame_rate?;
+        let bit_rate = self.bit_rate? as f64;
+        (pixels > 0.0).then(|| bit_rate / pixels)
+    }
+}
+
+#[derive(Deserialize)]
+struct Probe {
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+    format: Option<ProbeFormat>,
+}
+
+#[derive(Deserialize)]
+struct ProbeStream {
+    codec_name: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    avg_frame_rate: Option<String>,
+    bit_rate: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProbeFormat {
+    bit_rate: Option<String>,
+}
+
+/// Codec and bitrate of the first video stream as reported by ffprobe.
+pub fn probe_video(tools: &FfmpegTools, input: &Path) -> Option<VideoInfo> {
+    let result = Command::new(&tools.ffprobe)
+        .args([
+            "-v",
+            "error",
+            "-select_streams",
+            "v:0",
+            "-show_entries",
+            "stream=codec_name,width,height,avg_frame_rate,bit_rate:format=bit_rate",
+            "-of",
+            "json",
+        ])
+        .arg(input)
+        .output()
+        .ok()?;
+    parse_probe(&String::from_utf8_lossy(&result.stdout))
+}
+
+fn parse_probe(json: &str) -> Option<VideoInfo> {
+    let probe: Probe = serde_json::from_str(json).ok()?;
+    let stream = probe.streams.into_iter().next()?;
+    let bit_rate = stream
+        .bit_rate
+        .or(probe.format.and_then(|format| format.bit_rate))
+        .and_then(|bit_rate| bit_rate.parse().ok());
+    // a rational like 30000/1001, or 0/0 when unknown
+    let frame_rate = stream.avg_frame_rate.and_then(|rate| {
+        let (num, den) = rate.split_once('/')?;
+        let (num, den) = (num.parse::<f64>().ok()?, den.parse::<f64>().ok()?);
+        (num > 0.0 && den > 0.0).then_some(num / den)
+    });
+    Some(VideoInfo {
+        codec: stream.codec_name?,
+        width: stream.width?,
+        height: stream.height?,
+        frame_rate,
+        bit_rate,
+    })
+}
+
+/// Number of audio streams in a file as reported by ffprobe.
+fn probe_audio_streams(tools: &FfmpegTools, input: &Path) -> Option<usize> {
+    let result = Command::new(&tools.ffprobe)
+        .args([
+            "-v",
+            "error",
+            "-select_streams",
+            "a",
+            "-show_entries",
+            "stream=index",
+            "-of",
+            "csv=p=0",
+        ])
+        .arg(input)
+        .output()
+        .ok()
+        .filter(|result| result.status.success())?;
+    Some(String::from_utf8_lossy(&result.stdout).lines().count())
+}
+
+/// Whether a transcode of `source` length is complete, allowing for 1% or
+/// [`DURATION_TOLERANCE`], whichever is more.
+fn durations_match(source: Duration, output: Duration) -> bool {
+    let tolerance = DURATION_TOLERANCE.max(source / 100);
+    source.abs_diff(output) <= tolerance
+}
+
+/// Checks that `output` is a complete re-encode of `input` before it
+/// replaces it: the lengths match, every stream decodes without errors and
+/// the audio is still there unless `profile` strips it. A truncated or
+/// corrupt encode ffmpeg still exited successfully for fails here.
+pub fn verify_transcode(
+    tools: &FfmpegTools,
+    input: &Path,
+    output: &Path,
+    profile: &TranscodeProfile,
+    limits: Limits,
+) -> io::Result<()> {
+    let source_duration = probe_duration(tools, input);
+    let output_duration = probe_duration(tools, output)
+        .ok_or_else(|| io::Error::other("ffprobe cannot read the output"))?;
+    if let Some(source_duration) = source_duration {
+        if !durations_match(source_duration, output_duration) {
+            return Err(io::Error::other(format!(
+                "output is {:.1}s long, the source {:.1}s",
+                output_duration.as_secs_f64(),
+                source_duration.as_secs_f64()
+            )));
+        }
+    }
+    if profile.audio != AudioHandling::Strip
+        && probe_audio_streams(tools, input).unwrap_or_default() > 0
+        && probe_audio_streams(tools, output).unwrap_or_default() == 0
+    {
+        return Err(io::Error::other("output lost the audio"));
+    }
+
+    let mut command = Command::new(&tools.ffmpeg);
+    command
+        .args(["-hide_banner", "-nostdin", "-v", "error", "-i"])
+        .arg(output);
+    if let Some(threads) = limits.threads {
+        command.args(["-threads".to_owned(), threads.to_string()]);
+    }
+    platform::lower_priority(&mut command, limits.nice, limits.idle_io);
+    let result = command
+        .args(["-f", "null", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .output()?;
+    let errors = String::from_utf8_lossy(&result.stderr);
+    if !result.status.success() || !errors.trim().is_empty() {
+        return Err(io::Error::other(format!(
+            "output does not decode: {}",
+            errors.lines().next().unwrap_or_default()
+        )));
+    }
+    Ok(())
+}
+
+/// Re-encodes `input` at `output` with `profile`, calling `on_progress`
+/// with every progress report ffmpeg writes. Returns ffmpeg's stderr as
+/// the error message when the encode fails.
+pub fn transcode(
+    tools: &FfmpegTools,
+    input: &Path,
+    output: &Path,
+    profile: &TranscodeProfile,
+    limits: Limits,
+    mut on_progress: impl FnMut(&EncodeProgress),
+) -> io::Result<()> {
+    let mut command = Command::new(&tools.ffmpeg);
+    command
+        .args(["-hide_banner", "-nostdin", "-nostats", "-y"])
+        .args(["-progress", "pipe:1", "-i"])
+        .arg(input)
+        .args(["-map_metadata", "0"])
+        .args(profile.ffmpeg_args());
+    if let Some(threads) = limits.threads {
+        command.args(["-threads".to_owned(), threads.to_string()]);
+    }
+    platform::lower_priority(&mut command, limits.nice, limits.idle_io);
+    let mut child = command
+        .arg(output)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // stderr is drained on its own thread so ffmpeg never blocks on a full pipe
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let errors = thread::spawn(move || {
+        let mut errors = String::new();
+        let _ = stderr.read_to_string(&mut errors);
+        errors
+    });
+    let mut progress = EncodeProgress {
+        duration: probe_duration(tools, input),
+        ..EncodeProgress::default()
+    };
+    let stdout = child.stdout.take().expect("stdout is piped");
+    for line in BufReader::new(stdout).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(err) => {
+                // stop ffmpeg and reap it, so it does not linger as a zombie
+                let _ = child.kill();
+                let _ = child.wait();
+                let _ = errors.join();
+                return Err(err);
+            }
+        };
+        if progress.update(&line) {
+            on_progress(&progress);
+        }
+    }
+    let status = child.wait()?;
+    let errors = errors.join().unwrap_or_default();
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(errors.trim().to_owned()))
+    }
+}
+
+/// Capture tags of `source` that `output` lacks or holds another value
+/// for, as `(key, value)` in the order of [`CAPTURE_TAGS`].
+fn lost_capture_tags(
+    source: &HashMap<String, String>,
+    output: &HashMap<String, String>,
+) -> Vec<(String, String)> {
+    CAPTURE_TAGS
+        .iter()
+        .filter_map(|&key| {
+            let value = source.get(key).filter(|value| !value.is_empty())?;
+            (output.get(key) != Some(value)).then(|| (key.to_owned(), value.clone()))
+        })
+        .collect()
+}
+
+/// Writes the capture dates, location and camera of `input` back into its
+/// re-encode at `output` where the encode dropped them, as the MP4 muxer
+/// does with QuickTime keys. The streams are copied into a file next to
+/// `output` that then replaces it. Returns the keys restored.
+pub fn restore_capture_tags(
+    tools: &FfmpegTools,
+    input: &Path,
+    output: &Path,
+) -> io::Result<Vec<String>> {
+    let lost = lost_capture_tags(
+        &container::read_tags(input)?,
+        &container::read_tags(output)?,
+    );
+    if lost.is_empty() {
+        return Ok(Vec::new());
+    }
+    let extension = output
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let remuxed = output.with_extension(format!("metadata.{}", extension));
+
+    let mut command = Command::new(&tools.ffmpeg);
+    command
+        .args(["-hide_banner", "-nostdin", "-v", "error", "-y", "-i"])
+        .arg(output)
+        .args(["-map", "0", "-c", "copy", "-map_metadata", "0"]);
+    if matches!(extension.as_str(), "mp4" | "m4v" | "mov") {
+        // keys like com.apple.quicktime.location.ISO6709 are only written
+        // as QuickTime metadata
+        command.args(["-movflags", "+use_metadata_tags+faststart"]);
+    }
+    for (key, value) in &lost {
+        command.arg("-metadata").arg(format!("{}={}", key, value));
+    }
+    let result = command
+        .arg(&remuxed)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .output()?;
+    if !result.status.success() {
+        let _ = fs::remove_file(&remuxed);
+        let errors = String::from_utf8_lossy(&result.stderr);
+        return Err(io::Error::other(format!(
+            "failed to restore metadata: {}",
+            errors.lines().next().unwrap_or_default()
+        )));
+    }
+    fs::rename(&remuxed, output)?;
+    Ok(lost.into_iter().map(|(key, _)| key).collect())
+}
+
+/// Format PNGs are converted to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ImageTarget {
+    /// Lossless WebP
+    Webp,
+    /// Lossy AVIF at the configured quality
+    Avif,
+}
+
+impl ImageTarget {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageTarget::Webp => "webp",
+            ImageTarget::Avif => "avif",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ImageTarget::Webp => "image/webp",
+            ImageTarget::Avif => "image/avif",
+        }
+    }
+}
+
+/// Re-encodes a JPEG at `quality` (1-100) to `output`. The EXIF, XMP, ICC
+/// and IPTC segments of the original are carried over, so the capture date
+/// and orientation survive.
+pub fn optimize_jpeg(input: &Path, output: &Path, quality: u8) -> io::Result<()> {
+    let original = fs::read(input)?;
+    let image = image::load_from_memory(&original).map_err(io::Error::other)?;
+    let mut encoded = Vec::new();
+    image
+        .to_rgb8()
+        .write_with_encoder(JpegEncoder::new_with_quality(&mut encoded, quality))
+        .map_err(io::Error::other)?;
+    if encoded.len() < 2 {
+        return Err(io::Error::other("encoder produced no JPEG"));
+    }
+    let mut jpeg = encoded[..2].to_vec();
+    for segment in jpeg_metadata_segments(&original) {
+        jpeg.extend_from_slice(segment);
+    }
+    jpeg.extend_from_slice(&encoded[2..]);
+    fs::write(output, jpeg)
+}
+
+/// Converts a PNG to `target` at `output`; `quality` only applies to AVIF.
+pub fn convert_png(
+    input: &Path,
+    output: &Path,
+    target: ImageTarget,
+    quality: u8,
+) -> io::Result<()> {
+    let image = image::open(input).map_err(io::Error::other)?.to_rgba8();
+    let mut writer = BufWriter::new(File::create(output)?);
+    match target {
+        ImageTarget::Webp => image.write_with_encoder(WebPEncoder::new_lossless(&mut writer)),
+        ImageTarget::Avif => image.write_with_encoder(AvifEncoder::new_with_speed_quality(
+            &mut writer,
+            AVIF_SPEED,
+            quality,
+        )),
+    }
+    .map_err(io::Error::other)?;
+    writer.flush()
+}
+
+/// Checks that the optimized image at `output` decodes to the size of
+/// `input` before it replaces it. Formats this build only encodes, such as
+/// AVIF, cannot be checked and pass.
+pub fn verify_image(input: &Path, output: &Path) -> io::Result<()> {
+    let expected = image::image_dimensions(input).map_err(io::Error::other)?;
+    let found = match image::open(output) {
+        Ok(image) => (image.width(), image.height()),
+        Err(image::ImageError::Unsupported(_)) => return Ok(()),
+        Err(err) => return Err(io::Error::other(format!("output does not decode: {}", err))),
+    };
+    if found != expected {
+        return Err(io::Error::other(format!(
+            "output is {}x{}, the source {}x{}",
+            found.0, found.1, expected.0, expected.1
+        )));
+    }
+    Ok(())
+}
+
+/// The APP1 (EXIF, XMP), APP2 (ICC) and APP13 (IPTC) segments before the
+/// image data of a JPEG, markers included.
+fn jpeg_metadata_segments(jpeg: &[u8]) -> Vec<&[u8]> {
+    let mut segments = Vec::new();
+    // skip the start of image marker
+    let mut pos = 2;
+    while let Some(&[0xFF, marker, high, low]) = jpeg.get(pos..pos + 4) {
+        // start of scan: entropy coded data follows
+        if marker == 0xDA {
+            break;
+        }
+        let end = pos + 2 + u16::from_be_bytes([high, low]) as usize;
+        let Some(segment) = jpeg.get(pos..end) else {
+            break;
+        };
+        if matches!(marker, 0xE1 | 0xE2 | 0xED) {
+            segments.push(segment);
+        }
+        pos = end;
+    }
+    segments
+}
+
+#[test]
+fn test_jpeg_metadata_segments() {
+    let jpeg = [
+        &[0xFF, 0xD8][..],
+        &[0xFF, 0xE0, 0x00, 0x04, 0x4A, 0x46],
+        &[0xFF, 0xE1, 0x00, 0x06, b'E', b'x', b'i', b'f'],
+        &[0xFF, 0xDB, 0x00, 0x03, 0x00],
+        &[0xFF, 0xDA, 0x00, 0x02, 0xFF, 0xE1],
+    ]
+    .concat();
+    assert_eq!(
+        vec![&[0xFF, 0xE1, 0x00, 0x06, b'E', b'x', b'i', b'f'][..]],
+        jpeg_metadata_segments(&jpeg)
+    );
+}
+
+#[test]
+fn test_transcode_profile() {
+    for name in PRESETS {
+        assert!(TranscodeProfile::preset(name).is_some());
+    }
+    assert_eq!(None, TranscodeProfile::preset("vp9"));
+    assert_eq!(
+        "-c:v libsvtav1 -crf 35 -preset 8 -c:a copy",
+        TranscodeProfile::default().ffmpeg_args().join(" ")
+    );
+    assert_eq!(
+        "-c:v libx264 -crf 23 -preset medium -c:a aac -b:a 160k -pix_fmt yuv420p -movflags +faststart",
+        TranscodeProfile::preset("compat-h264")
+            .unwrap()
+            .ffmpeg_args()
+            .join(" ")
+    );
+}
+
+#[test]
+fn test_lost_capture_tags() {
+    let tags = |pairs: &[(&str, &str)]| {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>()
+    };
+    let source = tags(&[
+        ("creation_time", "2023-09-01T22:49:41.000000Z"),
+        ("com.apple.quicktime.location.ISO6709", "+35.6895+139.6917/"),
+        ("com.apple.quicktime.make", ""),
+        ("encoder", "Lavf60.3.100"),
+    ]);
+    // the encode kept the date, reset to the time of encoding, or lost it
+    let kept = tags(&[
+        ("creation_time", "2023-09-01T22:49:41.000000Z"),
+        ("com.apple.quicktime.location.ISO6709", "+35.6895+139.6917/"),
+    ]);
+    assert_eq!(
+        Vec::<(String, String)>::new(),
+        lost_capture_tags(&source, &kept)
+    );
+    let reset = tags(&[("creation_time", "2024-01-01T00:00:00.000000Z")]);
+    assert_eq!(
+        vec![
+            (
+                "creation_time".to_owned(),
+                "2023-09-01T22:49:41.000000Z".to_owned()
+            ),
+            (
+                "com.apple.quicktime.location.ISO6709".to_owned(),
+                "+35.6895+139.6917/".to_owned()
+            ),
+        ],
+        lost_capture_tags(&source, &reset)
+    );
+}
+
+#[test]
+fn test_encode_progress() {
+    let mut progress = EncodeProgress {
+        duration: Some(Duration::from_secs(100)),
+        ..EncodeProgress::default()
+    };
+    let report = "frame=250\nfps=25.00\nout_time_us=25000000\nspeed=2.5x\nprogress=continue\n";
+    let ends = report
+        .lines()
+        .map(|line| progress.update(line))
+        .collect::<Vec<_>>();
+    assert_eq!(vec![false, false, false, false, true], ends);
+    assert_eq!(Some(0.25), progress.fraction());
+    assert_eq!(Some(Duration::from_secs(30)), progress.eta());
+    assert_eq!(Some(25.0), progress.fps);
+}
+
+#[test]
+fn test_parse_probe() {
+    let info = parse_probe(
+        r#"{"programs": [], "streams": [{"codec_name": "hevc", "width": 1920, "height": 1080,
+            "avg_frame_rate": "30/1"}], "format": {"bit_rate": "6220800"}}"#,
+    )
+    .unwrap();
+    assert_eq!("hevc", info.codec);
+    assert_eq!(Some(6220800), info.bit_rate);
+    assert_eq!(Some(0.1), info.bits_per_pixel());
+    let unknown_rate = parse_probe(
+        r#"{"streams": [{"codec_name": "h264", "width": 640, "height": 480,
+            "avg_frame_rate": "0/0", "bit_rate": "1000"}]}"#,
+    )
+    .unwrap();
+    assert_eq!(Some(1000), unknown_rate.bit_rate);
+    assert_eq!(None, unknown_rate.bits_per_pixel());
+    assert_eq!(None, parse_probe(r#"{"streams": []}"#));
+}
+
+#[test]
+fn test_durations_match() {
+    let minute = Duration::from_secs(60);
+    assert!(durations_match(minute, minute - Duration::from_millis(500)));
+    assert!(!durations_match(minute, minute / 2));
+    // an hour may be off by 36 seconds
+    let hour = Duration::from_secs(3600);
+    assert!(durations_match(hour, hour + Duration::from_secs(30)));
+    assert!(!durations_match(hour, hour - Duration::from_secs(40)));
+}
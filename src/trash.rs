@@ -0,0 +1,116 @@
+use std::{
+    env,
+    ffi::OsString,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::Local;
+
+use crate::{
+    linker::{self, LinkStrategy},
+    platform,
+};
+
+/// The user's XDG trash, `$XDG_DATA_HOME/Trash` or `~/.local/share/Trash`.
+pub fn xdg_trash_dir() -> Option<PathBuf> {
+    env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")))
+        .map(|data| data.join("Trash"))
+}
+
+/// Moves `path` into `trash_dir` following the freedesktop.org trash layout,
+/// so desktop file managers can list and restore it. Returns the new path.
+pub fn trash(path: &Path, trash_dir: &Path) -> io::Result<PathBuf> {
+    let path = platform::canonicalize(path)?;
+    let files_dir = trash_dir.join("files");
+    let info_dir = trash_dir.join("info");
+    fs::create_dir_all(&files_dir)?;
+    fs::create_dir_all(&info_dir)?;
+
+    let file_name = path.file_name().unwrap_or(path.as_os_str());
+    let info = format!(
+        "[Trash Info]\nPath={}\nDeletionDate={}\n",
+        platform::encode_path(&path),
+        Local::now().format("%Y-%m-%dT%H:%M:%S")
+    );
+    for counter in 0.. {
+        let mut name = OsString::from(file_name);
+        if counter > 0 {
+            name.push(format!(".{}", counter));
+        }
+        let mut info_name = name.clone();
+        info_name.push(".trashinfo");
+        let info_path = info_dir.join(info_name);
+        // claiming the info file first reserves the name against other writers
+        let mut info_file = match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&info_path)
+        {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        };
+        let destination = files_dir.join(name);
+        let moved = info_file
+            .write_all(info.as_bytes())
+            .and_then(|()| linker::place(LinkStrategy::Move, &path, &destination));
+        return match moved {
+            Ok(()) => Ok(destination),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                let _ = fs::remove_file(&info_path);
+                continue;
+            }
+            Err(err) => {
+                let _ = fs::remove_file(&info_path);
+                Err(err)
+            }
+        };
+    }
+    unreachable!()
+}
+
+/// Moves `trashed`, a file [`trash`] put into `files` of a trash directory,
+/// back to `path` and drops its info file. An existing `path` is never
+/// overwritten.
+pub fn restore(trashed: &Path, path: &Path) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    linker::place(LinkStrategy::Move, trashed, path)?;
+    if let (Some(files_dir), Some(name)) = (trashed.parent(), trashed.file_name()) {
+        let mut info_name = name.to_owned();
+        info_name.push(".trashinfo");
+        let info_path = files_dir.with_file_name("info").join(info_name);
+        let _ = fs::remove_file(info_path);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_trash() {
+    let dir = env::temp_dir().join(format!("deduper-trash-{}", std::process::id()));
+    let trash_dir = dir.join("Trash");
+    fs::create_dir_all(&dir).unwrap();
+    let mut trashed = Vec::new();
+    for _ in 0..2 {
+        fs::write(dir.join("a b.jpg"), b"data").unwrap();
+        trashed.push(trash(&dir.join("a b.jpg"), &trash_dir).unwrap());
+    }
+    assert!(!dir.join("a b.jpg").exists());
+    assert!(trash_dir.join("files/a b.jpg").exists());
+    assert!(trash_dir.join("files/a b.jpg.1").exists());
+    let info = fs::read_to_string(trash_dir.join("info/a b.jpg.trashinfo")).unwrap();
+    assert!(info.contains("a%20b.jpg\n"));
+
+    restore(&trashed[1], &dir.join("a b.jpg")).unwrap();
+    assert!(dir.join("a b.jpg").exists());
+    assert!(!trash_dir.join("info/a b.jpg.1.trashinfo").exists());
+    // the name is taken now
+    assert!(restore(&trashed[0], &dir.join("a b.jpg")).is_err());
+    fs::remove_dir_all(&dir).unwrap();
+}
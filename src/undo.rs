@@ -0,0 +1,273 @@
+//! Rolling back what destructive commands did, from the `undo_log` table.
+//! Deleted and linked duplicates are restored from the original they
+//! duplicated and re-encoded files from any recorded copy of their old
+//! contents, so those can only be undone while such a copy is left.
+
+use std::{ffi::OsString, fs, io, path::Path};
+
+use chrono::Utc;
+
+use crate::{
+    database::{File, LockDB, UndoAction, UndoEntry},
+    error::{DeduperError, Result},
+    extractor::MediaSource,
+    hasher::{self, HashAlgorithm},
+    linker::{self, LinkStrategy},
+    platform, quarantine, scanner, trash,
+};
+
+/// The run a destructive command logs what it does under.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UndoLog {
+    run_id: i64,
+}
+
+impl UndoLog {
+    /// Starts a run of `command` to log to.
+    pub fn start(db: &LockDB, command: &str) -> rusqlite::Result<Self> {
+        let run_id = db.start_run(command, "", Utc::now().timestamp())?;
+        Ok(Self { run_id })
+    }
+
+    /// Logs to the run `run_id`, e.g. that of a [`crate::journal::Journal`].
+    pub fn of_run(run_id: i64) -> Self {
+        Self { run_id }
+    }
+
+    pub fn run_id(&self) -> i64 {
+        self.run_id
+    }
+
+    /// Logs that `action` was done to `path`, which held the contents
+    /// `hash` before; see [`UndoAction`] for what `target` is.
+    pub fn record(
+        &self,
+        db: &LockDB,
+        action: UndoAction,
+        path: &Path,
+        target: Option<&Path>,
+        hash: &str,
+        hash_algorithm: &str,
+    ) -> rusqlite::Result<()> {
+        db.log_undo(self.run_id, action, path, target, hash, hash_algorithm)
+    }
+
+    pub fn finish(self, db: &LockDB) -> rusqlite::Result<()> {
+        db.finish_run(self.run_id, Utc::now().timestamp())
+    }
+}
+
+/// Reverses `entry` and marks it undone. Restored files are recorded
+/// again with the row of another file of their contents. Nothing that is
+/// in the way is overwritten, except the link or re-encoding the action
+/// left at the path.
+pub fn undo(db: &LockDB, entry: &UndoEntry) -> Result<()> {
+    let path = entry.path.as_path();
+    let Some(target) = entry.target.as_deref() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("nothing recorded to undo {} from", path.to_string_lossy()),
+        )
+        .into());
+    };
+    let algorithm = entry
+        .hash_algorithm
+        .parse::<HashAlgorithm>()
+        .unwrap_or_default();
+    match entry.action {
+        UndoAction::Deleted => {
+            restore_copy(target, path, &entry.hash, algorithm, false)?;
+            record_restored(db, entry)?;
+        }
+        UndoAction::Trashed => {
+            trash::restore(target, path)?;
+            record_restored(db, entry)?;
+        }
+        UndoAction::Quarantined => {
+            quarantine::restore(target, path)?;
+            record_restored(db, entry)?;
+        }
+        UndoAction::Linked => restore_copy(target, path, &entry.hash, algorithm, false)?,
+        UndoAction::Moved => {
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            linker::place(LinkStrategy::Move, target, path)?;
+            db.move_dest_path(target, path)?;
+        }
+        UndoAction::Transcoded => {
+            let copy = db
+                .find_files_by_hash(&entry.hash)?
+                .into_iter()
+                .map(|file| file.path)
+                .find(|copy| copy != path && copy.is_file())
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!(
+                            "no copy of the old contents of {} is left",
+                            path.to_string_lossy()
+                        ),
+                    )
+                })?;
+            restore_copy(&copy, path, &entry.hash, algorithm, target == path)?;
+            if target != path {
+                fs::remove_file(target)?;
+                db.delete_file(target)?;
+            }
+            record_restored(db, entry)?;
+        }
+    }
+    db.mark_undone(entry.id)?;
+    Ok(())
+}
+
+/// Puts a copy of `source`, which must still hold the contents `hash`, at
+/// `path`. The copy is made next to `path` and renamed into place; with
+/// `replace` that replaces what is there, otherwise `path` must be free
+/// or a link to `source`.
+fn restore_copy(
+    source: &Path,
+    path: &Path,
+    hash: &str,
+    algorithm: HashAlgorithm,
+    replace: bool,
+) -> Result<()> {
+    if !replace {
+        if let Ok(metadata) = fs::symlink_metadata(path) {
+            let linked = metadata.file_type().is_symlink()
+                || fs::metadata(source).is_ok_and(|source| platform::same_file(&source, &metadata));
+            if !linked {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists", path.to_string_lossy()),
+                )
+                .into());
+            }
+        }
+    }
+    let verify = |path: &Path| match hasher::file_hash(path, algorithm) {
+        Ok(found) if found.matches(hash) => Ok(()),
+        Ok(_) => Err(DeduperError::HashMismatch(path.to_owned())),
+        Err(err) => Err(err.into()),
+    };
+    verify(source)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut temp_name = OsString::from(".");
+    temp_name.push(path.file_name().unwrap_or_default());
+    temp_name.push(".deduper-undo");
+    let temp_path = path.with_file_name(temp_name);
+    let copied = linker::place(LinkStrategy::Copy, source, &temp_path)
+        .map_err(DeduperError::from)
+        .and_then(|()| verify(&temp_path))
+        .and_then(|()| fs::rename(&temp_path, path).map_err(DeduperError::from));
+    if copied.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    copied
+}
+
+/// Records the restored `entry.path` with the row of another file of its
+/// contents.
+fn record_restored(db: &LockDB, entry: &UndoEntry) -> Result<()> {
+    let Some(template) = db
+        .find_files_by_hash(&entry.hash)?
+        .into_iter()
+        .find(|file| file.path != entry.path)
+    else {
+        return Ok(());
+    };
+    let metadata = fs::metadata(&entry.path)?;
+    db.upsert_file(&File {
+        path: entry.path.clone(),
+        modified_at: scanner::modified_at(&metadata),
+        size: metadata.len(),
+        original: false,
+        host: None,
+        volume: None,
+        volume_path: None,
+        dev: None,
+        inode: None,
+        source: MediaSource::of(&entry.path).map(|source| source.name().to_owned()),
+        label: None,
+        ..template
+    })?;
+    Ok(())
+}
+
+#[test]
+fn test_restore_copy() {
+    let dir = std::env::temp_dir().join(format!("deduper-undo-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let original = dir.join("original.jpg");
+    let path = dir.join("2023/copy.jpg");
+    fs::write(&original, b"photo").unwrap();
+    let algorithm = HashAlgorithm::Sha256;
+    let hash = hasher::file_hash(&original, algorithm).unwrap().to_string();
+
+    restore_copy(&original, &path, &hash, algorithm, false).unwrap();
+    assert_eq!(b"photo", &fs::read(&path).unwrap()[..]);
+    // what took the place since is left alone
+    assert!(restore_copy(&original, &path, &hash, algorithm, false).is_err());
+    // a source that changed is not copied
+    fs::write(&original, b"edited").unwrap();
+    fs::remove_file(&path).unwrap();
+    assert!(restore_copy(&original, &path, &hash, algorithm, false).is_err());
+    assert!(!path.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_undo_move() {
+    use crate::database::DB;
+
+    let dir = std::env::temp_dir().join(format!("deduper-undo-move-{}", std::process::id()));
+    fs::create_dir_all(dir.join("dest/2023")).unwrap();
+    let source = dir.join("a.jpg");
+    let placed = dir.join("dest/a.jpg");
+    let relaid = dir.join("dest/2023/a.jpg");
+    fs::write(&relaid, b"photo").unwrap();
+    let database = DB::new(&dir.join("deduper.db")).unwrap();
+    let db = database.lock();
+    db.upsert_file(&File {
+        size: 5,
+        original: true,
+        ..File::test(&source, "abc")
+    })
+    .unwrap();
+    db.set_dest_path(&source, &relaid).unwrap();
+    // organize moved the file to dest, relayout then into 2023
+    let log = UndoLog::start(&db, "organize").unwrap();
+    log.record(
+        &db,
+        UndoAction::Moved,
+        &source,
+        Some(&placed),
+        "abc",
+        "blake3",
+    )
+    .unwrap();
+    log.record(
+        &db,
+        UndoAction::Moved,
+        &placed,
+        Some(&relaid),
+        "abc",
+        "blake3",
+    )
+    .unwrap();
+
+    let mut entries = db.undo_entries(log.run_id()).unwrap();
+    entries.sort_by_key(|entry| std::cmp::Reverse(entry.id));
+    undo(&db, &entries[0]).unwrap();
+    assert!(placed.is_file());
+    assert_eq!(Some(placed.clone()), db.find_dest_path(&source).unwrap());
+    undo(&db, &entries[1]).unwrap();
+    assert_eq!(b"photo", &fs::read(&source).unwrap()[..]);
+    assert_eq!(None, db.find_dest_path(&source).unwrap());
+    drop(db);
+    drop(database);
+    fs::remove_dir_all(&dir).unwrap();
+}
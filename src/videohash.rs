@@ -0,0 +1,134 @@
+use std::{
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::{phash, transcoder::FfmpegTools};
+
+/// Average differing bits per frame below which two clips are reported as
+/// probable duplicates. Re-encodes usually stay within two or three.
+pub const DEFAULT_DISTANCE: f64 = 6.0;
+
+/// Frames sampled, one per second from the start, so a clip is
+/// fingerprinted by its first ten minutes.
+const MAX_FRAMES: usize = 600;
+
+/// Seconds one clip may be trimmed by at the start compared to another.
+const MAX_SHIFT: usize = 5;
+
+/// Share of the longer clip the compared frames have to cover, so a short
+/// clip does not match the beginning of a long one.
+const MIN_OVERLAP: f64 = 0.9;
+
+/// Clips shorter than this are too short to tell apart by their frames.
+const MIN_FRAMES: usize = 3;
+
+/// Sequence of difference hashes of frames sampled once a second by
+/// ffmpeg, see [`phash::luma_dhash`]. `None` when ffmpeg cannot decode the
+/// file or it has no video.
+pub fn fingerprint(tools: &FfmpegTools, path: &Path) -> Option<Vec<u64>> {
+    let result = Command::new(&tools.ffmpeg)
+        .args(["-hide_banner", "-nostdin", "-v", "error", "-i"])
+        .arg(path)
+        .args([
+            "-an",
+            "-vf",
+            "fps=1,scale=9:8:flags=area,format=gray",
+            "-frames:v",
+            &MAX_FRAMES.to_string(),
+            "-f",
+            "rawvideo",
+            "-",
+        ])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|result| result.status.success())?;
+    let frames = result
+        .stdout
+        .chunks_exact(9 * 8)
+        .map(phash::luma_dhash)
+        .collect::<Vec<_>>();
+    (!frames.is_empty()).then_some(frames)
+}
+
+/// Average differing bits per frame of two fingerprints, at the offset of
+/// up to [`MAX_SHIFT`] seconds either way that matches best. `None` when
+/// the clips are too different in length to compare.
+pub fn distance(a: &[u64], b: &[u64]) -> Option<f64> {
+    let longer = a.len().max(b.len());
+    (0..=MAX_SHIFT)
+        .flat_map(|shift| [(shift, 0), (0, shift)])
+        .filter_map(|(skip_a, skip_b)| {
+            let (a, b) = (a.get(skip_a..)?, b.get(skip_b..)?);
+            let overlap = a.len().min(b.len());
+            if overlap < MIN_FRAMES || (overlap as f64) < longer as f64 * MIN_OVERLAP {
+                return None;
+            }
+            let bits = a
+                .iter()
+                .zip(b)
+                .map(|(a, b)| phash::hamming_distance(*a, *b))
+                .sum::<u32>();
+            Some(f64::from(bits) / overlap as f64)
+        })
+        .min_by(|a, b| a.total_cmp(b))
+}
+
+/// Groups clips whose fingerprints are transitively within `max_distance`,
+/// see [`distance`]. Singletons are dropped; each group keeps the input
+/// order. Every pair is compared, which is fine for the videos of a
+/// library.
+pub fn probable_duplicates<T>(items: Vec<(Vec<u64>, T)>, max_distance: f64) -> Vec<Vec<T>> {
+    let mut links = Vec::new();
+    for (index, (a, _)) in items.iter().enumerate() {
+        for (other, (b, _)) in items.iter().enumerate().skip(index + 1) {
+            if distance(a, b).is_some_and(|distance| distance <= max_distance) {
+                links.push((index, other));
+            }
+        }
+    }
+    phash::group_linked(items.into_iter().map(|(_, item)| item).collect(), links)
+}
+
+/// Fingerprints are stored as the little-endian bytes of each frame hash.
+pub fn to_bytes(frames: &[u64]) -> Vec<u8> {
+    frames
+        .iter()
+        .flat_map(|frame| frame.to_le_bytes())
+        .collect()
+}
+
+pub fn from_bytes(bytes: &[u8]) -> Vec<u64> {
+    bytes
+        .chunks_exact(8)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+        .collect()
+}
+
+#[test]
+fn test_probable_duplicates() {
+    let clip = (0..20u64)
+        .map(|n| n.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+        .collect::<Vec<_>>();
+    // re-encoded: a bit off in every frame
+    let reencoded = clip.iter().map(|frame| frame ^ 1).collect::<Vec<_>>();
+    // trimmed by two seconds at the start
+    let trimmed = clip[2..].to_vec();
+    let other = clip.iter().map(|frame| !frame).collect::<Vec<_>>();
+    assert_eq!(Some(1.0), distance(&clip, &reencoded));
+    assert_eq!(Some(0.0), distance(&clip, &trimmed));
+    assert_eq!(None, distance(&clip, &clip[..5]));
+    let groups = probable_duplicates(
+        vec![
+            (clip.clone(), "clip"),
+            (other, "other"),
+            (trimmed, "trimmed"),
+            (reencoded, "reencoded"),
+        ],
+        DEFAULT_DISTANCE,
+    );
+    assert_eq!(vec![vec!["clip", "trimmed", "reencoded"]], groups);
+    assert_eq!(clip, from_bytes(&to_bytes(&clip)));
+}
@@ -0,0 +1,262 @@
+//! The filesystems files live on, so paths on removable drives can be found
+//! again after the drive is mounted somewhere else, and a drive that is not
+//! mounted can be told apart from deleted files.
+//!
+//! A volume is identified like in fstab, as `UUID=...` or, for filesystems
+//! without a UUID, `LABEL=...`. Only Linux is supported; elsewhere no file
+//! is given a volume.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// A mounted filesystem. `root` is the directory of the filesystem that is
+/// mounted, `/` unless it is a bind mount or a btrfs subvolume.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Mount {
+    volume: Option<String>,
+    root: PathBuf,
+    mount_point: PathBuf,
+}
+
+/// Where a file is within its volume.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Location {
+    /// `UUID=...` or `LABEL=...`
+    pub volume: String,
+    /// The path from the root of the filesystem, independent of where it is
+    /// mounted
+    pub path: PathBuf,
+}
+
+/// The mount table as of when it was read.
+#[derive(Debug, Default, Clone)]
+pub struct Volumes {
+    mounts: Vec<Mount>,
+}
+
+impl Volumes {
+    /// Reads the mounts of this process and the UUIDs and labels udev lists
+    /// in `/dev/disk`.
+    #[cfg(target_os = "linux")]
+    pub fn detect() -> Self {
+        use std::collections::HashMap;
+
+        let Ok(mountinfo) = fs::read_to_string("/proc/self/mountinfo") else {
+            return Self::default();
+        };
+        let mut volumes = HashMap::new();
+        // UUIDs come last so they win over labels
+        for (dir, prefix) in [
+            ("/dev/disk/by-label", "LABEL="),
+            ("/dev/disk/by-uuid", "UUID="),
+        ] {
+            let Ok(entries) = fs::read_dir(dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                if let Ok(device) = fs::canonicalize(entry.path()) {
+                    let name = unescape_udev(&entry.file_name().to_string_lossy());
+                    volumes.insert(device, format!("{}{}", prefix, name));
+                }
+            }
+        }
+        let mounts = parse_mountinfo(&mountinfo)
+            .into_iter()
+            .map(|(source, root, mount_point)| Mount {
+                volume: fs::canonicalize(source)
+                    .ok()
+                    .and_then(|device| volumes.get(&device).cloned()),
+                root,
+                mount_point,
+            })
+            .collect();
+        Self { mounts }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn detect() -> Self {
+        Self::default()
+    }
+
+    /// The volume `path` is on and where on it, or `None` when the
+    /// filesystem has neither UUID nor label, like tmpfs or a network share.
+    pub fn locate(&self, path: &Path) -> Option<Location> {
+        // the directory rather than the file, so a symlink stays where it is
+        let path = match (path.parent(), path.file_name()) {
+            (Some(parent), Some(name)) => fs::canonicalize(parent).ok()?.join(name),
+            _ => fs::canonicalize(path).ok()?,
+        };
+        // the innermost mount; of mounts on the same point the last one
+        // hides the others
+        let mount = self
+            .mounts
+            .iter()
+            .filter(|mount| path.starts_with(&mount.mount_point))
+            .max_by_key(|mount| mount.mount_point.components().count())?;
+        let relative = path.strip_prefix(&mount.mount_point).ok()?;
+        Some(Location {
+            volume: mount.volume.clone()?,
+            path: mount.root.join(relative),
+        })
+    }
+
+    /// Where the file at `path` on `volume` is now, or `None` when no
+    /// mount of the volume reaches it.
+    pub fn resolve(&self, volume: &str, path: &Path) -> Option<PathBuf> {
+        self.mounts
+            .iter()
+            .filter(|mount| mount.volume.as_deref() == Some(volume))
+            .filter_map(|mount| {
+                let relative = path.strip_prefix(&mount.root).ok()?;
+                Some((
+                    mount.root.components().count(),
+                    mount.mount_point.join(relative),
+                ))
+            })
+            .max_by_key(|(depth, _)| *depth)
+            .map(|(_, path)| path)
+    }
+
+    /// Whether any filesystem of `volume` is mounted.
+    pub fn is_mounted(&self, volume: &str) -> bool {
+        self.mounts
+            .iter()
+            .any(|mount| mount.volume.as_deref() == Some(volume))
+    }
+}
+
+/// The source, root and mount point of every line of
+/// `/proc/self/mountinfo`:
+/// `36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue`
+fn parse_mountinfo(mountinfo: &str) -> Vec<(PathBuf, PathBuf, PathBuf)> {
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let fields = line.split(' ').collect::<Vec<_>>();
+            let root = fields.get(3)?;
+            let mount_point = fields.get(4)?;
+            // optional fields end with a lone dash, then the type and source
+            let separator = fields.iter().position(|&field| field == "-")?;
+            let source = fields.get(separator + 2)?;
+            Some((
+                PathBuf::from(unescape_mountinfo(source)),
+                PathBuf::from(unescape_mountinfo(root)),
+                PathBuf::from(unescape_mountinfo(mount_point)),
+            ))
+        })
+        .collect()
+}
+
+/// Undoes the octal escapes of space, tab, newline and backslash.
+fn unescape_mountinfo(field: &str) -> String {
+    unescape(field, |escape| {
+        let code = escape.get(..3)?;
+        let byte = u8::from_str_radix(code, 8).ok()?;
+        Some((byte, 3))
+    })
+}
+
+/// Undoes the `\x20` escapes udev writes into the names of labels.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn unescape_udev(name: &str) -> String {
+    unescape(name, |escape| {
+        let code = escape.strip_prefix('x')?.get(..2)?;
+        let byte = u8::from_str_radix(code, 16).ok()?;
+        Some((byte, 3))
+    })
+}
+
+/// Replaces every backslash escape `decode` understands with the byte it
+/// stands for; `decode` is given what follows the backslash and returns
+/// the byte and how many characters it took.
+fn unescape(s: &str, decode: impl Fn(&str) -> Option<(u8, usize)>) -> String {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(start) = rest.find('\\') {
+        bytes.extend_from_slice(&rest.as_bytes()[..start]);
+        rest = &rest[start + 1..];
+        match decode(rest) {
+            Some((byte, len)) => {
+                bytes.push(byte);
+                rest = &rest[len..];
+            }
+            None => bytes.push(b'\\'),
+        }
+    }
+    bytes.extend_from_slice(rest.as_bytes());
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[test]
+fn test_parse_mountinfo() {
+    let mountinfo = "\
+22 1 259:2 / / rw,relatime shared:1 - ext4 /dev/nvme0n1p2 rw
+45 22 8:17 / /media/me/My\\040Photos rw,nosuid shared:60 - exfat /dev/sdb1 rw,uid=1000
+46 22 0:40 /@home /home rw,relatime shared:2 - btrfs /dev/sda2 rw,subvol=/@home";
+    assert_eq!(
+        vec![
+            ("/dev/nvme0n1p2".into(), "/".into(), "/".into()),
+            ("/dev/sdb1".into(), "/".into(), "/media/me/My Photos".into()),
+            ("/dev/sda2".into(), "/@home".into(), "/home".into()),
+        ],
+        parse_mountinfo(mountinfo)
+    );
+    assert_eq!("My Photos", unescape_udev("My\\x20Photos"));
+    assert_eq!("a\\b", unescape_mountinfo("a\\b"));
+}
+
+#[test]
+fn test_resolve() {
+    let mount = |volume: Option<&str>, root: &str, mount_point: &str| Mount {
+        volume: volume.map(str::to_owned),
+        root: root.into(),
+        mount_point: mount_point.into(),
+    };
+    let volumes = Volumes {
+        mounts: vec![
+            mount(Some("UUID=1234-ABCD"), "/", "/media/me/CARD"),
+            mount(Some("UUID=b7e1"), "/@home", "/home"),
+            mount(Some("UUID=b7e1"), "/", "/mnt/pool"),
+        ],
+    };
+    assert_eq!(
+        Some(PathBuf::from("/media/me/CARD/DCIM/IMG_1.JPG")),
+        volumes.resolve("UUID=1234-ABCD", Path::new("/DCIM/IMG_1.JPG"))
+    );
+    // the subvolume mount is preferred over the whole filesystem
+    assert_eq!(
+        Some(PathBuf::from("/home/me/a.jpg")),
+        volumes.resolve("UUID=b7e1", Path::new("/@home/me/a.jpg"))
+    );
+    assert_eq!(
+        Some(PathBuf::from("/mnt/pool/@photos/a.jpg")),
+        volumes.resolve("UUID=b7e1", Path::new("/@photos/a.jpg"))
+    );
+    assert_eq!(None, volumes.resolve("LABEL=BACKUP", Path::new("/a.jpg")));
+    assert!(!volumes.is_mounted("LABEL=BACKUP"));
+
+    let dir = std::env::temp_dir()
+        .canonicalize()
+        .unwrap()
+        .join(format!("deduper-volume-{}", std::process::id()));
+    fs::create_dir_all(dir.join("photos")).unwrap();
+    fs::create_dir_all(dir.join("tmpfs")).unwrap();
+    let volumes = Volumes {
+        mounts: vec![
+            mount(Some("UUID=1"), "/", dir.to_str().unwrap()),
+            mount(None, "/", dir.join("tmpfs").to_str().unwrap()),
+        ],
+    };
+    assert_eq!(
+        Some(Location {
+            volume: "UUID=1".to_owned(),
+            path: PathBuf::from("/photos/a.jpg")
+        }),
+        volumes.locate(&dir.join("photos/a.jpg"))
+    );
+    // a filesystem without identity
+    assert_eq!(None, volumes.locate(&dir.join("tmpfs/x.jpg")));
+    fs::remove_dir_all(&dir).unwrap();
+}
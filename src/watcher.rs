@@ -0,0 +1,105 @@
+use std::{
+    collections::HashMap,
+    io,
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+use notify::{event::ModifyKind, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{
+    error::Result,
+    media::{walk_files, WalkOptions},
+};
+
+/// Watches source directories for new and modified files. A file is only
+/// handed out once it has gone unchanged for the settle time, so files that
+/// are still being copied or uploaded are not picked up half-written.
+pub struct SourceWatcher {
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    /// Changed paths and when they last changed.
+    pending: HashMap<PathBuf, Instant>,
+    settle: Duration,
+}
+
+impl SourceWatcher {
+    pub fn new(sources: &[PathBuf], settle: Duration) -> Result<Self> {
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        for source in sources {
+            watcher.watch(source, RecursiveMode::Recursive)?;
+        }
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            pending: HashMap::new(),
+            settle,
+        })
+    }
+
+    /// Waits up to `timeout` for files to settle and returns them. A
+    /// directory moved into a source gives every file below it.
+    pub fn poll(&mut self, timeout: Duration) -> Vec<Result<PathBuf>> {
+        let deadline = Instant::now() + timeout;
+        let mut settled = Vec::new();
+        loop {
+            // wake for the deadline or the next pending path to settle
+            let wake = self
+                .pending
+                .values()
+                .map(|changed| *changed + self.settle)
+                .fold(deadline, Instant::min);
+            match self
+                .events
+                .recv_timeout(wake.saturating_duration_since(Instant::now()))
+            {
+                Ok(Ok(event)) if is_change(&event.kind) => {
+                    let now = Instant::now();
+                    for path in event.paths {
+                        self.pending.insert(path, now);
+                    }
+                }
+                Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+                Ok(Err(err)) => settled.push(Err(err.into())),
+                Err(RecvTimeoutError::Disconnected) => return settled,
+            }
+            self.take_settled(&mut settled);
+            if !settled.is_empty() || Instant::now() >= deadline {
+                return settled;
+            }
+        }
+    }
+
+    fn take_settled(&mut self, settled: &mut Vec<Result<PathBuf>>) {
+        let now = Instant::now();
+        let ready = self
+            .pending
+            .iter()
+            .filter(|(_, changed)| now.duration_since(**changed) >= self.settle)
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        for path in ready {
+            self.pending.remove(&path);
+            // paths renamed away or deleted before settling are dropped
+            if path.is_dir() {
+                let files = walk_files(std::slice::from_ref(&path), &WalkOptions::default())
+                    .map(|entry| entry.map_err(|err| io::Error::from(err).into()))
+                    .collect::<Vec<_>>();
+                settled.extend(files);
+            } else if path.is_file() {
+                settled.push(Ok(path));
+            }
+        }
+    }
+}
+
+fn is_change(kind: &EventKind) -> bool {
+    matches!(
+        kind,
+        EventKind::Any
+            | EventKind::Create(_)
+            | EventKind::Modify(ModifyKind::Any | ModifyKind::Data(_) | ModifyKind::Name(_))
+    )
+}